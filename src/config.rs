@@ -0,0 +1,84 @@
+use crate::decode::DecodeLimits;
+use crate::module::{Feature, FEATURES};
+use std::collections::BTreeSet;
+
+/// デコード、検証、インスタンス化で共有する設定。
+/// 本番のホストが対応しているproposalだけを有効にしておけば、それ以外を使うmoduleはどの段階でも同じエラーになる
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// 有効にしたproposal。waiが対応していないものは入れても有効にならない
+    pub(crate) features: BTreeSet<Feature>,
+    pub decode_limits: DecodeLimits,
+    /// 関数呼び出しを入れ子にできる深さ。超えるとRuntimeError::CallStackExhaustedになる
+    pub max_call_depth: usize,
+    /// 1回のinvokeで実行できる命令の数。Noneなら制限しない
+    pub fuel: Option<u64>,
+    /// モジュール内で定義したメモリが確保できるページ数。Noneならmemory typeの上限まで
+    pub max_memory_pages: Option<u64>,
+    /// array.newなどで作れる配列の要素数。超えるとRuntimeError::ArrayTooLargeになる
+    pub max_array_len: usize,
+    /// 浮動小数点数の演算結果がNaNなら、符号と仮数部を決まった値(正のquiet NaN)にそろえる
+    pub canonicalize_nans: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            features: FEATURES.into_iter().filter(Feature::is_supported).collect(),
+            decode_limits: DecodeLimits::default(),
+            max_call_depth: 10_000,
+            fuel: None,
+            max_memory_pages: None,
+            max_array_len: 1 << 24,
+            canonicalize_nans: false,
+        }
+    }
+}
+
+impl Config {
+    /// waiが対応しているproposalをすべて有効にした設定
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// proposalを1つも有効にしない設定。必要なものだけenableで足す
+    pub fn mvp() -> Self {
+        Self {
+            features: BTreeSet::new(),
+            ..Self::default()
+        }
+    }
+
+    pub fn enable(&mut self, feature: Feature) -> &mut Self {
+        self.features.insert(feature);
+        self
+    }
+
+    pub fn disable(&mut self, feature: Feature) -> &mut Self {
+        self.features.remove(&feature);
+        self
+    }
+
+    pub fn is_enabled(&self, feature: &Feature) -> bool {
+        feature.is_supported() && self.features.contains(feature)
+    }
+
+    /// 有効になっているproposal
+    pub fn features(&self) -> impl Iterator<Item = &Feature> {
+        self.features
+            .iter()
+            .filter(|feature| feature.is_supported())
+    }
+
+    /// featuresのうち有効になっていないもの。Featureの順に並べる
+    pub(crate) fn disabled(&self, features: impl IntoIterator<Item = Feature>) -> Vec<Feature> {
+        let mut features: Vec<Feature> = features
+            .into_iter()
+            .filter(|feature| !self.is_enabled(feature))
+            .collect();
+        features.sort();
+        features.dedup();
+
+        features
+    }
+}
@@ -1,6 +1,7 @@
 use crate::decode::error::DecodeError;
+use crate::decode::limits::DecodeLimits;
 use crate::instruction::Instruction;
-use crate::module::{Section, SectionType};
+use crate::module::{Feature, Section, SectionType};
 use crate::opcode::Opcode;
 use crate::types::*;
 use std::convert::TryFrom;
@@ -9,14 +10,57 @@ use std::io::Read;
 
 const MAGIC_NUMBER: &[u8] = b"\0asm";
 const FUNC_TYPE: u8 = 0x60;
+const STRUCT_TYPE: u8 = 0x5F;
+const ARRAY_TYPE: u8 = 0x5E;
+const SUB_TYPE: u8 = 0x50;
+const SUB_FINAL_TYPE: u8 = 0x4F;
+const REC_TYPE: u8 = 0x4E;
+const REF_TYPE: u8 = 0x64;
+const REF_NULL_TYPE: u8 = 0x63;
+/// simdのv128。対応していない
+const V128_TYPE: u8 = 0x7B;
 
 pub(crate) struct Decoder<'a> {
     reader: Cursor<&'a [u8]>,
+    /// readerの先頭がファイルの先頭から何バイト目か
+    offset: usize,
+    /// 最後に読み始めたバイトのreader内の位置。エラーの位置として使う
+    mark: usize,
+    /// importした関数の数。code sectionの関数のindexはこの後ろから数える
+    imported_funcs: u32,
+    limits: DecodeLimits,
+    /// code sectionの関数ごとに、命令が始まるファイル上の位置。record_offsetsを呼んだときだけ集める
+    code_offsets: Option<Vec<Vec<usize>>>,
 }
 
 impl<'a> Decoder<'a> {
-    pub(crate) fn new(reader: Cursor<&'a [u8]>) -> Self {
-        Self { reader }
+    pub(crate) fn new(reader: Cursor<&'a [u8]>, limits: DecodeLimits) -> Self {
+        Self {
+            reader,
+            offset: 0,
+            mark: 0,
+            imported_funcs: 0,
+            limits,
+            code_offsets: None,
+        }
+    }
+
+    /// これから読むcode sectionで、命令ごとのファイル上の位置を集める
+    pub(crate) fn record_offsets(&mut self) {
+        self.code_offsets = Some(Vec::new());
+    }
+
+    /// 集めた命令の位置。record_offsetsを呼んでいなければ空
+    pub(crate) fn take_offsets(&mut self) -> Vec<Vec<usize>> {
+        self.code_offsets.take().unwrap_or_default()
+    }
+
+    /// ファイルのoffsetバイト目から始まる断片を読むDecoderを作る。StreamingDecoderが使う
+    pub(crate) fn fragment(bytes: &'a [u8], offset: usize, limits: DecodeLimits) -> Self {
+        Self {
+            offset,
+            ..Self::new(Cursor::new(bytes), limits)
+        }
     }
 
     pub(crate) fn is_end(&self) -> bool {
@@ -25,29 +69,32 @@ impl<'a> Decoder<'a> {
 
     /// wasmバイナリのマジックナンバーを見て適切なファイルか(本当にwasmか)をチェックする
     pub(crate) fn validate_wasm_format(&mut self) -> Result<(), DecodeError> {
-        let mut magic_number = [0; 4];
-        self.reader.read_exact(&mut magic_number)?;
+        let magic_number = self.read_byte(4)?;
 
-        if magic_number != *MAGIC_NUMBER {
-            return Err(DecodeError::InvalidWasmFile);
+        if magic_number != MAGIC_NUMBER {
+            return Err(self.error(DecodeError::InvalidWasmFile));
         }
 
         Ok(())
     }
 
     pub(crate) fn decode_version(&mut self) -> Result<u32, DecodeError> {
-        let mut version = [0; 4];
-        self.reader.read_exact(&mut version)?;
+        let version = self.read_u32()?;
+        if version != 1 {
+            return Err(self.error(DecodeError::UnknownVersion(version)));
+        }
 
-        Ok(u32::from_le_bytes(version))
+        Ok(version)
     }
 
     pub(crate) fn decode_section_type(&mut self) -> Result<(SectionType, u32), DecodeError> {
-        let mut section_number = [0; 1];
-        self.reader.read_exact(&mut section_number)?;
+        let section_number = self.read_next()?;
+        let section_type = SectionType::from(section_number);
+        if let SectionType::Unsuport = section_type {
+            return Err(self.unexpected(format!("unknown section id {}", section_number)));
+        }
 
         let section_size = self.decode_ver_uint_n()?;
-        let section_type = SectionType::from(section_number[0]);
 
         Ok((section_type, section_size.into()))
     }
@@ -70,117 +117,297 @@ impl<'a> Decoder<'a> {
             SectionType::Element => self.decode_element_section(section_size)?,
             SectionType::Code => self.decode_code_section(section_size)?,
             SectionType::Data => self.decode_data_section(section_size)?,
-            SectionType::Unsuport => todo!(),
+            SectionType::Tag => self.decode_tag_section(section_size)?,
+            SectionType::DataCount => self.decode_data_count_section(section_size)?,
+            SectionType::Unsuport => {
+                unreachable!("unknown section is rejected by decode_section_type")
+            }
         };
 
         Ok(section)
     }
 
+    /// 中身は解釈せずに名前とバイト列のまま持っておく。位置はModule::take_inで決まる
     fn decode_custom_section(&mut self, size: u32) -> Result<Section, DecodeError> {
-        let mut custom_section = vec![0; size as usize];
-        self.reader.read_exact(&mut custom_section)?;
-
-        Ok(Section::Custom(())) // TODO implement!
+        let mut custom_section_decoder = self.sub_decoder(size as usize)?;
+        let name = custom_section_decoder.decode_name()?;
+        let rest = size as usize - custom_section_decoder.position();
+        let data = custom_section_decoder.read_byte(rest)?;
+
+        Ok(Section::Custom(CustomSection {
+            name,
+            data,
+            after: None,
+        }))
     }
 
     fn decode_type_section(&mut self, size: u32) -> Result<Section, DecodeError> {
-        let mut type_section = vec![0; size as usize];
-        self.reader.read_exact(&mut type_section)?;
-
-        let mut type_section_decoder = Decoder::new(Cursor::new(&type_section));
+        let mut type_section_decoder = self.sub_decoder(size as usize)?;
         let mut type_section = TypeSection {
             entries: Vec::new(),
         };
 
-        let entry_count = type_section_decoder.decode_ver_uint_n()?;
-        for _ in 0..entry_count.into() {
-            let func_type = type_section_decoder.read_next()?;
-            if func_type != FUNC_TYPE {
-                return Err(DecodeError::Unexpected(String::new()));
+        // 各エントリはrecグループ。recで包まれていない型は1つだけのグループになる
+        let group_count: u32 = type_section_decoder.decode_ver_uint_n()?.into();
+        for rec_group in 0..group_count {
+            let form = type_section_decoder.read_next()?;
+            if form == REC_TYPE {
+                let count: u32 = type_section_decoder.decode_ver_uint_n()?.into();
+                for _ in 0..count {
+                    let form = type_section_decoder.read_next()?;
+                    let sub_type = type_section_decoder.decode_sub_type(form, rec_group)?;
+                    type_section.entries.push(sub_type);
+                }
+            } else {
+                let sub_type = type_section_decoder.decode_sub_type(form, rec_group)?;
+                type_section.entries.push(sub_type);
             }
+        }
 
-            let mut func_type = FuncType {
-                params: vec![],
-                returns: vec![],
-            };
+        type_section_decoder.expect_end()?;
+
+        Ok(Section::Type(type_section))
+    }
 
-            let arg_count = type_section_decoder.decode_ver_uint_n()?;
-            for _ in 0..arg_count.into() {
-                let t = type_section_decoder.decode_ver_uint_n()?;
-                func_type.params.push(ValueType::from(t));
+    fn decode_sub_type(&mut self, form: u8, rec_group: u32) -> Result<SubType, DecodeError> {
+        if form != SUB_TYPE && form != SUB_FINAL_TYPE {
+            return Ok(SubType::new(self.decode_composite_type(form)?, rec_group));
+        }
+
+        let supertype = match u32::from(self.decode_ver_uint_n()?) {
+            0 => None,
+            1 => Some(self.decode_ver_uint_n()?.into()),
+            count => {
+                return Err(self.unexpected(format!(
+                    "sub type can have at most one supertype, but got {}",
+                    count
+                )))
             }
+        };
+        let is_final = form == SUB_FINAL_TYPE;
+        let form = self.read_next()?;
+
+        Ok(SubType {
+            is_final,
+            supertype,
+            composite: self.decode_composite_type(form)?,
+            rec_group,
+        })
+    }
+
+    fn decode_composite_type(&mut self, form: u8) -> Result<CompositeType, DecodeError> {
+        let composite = match form {
+            FUNC_TYPE => {
+                let mut func_type = FuncType {
+                    params: vec![],
+                    returns: vec![],
+                };
+
+                let arg_count = self.decode_ver_uint_n()?;
+                for _ in 0..arg_count.into() {
+                    func_type.params.push(self.decode_value_type()?);
+                }
+
+                let returns_count = self.decode_ver_uint_n()?;
+                for _ in 0..returns_count.into() {
+                    func_type.returns.push(self.decode_value_type()?);
+                }
 
-            let returns_count = type_section_decoder.decode_ver_uint_n()?;
-            for _ in 0..returns_count.into() {
-                let t = type_section_decoder.decode_ver_uint_n()?;
-                func_type.returns.push(ValueType::from(t));
+                CompositeType::Func(func_type)
             }
+            STRUCT_TYPE => {
+                let count: u32 = self.decode_ver_uint_n()?.into();
+                let mut fields = vec![];
+                for _ in 0..count {
+                    fields.push(self.decode_field_type()?);
+                }
 
-            type_section.entries.push(func_type)
-        }
+                CompositeType::Struct(StructType { fields })
+            }
+            ARRAY_TYPE => CompositeType::Array(self.decode_field_type()?),
+            _ => return Err(self.unexpected(format!("unexpected composite type {:x}", form))),
+        };
 
-        Ok(Section::Type(type_section))
+        Ok(composite)
+    }
+
+    fn decode_field_type(&mut self) -> Result<FieldType, DecodeError> {
+        let storage = match self.peek_next()? {
+            0x78 => {
+                self.read_next()?;
+                StorageType::I8
+            }
+            0x77 => {
+                self.read_next()?;
+                StorageType::I16
+            }
+            _ => StorageType::Value(self.decode_value_type()?),
+        };
+        let mutable = self.read_next()? == 0x01;
+
+        Ok(FieldType { storage, mutable })
     }
 
     fn decode_import_section(&mut self, size: u32) -> Result<Section, DecodeError> {
-        let mut custom_section = vec![0; size as usize];
-        self.reader.read_exact(&mut custom_section)?;
+        let mut import_section_decoder = self.sub_decoder(size as usize)?;
+        let mut import_section = ImportSection {
+            entries: Vec::new(),
+        };
+
+        let count: u32 = import_section_decoder.decode_ver_uint_n()?.into();
+        for _ in 0..count {
+            let module_str = import_section_decoder.decode_name()?;
+            let field_str = import_section_decoder.decode_name()?;
 
-        Ok(Section::Import(()))
+            let kind = match ExternalKind::from(import_section_decoder.decode_ver_uint_n()?) {
+                ExternalKind::Function => {
+                    ImportKind::Function(import_section_decoder.decode_ver_uint_n()?.into())
+                }
+                ExternalKind::Table => {
+                    ImportKind::Table(import_section_decoder.decode_table_type()?)
+                }
+                ExternalKind::Memory => {
+                    ImportKind::Memory(import_section_decoder.decode_memory_type()?)
+                }
+                ExternalKind::Global => {
+                    ImportKind::Global(import_section_decoder.decode_global_type()?)
+                }
+                ExternalKind::Tag => ImportKind::Tag(import_section_decoder.decode_tag_type()?),
+                ExternalKind::Unknown => {
+                    return Err(self.unexpected(format!(
+                        "unknown import kind of {}.{}",
+                        module_str, field_str
+                    )))
+                }
+            };
+
+            import_section.entries.push(ImportEntry {
+                module_str,
+                field_str,
+                kind,
+            });
+        }
+
+        self.imported_funcs = import_section
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry.kind, ImportKind::Function(_)))
+            .count() as u32;
+
+        import_section_decoder.expect_end()?;
+
+        Ok(Section::Import(import_section))
     }
 
     fn decode_function_section(&mut self, size: u32) -> Result<Section, DecodeError> {
-        let mut func_section = vec![0; size as usize];
-        self.reader.read_exact(&mut func_section)?;
-
-        let mut func_section_decoder = Decoder::new(Cursor::new(&func_section));
+        let mut func_section_decoder = self.sub_decoder(size as usize)?;
         let mut func_section = FunctionSection { types: Vec::new() };
 
         let count: u32 = func_section_decoder.decode_ver_uint_n()?.into();
+        func_section_decoder.check_limit("functions", count, self.limits.max_functions)?;
         for _ in 0..count {
             let t = func_section_decoder.decode_ver_uint_n()?;
             func_section.types.push(t.into());
         }
 
+        func_section_decoder.expect_end()?;
+
         Ok(Section::Function(func_section))
     }
 
     fn decode_table_section(&mut self, size: u32) -> Result<Section, DecodeError> {
-        let mut custom_section = vec![0; size as usize];
-        self.reader.read_exact(&mut custom_section)?;
+        let mut table_section_decoder = self.sub_decoder(size as usize)?;
+        let mut table_section = TableSection {
+            entries: Vec::new(),
+        };
+
+        let count: u32 = table_section_decoder.decode_ver_uint_n()?.into();
+        for _ in 0..count {
+            let table_type = table_section_decoder.decode_table_type()?;
+            table_section.entries.push(table_type);
+        }
 
-        Ok(Section::Table(()))
+        table_section_decoder.expect_end()?;
+
+        Ok(Section::Table(table_section))
     }
 
     fn decode_memory_section(&mut self, size: u32) -> Result<Section, DecodeError> {
-        let mut custom_section = vec![0; size as usize];
-        self.reader.read_exact(&mut custom_section)?;
+        let mut memory_section_decoder = self.sub_decoder(size as usize)?;
+        let mut memory_section = MemorySection {
+            entries: Vec::new(),
+        };
+
+        let count: u32 = memory_section_decoder.decode_ver_uint_n()?.into();
+        for _ in 0..count {
+            let memory_type = memory_section_decoder.decode_memory_type()?;
+            memory_section.entries.push(memory_type);
+        }
+
+        memory_section_decoder.expect_end()?;
+
+        Ok(Section::Memory(memory_section))
+    }
 
-        Ok(Section::Memory(()))
+    fn decode_data_count_section(&mut self, size: u32) -> Result<Section, DecodeError> {
+        let mut data_count_decoder = self.sub_decoder(size as usize)?;
+        let count = data_count_decoder.decode_ver_uint_n()?;
+        data_count_decoder.expect_end()?;
+
+        Ok(Section::DataCount(count.into()))
+    }
+
+    fn decode_tag_section(&mut self, size: u32) -> Result<Section, DecodeError> {
+        let mut tag_section_decoder = self.sub_decoder(size as usize)?;
+        let mut tag_section = TagSection {
+            entries: Vec::new(),
+        };
+
+        let count: u32 = tag_section_decoder.decode_ver_uint_n()?.into();
+        for _ in 0..count {
+            let tag_type = tag_section_decoder.decode_tag_type()?;
+            tag_section.entries.push(tag_type);
+        }
+
+        tag_section_decoder.expect_end()?;
+
+        Ok(Section::Tag(tag_section))
     }
 
     fn decode_global_section(&mut self, size: u32) -> Result<Section, DecodeError> {
-        let mut custom_section = vec![0; size as usize];
-        self.reader.read_exact(&mut custom_section)?;
+        let mut global_section_decoder = self.sub_decoder(size as usize)?;
+        let mut global_section = GlobalSection {
+            entries: Vec::new(),
+        };
+
+        let count: u32 = global_section_decoder.decode_ver_uint_n()?.into();
+        for _ in 0..count {
+            let global_type = global_section_decoder.decode_global_type()?;
+            let init = global_section_decoder.decode_const_expr()?;
+            global_section
+                .entries
+                .push(GlobalEntry { global_type, init });
+        }
 
-        Ok(Section::Global(()))
+        global_section_decoder.expect_end()?;
+
+        Ok(Section::Global(global_section))
     }
 
     fn decode_export_section(&mut self, size: u32) -> Result<Section, DecodeError> {
-        let mut section = vec![0; size as usize];
-        self.reader.read_exact(&mut section)?;
-
-        let mut export_section_decoder = Decoder::new(Cursor::new(&section));
+        let mut export_section_decoder = self.sub_decoder(size as usize)?;
         let mut export_section = ExportSection {
             entries: Vec::new(),
         };
 
         let count: u32 = export_section_decoder.decode_ver_uint_n()?.into();
         for _ in 0..count {
-            let field_len = export_section_decoder.decode_ver_uint_n()?;
-            let field_str =
-                String::from_utf8(export_section_decoder.read_byte(field_len.into())?).unwrap();
+            let field_str = export_section_decoder.decode_name()?;
             let kind = ExternalKind::from(export_section_decoder.decode_ver_uint_n()?);
+            if kind == ExternalKind::Unknown {
+                return Err(export_section_decoder
+                    .unexpected(format!("unknown export kind of {}", field_str)));
+            }
             let index = export_section_decoder.decode_ver_uint_n()?.into();
 
             let entry = ExportEntry {
@@ -191,102 +418,402 @@ impl<'a> Decoder<'a> {
             export_section.entries.push(entry);
         }
 
+        export_section_decoder.expect_end()?;
+
         Ok(Section::Export(export_section))
     }
 
     fn decode_start_section(&mut self, size: u32) -> Result<Section, DecodeError> {
-        let mut custom_section = vec![0; size as usize];
-        self.reader.read_exact(&mut custom_section)?;
+        let mut start_section_decoder = self.sub_decoder(size as usize)?;
+        let index = start_section_decoder.decode_ver_uint_n()?;
+        start_section_decoder.expect_end()?;
 
-        Ok(Section::Start(()))
+        Ok(Section::Start(index.into()))
     }
 
     fn decode_element_section(&mut self, size: u32) -> Result<Section, DecodeError> {
-        let mut custom_section = vec![0; size as usize];
-        self.reader.read_exact(&mut custom_section)?;
+        let mut element_section_decoder = self.sub_decoder(size as usize)?;
+        let mut element_section = ElementSection {
+            segments: Vec::new(),
+        };
+
+        let count: u32 = element_section_decoder.decode_ver_uint_n()?.into();
+        for _ in 0..count {
+            let segment = element_section_decoder.decode_element_segment()?;
+            element_section.segments.push(segment);
+        }
+
+        element_section_decoder.expect_end()?;
 
-        Ok(Section::Element(()))
+        Ok(Section::Element(element_section))
     }
 
-    fn decode_code_section(&mut self, size: u32) -> Result<Section, DecodeError> {
-        let mut section = vec![0; size as usize];
-        self.reader.read_exact(&mut section)?;
+    /// flagsのbit0はpassiveかdeclarative、bit1はtable indexの明示(activeのとき)かdeclarative、
+    /// bit2は要素が関数indexではなく定数式であることを表す
+    fn decode_element_segment(&mut self) -> Result<ElementSegment, DecodeError> {
+        let flags: u32 = self.decode_ver_uint_n()?.into();
+        if flags > 0x07 {
+            return Err(self.unexpected(format!("unexpected element segment flags {:x}", flags)));
+        }
+
+        let mode = match flags & 0x03 {
+            0x00 => ElementMode::Active {
+                table: 0,
+                offset: self.decode_const_expr()?,
+            },
+            0x02 => ElementMode::Active {
+                table: self.decode_ver_uint_n()?.into(),
+                offset: self.decode_const_expr()?,
+            },
+            0x01 => ElementMode::Passive,
+            _ => ElementMode::Declarative,
+        };
+
+        // flagsが0と4のときは型が省略されてfuncrefになる
+        let expressions = flags & 0x04 != 0;
+        let elem_type = match (flags & 0x03, expressions) {
+            (0x00, _) => RefType::nullable(HeapType::Func),
+            (_, true) => self.decode_ref_type()?,
+            (_, false) => {
+                let kind = self.read_next()?;
+                if kind != 0x00 {
+                    return Err(self.unexpected(format!("unexpected element kind {:x}", kind)));
+                }
+                RefType::nullable(HeapType::Func)
+            }
+        };
+
+        let count: u32 = self.decode_ver_uint_n()?.into();
+        let items = if expressions {
+            let mut exprs = vec![];
+            for _ in 0..count {
+                exprs.push(self.decode_const_expr()?);
+            }
+            ElementItems::Expressions(exprs)
+        } else {
+            let mut funcs = vec![];
+            for _ in 0..count {
+                funcs.push(self.decode_ver_uint_n()?.into());
+            }
+            ElementItems::Functions(funcs)
+        };
+
+        Ok(ElementSegment {
+            mode,
+            elem_type,
+            items,
+        })
+    }
 
-        let mut code_section_decoder = Decoder::new(Cursor::new(&section));
+    fn decode_code_section(&mut self, size: u32) -> Result<Section, DecodeError> {
+        let mut code_section_decoder = self.sub_decoder(size as usize)?;
         let mut code_section = CodeSection { bodies: Vec::new() };
 
-        let count: u32 = code_section_decoder.decode_ver_uint_n()?.into();
+        let count = code_section_decoder.decode_code_count()?;
+        for i in 0..count {
+            let index = self.imported_funcs + i;
+            let (function_body, offsets) = code_section_decoder
+                .decode_code_entry_with_offsets()
+                .map_err(|e| e.in_function(index))?;
+            code_section.bodies.push(function_body);
+            if let Some(code_offsets) = &mut self.code_offsets {
+                code_offsets.push(offsets);
+            }
+        }
 
-        for _ in 0..count {
-            let body_size = code_section_decoder.decode_ver_uint_n()?;
-            let body_bytes = &code_section_decoder.read_byte(body_size.into())?;
-            let mut body = Decoder::new(Cursor::new(body_bytes));
+        code_section_decoder.expect_end()?;
 
-            let local_count = body.decode_ver_uint_n()?;
+        Ok(Section::Code(code_section))
+    }
 
-            let mut function_body = FunctionBody {
-                locales: Vec::new(),
-                code: Vec::new(),
-            };
+    /// code sectionの先頭にある関数本体の数
+    pub(crate) fn decode_code_count(&mut self) -> Result<u32, DecodeError> {
+        let count = self.decode_u32()?;
+        self.check_limit("functions", count, self.limits.max_functions)?;
 
-            for _ in 0..local_count.into() {
-                let count = body.decode_ver_uint_n()?;
-                let t = body.decode_ver_uint_n()?;
-                let local_entry = LocalEntry {
-                    count: count.into(),
-                    value_type: ValueType::from(t),
-                };
+        Ok(count)
+    }
 
-                function_body.locales.push(local_entry);
+    pub(crate) fn decode_code_entry(&mut self) -> Result<FunctionBody, DecodeError> {
+        self.decode_code_entry_with_offsets()
+            .map(|(function_body, _)| function_body)
+    }
+
+    /// 関数本体と、その命令それぞれが始まるファイル上の位置
+    fn decode_code_entry_with_offsets(
+        &mut self,
+    ) -> Result<(FunctionBody, Vec<usize>), DecodeError> {
+        let body_size = self.decode_ver_uint_n()?;
+        self.check_limit("body size", u32::from(body_size), self.limits.max_body_size)?;
+        let mut body = self.sub_decoder(body_size.into())?;
+
+        let local_count = body.decode_ver_uint_n()?;
+
+        let mut function_body = FunctionBody {
+            locales: Vec::new(),
+            code: Vec::new(),
+        };
+
+        let mut total: u64 = 0;
+        for _ in 0..local_count.into() {
+            let count = body.decode_ver_uint_n()?;
+            total += u64::from(u32::from(count));
+            if total > u64::from(u32::MAX) {
+                return Err(body.unexpected("too many locals".to_string()));
             }
+            body.check_limit("locals", total, body.limits.max_locals)?;
+            let local_entry = LocalEntry {
+                count: count.into(),
+                value_type: body.decode_value_type()?,
+            };
 
-            function_body.code = body.decode_function_body()?;
-            code_section.bodies.push(function_body);
+            function_body.locales.push(local_entry);
         }
 
-        Ok(Section::Code(code_section))
+        let (code, offsets) = body.decode_function_body()?;
+        function_body.code = code;
+
+        Ok((function_body, offsets))
     }
 
     fn decode_data_section(&mut self, size: u32) -> Result<Section, DecodeError> {
-        let mut section = vec![0; size as usize];
-        self.reader.read_exact(&mut section)?;
-
-        let mut data_section_decoder = Decoder::new(Cursor::new(&section));
+        let mut data_section_decoder = self.sub_decoder(size as usize)?;
 
         let count = data_section_decoder.decode_ver_uint_n()?;
 
         let mut segments = vec![];
         for _ in 0..count.into() {
-            let index = data_section_decoder.decode_ver_uint_n()?;
-            let _opcode = data_section_decoder.read_next()?;
-
-            let offset = data_section_decoder.decode_ver_uint_n()?;
-
-            // NOTE END命令が入っているので読み飛ばしてしまう
-            // 本来であればopcodeを見て、ENDが来るまでdecodeするのがいいが、その必要性がないので雑に実装する
-            data_section_decoder.read_next()?;
+            // flagsが0ならmemory 0へのactive segment、1ならpassive、2ならmemory indexを明示したactive segment
+            let flags: u32 = data_section_decoder.decode_ver_uint_n()?.into();
+            let (index, offset, passive) = match flags {
+                0x00 => (0, data_section_decoder.decode_const_expr()?, false),
+                0x01 => (0, vec![], true),
+                0x02 => {
+                    let index = data_section_decoder.decode_ver_uint_n()?.into();
+                    (index, data_section_decoder.decode_const_expr()?, false)
+                }
+                _ => {
+                    return Err(
+                        self.unexpected(format!("unexpected data segment flags {:x}", flags))
+                    )
+                }
+            };
 
             let size = data_section_decoder.decode_ver_uint_n()?;
+            data_section_decoder.check_limit(
+                "data segment size",
+                u32::from(size),
+                self.limits.max_data_segment_size,
+            )?;
 
             let data = data_section_decoder.read_byte(usize::from(size))?;
 
             segments.push(DataSegment {
                 data,
-                index: index.into(),
-                offset: offset.into(),
+                index,
+                offset,
+                passive,
             })
         }
 
+        data_section_decoder.expect_end()?;
+
         Ok(Section::Data(DataSection { segments }))
     }
 
+    pub(crate) fn decode_name(&mut self) -> Result<String, DecodeError> {
+        let len = self.decode_ver_uint_n()?;
+        let bytes = self.read_byte(len.into())?;
+
+        String::from_utf8(bytes).map_err(|e| self.unexpected(format!("{}", e)))
+    }
+
+    fn decode_value_type(&mut self) -> Result<ValueType, DecodeError> {
+        let x = self.read_next()?;
+        let value_type = match x {
+            0x7c..=0x7f => ValueType::from(x),
+            V128_TYPE => return Err(self.unsupported(Feature::Simd128)),
+            REF_TYPE | REF_NULL_TYPE => {
+                ValueType::Ref(RefType::new(x == REF_NULL_TYPE, self.decode_heap_type()?))
+            }
+            _ => match HeapType::from_abstract(x) {
+                Some(heap_type) => ValueType::Ref(RefType::nullable(heap_type)),
+                None => return Err(self.unexpected(format!("unexpected value type {:x}", x))),
+            },
+        };
+
+        Ok(value_type)
+    }
+
+    fn decode_ref_type(&mut self) -> Result<RefType, DecodeError> {
+        match self.decode_value_type()? {
+            ValueType::Ref(ref_type) => Ok(ref_type),
+            t => Err(self.unexpected(format!("expect reference type, but got {:?}", t))),
+        }
+    }
+
+    /// ヒープ型はs33でエンコードされていて、負の値(1バイト)が抽象ヒープ型、それ以外がtype index
+    fn decode_heap_type(&mut self) -> Result<HeapType, DecodeError> {
+        if let Some(heap_type) = HeapType::from_abstract(self.peek_next()?) {
+            self.read_next()?;
+            return Ok(heap_type);
+        }
+
+        Ok(HeapType::Concrete(self.decode_ver_uint_n()?.into()))
+    }
+
+    fn decode_block_type(&mut self) -> Result<BlockType, DecodeError> {
+        match self.peek_next()? {
+            REF_TYPE | REF_NULL_TYPE => Ok(BlockType::Ref(self.decode_ref_type()?)),
+            // 0x40未満か続きのあるs33は、型をtype indexで指すmulti valueのblock
+            x if x < 0x40 || x & 0x80 != 0 => {
+                let index = self.decode_i64()?;
+                match u32::try_from(index) {
+                    Ok(index) => Ok(BlockType::TypeIndex(index)),
+                    Err(_) => Err(self.error(DecodeError::InvalidNumeric(format!(
+                        "block type index is {}",
+                        index
+                    )))),
+                }
+            }
+            _ => {
+                let x = self.read_next()?;
+                match x {
+                    V128_TYPE => Err(self.unsupported(Feature::Simd128)),
+                    _ => BlockType::try_from(x).map_err(|e| self.error(e)),
+                }
+            }
+        }
+    }
+
+    fn decode_limits(&mut self) -> Result<ResizableLimits, DecodeError> {
+        let flags = self.read_next()?;
+        self.decode_limits_with_flags(flags)
+    }
+
+    /// flagsのbit0が最大値の有無、bit1がshared、bit2がmemory64(64bitのindex)を表す
+    fn decode_limits_with_flags(&mut self, flags: u8) -> Result<ResizableLimits, DecodeError> {
+        if flags & !0x07 != 0 {
+            return Err(self.unexpected(format!("unexpected limits flags {:x}", flags)));
+        }
+
+        let initial = self.decode_ver_uint64()?;
+        let maximum = match flags & 0x01 {
+            0x00 => None,
+            _ => Some(self.decode_ver_uint64()?),
+        };
+
+        Ok(ResizableLimits { initial, maximum })
+    }
+
+    fn decode_memory_type(&mut self) -> Result<MemoryType, DecodeError> {
+        let flags = self.read_next()?;
+        let limits = self.decode_limits_with_flags(flags)?;
+
+        if flags & 0x02 != 0 && limits.maximum.is_none() {
+            return Err(self.unexpected("shared memory must have maximum".to_string()));
+        }
+
+        Ok(MemoryType {
+            limits,
+            memory64: flags & 0x04 != 0,
+            shared: flags & 0x02 != 0,
+        })
+    }
+
+    fn decode_table_type(&mut self) -> Result<TableType, DecodeError> {
+        let elem_type = match self.decode_value_type()? {
+            ValueType::Ref(ref_type) => ref_type,
+            t => {
+                return Err(self.unexpected(format!(
+                    "table element type must be reference type, but got {:?}",
+                    t
+                )))
+            }
+        };
+        let limits = self.decode_limits()?;
+
+        Ok(TableType { elem_type, limits })
+    }
+
+    fn decode_global_type(&mut self) -> Result<GlobalType, DecodeError> {
+        let content_type = self.decode_value_type()?;
+        let mutability = self.read_next()? == 0x01;
+
+        Ok(GlobalType {
+            content_type,
+            mutability,
+        })
+    }
+
+    fn decode_tag_type(&mut self) -> Result<TagType, DecodeError> {
+        let attribute = self.read_next()?;
+        if attribute != 0x00 {
+            return Err(self.unexpected(format!("unexpected tag attribute {:x}", attribute)));
+        }
+        let type_index = self.decode_ver_uint_n()?.into();
+
+        Ok(TagType {
+            attribute,
+            type_index,
+        })
+    }
+
+    fn decode_catch_clause(&mut self) -> Result<CatchClause, DecodeError> {
+        let kind = self.read_next()?;
+        let clause = match kind {
+            0x00 => CatchClause::Catch(
+                self.decode_ver_uint_n()?.into(),
+                self.decode_ver_uint_n()?.into(),
+            ),
+            0x01 => CatchClause::CatchRef(
+                self.decode_ver_uint_n()?.into(),
+                self.decode_ver_uint_n()?.into(),
+            ),
+            0x02 => CatchClause::CatchAll(self.decode_ver_uint_n()?.into()),
+            0x03 => CatchClause::CatchAllRef(self.decode_ver_uint_n()?.into()),
+            _ => return Err(self.unexpected(format!("unexpected catch clause {:x}", kind))),
+        };
+
+        Ok(clause)
+    }
+
+    /// u32のLEB128は高々5バイトで、5バイト目は下位4bitしか使えない
     fn decode_ver_uint_n(&mut self) -> Result<VerUintN, DecodeError> {
+        let mut value = 0;
+        for i in 0..5 {
+            let bytes = u32::from(self.read_next()?);
+            if i == 4 && bytes & 0x70 != 0 {
+                return Err(
+                    self.error(DecodeError::InvalidNumeric("integer too large".to_string()))
+                );
+            }
+            value |= (bytes & 0x7f) << (i * 7);
+
+            if bytes & 0x80 == 0 {
+                return Ok(VerUintN::from(value));
+            }
+        }
+
+        Err(self.error(DecodeError::InvalidNumeric(
+            "integer representation too long".to_string(),
+        )))
+    }
+
+    pub(crate) fn decode_u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(self.decode_ver_uint_n()?.into())
+    }
+
+    fn decode_ver_uint64(&mut self) -> Result<u64, DecodeError> {
         let mut value = 0;
         let mut i = 0;
         loop {
-            let bytes = u32::from(self.read_next()?);
+            let bytes = u64::from(self.read_next()?);
             value += (bytes & 0x7f).checked_shl(i * 7).ok_or_else(|| {
-                DecodeError::InvalidNumeric(format!("value is {}, byte is {:x}", value, bytes))
+                self.error(DecodeError::InvalidNumeric(format!(
+                    "value is {}, byte is {:x}",
+                    value, bytes
+                )))
             })?;
 
             i += 1;
@@ -296,21 +823,28 @@ impl<'a> Decoder<'a> {
             }
         }
 
-        Ok(VerUintN::from(value))
+        Ok(value)
     }
 
+    /// 符号付きLEB128をデコードする。i32.constの値もこれで読んでからi32にキャストする
     fn decode_i64(&mut self) -> Result<i64, DecodeError> {
         let mut value = 0;
-        let mut i = 0;
+        let mut shift = 0;
         loop {
             let bytes = i64::from(self.read_next()?);
-            value += (bytes & 0x7f).checked_shl(i * 7).ok_or_else(|| {
-                DecodeError::InvalidNumeric(format!("value is {}, byte is {:x}", value, bytes))
+            value |= (bytes & 0x7f).checked_shl(shift).ok_or_else(|| {
+                self.error(DecodeError::InvalidNumeric(format!(
+                    "value is {}, byte is {:x}",
+                    value, bytes
+                )))
             })?;
 
-            i += 1;
+            shift += 7;
 
             if bytes & 0x80 == 0 {
+                if shift < 64 && bytes & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
                 break;
             }
         }
@@ -318,188 +852,420 @@ impl<'a> Decoder<'a> {
         Ok(value)
     }
 
-    fn decode_function_body(&mut self) -> Result<Vec<Instruction>, DecodeError> {
+    /// data segmentなどのoffsetに使われる定数式をデコードする
+    /// globalの初期値などの定数式をENDまでデコードする
+    fn decode_const_expr(&mut self) -> Result<Vec<Instruction>, DecodeError> {
         let mut instructions = Vec::new();
         loop {
-            let opcode = match Opcode::try_from(self.read_next()?) {
-                Ok(v) => v,
-                Err(e) => {
-                    return Err(DecodeError::Unexpected(format!(
-                        "error: {}\nnow decoded instructions is {:?}",
-                        e, instructions
-                    )))
+            let opcode = self.decode_opcode()?;
+            if opcode == Opcode::End {
+                break;
+            }
+
+            instructions.push(self.decode_instruction(opcode)?);
+        }
+
+        Ok(instructions)
+    }
+
+    /// 対応していないproposalの命令は、続くバイトを別の命令として読んでしまわないように、その場でエラーにする
+    fn decode_opcode(&mut self) -> Result<Opcode, DecodeError> {
+        let x = self.read_next()?;
+        let feature = match x {
+            0x12 | 0x13 => Feature::TailCall,
+            0xC0..=0xC4 => Feature::SignExt,
+            0xFD => {
+                let mark = self.mark;
+                let sub_opcode = u32::from(self.decode_ver_uint_n()?);
+                self.mark = mark;
+                // relaxed simdの命令は0x100から
+                match sub_opcode {
+                    0x100.. => Feature::RelaxedSimd,
+                    _ => Feature::Simd128,
                 }
-            };
+            }
+            _ => return Opcode::try_from(x).map_err(|e| self.error(e)),
+        };
+
+        Err(self.unsupported(feature))
+    }
+
+    fn decode_function_body(&mut self) -> Result<(Vec<Instruction>, Vec<usize>), DecodeError> {
+        let mut instructions = Vec::new();
+        let mut offsets = Vec::new();
+        let mut depth: u32 = 0;
+        loop {
+            let opcode = self.decode_opcode()?;
 
             if self.is_end() {
+                if opcode != Opcode::End {
+                    return Err(self.unexpected("END opcode expected".to_string()));
+                }
                 break;
             }
 
-            let instruction = match opcode {
-                // expect BlockType
-                Opcode::Block => Instruction::Block(BlockType::try_from(self.read_next()?)?),
-                Opcode::Loop => Instruction::Loop(BlockType::try_from(self.read_next()?)?),
-                Opcode::If => Instruction::If(BlockType::try_from(self.read_next()?)?),
-
-                // expect VerUintN
-                Opcode::Br => Instruction::Br(self.decode_ver_uint_n()?),
-                Opcode::BrIf => Instruction::BrIf(self.decode_ver_uint_n()?),
-                Opcode::GetLocal => Instruction::GetLocal(self.decode_ver_uint_n()?),
-                Opcode::SetLocal => Instruction::SetLocal(self.decode_ver_uint_n()?),
-                Opcode::TeeLocal => Instruction::TeeLocal(self.decode_ver_uint_n()?),
-                Opcode::GetGlobal => Instruction::GetGlobal(self.decode_ver_uint_n()?),
-                Opcode::SetGlobal => Instruction::SetGlobal(self.decode_ver_uint_n()?),
-                Opcode::Call => Instruction::Call(self.decode_ver_uint_n()?),
-                Opcode::CurrentMemory => Instruction::CurrentMemory(self.decode_ver_uint_n()?),
-                Opcode::GrowMemory => Instruction::GrowMemory(self.decode_ver_uint_n()?),
-
-                Opcode::BrTable => {
-                    let target_count = self.decode_ver_uint_n()?;
-                    let mut target_tables = vec![];
-                    for _ in 0..u32::from(target_count) {
-                        target_tables.push(self.decode_ver_uint_n()?);
-                    }
-                    let default_target = self.decode_ver_uint_n()?;
-
-                    Instruction::BrTable(target_tables, default_target)
+            match opcode {
+                Opcode::Block | Opcode::Loop | Opcode::If | Opcode::TryTable => {
+                    depth += 1;
+                    self.check_limit("nesting depth", depth, self.limits.max_nesting_depth)?;
                 }
-                Opcode::CallIndirect => {
-                    let type_index = self.decode_ver_uint_n()?;
-                    let reserved = self.decode_ver_uint_n()?;
+                Opcode::End => depth = depth.saturating_sub(1),
+                _ => {}
+            }
 
-                    Instruction::CallIndirect(type_index, reserved)
+            offsets.push(self.offset + self.mark);
+            instructions.push(self.decode_instruction(opcode)?);
+        }
+
+        Ok((instructions, offsets))
+    }
+
+    fn decode_instruction(&mut self, opcode: Opcode) -> Result<Instruction, DecodeError> {
+        let instruction = match opcode {
+            // expect BlockType
+            Opcode::Block => Instruction::Block(self.decode_block_type()?),
+            Opcode::Loop => Instruction::Loop(self.decode_block_type()?),
+            Opcode::If => Instruction::If(self.decode_block_type()?),
+            Opcode::TryTable => {
+                let block_type = self.decode_block_type()?;
+                let count: u32 = self.decode_ver_uint_n()?.into();
+                let mut catches = vec![];
+                for _ in 0..count {
+                    catches.push(self.decode_catch_clause()?);
                 }
 
-                Opcode::I32Load => Instruction::I32Load(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I64Load => Instruction::I64Load(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::F32Load => Instruction::F32Load(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::F64Load => Instruction::F64Load(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I32Load8S => Instruction::I32Load8S(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I32Load8U => Instruction::I32Load8U(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I32Load16S => Instruction::I32Load16S(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I32Load16U => Instruction::I32Load16U(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I64Load8S => Instruction::I64Load8S(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I64Load8U => Instruction::I64Load8U(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I64Load16S => Instruction::I64Load16S(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I64Load16U => Instruction::I64Load16U(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I64Load32S => Instruction::I64Load32S(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I64Load32U => Instruction::I64Load32U(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I32Store => Instruction::I32Store(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I64Store => Instruction::I64Store(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::F32Store => Instruction::F32Store(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::F64Store => Instruction::F64Store(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I32Store8 => Instruction::I32Store8(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I32Store16 => Instruction::I32Store16(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I64Store8 => Instruction::I64Store8(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I64Store16 => Instruction::I64Store16(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-                Opcode::I64Store32 => Instruction::I64Store32(
-                    u32::from(self.decode_ver_uint_n()?),
-                    u32::from(self.decode_ver_uint_n()?),
-                ),
-
-                Opcode::I32Const => Instruction::I32Const(i32::from(self.decode_ver_uint_n()?)),
-                Opcode::I64Const => Instruction::I64Const(self.decode_i64()?),
-                Opcode::F32Const => {
-                    let v = self.read_u32()?;
-                    let v = f32::from_bits(v);
-                    Instruction::F32Const(v)
+                Instruction::TryTable(block_type, catches)
+            }
+
+            // expect VerUintN
+            Opcode::Br => Instruction::Br(self.decode_ver_uint_n()?),
+            Opcode::BrIf => Instruction::BrIf(self.decode_ver_uint_n()?),
+            Opcode::GetLocal => Instruction::GetLocal(self.decode_ver_uint_n()?),
+            Opcode::SetLocal => Instruction::SetLocal(self.decode_ver_uint_n()?),
+            Opcode::TeeLocal => Instruction::TeeLocal(self.decode_ver_uint_n()?),
+            Opcode::GetGlobal => Instruction::GetGlobal(self.decode_ver_uint_n()?),
+            Opcode::SetGlobal => Instruction::SetGlobal(self.decode_ver_uint_n()?),
+            Opcode::TableGet => Instruction::TableGet(self.decode_ver_uint_n()?),
+            Opcode::TableSet => Instruction::TableSet(self.decode_ver_uint_n()?),
+            Opcode::Call => Instruction::Call(self.decode_ver_uint_n()?),
+            Opcode::CallRef => Instruction::CallRef(self.decode_ver_uint_n()?),
+            Opcode::ReturnCallRef => Instruction::ReturnCallRef(self.decode_ver_uint_n()?),
+            Opcode::RefFunc => Instruction::RefFunc(self.decode_ver_uint_n()?),
+            Opcode::BrOnNull => Instruction::BrOnNull(self.decode_ver_uint_n()?),
+            Opcode::BrOnNonNull => Instruction::BrOnNonNull(self.decode_ver_uint_n()?),
+            Opcode::Throw => Instruction::Throw(self.decode_ver_uint_n()?),
+            Opcode::CurrentMemory => Instruction::CurrentMemory(self.decode_ver_uint_n()?),
+            Opcode::GrowMemory => Instruction::GrowMemory(self.decode_ver_uint_n()?),
+
+            Opcode::BrTable => {
+                let target_count = self.decode_ver_uint_n()?;
+                let mut target_tables = vec![];
+                for _ in 0..u32::from(target_count) {
+                    target_tables.push(self.decode_ver_uint_n()?);
                 }
+                let default_target = self.decode_ver_uint_n()?;
+
+                Instruction::BrTable(target_tables, default_target)
+            }
+            Opcode::CallIndirect => {
+                let type_index = self.decode_ver_uint_n()?;
+                let reserved = self.decode_ver_uint_n()?;
+
+                Instruction::CallIndirect(type_index, reserved)
+            }
+
+            Opcode::I32Load => Instruction::I32Load(self.decode_memarg()?),
+            Opcode::I64Load => Instruction::I64Load(self.decode_memarg()?),
+            Opcode::F32Load => Instruction::F32Load(self.decode_memarg()?),
+            Opcode::F64Load => Instruction::F64Load(self.decode_memarg()?),
+            Opcode::I32Load8S => Instruction::I32Load8S(self.decode_memarg()?),
+            Opcode::I32Load8U => Instruction::I32Load8U(self.decode_memarg()?),
+            Opcode::I32Load16S => Instruction::I32Load16S(self.decode_memarg()?),
+            Opcode::I32Load16U => Instruction::I32Load16U(self.decode_memarg()?),
+            Opcode::I64Load8S => Instruction::I64Load8S(self.decode_memarg()?),
+            Opcode::I64Load8U => Instruction::I64Load8U(self.decode_memarg()?),
+            Opcode::I64Load16S => Instruction::I64Load16S(self.decode_memarg()?),
+            Opcode::I64Load16U => Instruction::I64Load16U(self.decode_memarg()?),
+            Opcode::I64Load32S => Instruction::I64Load32S(self.decode_memarg()?),
+            Opcode::I64Load32U => Instruction::I64Load32U(self.decode_memarg()?),
+            Opcode::I32Store => Instruction::I32Store(self.decode_memarg()?),
+            Opcode::I64Store => Instruction::I64Store(self.decode_memarg()?),
+            Opcode::F32Store => Instruction::F32Store(self.decode_memarg()?),
+            Opcode::F64Store => Instruction::F64Store(self.decode_memarg()?),
+            Opcode::I32Store8 => Instruction::I32Store8(self.decode_memarg()?),
+            Opcode::I32Store16 => Instruction::I32Store16(self.decode_memarg()?),
+            Opcode::I64Store8 => Instruction::I64Store8(self.decode_memarg()?),
+            Opcode::I64Store16 => Instruction::I64Store16(self.decode_memarg()?),
+            Opcode::I64Store32 => Instruction::I64Store32(self.decode_memarg()?),
+
+            Opcode::I32Const => Instruction::I32Const(self.decode_i64()? as i32),
+            Opcode::I64Const => Instruction::I64Const(self.decode_i64()?),
+            Opcode::F32Const => {
+                let v = self.read_u32()?;
+                let v = f32::from_bits(v);
+                Instruction::F32Const(v)
+            }
+
+            Opcode::F64Const => {
+                let v = self.read_u64()?;
+                let v = f64::from_bits(v);
+                Instruction::F64Const(v)
+            }
+            Opcode::RefNull => Instruction::RefNull(self.decode_heap_type()?),
+            Opcode::Prefix => self.decode_prefix_instruction()?,
+            Opcode::AtomicPrefix => self.decode_atomic_instruction()?,
+            Opcode::GcPrefix => self.decode_gc_instruction()?,
+            _ => Instruction::from(opcode),
+        };
+
+        Ok(instruction)
+    }
+
+    /// 0xFCから始まる命令をデコードする
+    fn decode_prefix_instruction(&mut self) -> Result<Instruction, DecodeError> {
+        let sub_opcode: u32 = self.decode_ver_uint_n()?.into();
+
+        let instruction = match sub_opcode {
+            0x00..=0x07 => return Err(self.unsupported(Feature::NontrappingFptoint)),
+            0x08 => Instruction::MemoryInit(self.decode_ver_uint_n()?, self.decode_ver_uint_n()?),
+            0x09 => Instruction::DataDrop(self.decode_ver_uint_n()?),
+            0x0A => Instruction::MemoryCopy(self.decode_ver_uint_n()?, self.decode_ver_uint_n()?),
+            0x0B => Instruction::MemoryFill(self.decode_ver_uint_n()?),
+            0x0C => Instruction::TableInit(self.decode_ver_uint_n()?, self.decode_ver_uint_n()?),
+            0x0D => Instruction::ElemDrop(self.decode_ver_uint_n()?),
+            0x0E => Instruction::TableCopy(self.decode_ver_uint_n()?, self.decode_ver_uint_n()?),
+            0x0F => Instruction::TableGrow(self.decode_ver_uint_n()?),
+            0x10 => Instruction::TableSize(self.decode_ver_uint_n()?),
+            0x11 => Instruction::TableFill(self.decode_ver_uint_n()?),
+            _ => {
+                return Err(self.unexpected(format!("unexpected prefix opcode fc {:x}", sub_opcode)))
+            }
+        };
+
+        Ok(instruction)
+    }
+
+    /// 0xFEから始まるthreads proposalの命令をデコードする
+    fn decode_atomic_instruction(&mut self) -> Result<Instruction, DecodeError> {
+        let sub_opcode: u32 = self.decode_ver_uint_n()?.into();
+
+        let instruction = match sub_opcode {
+            0x00 => Instruction::AtomicNotify(self.decode_memarg()?),
+            0x01 => Instruction::AtomicWait32(self.decode_memarg()?),
+            0x02 => Instruction::AtomicWait64(self.decode_memarg()?),
+            0x03 => {
+                self.read_next()?; // reserved
+                Instruction::AtomicFence
+            }
+            0x10..=0x16 => {
+                let width = AtomicWidth::from((sub_opcode - 0x10) as u8);
+                Instruction::AtomicLoad(width, self.decode_memarg()?)
+            }
+            0x17..=0x1D => {
+                let width = AtomicWidth::from((sub_opcode - 0x17) as u8);
+                Instruction::AtomicStore(width, self.decode_memarg()?)
+            }
+            0x1E..=0x47 => {
+                let op = match (sub_opcode - 0x1E) / 7 {
+                    0 => AtomicRmwOp::Add,
+                    1 => AtomicRmwOp::Sub,
+                    2 => AtomicRmwOp::And,
+                    3 => AtomicRmwOp::Or,
+                    4 => AtomicRmwOp::Xor,
+                    _ => AtomicRmwOp::Xchg,
+                };
+                let width = AtomicWidth::from((sub_opcode - 0x1E) as u8);
+                Instruction::AtomicRmw(op, width, self.decode_memarg()?)
+            }
+            0x48..=0x4E => {
+                let width = AtomicWidth::from((sub_opcode - 0x48) as u8);
+                Instruction::AtomicCmpxchg(width, self.decode_memarg()?)
+            }
+            _ => {
+                return Err(self.unexpected(format!("unexpected atomic opcode fe {:x}", sub_opcode)))
+            }
+        };
+
+        Ok(instruction)
+    }
 
-                Opcode::F64Const => {
-                    let v = self.read_u64()?;
-                    let v = f64::from_bits(v);
-                    Instruction::F64Const(v)
+    /// 0xFBから始まるGC proposalの命令をデコードする
+    fn decode_gc_instruction(&mut self) -> Result<Instruction, DecodeError> {
+        let sub_opcode: u32 = self.decode_ver_uint_n()?.into();
+
+        let instruction = match sub_opcode {
+            0x00 => Instruction::StructNew(self.decode_ver_uint_n()?),
+            0x01 => Instruction::StructNewDefault(self.decode_ver_uint_n()?),
+            0x02 => Instruction::StructGet(self.decode_ver_uint_n()?, self.decode_ver_uint_n()?),
+            0x03 => Instruction::StructGetS(self.decode_ver_uint_n()?, self.decode_ver_uint_n()?),
+            0x04 => Instruction::StructGetU(self.decode_ver_uint_n()?, self.decode_ver_uint_n()?),
+            0x05 => Instruction::StructSet(self.decode_ver_uint_n()?, self.decode_ver_uint_n()?),
+            0x06 => Instruction::ArrayNew(self.decode_ver_uint_n()?),
+            0x07 => Instruction::ArrayNewDefault(self.decode_ver_uint_n()?),
+            0x08 => {
+                Instruction::ArrayNewFixed(self.decode_ver_uint_n()?, self.decode_ver_uint_n()?)
+            }
+            0x09 => Instruction::ArrayNewData(self.decode_ver_uint_n()?, self.decode_ver_uint_n()?),
+            0x0B => Instruction::ArrayGet(self.decode_ver_uint_n()?),
+            0x0C => Instruction::ArrayGetS(self.decode_ver_uint_n()?),
+            0x0D => Instruction::ArrayGetU(self.decode_ver_uint_n()?),
+            0x0E => Instruction::ArraySet(self.decode_ver_uint_n()?),
+            0x0F => Instruction::ArrayLen,
+            0x10 => Instruction::ArrayFill(self.decode_ver_uint_n()?),
+            0x11 => Instruction::ArrayCopy(self.decode_ver_uint_n()?, self.decode_ver_uint_n()?),
+            0x14 | 0x15 => {
+                let heap_type = self.decode_heap_type()?;
+                Instruction::RefTest(RefType::new(sub_opcode == 0x15, heap_type))
+            }
+            0x16 | 0x17 => {
+                let heap_type = self.decode_heap_type()?;
+                Instruction::RefCast(RefType::new(sub_opcode == 0x17, heap_type))
+            }
+            0x18 | 0x19 => {
+                // flagsのbit0が入力、bit1が出力の型がnullableかどうか
+                let flags = self.read_next()?;
+                let label = self.decode_ver_uint_n()?;
+                let from = RefType::new(flags & 0x01 != 0, self.decode_heap_type()?);
+                let to = RefType::new(flags & 0x02 != 0, self.decode_heap_type()?);
+
+                if sub_opcode == 0x18 {
+                    Instruction::BrOnCast(label, from, to)
+                } else {
+                    Instruction::BrOnCastFail(label, from, to)
                 }
-                Opcode::Prefix => Instruction::Prefix(self.decode_ver_uint_n()?),
-                _ => Instruction::from(opcode),
-            };
+            }
+            0x1A => Instruction::AnyConvertExtern,
+            0x1B => Instruction::ExternConvertAny,
+            0x1C => Instruction::RefI31,
+            0x1D => Instruction::I31GetS,
+            0x1E => Instruction::I31GetU,
+            _ => return Err(self.unexpected(format!("unexpected gc opcode fb {:x}", sub_opcode))),
+        };
 
-            instructions.push(instruction);
-        }
+        Ok(instruction)
+    }
 
-        Ok(instructions)
+    /// alignのbit6が立っているときはoffsetの前にmemory indexが続く
+    fn decode_memarg(&mut self) -> Result<MemArg, DecodeError> {
+        let align: u32 = self.decode_ver_uint_n()?.into();
+        let memory = if align & 0x40 != 0 {
+            self.decode_ver_uint_n()?.into()
+        } else {
+            0
+        };
+        let offset = self.decode_ver_uint64()?;
+
+        Ok(MemArg {
+            align: align & !0x40,
+            offset,
+            memory,
+        })
     }
 
-    fn read_next(&mut self) -> Result<u8, DecodeError> {
+    fn peek_next(&mut self) -> Result<u8, DecodeError> {
+        let position = self.reader.position();
+        let next = self.read_next()?;
+        self.reader.set_position(position);
+
+        Ok(next)
+    }
+
+    pub(crate) fn read_next(&mut self) -> Result<u8, DecodeError> {
+        self.mark = self.position();
         let mut buf = [0u8; 1];
-        self.reader.read_exact(&mut buf)?;
+        self.reader
+            .read_exact(&mut buf)
+            .map_err(|e| self.error(e.into()))?;
 
         Ok(buf[0])
     }
 
-    fn read_byte(&mut self, size: usize) -> Result<Vec<u8>, DecodeError> {
+    pub(crate) fn read_byte(&mut self, size: usize) -> Result<Vec<u8>, DecodeError> {
+        self.mark = self.position();
+        // 残りより長いときは確保する前にエラーにする
+        if size > self.reader.get_ref().len() - self.mark {
+            let eof = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+            return Err(self.error(eof.into()));
+        }
         let mut buf = vec![0; size];
-        self.reader.read_exact(&mut buf)?;
+        self.reader
+            .read_exact(&mut buf)
+            .map_err(|e| self.error(e.into()))?;
 
         Ok(buf)
     }
 
+    /// 続くsizeバイトだけを読むDecoderを作る。位置は元のファイルの先頭から数えたまま引き継ぐ
+    pub(crate) fn sub_decoder(&mut self, size: usize) -> Result<Decoder<'a>, DecodeError> {
+        self.mark = self.position();
+        let bytes: &'a [u8] = self.reader.get_ref();
+        let end = match self.mark.checked_add(size) {
+            Some(end) if end <= bytes.len() => end,
+            _ => {
+                let eof = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+                return Err(self.error(eof.into()));
+            }
+        };
+        self.reader.set_position(end as u64);
+
+        Ok(Decoder {
+            reader: Cursor::new(&bytes[self.mark..end]),
+            offset: self.offset + self.mark,
+            mark: 0,
+            imported_funcs: self.imported_funcs,
+            limits: self.limits,
+            code_offsets: None,
+        })
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.reader.position() as usize
+    }
+
+    /// 最後に読んだ値がDecodeLimitsの上限を超えていないか確認する
+    fn check_limit(
+        &self,
+        limit: &'static str,
+        value: impl Into<u64>,
+        max: u32,
+    ) -> Result<(), DecodeError> {
+        let value = value.into();
+        if value > u64::from(max) {
+            return Err(self.error(DecodeError::LimitExceeded {
+                limit,
+                value,
+                max: u64::from(max),
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// sectionのsizeをちょうど読み切ったか確認する
+    fn expect_end(&self) -> Result<(), DecodeError> {
+        if !self.is_end() {
+            return Err(DecodeError::SectionSizeMismatch.at(self.offset + self.position()));
+        }
+
+        Ok(())
+    }
+
+    /// 最後に読み始めたバイトの位置をエラーにつける
+    pub(crate) fn error(&self, error: DecodeError) -> DecodeError {
+        error.at(self.offset + self.mark)
+    }
+
+    fn unexpected(&self, message: String) -> DecodeError {
+        self.error(DecodeError::Unexpected(message))
+    }
+
+    fn unsupported(&self, feature: Feature) -> DecodeError {
+        self.error(DecodeError::UnsupportedFeatures {
+            features: vec![feature],
+            producers: None,
+        })
+    }
+
     pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
         Ok(self
             .read_byte(4)?
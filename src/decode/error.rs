@@ -1,3 +1,5 @@
+use crate::module::{Feature, Producers, SectionType};
+use crate::validate::ValidationError;
 use std::error::Error;
 use std::fmt::{self, Display};
 
@@ -7,6 +9,87 @@ pub enum DecodeError {
     InvalidNumeric(String),
     Unexpected(String),
     IOError(std::io::Error),
+    UnknownVersion(u32),
+    /// custom section以外のsectionが2回現れた
+    DuplicateSection(SectionType),
+    /// sectionが決められた順番に並んでいない
+    SectionOutOfOrder(SectionType),
+    /// sectionのsizeと中身を読んだバイト数が一致しない
+    SectionSizeMismatch,
+    /// function sectionとcode section、data count sectionとdata sectionの要素数が一致しない
+    InconsistentLengths(String),
+    /// DecodeLimitsの上限を超えた
+    LimitExceeded {
+        limit: &'static str,
+        value: u64,
+        max: u64,
+    },
+    /// このwaiが対応していないか、Configで無効にしたproposalを使っている。producersはmoduleを作ったツールチェーン
+    UnsupportedFeatures {
+        features: Vec<Feature>,
+        producers: Option<Producers>,
+    },
+    /// デコードはできたが、検証に失敗した
+    Invalid(ValidationError),
+    /// どこでデコードに失敗したか。decode::decodeが返すエラーはすべてこれで包まれている
+    At(Box<DecodeError>, Location),
+}
+
+/// デコードに失敗した場所
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    /// ファイルの先頭からのバイト数
+    pub offset: usize,
+    /// デコード中だったsection。sectionのヘッダを読んでいるときはNone
+    pub section: Option<SectionType>,
+    /// code sectionの中なら、importした関数も含めた関数のindex
+    pub function: Option<u32>,
+}
+
+impl DecodeError {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            DecodeError::At(_, location) => Some(location),
+            _ => None,
+        }
+    }
+
+    /// 位置情報を除いたエラーの中身
+    pub fn kind(&self) -> &DecodeError {
+        match self {
+            DecodeError::At(error, _) => error,
+            error => error,
+        }
+    }
+
+    /// すでに位置がついていれば、内側で読んでいた位置の方を残す
+    pub(crate) fn at(self, offset: usize) -> Self {
+        match self {
+            DecodeError::At(..) => self,
+            error => DecodeError::At(
+                Box::new(error),
+                Location {
+                    offset,
+                    section: None,
+                    function: None,
+                },
+            ),
+        }
+    }
+
+    pub(crate) fn in_section(mut self, section: SectionType) -> Self {
+        if let DecodeError::At(_, location) = &mut self {
+            location.section.get_or_insert(section);
+        }
+        self
+    }
+
+    pub(crate) fn in_function(mut self, index: u32) -> Self {
+        if let DecodeError::At(_, location) = &mut self {
+            location.function.get_or_insert(index);
+        }
+        self
+    }
 }
 
 impl Error for DecodeError {}
@@ -18,12 +101,65 @@ impl Display for DecodeError {
             InvalidNumeric(s) => write!(f, "invalid numeric: {}", s),
             Unexpected(s) => write!(f, "unexpected byte. details: {}", s),
             IOError(i) => write!(f, "io error: {}", i),
+            UnknownVersion(v) => write!(f, "unknown binary version {}", v),
+            DuplicateSection(s) => write!(f, "duplicate {:?} section", s),
+            SectionOutOfOrder(s) => write!(f, "{:?} section is out of order", s),
+            SectionSizeMismatch => write!(f, "section size mismatch"),
+            InconsistentLengths(s) => write!(f, "{} have inconsistent lengths", s),
+            LimitExceeded { limit, value, max } => {
+                write!(f, "{} {} exceeds the limit {}", limit, value, max)
+            }
+            UnsupportedFeatures {
+                features,
+                producers,
+            } => {
+                let (unsupported, disabled): (Vec<&Feature>, Vec<&Feature>) =
+                    features.iter().partition(|feature| !feature.is_supported());
+                let unsupported: Vec<&str> = unsupported.into_iter().map(Feature::name).collect();
+                let disabled: Vec<&str> = disabled.into_iter().map(Feature::name).collect();
+                write!(f, "module uses features ")?;
+                if !unsupported.is_empty() {
+                    write!(f, "not supported by wai: {}", unsupported.join(", "))?;
+                }
+                if !unsupported.is_empty() && !disabled.is_empty() {
+                    write!(f, "; ")?;
+                }
+                if !disabled.is_empty() {
+                    write!(f, "disabled by the configuration: {}", disabled.join(", "))?;
+                }
+                match producers {
+                    Some(producers) => write!(f, " (produced by {})", producers),
+                    None => Ok(()),
+                }
+            }
+            Invalid(e) => write!(f, "{}", e),
+            At(e, location) => write!(f, "{} ({})", e, location),
         }
     }
 }
 
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at offset 0x{:x}", self.offset)?;
+        if let Some(section) = self.section {
+            write!(f, " in {:?} section", section)?;
+        }
+        if let Some(function) = self.function {
+            write!(f, ", func[{}]", function)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl From<std::io::Error> for DecodeError {
     fn from(error: std::io::Error) -> Self {
         Self::IOError(error)
     }
 }
+
+impl From<ValidationError> for DecodeError {
+    fn from(error: ValidationError) -> Self {
+        Self::Invalid(error)
+    }
+}
@@ -0,0 +1,98 @@
+use super::decoder::Decoder;
+use super::error::DecodeError;
+use super::limits::DecodeLimits;
+use crate::module::{Feature, FeaturePolicy, Producers, TargetFeature};
+
+/// "producers" custom sectionの中身を読む。壊れていたらそこで読むのをやめて、それまでに読めたものを返す
+pub(crate) fn decode_producers(data: &[u8]) -> Producers {
+    let mut producers = Producers::default();
+    let mut decoder = Decoder::fragment(data, 0, DecodeLimits::default());
+    let _ = decode_producer_fields(&mut decoder, &mut producers);
+
+    producers
+}
+
+fn decode_producer_fields(
+    decoder: &mut Decoder,
+    producers: &mut Producers,
+) -> Result<(), DecodeError> {
+    let count = decoder.decode_u32()?;
+    for _ in 0..count {
+        let field = decoder.decode_name()?;
+        let mut values = Vec::new();
+        for _ in 0..decoder.decode_u32()? {
+            values.push((decoder.decode_name()?, decoder.decode_name()?));
+        }
+        producers.fields.push((field, values));
+    }
+
+    Ok(())
+}
+
+/// "target_features" custom sectionの中身を読む。壊れていたらそこで読むのをやめて、それまでに読めたものを返す
+pub(crate) fn decode_target_features(data: &[u8]) -> Vec<TargetFeature> {
+    let mut features = Vec::new();
+    let mut decoder = Decoder::fragment(data, 0, DecodeLimits::default());
+    let _ = decode_feature_entries(&mut decoder, &mut features);
+
+    features
+}
+
+fn decode_feature_entries(
+    decoder: &mut Decoder,
+    features: &mut Vec<TargetFeature>,
+) -> Result<(), DecodeError> {
+    let count = decoder.decode_u32()?;
+    for _ in 0..count {
+        let policy = match decoder.read_next()? {
+            b'+' => FeaturePolicy::Used,
+            b'=' => FeaturePolicy::Required,
+            b'-' => FeaturePolicy::Disallowed,
+            x => {
+                return Err(
+                    decoder.error(DecodeError::Unexpected(format!("feature prefix {:x}", x)))
+                )
+            }
+        };
+        let feature = Feature::from_name(&decoder.decode_name()?);
+        features.push(TargetFeature { policy, feature });
+    }
+
+    Ok(())
+}
+
+/// 名前がnamesのどれかのcustom sectionを拾う。デコードに失敗したmoduleでも使えるように、
+/// sectionの中身は解釈せずにヘッダだけをたどり、読めなくなったところでやめる。(位置, 名前, 中身)を返す
+pub(crate) fn find_custom_sections(buf: &[u8], names: &[&str]) -> Vec<(usize, String, Vec<u8>)> {
+    let mut sections = Vec::new();
+    let mut decoder = Decoder::fragment(buf, 0, DecodeLimits::default());
+    let _ = walk_sections(&mut decoder, names, &mut sections);
+
+    sections
+}
+
+fn walk_sections(
+    decoder: &mut Decoder,
+    names: &[&str],
+    sections: &mut Vec<(usize, String, Vec<u8>)>,
+) -> Result<(), DecodeError> {
+    decoder.validate_wasm_format()?;
+    decoder.decode_version()?;
+
+    while !decoder.is_end() {
+        let start = decoder.position();
+        let id = decoder.read_next()?;
+        let size = decoder.decode_u32()? as usize;
+        let mut section = decoder.sub_decoder(size)?;
+        if id != 0 {
+            continue;
+        }
+        let name = section.decode_name()?;
+        if names.contains(&name.as_str()) {
+            let data = section.read_byte(size - section.position())?;
+            sections.push((start, name, data));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,31 @@
+/// デコード時に受け付ける大きさの上限。
+/// 信頼できないmoduleを読むときに、小さなファイルから巨大な確保をさせられないようにする。
+/// デフォルト値はJS APIの実装上の上限に合わせている
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodeLimits {
+    /// ファイル全体のバイト数
+    pub max_module_size: usize,
+    /// 定義する関数の数
+    pub max_functions: u32,
+    /// 1つの関数で宣言できるlocalの数。引数は含まない
+    pub max_locals: u32,
+    /// 1つの関数本体のバイト数
+    pub max_body_size: u32,
+    /// 1つのdata segmentのバイト数
+    pub max_data_segment_size: u32,
+    /// block、loop、if、try_tableを入れ子にできる深さ
+    pub max_nesting_depth: u32,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_module_size: 1 << 30,
+            max_functions: 1_000_000,
+            max_locals: 50_000,
+            max_body_size: 7_654_321,
+            max_data_segment_size: 1 << 30,
+            max_nesting_depth: 10_000,
+        }
+    }
+}
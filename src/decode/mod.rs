@@ -1,31 +1,210 @@
 mod decoder;
 pub mod error;
+mod features;
+mod limits;
+mod names;
+mod stream;
 
 use decoder::Decoder;
-pub use error::DecodeError;
+pub use error::{DecodeError, Location};
+pub(crate) use features::{decode_producers, decode_target_features};
+pub use limits::DecodeLimits;
+pub(crate) use names::decode_names;
+pub use stream::{Payload, StreamingDecoder};
 
-use crate::module::Module;
+use crate::config::Config;
+use crate::module::{Module, Section, SectionType};
 use std::io::Cursor;
 
 /// wasmバイナリをデコードしてwasm moduleを返す
 pub fn decode(buf: &[u8]) -> Result<Module, DecodeError> {
-    let mut decoder = Decoder::new(Cursor::new(buf));
+    decode_with_limits(buf, &DecodeLimits::default())
+}
+
+/// limitsを超える大きさのmoduleはメモリを確保する前にエラーにする
+pub fn decode_with_limits(buf: &[u8], limits: &DecodeLimits) -> Result<Module, DecodeError> {
+    let config = Config {
+        decode_limits: *limits,
+        ..Config::default()
+    };
+    decode_with_config(buf, &config)
+}
+
+/// configの上限でデコードし、configで有効にしていないproposalを使っていればエラーにする
+pub fn decode_with_config(buf: &[u8], config: &Config) -> Result<Module, DecodeError> {
+    decode_module(buf, config, false).map(|(m, _)| m)
+}
+
+/// 関数ごとに、命令が始まるファイル上の位置もあわせて返す。offsetつきでテキスト形式に書き出すときに使う
+pub(crate) fn decode_with_offsets(buf: &[u8]) -> Result<(Module, Vec<Vec<usize>>), DecodeError> {
+    decode_module(buf, &Config::default(), true)
+}
+
+type Decoded = (Module, Vec<Vec<usize>>);
+
+fn decode_module(
+    buf: &[u8],
+    config: &Config,
+    record_offsets: bool,
+) -> Result<Decoded, DecodeError> {
+    let result = decode_sections(buf, &config.decode_limits, record_offsets);
+    check_features(buf, config, result)
+}
+
+/// 対応していないか無効にしたproposalを使っていれば、target_features sectionで宣言されたものと
+/// デコード中に命令から見つけたもの、デコードしたmoduleの中身から見つけたものをまとめて1つのエラーにする
+fn check_features(
+    buf: &[u8],
+    config: &Config,
+    result: Result<Decoded, DecodeError>,
+) -> Result<Decoded, DecodeError> {
+    let (mut missing, mut location) = match &result {
+        Ok(_) => (Vec::new(), None),
+        Err(e) => match e.kind() {
+            DecodeError::UnsupportedFeatures { features, .. } => {
+                (features.clone(), e.location().copied())
+            }
+            _ => return result,
+        },
+    };
+
+    let mut producers = None;
+    for (offset, name, data) in
+        features::find_custom_sections(buf, &["target_features", "producers"])
+    {
+        if name == "producers" {
+            producers = Some(decode_producers(&data));
+            continue;
+        }
+
+        let declared = decode_target_features(&data)
+            .into_iter()
+            .filter(|t| t.is_used())
+            .map(|t| t.feature);
+        let declared = config.disabled(declared);
+        if !declared.is_empty() {
+            location.get_or_insert(Location {
+                offset,
+                section: Some(SectionType::Custom),
+                function: None,
+            });
+            missing.extend(declared);
+        }
+    }
+
+    // 宣言していなくても、moduleの中身が使っていれば見つかる。どこで使ったかまでは追わない
+    if let Ok((m, _)) = &result {
+        let used = config.disabled(m.used_features());
+        if !used.is_empty() {
+            location.get_or_insert(Location {
+                offset: buf.len(),
+                section: None,
+                function: None,
+            });
+            missing.extend(used);
+        }
+    }
+
+    let location = match location {
+        Some(location) if !missing.is_empty() => location,
+        _ => return result,
+    };
+    missing.sort();
+    missing.dedup();
+    let error = DecodeError::UnsupportedFeatures {
+        features: missing,
+        producers: producers.filter(|p| !p.is_empty()),
+    };
+
+    Err(DecodeError::At(Box::new(error), location))
+}
+
+fn decode_sections(
+    buf: &[u8],
+    limits: &DecodeLimits,
+    record_offsets: bool,
+) -> Result<(Module, Vec<Vec<usize>>), DecodeError> {
+    if buf.len() > limits.max_module_size {
+        return Err(DecodeError::LimitExceeded {
+            limit: "module size",
+            value: buf.len() as u64,
+            max: limits.max_module_size as u64,
+        }
+        .at(0));
+    }
+
+    let mut decoder = Decoder::new(Cursor::new(buf), *limits);
+    if record_offsets {
+        decoder.record_offsets();
+    }
 
     let mut m = Module::default();
 
     decoder.validate_wasm_format()?;
     m.version = decoder.decode_version()?;
 
+    let mut last_order = 0;
     loop {
         if decoder.is_end() {
             break;
         }
 
+        let start = decoder.position();
         let (section_type, section_size) = decoder.decode_section_type()?;
-        let section = decoder.decode_section(section_type, section_size)?;
+
+        last_order = check_order(last_order, section_type).map_err(|e| e.at(start))?;
+
+        let section = decoder
+            .decode_section(section_type, section_size)
+            .map_err(|e| decoder.error(e).in_section(section_type))?;
+        check_lengths(&m, &section).map_err(|e| e.at(start).in_section(section_type))?;
 
         m.take_in(section);
     }
 
-    Ok(m)
+    // code sectionやdata sectionが無いときは、対応するsectionの要素数が0でなければならない
+    if m.code_section.is_none() {
+        check_lengths(&m, &Section::Code(Default::default())).map_err(|e| e.at(buf.len()))?;
+    }
+    if m.data_section.is_none() {
+        check_lengths(&m, &Section::Data(Default::default())).map_err(|e| e.at(buf.len()))?;
+    }
+
+    Ok((m, decoder.take_offsets()))
+}
+
+/// custom section以外は決まった順番に高々1回ずつ現れる。読んだsectionまでの順番を返す
+fn check_order(last_order: u8, section_type: SectionType) -> Result<u8, DecodeError> {
+    match section_type.order() {
+        Some(order) if order == last_order => Err(DecodeError::DuplicateSection(section_type)),
+        Some(order) if order < last_order => Err(DecodeError::SectionOutOfOrder(section_type)),
+        Some(order) => Ok(order),
+        None => Ok(last_order),
+    }
+}
+
+/// function sectionとcode section、data count sectionとdata sectionの要素数が一致するか
+fn check_lengths(m: &Module, section: &Section) -> Result<(), DecodeError> {
+    match section {
+        Section::Code(code_section) => {
+            let funcs = m.function_section.as_ref().map_or(0, |s| s.types.len());
+            if funcs != code_section.bodies.len() {
+                return Err(DecodeError::InconsistentLengths(
+                    "function and code section".to_string(),
+                ));
+            }
+        }
+        Section::Data(data_section) => {
+            if let Some(count) = m.data_count_section {
+                if count as usize != data_section.segments.len() {
+                    return Err(DecodeError::InconsistentLengths(
+                        "data count and data section".to_string(),
+                    ));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
 }
@@ -0,0 +1,68 @@
+use super::decoder::Decoder;
+use super::error::DecodeError;
+use super::limits::DecodeLimits;
+use crate::module::NameMap;
+use std::collections::BTreeMap;
+
+/// "name" custom sectionの中身を読む。
+/// 名前が無くてもmoduleは使えるので、壊れていたらそこで読むのをやめて、それまでに読めた名前を返す
+pub(crate) fn decode_names(data: &[u8]) -> NameMap {
+    let mut names = NameMap::default();
+    let mut decoder = Decoder::fragment(data, 0, DecodeLimits::default());
+    let _ = decode_subsections(&mut decoder, &mut names);
+
+    names
+}
+
+/// subsectionのidごとに読む。知らないidのsubsectionは読み飛ばす
+fn decode_subsections(decoder: &mut Decoder, names: &mut NameMap) -> Result<(), DecodeError> {
+    while !decoder.is_end() {
+        let id = decoder.read_next()?;
+        let size = decoder.decode_u32()?;
+        let mut sub = decoder.sub_decoder(size as usize)?;
+
+        match id {
+            0 => names.module = Some(sub.decode_name()?),
+            1 => names.functions = decode_name_map(&mut sub)?,
+            2 => names.locals = decode_indirect_name_map(&mut sub)?,
+            3 => names.labels = decode_indirect_name_map(&mut sub)?,
+            4 => names.types = decode_name_map(&mut sub)?,
+            5 => names.tables = decode_name_map(&mut sub)?,
+            6 => names.memories = decode_name_map(&mut sub)?,
+            7 => names.globals = decode_name_map(&mut sub)?,
+            8 => names.elems = decode_name_map(&mut sub)?,
+            9 => names.data = decode_name_map(&mut sub)?,
+            10 => names.fields = decode_indirect_name_map(&mut sub)?,
+            11 => names.tags = decode_name_map(&mut sub)?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// indexと名前の組の並び
+fn decode_name_map(decoder: &mut Decoder) -> Result<BTreeMap<u32, String>, DecodeError> {
+    let mut map = BTreeMap::new();
+    let count = decoder.decode_u32()?;
+    for _ in 0..count {
+        let index = decoder.decode_u32()?;
+        map.insert(index, decoder.decode_name()?);
+    }
+
+    Ok(map)
+}
+
+/// 関数などのindexと、それに属するものの名前の並び
+fn decode_indirect_name_map(
+    decoder: &mut Decoder,
+) -> Result<BTreeMap<u32, BTreeMap<u32, String>>, DecodeError> {
+    let mut map = BTreeMap::new();
+    let count = decoder.decode_u32()?;
+    for _ in 0..count {
+        let index = decoder.decode_u32()?;
+        map.insert(index, decode_name_map(decoder)?);
+    }
+
+    Ok(map)
+}
@@ -0,0 +1,414 @@
+use super::decoder::Decoder;
+use super::{check_lengths, check_order, DecodeError, DecodeLimits};
+use crate::config::Config;
+use crate::module::{Module, Section, SectionType};
+use crate::types::{CodeSection, ImportKind};
+use crate::validate::Context;
+use std::io::{self, Read};
+use std::ops::Range;
+
+/// magic numberとversionのバイト数
+const HEADER_SIZE: usize = 8;
+/// read_fromで一度に読むバイト数
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// StreamingDecoderが読み進めるごとに返す通知。rangeはファイルの先頭からのバイト位置
+#[derive(Debug, Clone, PartialEq)]
+pub enum Payload {
+    /// magic numberとversionを読んだ
+    Header { version: u32 },
+    /// sectionを1つ読み終えた。code sectionは最後の関数本体の後に届く
+    Section {
+        section_type: SectionType,
+        range: Range<usize>,
+    },
+    /// code sectionが始まった。この後に関数本体がcount個続く
+    CodeSectionStart { count: u32, range: Range<usize> },
+    /// 関数本体を1つ読んで検証した。indexはimportした関数も含めたもの
+    FunctionBody { index: u32, range: Range<usize> },
+}
+
+enum State {
+    Header,
+    Section,
+    /// code sectionの途中。start、endはsection全体の位置
+    Code {
+        start: usize,
+        end: usize,
+        remaining: u32,
+        section: CodeSection,
+    },
+}
+
+/// バイト列をチャンクごとに受け取り、揃ったsectionや関数本体から順にデコードする。
+/// 関数本体は届いた時点で検証するので、最後のバイトを待たずにエラーを返せる
+pub struct StreamingDecoder {
+    /// まだデコードしていないバイト
+    buf: Vec<u8>,
+    /// bufの先頭がファイルの先頭から何バイト目か
+    offset: usize,
+    state: State,
+    module: Module,
+    last_order: u8,
+    imported_funcs: u32,
+    /// code sectionより前のsectionを検証し終えたところで作る
+    context: Option<Context>,
+    config: Config,
+}
+
+impl Default for StreamingDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamingDecoder {
+    pub fn new() -> Self {
+        Self::with_limits(DecodeLimits::default())
+    }
+
+    pub fn with_limits(limits: DecodeLimits) -> Self {
+        Self::with_config(Config {
+            decode_limits: limits,
+            ..Config::default()
+        })
+    }
+
+    /// configの上限でデコードし、configで有効にしていないproposalを使っていればfinishでエラーにする
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            buf: Vec::new(),
+            offset: 0,
+            state: State::Header,
+            module: Module::default(),
+            last_order: 0,
+            imported_funcs: 0,
+            context: None,
+            config,
+        }
+    }
+
+    /// デコードし終えたバイト数。進捗の表示に使う
+    pub fn consumed(&self) -> usize {
+        self.offset
+    }
+
+    /// チャンクを追加して、読めるところまでデコードする。
+    /// エラーを返した後のStreamingDecoderは使えない
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<Payload>, DecodeError> {
+        let size = self.offset + self.buf.len() + chunk.len();
+        if size > self.config.decode_limits.max_module_size {
+            return Err(DecodeError::LimitExceeded {
+                limit: "module size",
+                value: size as u64,
+                max: self.config.decode_limits.max_module_size as u64,
+            }
+            .at(0));
+        }
+
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.extend_from_slice(chunk);
+
+        let mut payloads = vec![];
+        let mut pos = 0;
+        while let Some((payload, len)) = self.next_payload(&buf[pos..], self.offset + pos)? {
+            payloads.push(payload);
+            pos += len;
+        }
+
+        buf.drain(..pos);
+        self.buf = buf;
+        self.offset += pos;
+
+        Ok(payloads)
+    }
+
+    /// 入力の終わりで呼ぶ。途中で切れていればエラーにし、
+    /// 関数本体以外の残りの検証をしてmoduleを返す
+    pub fn finish(mut self) -> Result<Module, DecodeError> {
+        if !self.buf.is_empty() || !matches!(self.state, State::Section) {
+            let eof = io::Error::from(io::ErrorKind::UnexpectedEof);
+            return Err(DecodeError::from(eof).at(self.offset));
+        }
+
+        let end = self.offset;
+        let m = &self.module;
+        // 届いた順に読むので先にtarget_features sectionを探せない。読み終えたmoduleで調べる
+        let missing = self.config.disabled(m.used_features());
+        if !missing.is_empty() {
+            let error = DecodeError::UnsupportedFeatures {
+                features: missing,
+                producers: m.producers(),
+            };
+            return Err(error.at(end));
+        }
+        if m.code_section.is_none() {
+            check_lengths(m, &Section::Code(Default::default())).map_err(|e| e.at(end))?;
+        }
+        if m.data_section.is_none() {
+            check_lengths(m, &Section::Data(Default::default())).map_err(|e| e.at(end))?;
+        }
+
+        let context = match self.context.take() {
+            Some(context) => context,
+            None => {
+                let context = Context::new(m);
+                context.validate_declarations(m)?;
+                context
+            }
+        };
+        context.validate_data(m)?;
+        if m.code_section.is_none() {
+            context.validate_code(m)?;
+        }
+
+        Ok(self.module)
+    }
+
+    /// readerを最後まで読みながらデコードする。通知が届くたびにon_payloadを呼ぶ
+    pub fn read_from(
+        mut self,
+        mut reader: impl Read,
+        mut on_payload: impl FnMut(&Payload),
+    ) -> Result<Module, DecodeError> {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        loop {
+            let n = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            for payload in self.push(&chunk[..n])? {
+                on_payload(&payload);
+            }
+        }
+
+        self.finish()
+    }
+
+    /// bytesの先頭から1つ読む。まだバイトが足りなければNoneを返す
+    fn next_payload(
+        &mut self,
+        bytes: &[u8],
+        offset: usize,
+    ) -> Result<Option<(Payload, usize)>, DecodeError> {
+        match self.state {
+            State::Header => self.header(bytes, offset),
+            State::Section => self.section(bytes, offset),
+            State::Code { .. } => self.code_entry(bytes, offset),
+        }
+    }
+
+    fn header(
+        &mut self,
+        bytes: &[u8],
+        offset: usize,
+    ) -> Result<Option<(Payload, usize)>, DecodeError> {
+        if bytes.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let mut decoder =
+            Decoder::fragment(&bytes[..HEADER_SIZE], offset, self.config.decode_limits);
+        decoder.validate_wasm_format()?;
+        let version = decoder.decode_version()?;
+
+        self.module.version = version;
+        self.state = State::Section;
+
+        Ok(Some((Payload::Header { version }, HEADER_SIZE)))
+    }
+
+    fn section(
+        &mut self,
+        bytes: &[u8],
+        offset: usize,
+    ) -> Result<Option<(Payload, usize)>, DecodeError> {
+        // section idとsizeが揃うまで待つ
+        let header = match leb128_len(bytes.get(1..).unwrap_or(&[])) {
+            Some(n) => 1 + n,
+            None => return Ok(None),
+        };
+
+        let mut decoder = Decoder::fragment(bytes, offset, self.config.decode_limits);
+        let (section_type, size) = decoder.decode_section_type()?;
+        let order = check_order(self.last_order, section_type).map_err(|e| e.at(offset))?;
+
+        // code sectionは全体を待たずに関数本体ごとに読む
+        if section_type == SectionType::Code {
+            return self.code_section(bytes, offset, header, size as usize, order);
+        }
+
+        let len = header + size as usize;
+        if bytes.len() < len {
+            return Ok(None);
+        }
+
+        let mut decoder = Decoder::fragment(&bytes[..len], offset, self.config.decode_limits);
+        decoder.decode_section_type()?;
+        let section = decoder
+            .decode_section(section_type, size)
+            .map_err(|e| decoder.error(e).in_section(section_type))?;
+        check_lengths(&self.module, &section).map_err(|e| e.at(offset).in_section(section_type))?;
+
+        self.module.take_in(section);
+        self.last_order = order;
+        if section_type == SectionType::Import {
+            self.imported_funcs = self.module.import_section.as_ref().map_or(0, |s| {
+                s.entries
+                    .iter()
+                    .filter(|entry| matches!(entry.kind, ImportKind::Function(_)))
+                    .count() as u32
+            });
+        }
+
+        let range = offset..offset + len;
+        Ok(Some((
+            Payload::Section {
+                section_type,
+                range,
+            },
+            len,
+        )))
+    }
+
+    fn code_section(
+        &mut self,
+        bytes: &[u8],
+        offset: usize,
+        header: usize,
+        size: usize,
+        order: u8,
+    ) -> Result<Option<(Payload, usize)>, DecodeError> {
+        let end = header + size;
+        let available = bytes.len().min(end);
+        // 関数本体の数が揃うまで待つ
+        let count_len = match leb128_len(&bytes[header..available]) {
+            Some(n) => n,
+            None if available == end => size,
+            None => return Ok(None),
+        };
+
+        let count_bytes = &bytes[header..header + count_len];
+        let mut decoder =
+            Decoder::fragment(count_bytes, offset + header, self.config.decode_limits);
+        let count = decoder
+            .decode_code_count()
+            .map_err(|e| decoder.error(e).in_section(SectionType::Code))?;
+
+        // ここまでで関数本体の検証に必要なsectionは揃っている
+        let context = Context::new(&self.module);
+        context.validate_declarations(&self.module)?;
+        self.context = Some(context);
+
+        self.last_order = order;
+        self.state = State::Code {
+            start: offset,
+            end: offset + end,
+            remaining: count,
+            section: CodeSection::default(),
+        };
+
+        let range = offset..offset + end;
+        Ok(Some((
+            Payload::CodeSectionStart { count, range },
+            header + count_len,
+        )))
+    }
+
+    fn code_entry(
+        &mut self,
+        bytes: &[u8],
+        offset: usize,
+    ) -> Result<Option<(Payload, usize)>, DecodeError> {
+        let (start, end, remaining, section) = match &mut self.state {
+            State::Code {
+                start,
+                end,
+                remaining,
+                section,
+            } => (*start, *end, remaining, section),
+            _ => unreachable!("code_entry is called only in code section"),
+        };
+
+        if *remaining == 0 {
+            if offset != end {
+                return Err(DecodeError::SectionSizeMismatch
+                    .at(offset)
+                    .in_section(SectionType::Code));
+            }
+
+            let section = Section::Code(std::mem::take(section));
+            check_lengths(&self.module, &section)
+                .map_err(|e| e.at(start).in_section(SectionType::Code))?;
+            self.module.take_in(section);
+            self.state = State::Section;
+
+            let range = start..end;
+            return Ok(Some((
+                Payload::Section {
+                    section_type: SectionType::Code,
+                    range,
+                },
+                0,
+            )));
+        }
+
+        let index = self.imported_funcs + section.bodies.len() as u32;
+        let error = |decoder: &Decoder, e| {
+            decoder
+                .error(e)
+                .in_function(index)
+                .in_section(SectionType::Code)
+        };
+
+        // section内に残っている分だけを見る
+        let window = &bytes[..bytes.len().min(end - offset)];
+        let complete = window.len() == end - offset;
+        let size_len = match leb128_len(window) {
+            Some(n) => n,
+            None if complete => window.len(),
+            None => return Ok(None),
+        };
+
+        let mut decoder = Decoder::fragment(&window[..size_len], offset, self.config.decode_limits);
+        let body_size = decoder.decode_u32().map_err(|e| error(&decoder, e))?;
+
+        // 上限を超える本体は、届くのを待たずにdecode_code_entryにエラーを返させる
+        let len = size_len + body_size as usize;
+        if window.len() < len && !complete && body_size <= self.config.decode_limits.max_body_size {
+            return Ok(None);
+        }
+
+        let mut decoder = Decoder::fragment(
+            &window[..len.min(window.len())],
+            offset,
+            self.config.decode_limits,
+        );
+        let body = decoder
+            .decode_code_entry()
+            .map_err(|e| error(&decoder, e))?;
+
+        self.context
+            .as_ref()
+            .expect("context is created at the start of code section")
+            .validate_function(index, &body)?;
+        section.bodies.push(body);
+        *remaining -= 1;
+
+        let range = offset..offset + len;
+        Ok(Some((Payload::FunctionBody { index, range }, len)))
+    }
+}
+
+/// LEB128の数値が何バイトか。終わりのバイトがまだ届いていなければNone。
+/// u32は高々5バイトなので、5バイトで終わらないときの判定はDecoderに任せる
+fn leb128_len(bytes: &[u8]) -> Option<usize> {
+    match bytes.iter().take(5).position(|b| b & 0x80 == 0) {
+        Some(i) => Some(i + 1),
+        None if bytes.len() >= 5 => Some(5),
+        None => None,
+    }
+}
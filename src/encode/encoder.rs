@@ -0,0 +1,890 @@
+use crate::module::SectionType;
+use crate::opcode::Opcode;
+use crate::types::*;
+
+const MAGIC_NUMBER: &[u8] = b"\0asm";
+const VERSION: u32 = 1;
+const FUNC_TYPE: u8 = 0x60;
+const STRUCT_TYPE: u8 = 0x5F;
+const ARRAY_TYPE: u8 = 0x5E;
+const SUB_TYPE: u8 = 0x50;
+const SUB_FINAL_TYPE: u8 = 0x4F;
+const REC_TYPE: u8 = 0x4E;
+const REF_TYPE: u8 = 0x64;
+const REF_NULL_TYPE: u8 = 0x63;
+
+/// Decoderの逆。各sectionをバイト列に書き出す
+pub(crate) struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub(crate) fn encode_header(&mut self) {
+        self.buf.extend_from_slice(MAGIC_NUMBER);
+        self.buf.extend_from_slice(&VERSION.to_le_bytes());
+    }
+
+    /// 中身を書いてから、section idとsizeをつけて追加する
+    pub(crate) fn encode_section(&mut self, section_type: SectionType, f: impl FnOnce(&mut Self)) {
+        let mut section = Self::new();
+        f(&mut section);
+
+        self.write_byte(section_type.id());
+        self.encode_bytes(&section.buf);
+    }
+
+    pub(crate) fn encode_custom_section(&mut self, custom: &CustomSection) {
+        self.encode_name(&custom.name);
+        self.buf.extend_from_slice(&custom.data);
+    }
+
+    /// 同じrecグループの型は続けて並んでいる。1つだけのグループはrecで包まずに書く
+    pub(crate) fn encode_type_section(&mut self, section: &TypeSection) {
+        let mut groups: Vec<&[SubType]> = vec![];
+        let mut start = 0;
+        for i in 1..=section.entries.len() {
+            if i == section.entries.len()
+                || section.entries[i].rec_group != section.entries[start].rec_group
+            {
+                groups.push(&section.entries[start..i]);
+                start = i;
+            }
+        }
+
+        self.encode_u32(groups.len() as u32);
+        for group in groups {
+            if group.len() != 1 {
+                self.write_byte(REC_TYPE);
+                self.encode_u32(group.len() as u32);
+            }
+            for sub_type in group {
+                self.encode_sub_type(sub_type);
+            }
+        }
+    }
+
+    fn encode_sub_type(&mut self, sub_type: &SubType) {
+        if !sub_type.is_final || sub_type.supertype.is_some() {
+            self.write_byte(if sub_type.is_final {
+                SUB_FINAL_TYPE
+            } else {
+                SUB_TYPE
+            });
+            match sub_type.supertype {
+                Some(index) => {
+                    self.encode_u32(1);
+                    self.encode_u32(index);
+                }
+                None => self.encode_u32(0),
+            }
+        }
+
+        self.encode_composite_type(&sub_type.composite);
+    }
+
+    fn encode_composite_type(&mut self, composite: &CompositeType) {
+        match composite {
+            CompositeType::Func(func_type) => {
+                self.write_byte(FUNC_TYPE);
+                self.encode_u32(func_type.params.len() as u32);
+                for t in &func_type.params {
+                    self.encode_value_type(*t);
+                }
+                self.encode_u32(func_type.returns.len() as u32);
+                for t in &func_type.returns {
+                    self.encode_value_type(*t);
+                }
+            }
+            CompositeType::Struct(struct_type) => {
+                self.write_byte(STRUCT_TYPE);
+                self.encode_u32(struct_type.fields.len() as u32);
+                for field in &struct_type.fields {
+                    self.encode_field_type(field);
+                }
+            }
+            CompositeType::Array(field) => {
+                self.write_byte(ARRAY_TYPE);
+                self.encode_field_type(field);
+            }
+        }
+    }
+
+    fn encode_field_type(&mut self, field: &FieldType) {
+        match field.storage {
+            StorageType::I8 => self.write_byte(0x78),
+            StorageType::I16 => self.write_byte(0x77),
+            StorageType::Value(t) => self.encode_value_type(t),
+        }
+        self.write_byte(field.mutable as u8);
+    }
+
+    pub(crate) fn encode_import_section(&mut self, section: &ImportSection) {
+        self.encode_u32(section.entries.len() as u32);
+        for entry in &section.entries {
+            self.encode_name(&entry.module_str);
+            self.encode_name(&entry.field_str);
+            match &entry.kind {
+                ImportKind::Function(type_index) => {
+                    self.write_byte(0x00);
+                    self.encode_u32(*type_index);
+                }
+                ImportKind::Table(table_type) => {
+                    self.write_byte(0x01);
+                    self.encode_table_type(table_type);
+                }
+                ImportKind::Memory(memory_type) => {
+                    self.write_byte(0x02);
+                    self.encode_memory_type(memory_type);
+                }
+                ImportKind::Global(global_type) => {
+                    self.write_byte(0x03);
+                    self.encode_global_type(global_type);
+                }
+                ImportKind::Tag(tag_type) => {
+                    self.write_byte(0x04);
+                    self.encode_tag_type(tag_type);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn encode_function_section(&mut self, section: &FunctionSection) {
+        self.encode_u32(section.types.len() as u32);
+        for t in &section.types {
+            self.encode_u32(*t);
+        }
+    }
+
+    pub(crate) fn encode_table_section(&mut self, section: &TableSection) {
+        self.encode_u32(section.entries.len() as u32);
+        for table_type in &section.entries {
+            self.encode_table_type(table_type);
+        }
+    }
+
+    pub(crate) fn encode_memory_section(&mut self, section: &MemorySection) {
+        self.encode_u32(section.entries.len() as u32);
+        for memory_type in &section.entries {
+            self.encode_memory_type(memory_type);
+        }
+    }
+
+    pub(crate) fn encode_tag_section(&mut self, section: &TagSection) {
+        self.encode_u32(section.entries.len() as u32);
+        for tag_type in &section.entries {
+            self.encode_tag_type(tag_type);
+        }
+    }
+
+    pub(crate) fn encode_global_section(&mut self, section: &GlobalSection) {
+        self.encode_u32(section.entries.len() as u32);
+        for entry in &section.entries {
+            self.encode_global_type(&entry.global_type);
+            self.encode_const_expr(&entry.init);
+        }
+    }
+
+    pub(crate) fn encode_export_section(&mut self, section: &ExportSection) {
+        self.encode_u32(section.entries.len() as u32);
+        for entry in &section.entries {
+            self.encode_name(&entry.field_str);
+            self.write_byte(match entry.kind {
+                ExternalKind::Function => 0x00,
+                ExternalKind::Table => 0x01,
+                ExternalKind::Memory => 0x02,
+                ExternalKind::Global => 0x03,
+                ExternalKind::Tag => 0x04,
+                ExternalKind::Unknown => unreachable!("unknown export kind is rejected by decoder"),
+            });
+            self.encode_u32(entry.index);
+        }
+    }
+
+    pub(crate) fn encode_element_section(&mut self, section: &ElementSection) {
+        self.encode_u32(section.segments.len() as u32);
+        for segment in &section.segments {
+            self.encode_element_segment(segment);
+        }
+    }
+
+    /// flagsはdecode_element_segmentを参照。省略できるときはtable indexと型を省略する
+    fn encode_element_segment(&mut self, segment: &ElementSegment) {
+        let expressions = matches!(segment.items, ElementItems::Expressions(_));
+        let funcref = segment.elem_type == RefType::nullable(HeapType::Func);
+
+        let mode_flags = match &segment.mode {
+            ElementMode::Active { table: 0, .. } if funcref => 0x00,
+            ElementMode::Active { .. } => 0x02,
+            ElementMode::Passive => 0x01,
+            ElementMode::Declarative => 0x03,
+        };
+        self.encode_u32(mode_flags | if expressions { 0x04 } else { 0x00 });
+
+        if let ElementMode::Active { table, offset } = &segment.mode {
+            if mode_flags == 0x02 {
+                self.encode_u32(*table);
+            }
+            self.encode_const_expr(offset);
+        }
+
+        if mode_flags != 0x00 {
+            if expressions {
+                self.encode_value_type(ValueType::Ref(segment.elem_type));
+            } else {
+                // elemkind。0x00(funcref)だけが定義されている
+                self.write_byte(0x00);
+            }
+        }
+
+        match &segment.items {
+            ElementItems::Functions(funcs) => {
+                self.encode_u32(funcs.len() as u32);
+                for f in funcs {
+                    self.encode_u32(*f);
+                }
+            }
+            ElementItems::Expressions(exprs) => {
+                self.encode_u32(exprs.len() as u32);
+                for expr in exprs {
+                    self.encode_const_expr(expr);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn encode_code_section(&mut self, section: &CodeSection) {
+        self.encode_u32(section.bodies.len() as u32);
+        for body in &section.bodies {
+            let mut entry = Self::new();
+            entry.encode_u32(body.locales.len() as u32);
+            for local in &body.locales {
+                entry.encode_u32(local.count);
+                entry.encode_value_type(local.value_type);
+            }
+            for instruction in &body.code {
+                entry.encode_instruction(instruction);
+            }
+            entry.write_opcode(Opcode::End);
+
+            self.encode_bytes(&entry.buf);
+        }
+    }
+
+    pub(crate) fn encode_data_section(&mut self, section: &DataSection) {
+        self.encode_u32(section.segments.len() as u32);
+        for segment in &section.segments {
+            match (segment.passive, segment.index) {
+                (true, _) => self.encode_u32(0x01),
+                (false, 0) => self.encode_u32(0x00),
+                (false, index) => {
+                    self.encode_u32(0x02);
+                    self.encode_u32(index);
+                }
+            }
+
+            if !segment.passive {
+                self.encode_const_expr(&segment.offset);
+            }
+
+            self.encode_bytes(&segment.data);
+        }
+    }
+
+    fn encode_name(&mut self, name: &str) {
+        self.encode_bytes(name.as_bytes());
+    }
+
+    /// 長さをつけたバイト列
+    fn encode_bytes(&mut self, bytes: &[u8]) {
+        self.encode_u32(bytes.len() as u32);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn encode_value_type(&mut self, t: ValueType) {
+        match t {
+            ValueType::I32 => self.write_byte(0x7f),
+            ValueType::I64 => self.write_byte(0x7e),
+            ValueType::F32 => self.write_byte(0x7d),
+            ValueType::F64 => self.write_byte(0x7c),
+            ValueType::Ref(ref_type) => match abstract_heap_type(ref_type.heap_type) {
+                // (ref null ht)の抽象ヒープ型は1バイトの省略形で書ける
+                Some(x) if ref_type.nullable => self.write_byte(x),
+                _ => {
+                    self.write_byte(if ref_type.nullable {
+                        REF_NULL_TYPE
+                    } else {
+                        REF_TYPE
+                    });
+                    self.encode_heap_type(ref_type.heap_type);
+                }
+            },
+            ValueType::Unknown => unreachable!("unknown type appears only in validation"),
+        }
+    }
+
+    /// type indexはs33で書く
+    fn encode_heap_type(&mut self, heap_type: HeapType) {
+        match heap_type {
+            HeapType::Concrete(index) => self.encode_i64(i64::from(index)),
+            _ => self.write_byte(abstract_heap_type(heap_type).unwrap()),
+        }
+    }
+
+    fn encode_block_type(&mut self, block_type: BlockType) {
+        match block_type {
+            BlockType::Empty => self.write_byte(0x40),
+            BlockType::I32 => self.encode_value_type(ValueType::I32),
+            BlockType::I64 => self.encode_value_type(ValueType::I64),
+            BlockType::F32 => self.encode_value_type(ValueType::F32),
+            BlockType::F64 => self.encode_value_type(ValueType::F64),
+            BlockType::Ref(ref_type) => self.encode_value_type(ValueType::Ref(ref_type)),
+            BlockType::TypeIndex(index) => self.encode_i64(i64::from(index)),
+        }
+    }
+
+    /// flagsのbit0は最大値の有無。それ以外のbitは呼び出し側が渡す
+    fn encode_limits(&mut self, flags: u8, limits: &ResizableLimits) {
+        self.write_byte(flags | limits.maximum.is_some() as u8);
+        self.encode_u64(limits.initial);
+        if let Some(maximum) = limits.maximum {
+            self.encode_u64(maximum);
+        }
+    }
+
+    fn encode_memory_type(&mut self, memory_type: &MemoryType) {
+        let flags = (memory_type.shared as u8) << 1 | (memory_type.memory64 as u8) << 2;
+        self.encode_limits(flags, &memory_type.limits);
+    }
+
+    fn encode_table_type(&mut self, table_type: &TableType) {
+        self.encode_value_type(ValueType::Ref(table_type.elem_type));
+        self.encode_limits(0, &table_type.limits);
+    }
+
+    fn encode_global_type(&mut self, global_type: &GlobalType) {
+        self.encode_value_type(global_type.content_type);
+        self.write_byte(global_type.mutability as u8);
+    }
+
+    fn encode_tag_type(&mut self, tag_type: &TagType) {
+        self.write_byte(tag_type.attribute);
+        self.encode_u32(tag_type.type_index);
+    }
+
+    fn encode_catch_clause(&mut self, clause: &CatchClause) {
+        match *clause {
+            CatchClause::Catch(tag, label) => {
+                self.write_byte(0x00);
+                self.encode_u32(tag);
+                self.encode_u32(label);
+            }
+            CatchClause::CatchRef(tag, label) => {
+                self.write_byte(0x01);
+                self.encode_u32(tag);
+                self.encode_u32(label);
+            }
+            CatchClause::CatchAll(label) => {
+                self.write_byte(0x02);
+                self.encode_u32(label);
+            }
+            CatchClause::CatchAllRef(label) => {
+                self.write_byte(0x03);
+                self.encode_u32(label);
+            }
+        }
+    }
+
+    /// 定数式を書いて、最後にENDをつける
+    fn encode_const_expr(&mut self, expr: &[Instruction]) {
+        for instruction in expr {
+            self.encode_instruction(instruction);
+        }
+        self.write_opcode(Opcode::End);
+    }
+
+    /// alignのbit6でmemory indexがあることを表す。memory 0のときは省略する
+    fn encode_memarg(&mut self, memarg: &MemArg) {
+        if memarg.memory == 0 {
+            self.encode_u32(memarg.align);
+        } else {
+            self.encode_u32(memarg.align | 0x40);
+            self.encode_u32(memarg.memory);
+        }
+        self.encode_u64(memarg.offset);
+    }
+
+    fn encode_instruction(&mut self, instruction: &Instruction) {
+        use Instruction::*;
+
+        match instruction {
+            Block(t) => self.encode_block_instruction(Opcode::Block, *t),
+            Loop(t) => self.encode_block_instruction(Opcode::Loop, *t),
+            If(t) => self.encode_block_instruction(Opcode::If, *t),
+            TryTable(t, catches) => {
+                self.encode_block_instruction(Opcode::TryTable, *t);
+                self.encode_u32(catches.len() as u32);
+                for clause in catches {
+                    self.encode_catch_clause(clause);
+                }
+            }
+
+            Br(x) => self.encode_index_instruction(Opcode::Br, *x),
+            BrIf(x) => self.encode_index_instruction(Opcode::BrIf, *x),
+            GetLocal(x) => self.encode_index_instruction(Opcode::GetLocal, *x),
+            SetLocal(x) => self.encode_index_instruction(Opcode::SetLocal, *x),
+            TeeLocal(x) => self.encode_index_instruction(Opcode::TeeLocal, *x),
+            GetGlobal(x) => self.encode_index_instruction(Opcode::GetGlobal, *x),
+            SetGlobal(x) => self.encode_index_instruction(Opcode::SetGlobal, *x),
+            TableGet(x) => self.encode_index_instruction(Opcode::TableGet, *x),
+            TableSet(x) => self.encode_index_instruction(Opcode::TableSet, *x),
+            Call(x) => self.encode_index_instruction(Opcode::Call, *x),
+            CallRef(x) => self.encode_index_instruction(Opcode::CallRef, *x),
+            ReturnCallRef(x) => self.encode_index_instruction(Opcode::ReturnCallRef, *x),
+            RefFunc(x) => self.encode_index_instruction(Opcode::RefFunc, *x),
+            BrOnNull(x) => self.encode_index_instruction(Opcode::BrOnNull, *x),
+            BrOnNonNull(x) => self.encode_index_instruction(Opcode::BrOnNonNull, *x),
+            Throw(x) => self.encode_index_instruction(Opcode::Throw, *x),
+            CurrentMemory(x) => self.encode_index_instruction(Opcode::CurrentMemory, *x),
+            GrowMemory(x) => self.encode_index_instruction(Opcode::GrowMemory, *x),
+
+            BrTable(targets, default) => {
+                self.write_opcode(Opcode::BrTable);
+                self.encode_u32(targets.len() as u32);
+                for target in targets {
+                    self.encode_u32((*target).into());
+                }
+                self.encode_u32((*default).into());
+            }
+            CallIndirect(type_index, table) => {
+                self.write_opcode(Opcode::CallIndirect);
+                self.encode_u32((*type_index).into());
+                self.encode_u32((*table).into());
+            }
+
+            I32Load(m) => self.encode_memory_instruction(Opcode::I32Load, m),
+            I64Load(m) => self.encode_memory_instruction(Opcode::I64Load, m),
+            F32Load(m) => self.encode_memory_instruction(Opcode::F32Load, m),
+            F64Load(m) => self.encode_memory_instruction(Opcode::F64Load, m),
+            I32Load8S(m) => self.encode_memory_instruction(Opcode::I32Load8S, m),
+            I32Load8U(m) => self.encode_memory_instruction(Opcode::I32Load8U, m),
+            I32Load16S(m) => self.encode_memory_instruction(Opcode::I32Load16S, m),
+            I32Load16U(m) => self.encode_memory_instruction(Opcode::I32Load16U, m),
+            I64Load8S(m) => self.encode_memory_instruction(Opcode::I64Load8S, m),
+            I64Load8U(m) => self.encode_memory_instruction(Opcode::I64Load8U, m),
+            I64Load16S(m) => self.encode_memory_instruction(Opcode::I64Load16S, m),
+            I64Load16U(m) => self.encode_memory_instruction(Opcode::I64Load16U, m),
+            I64Load32S(m) => self.encode_memory_instruction(Opcode::I64Load32S, m),
+            I64Load32U(m) => self.encode_memory_instruction(Opcode::I64Load32U, m),
+            I32Store(m) => self.encode_memory_instruction(Opcode::I32Store, m),
+            I64Store(m) => self.encode_memory_instruction(Opcode::I64Store, m),
+            F32Store(m) => self.encode_memory_instruction(Opcode::F32Store, m),
+            F64Store(m) => self.encode_memory_instruction(Opcode::F64Store, m),
+            I32Store8(m) => self.encode_memory_instruction(Opcode::I32Store8, m),
+            I32Store16(m) => self.encode_memory_instruction(Opcode::I32Store16, m),
+            I64Store8(m) => self.encode_memory_instruction(Opcode::I64Store8, m),
+            I64Store16(m) => self.encode_memory_instruction(Opcode::I64Store16, m),
+            I64Store32(m) => self.encode_memory_instruction(Opcode::I64Store32, m),
+
+            I32Const(v) => {
+                self.write_opcode(Opcode::I32Const);
+                self.encode_i64(i64::from(*v));
+            }
+            I64Const(v) => {
+                self.write_opcode(Opcode::I64Const);
+                self.encode_i64(*v);
+            }
+            F32Const(v) => {
+                self.write_opcode(Opcode::F32Const);
+                self.buf.extend_from_slice(&v.to_bits().to_le_bytes());
+            }
+            F64Const(v) => {
+                self.write_opcode(Opcode::F64Const);
+                self.buf.extend_from_slice(&v.to_bits().to_le_bytes());
+            }
+            RefNull(heap_type) => {
+                self.write_opcode(Opcode::RefNull);
+                self.encode_heap_type(*heap_type);
+            }
+
+            // 0xFC
+            Prefix(sub) => self.encode_prefix(Opcode::Prefix, (*sub).into()),
+            MemoryInit(data, memory) => {
+                self.encode_prefix(Opcode::Prefix, 0x08);
+                self.encode_u32((*data).into());
+                self.encode_u32((*memory).into());
+            }
+            DataDrop(data) => {
+                self.encode_prefix(Opcode::Prefix, 0x09);
+                self.encode_u32((*data).into());
+            }
+            MemoryCopy(dst, src) => {
+                self.encode_prefix(Opcode::Prefix, 0x0A);
+                self.encode_u32((*dst).into());
+                self.encode_u32((*src).into());
+            }
+            MemoryFill(memory) => {
+                self.encode_prefix(Opcode::Prefix, 0x0B);
+                self.encode_u32((*memory).into());
+            }
+            TableInit(elem, table) => {
+                self.encode_prefix(Opcode::Prefix, 0x0C);
+                self.encode_u32((*elem).into());
+                self.encode_u32((*table).into());
+            }
+            ElemDrop(elem) => {
+                self.encode_prefix(Opcode::Prefix, 0x0D);
+                self.encode_u32((*elem).into());
+            }
+            TableCopy(dst, src) => {
+                self.encode_prefix(Opcode::Prefix, 0x0E);
+                self.encode_u32((*dst).into());
+                self.encode_u32((*src).into());
+            }
+            TableGrow(table) => {
+                self.encode_prefix(Opcode::Prefix, 0x0F);
+                self.encode_u32((*table).into());
+            }
+            TableSize(table) => {
+                self.encode_prefix(Opcode::Prefix, 0x10);
+                self.encode_u32((*table).into());
+            }
+            TableFill(table) => {
+                self.encode_prefix(Opcode::Prefix, 0x11);
+                self.encode_u32((*table).into());
+            }
+
+            // 0xFE
+            AtomicNotify(m) => self.encode_atomic_instruction(0x00, m),
+            AtomicWait32(m) => self.encode_atomic_instruction(0x01, m),
+            AtomicWait64(m) => self.encode_atomic_instruction(0x02, m),
+            AtomicFence => {
+                self.encode_prefix(Opcode::AtomicPrefix, 0x03);
+                self.write_byte(0x00);
+            }
+            AtomicLoad(width, m) => self.encode_atomic_instruction(0x10 + atomic_width(*width), m),
+            AtomicStore(width, m) => self.encode_atomic_instruction(0x17 + atomic_width(*width), m),
+            AtomicRmw(op, width, m) => {
+                let op = match op {
+                    AtomicRmwOp::Add => 0,
+                    AtomicRmwOp::Sub => 1,
+                    AtomicRmwOp::And => 2,
+                    AtomicRmwOp::Or => 3,
+                    AtomicRmwOp::Xor => 4,
+                    AtomicRmwOp::Xchg => 5,
+                };
+                self.encode_atomic_instruction(0x1E + op * 7 + atomic_width(*width), m)
+            }
+            AtomicCmpxchg(width, m) => {
+                self.encode_atomic_instruction(0x48 + atomic_width(*width), m)
+            }
+
+            // 0xFB
+            StructNew(t) => self.encode_gc_instruction(0x00, &[*t]),
+            StructNewDefault(t) => self.encode_gc_instruction(0x01, &[*t]),
+            StructGet(t, f) => self.encode_gc_instruction(0x02, &[*t, *f]),
+            StructGetS(t, f) => self.encode_gc_instruction(0x03, &[*t, *f]),
+            StructGetU(t, f) => self.encode_gc_instruction(0x04, &[*t, *f]),
+            StructSet(t, f) => self.encode_gc_instruction(0x05, &[*t, *f]),
+            ArrayNew(t) => self.encode_gc_instruction(0x06, &[*t]),
+            ArrayNewDefault(t) => self.encode_gc_instruction(0x07, &[*t]),
+            ArrayNewFixed(t, n) => self.encode_gc_instruction(0x08, &[*t, *n]),
+            ArrayNewData(t, d) => self.encode_gc_instruction(0x09, &[*t, *d]),
+            ArrayGet(t) => self.encode_gc_instruction(0x0B, &[*t]),
+            ArrayGetS(t) => self.encode_gc_instruction(0x0C, &[*t]),
+            ArrayGetU(t) => self.encode_gc_instruction(0x0D, &[*t]),
+            ArraySet(t) => self.encode_gc_instruction(0x0E, &[*t]),
+            ArrayLen => self.encode_gc_instruction(0x0F, &[]),
+            ArrayFill(t) => self.encode_gc_instruction(0x10, &[*t]),
+            ArrayCopy(dst, src) => self.encode_gc_instruction(0x11, &[*dst, *src]),
+            RefTest(t) => {
+                self.encode_gc_instruction(if t.nullable { 0x15 } else { 0x14 }, &[]);
+                self.encode_heap_type(t.heap_type);
+            }
+            RefCast(t) => {
+                self.encode_gc_instruction(if t.nullable { 0x17 } else { 0x16 }, &[]);
+                self.encode_heap_type(t.heap_type);
+            }
+            BrOnCast(label, from, to) | BrOnCastFail(label, from, to) => {
+                let sub = if matches!(instruction, BrOnCast(..)) {
+                    0x18
+                } else {
+                    0x19
+                };
+                self.encode_gc_instruction(sub, &[]);
+                self.write_byte(from.nullable as u8 | (to.nullable as u8) << 1);
+                self.encode_u32((*label).into());
+                self.encode_heap_type(from.heap_type);
+                self.encode_heap_type(to.heap_type);
+            }
+            AnyConvertExtern => self.encode_gc_instruction(0x1A, &[]),
+            ExternConvertAny => self.encode_gc_instruction(0x1B, &[]),
+            RefI31 => self.encode_gc_instruction(0x1C, &[]),
+            I31GetS => self.encode_gc_instruction(0x1D, &[]),
+            I31GetU => self.encode_gc_instruction(0x1E, &[]),
+
+            // 即値を持たない命令
+            _ => self.write_opcode(simple_opcode(instruction)),
+        }
+    }
+
+    fn encode_block_instruction(&mut self, opcode: Opcode, block_type: BlockType) {
+        self.write_opcode(opcode);
+        self.encode_block_type(block_type);
+    }
+
+    fn encode_index_instruction(&mut self, opcode: Opcode, index: VerUintN) {
+        self.write_opcode(opcode);
+        self.encode_u32(index.into());
+    }
+
+    fn encode_memory_instruction(&mut self, opcode: Opcode, memarg: &MemArg) {
+        self.write_opcode(opcode);
+        self.encode_memarg(memarg);
+    }
+
+    fn encode_prefix(&mut self, prefix: Opcode, sub_opcode: u32) {
+        self.write_opcode(prefix);
+        self.encode_u32(sub_opcode);
+    }
+
+    fn encode_atomic_instruction(&mut self, sub_opcode: u32, memarg: &MemArg) {
+        self.encode_prefix(Opcode::AtomicPrefix, sub_opcode);
+        self.encode_memarg(memarg);
+    }
+
+    fn encode_gc_instruction(&mut self, sub_opcode: u32, immediates: &[VerUintN]) {
+        self.encode_prefix(Opcode::GcPrefix, sub_opcode);
+        for x in immediates {
+            self.encode_u32((*x).into());
+        }
+    }
+
+    pub(crate) fn encode_u32(&mut self, value: u32) {
+        self.encode_u64(u64::from(value));
+    }
+
+    /// 符号なしLEB128
+    fn encode_u64(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.write_byte(byte);
+                break;
+            }
+            self.write_byte(byte | 0x80);
+        }
+    }
+
+    /// 符号付きLEB128。i32.constの値もi64に符号拡張してから書く
+    fn encode_i64(&mut self, mut value: i64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+            if done {
+                self.write_byte(byte);
+                break;
+            }
+            self.write_byte(byte | 0x80);
+        }
+    }
+
+    fn write_opcode(&mut self, opcode: Opcode) {
+        self.write_byte(opcode.into());
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.buf.push(byte);
+    }
+}
+
+/// HeapType::from_abstractの逆
+fn abstract_heap_type(heap_type: HeapType) -> Option<u8> {
+    use HeapType::*;
+
+    let x = match heap_type {
+        Func => 0x70,
+        Extern => 0x6f,
+        Any => 0x6e,
+        Eq => 0x6d,
+        I31 => 0x6c,
+        Struct => 0x6b,
+        Array => 0x6a,
+        Exn => 0x69,
+        None => 0x71,
+        NoFunc => 0x73,
+        NoExtern => 0x72,
+        NoExn => 0x74,
+        Concrete(_) => return Option::None,
+    };
+
+    Some(x)
+}
+
+/// AtomicWidth::fromの逆。7個ずつ並んでいるatomic命令の中の位置
+fn atomic_width(width: AtomicWidth) -> u32 {
+    use AtomicWidth::*;
+
+    match width {
+        I32 => 0,
+        I64 => 1,
+        I32U8 => 2,
+        I32U16 => 3,
+        I64U8 => 4,
+        I64U16 => 5,
+        I64U32 => 6,
+    }
+}
+
+/// 即値を持たない命令のopcode。From<Opcode> for Instructionの逆
+fn simple_opcode(instruction: &Instruction) -> Opcode {
+    use Instruction::*;
+
+    match instruction {
+        Unreachable => Opcode::Unreachable,
+        Nop => Opcode::Nop,
+        Else => Opcode::Else,
+        ThrowRef => Opcode::ThrowRef,
+        End => Opcode::End,
+        Return => Opcode::Return,
+        Drop => Opcode::Drop,
+        Select => Opcode::Select,
+        I32Eqz => Opcode::I32Eqz,
+        I32Eq => Opcode::I32Eq,
+        I32Ne => Opcode::I32Ne,
+        I32LtS => Opcode::I32LtS,
+        I32LtU => Opcode::I32LtU,
+        I32GtS => Opcode::I32GtS,
+        I32GtU => Opcode::I32GtU,
+        I32LeS => Opcode::I32LeS,
+        I32LeU => Opcode::I32LeU,
+        I32GeS => Opcode::I32GeS,
+        I32GeU => Opcode::I32GeU,
+        I64Eqz => Opcode::I64Eqz,
+        I64Eq => Opcode::I64Eq,
+        I64Ne => Opcode::I64Ne,
+        I64LtS => Opcode::I64LtS,
+        I64LtU => Opcode::I64LtU,
+        I64GtS => Opcode::I64GtS,
+        I64GtU => Opcode::I64GtU,
+        I64LeS => Opcode::I64LeS,
+        I64LeU => Opcode::I64LeU,
+        I64GeS => Opcode::I64GeS,
+        I64GeU => Opcode::I64GeU,
+        F32Eq => Opcode::F32Eq,
+        F32Ne => Opcode::F32Ne,
+        F32Lt => Opcode::F32Lt,
+        F32Gt => Opcode::F32Gt,
+        F32Le => Opcode::F32Le,
+        F32Ge => Opcode::F32Ge,
+        F64Eq => Opcode::F64Eq,
+        F64Ne => Opcode::F64Ne,
+        F64Lt => Opcode::F64Lt,
+        F64Gt => Opcode::F64Gt,
+        F64Le => Opcode::F64Le,
+        F64Ge => Opcode::F64Ge,
+        I32Clz => Opcode::I32Clz,
+        I32Ctz => Opcode::I32Ctz,
+        I32Popcnt => Opcode::I32Popcnt,
+        I32Add => Opcode::I32Add,
+        I32Sub => Opcode::I32Sub,
+        I32Mul => Opcode::I32Mul,
+        I32DivS => Opcode::I32DivS,
+        I32DivU => Opcode::I32DivU,
+        I32RemS => Opcode::I32RemS,
+        I32RemU => Opcode::I32RemU,
+        I32And => Opcode::I32And,
+        I32Or => Opcode::I32Or,
+        I32Xor => Opcode::I32Xor,
+        I32Shl => Opcode::I32Shl,
+        I32ShrS => Opcode::I32ShrS,
+        I32ShrU => Opcode::I32ShrU,
+        I32Rotl => Opcode::I32Rotl,
+        I32Rotr => Opcode::I32Rotr,
+        I64Clz => Opcode::I64Clz,
+        I64Ctz => Opcode::I64Ctz,
+        I64Popcnt => Opcode::I64Popcnt,
+        I64Add => Opcode::I64Add,
+        I64Sub => Opcode::I64Sub,
+        I64Mul => Opcode::I64Mul,
+        I64DivS => Opcode::I64DivS,
+        I64DivU => Opcode::I64DivU,
+        I64RemS => Opcode::I64RemS,
+        I64RemU => Opcode::I64RemU,
+        I64And => Opcode::I64And,
+        I64Or => Opcode::I64Or,
+        I64Xor => Opcode::I64Xor,
+        I64Shl => Opcode::I64Shl,
+        I64ShrS => Opcode::I64ShrS,
+        I64ShrU => Opcode::I64ShrU,
+        I64Rotl => Opcode::I64Rotl,
+        I64Rotr => Opcode::I64Rotr,
+        F32Abs => Opcode::F32Abs,
+        F32Neg => Opcode::F32Neg,
+        F32Ceil => Opcode::F32Ceil,
+        F32Floor => Opcode::F32Floor,
+        F32Trunc => Opcode::F32Trunc,
+        F32Nearest => Opcode::F32Nearest,
+        F32Sqrt => Opcode::F32Sqrt,
+        F32Add => Opcode::F32Add,
+        F32Sub => Opcode::F32Sub,
+        F32Mul => Opcode::F32Mul,
+        F32Div => Opcode::F32Div,
+        F32Min => Opcode::F32Min,
+        F32Max => Opcode::F32Max,
+        F32Copysign => Opcode::F32Copysign,
+        F64Abs => Opcode::F64Abs,
+        F64Neg => Opcode::F64Neg,
+        F64Ceil => Opcode::F64Ceil,
+        F64Floor => Opcode::F64Floor,
+        F64Trunc => Opcode::F64Trunc,
+        F64Nearest => Opcode::F64Nearest,
+        F64Sqrt => Opcode::F64Sqrt,
+        F64Add => Opcode::F64Add,
+        F64Sub => Opcode::F64Sub,
+        F64Mul => Opcode::F64Mul,
+        F64Div => Opcode::F64Div,
+        F64Min => Opcode::F64Min,
+        F64Max => Opcode::F64Max,
+        F64Copysign => Opcode::F64Copysign,
+        I32WrapI64 => Opcode::I32WrapI64,
+        I32TruncSF32 => Opcode::I32TruncSF32,
+        I32TruncUF32 => Opcode::I32TruncUF32,
+        I32TruncSF64 => Opcode::I32TruncSF64,
+        I32TruncUF64 => Opcode::I32TruncUF64,
+        I64ExtendSI32 => Opcode::I64ExtendSI32,
+        I64ExtendUI32 => Opcode::I64ExtendUI32,
+        I64TruncSF32 => Opcode::I64TruncSF32,
+        I64TruncUF32 => Opcode::I64TruncUF32,
+        I64TruncSF64 => Opcode::I64TruncSF64,
+        I64TruncUF64 => Opcode::I64TruncUF64,
+        F32ConvertSI32 => Opcode::F32ConvertSI32,
+        F32ConvertUI32 => Opcode::F32ConvertUI32,
+        F32ConvertSI64 => Opcode::F32ConvertSI64,
+        F32ConvertUI64 => Opcode::F32ConvertUI64,
+        F32DemoteF64 => Opcode::F32DemoteF64,
+        F64ConvertSI32 => Opcode::F64ConvertSI32,
+        F64ConvertUI32 => Opcode::F64ConvertUI32,
+        F64ConvertSI64 => Opcode::F64ConvertSI64,
+        F64ConvertUI64 => Opcode::F64ConvertUI64,
+        F64PromoteF32 => Opcode::F64PromoteF32,
+        I32ReinterpretF32 => Opcode::I32ReinterpretF32,
+        I64ReinterpretF64 => Opcode::I64ReinterpretF64,
+        F32ReinterpretI32 => Opcode::F32ReinterpretI32,
+        F64ReinterpretI64 => Opcode::F64ReinterpretI64,
+        RefIsNull => Opcode::RefIsNull,
+        RefEq => Opcode::RefEq,
+        RefAsNonNull => Opcode::RefAsNonNull,
+        Reserved => Opcode::Reserved,
+        _ => unreachable!("{:?} has immediates", instruction),
+    }
+}
@@ -0,0 +1,116 @@
+mod encoder;
+
+use crate::module::{Module, SectionType};
+use encoder::Encoder;
+
+/// custom section以外のsectionを並べる順番
+const SECTION_ORDER: [SectionType; 13] = [
+    SectionType::Type,
+    SectionType::Import,
+    SectionType::Function,
+    SectionType::Table,
+    SectionType::Memory,
+    SectionType::Tag,
+    SectionType::Global,
+    SectionType::Export,
+    SectionType::Start,
+    SectionType::Element,
+    SectionType::DataCount,
+    SectionType::Code,
+    SectionType::Data,
+];
+
+/// wasm moduleをwasmバイナリにエンコードする。
+/// デコードしたmoduleはエンコードしてもう一度デコードすると元と同じmoduleになる
+pub fn encode(m: &Module) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    encoder.encode_header();
+
+    encode_custom_sections(&mut encoder, m, None);
+    for section_type in SECTION_ORDER {
+        encode_section(&mut encoder, m, section_type);
+        encode_custom_sections(&mut encoder, m, Some(section_type));
+    }
+
+    encoder.finish()
+}
+
+fn encode_section(encoder: &mut Encoder, m: &Module, section_type: SectionType) {
+    use SectionType::*;
+
+    match section_type {
+        Type => {
+            if let Some(s) = &m.type_section {
+                encoder.encode_section(Type, |e| e.encode_type_section(s));
+            }
+        }
+        Import => {
+            if let Some(s) = &m.import_section {
+                encoder.encode_section(Import, |e| e.encode_import_section(s));
+            }
+        }
+        Function => {
+            if let Some(s) = &m.function_section {
+                encoder.encode_section(Function, |e| e.encode_function_section(s));
+            }
+        }
+        Table => {
+            if let Some(s) = &m.table_section {
+                encoder.encode_section(Table, |e| e.encode_table_section(s));
+            }
+        }
+        Memory => {
+            if let Some(s) = &m.memory_section {
+                encoder.encode_section(Memory, |e| e.encode_memory_section(s));
+            }
+        }
+        Tag => {
+            if let Some(s) = &m.tag_section {
+                encoder.encode_section(Tag, |e| e.encode_tag_section(s));
+            }
+        }
+        Global => {
+            if let Some(s) = &m.global_section {
+                encoder.encode_section(Global, |e| e.encode_global_section(s));
+            }
+        }
+        Export => {
+            if let Some(s) = &m.export_section {
+                encoder.encode_section(Export, |e| e.encode_export_section(s));
+            }
+        }
+        Start => {
+            if let Some(index) = m.start_section {
+                encoder.encode_section(Start, |e| e.encode_u32(index));
+            }
+        }
+        Element => {
+            if let Some(s) = &m.element_section {
+                encoder.encode_section(Element, |e| e.encode_element_section(s));
+            }
+        }
+        DataCount => {
+            if let Some(count) = m.data_count_section {
+                encoder.encode_section(DataCount, |e| e.encode_u32(count));
+            }
+        }
+        Code => {
+            if let Some(s) = &m.code_section {
+                encoder.encode_section(Code, |e| e.encode_code_section(s));
+            }
+        }
+        Data => {
+            if let Some(s) = &m.data_section {
+                encoder.encode_section(Data, |e| e.encode_data_section(s));
+            }
+        }
+        Custom | Unsuport => unreachable!("{:?} is not a known section", section_type),
+    }
+}
+
+/// afterの直後にあったcustom sectionを元の順番で書く
+fn encode_custom_sections(encoder: &mut Encoder, m: &Module, after: Option<SectionType>) {
+    for custom in m.custom_sections.iter().filter(|c| c.after == after) {
+        encoder.encode_section(SectionType::Custom, |e| e.encode_custom_section(custom));
+    }
+}
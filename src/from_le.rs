@@ -1,3 +1,4 @@
+/// メモリから読んだリトルエンディアンのバイト列からの変換
 pub trait FromLe {
     fn from_le_bytes(b: &[u8]) -> Self;
 }
@@ -9,6 +10,14 @@ impl FromLe for u8 {
         Self::from_le_bytes(b)
     }
 }
+impl FromLe for i8 {
+    fn from_le_bytes(byte: &[u8]) -> Self {
+        let mut b: [u8; 1] = Default::default();
+        b.copy_from_slice(&byte[0..1]);
+        Self::from_le_bytes(b)
+    }
+}
+
 impl FromLe for u16 {
     fn from_le_bytes(byte: &[u8]) -> Self {
         let mut b: [u8; 2] = Default::default();
@@ -17,6 +26,14 @@ impl FromLe for u16 {
     }
 }
 
+impl FromLe for i16 {
+    fn from_le_bytes(byte: &[u8]) -> Self {
+        let mut b: [u8; 2] = Default::default();
+        b.copy_from_slice(&byte[0..2]);
+        Self::from_le_bytes(b)
+    }
+}
+
 impl FromLe for u32 {
     fn from_le_bytes(byte: &[u8]) -> Self {
         let mut b: [u8; 4] = Default::default();
@@ -0,0 +1,237 @@
+use super::Instance;
+use crate::runtime::{RuntimeError, RuntimeValue};
+use crate::types::{FuncType, ValueType};
+use std::marker::PhantomData;
+
+/// インスタンスの関数へのハンドル。名前を引かずにindexで呼べるので、何度も呼ぶ関数はこれを取っておく
+#[derive(Debug, Clone, PartialEq)]
+pub struct Func {
+    /// 取り出したインスタンスの番号
+    instance: u64,
+    index: u32,
+    func_type: FuncType,
+}
+
+impl Func {
+    pub(crate) fn new(instance: u64, index: u32, func_type: FuncType) -> Self {
+        Self {
+            instance,
+            index,
+            func_type,
+        }
+    }
+
+    pub(crate) fn instance(&self) -> u64 {
+        self.instance
+    }
+
+    /// 関数のindex
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn func_type(&self) -> &FuncType {
+        &self.func_type
+    }
+
+    /// このハンドルを取り出したinstanceで呼ぶ。別のinstanceを渡すとエラーになる。
+    /// 引数の型はInstance::invokeと同じく呼ぶたびに調べる
+    pub fn call(
+        &self,
+        instance: &mut Instance,
+        args: Vec<RuntimeValue>,
+    ) -> Result<Vec<RuntimeValue>, RuntimeError> {
+        instance.check_owner(self.instance, "function")?;
+        instance.call_function(self.index as usize, args)
+    }
+
+    /// 型がParams -> Resultsと一致すればTypedFuncにする
+    pub fn typed<Params, Results>(self) -> Result<TypedFunc<Params, Results>, RuntimeError>
+    where
+        Params: WasmParams,
+        Results: WasmResults,
+    {
+        let expect = FuncType::new(Params::value_types(), Results::value_types());
+        if expect != self.func_type {
+            return Err(RuntimeError::FuncTypeMismatch(expect, self.func_type));
+        }
+
+        Ok(TypedFunc {
+            func: self,
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// 型を確かめ済みの関数。Rustの値をそのまま渡して呼べる
+#[derive(Debug, Clone)]
+pub struct TypedFunc<Params, Results> {
+    func: Func,
+    _marker: PhantomData<fn(Params) -> Results>,
+}
+
+impl<Params, Results> TypedFunc<Params, Results>
+where
+    Params: WasmParams,
+    Results: WasmResults,
+{
+    pub fn func(&self) -> &Func {
+        &self.func
+    }
+
+    pub fn call(&self, instance: &mut Instance, params: Params) -> Result<Results, RuntimeError> {
+        let values = self.func.call(instance, params.into_values())?;
+        let actual: Vec<ValueType> = values.iter().map(RuntimeValue::to_type).collect();
+
+        Results::from_values(values)
+            .ok_or_else(|| RuntimeError::InvalidArgs(Results::value_types(), actual))
+    }
+}
+
+/// wasmの数値型に対応するRustの型
+pub trait WasmTy: Sized {
+    fn value_type() -> ValueType;
+    fn into_value(self) -> RuntimeValue;
+    fn from_value(value: RuntimeValue) -> Option<Self>;
+}
+
+impl WasmTy for i32 {
+    fn value_type() -> ValueType {
+        ValueType::I32
+    }
+
+    fn into_value(self) -> RuntimeValue {
+        RuntimeValue::I32(self)
+    }
+
+    fn from_value(value: RuntimeValue) -> Option<Self> {
+        match value {
+            RuntimeValue::I32(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl WasmTy for i64 {
+    fn value_type() -> ValueType {
+        ValueType::I64
+    }
+
+    fn into_value(self) -> RuntimeValue {
+        RuntimeValue::I64(self)
+    }
+
+    fn from_value(value: RuntimeValue) -> Option<Self> {
+        match value {
+            RuntimeValue::I64(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl WasmTy for f32 {
+    fn value_type() -> ValueType {
+        ValueType::F32
+    }
+
+    fn into_value(self) -> RuntimeValue {
+        RuntimeValue::F32(self)
+    }
+
+    fn from_value(value: RuntimeValue) -> Option<Self> {
+        match value {
+            RuntimeValue::F32(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl WasmTy for f64 {
+    fn value_type() -> ValueType {
+        ValueType::F64
+    }
+
+    fn into_value(self) -> RuntimeValue {
+        RuntimeValue::F64(self)
+    }
+
+    fn from_value(value: RuntimeValue) -> Option<Self> {
+        match value {
+            RuntimeValue::F64(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// TypedFuncの引数。WasmTyの値1つか、そのタプル
+pub trait WasmParams {
+    fn value_types() -> Vec<ValueType>;
+    fn into_values(self) -> Vec<RuntimeValue>;
+}
+
+/// TypedFuncの戻り値。WasmTyの値1つか、そのタプル
+pub trait WasmResults: Sized {
+    fn value_types() -> Vec<ValueType>;
+    fn from_values(values: Vec<RuntimeValue>) -> Option<Self>;
+}
+
+impl<T: WasmTy> WasmParams for T {
+    fn value_types() -> Vec<ValueType> {
+        vec![T::value_type()]
+    }
+
+    fn into_values(self) -> Vec<RuntimeValue> {
+        vec![self.into_value()]
+    }
+}
+
+impl<T: WasmTy> WasmResults for T {
+    fn value_types() -> Vec<ValueType> {
+        vec![T::value_type()]
+    }
+
+    fn from_values(values: Vec<RuntimeValue>) -> Option<Self> {
+        let [value] = <[RuntimeValue; 1]>::try_from(values).ok()?;
+        T::from_value(value)
+    }
+}
+
+/// 要素数ごとにタプルのWasmParams、WasmResultsを実装する
+macro_rules! impl_tuple {
+    ($($t:ident),*) => {
+        impl<$($t: WasmTy),*> WasmParams for ($($t,)*) {
+            fn value_types() -> Vec<ValueType> {
+                vec![$($t::value_type()),*]
+            }
+
+            #[allow(non_snake_case)]
+            fn into_values(self) -> Vec<RuntimeValue> {
+                let ($($t,)*) = self;
+                vec![$($t.into_value()),*]
+            }
+        }
+
+        impl<$($t: WasmTy),*> WasmResults for ($($t,)*) {
+            fn value_types() -> Vec<ValueType> {
+                vec![$($t::value_type()),*]
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn from_values(values: Vec<RuntimeValue>) -> Option<Self> {
+                if values.len() != <Self as WasmResults>::value_types().len() {
+                    return None;
+                }
+                let mut values = values.into_iter();
+                Some(($($t::from_value(values.next()?)?,)*))
+            }
+        }
+    };
+}
+
+impl_tuple!();
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
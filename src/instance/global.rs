@@ -0,0 +1,59 @@
+use super::Instance;
+use crate::runtime::{RuntimeError, RuntimeValue};
+use crate::types::GlobalType;
+
+/// インスタンスのglobalへのハンドル。値はインスタンスが持っているので、読み書きするたびにinstanceを渡す
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalRef {
+    /// 取り出したインスタンスの番号
+    instance: u64,
+    index: u32,
+    global_type: GlobalType,
+}
+
+impl GlobalRef {
+    pub(crate) fn new(instance: u64, index: u32, global_type: GlobalType) -> Self {
+        Self {
+            instance,
+            index,
+            global_type,
+        }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn global_type(&self) -> &GlobalType {
+        &self.global_type
+    }
+
+    pub fn get(&self, instance: &Instance) -> Result<RuntimeValue, RuntimeError> {
+        instance.check_owner(self.instance, "global")?;
+        match instance.runtime.globals.get(self.index as usize) {
+            Some(v) => Ok(*v),
+            None => Err(RuntimeError::NotFound(format!("global {}", self.index))),
+        }
+    }
+
+    /// mutableなglobalにだけ書き込める。値の型はglobalの型と一致しなければならない
+    pub fn set(&self, instance: &mut Instance, value: RuntimeValue) -> Result<(), RuntimeError> {
+        instance.check_owner(self.instance, "global")?;
+        if !self.global_type.mutability {
+            return Err(RuntimeError::ImmutableGlobal(self.index));
+        }
+        if !value.matches(&self.global_type.content_type) {
+            return Err(RuntimeError::InvalidArgs(
+                vec![self.global_type.content_type],
+                vec![value.to_type()],
+            ));
+        }
+
+        match instance.runtime.globals.get_mut(self.index as usize) {
+            Some(global) => *global = value,
+            None => return Err(RuntimeError::NotFound(format!("global {}", self.index))),
+        }
+
+        Ok(())
+    }
+}
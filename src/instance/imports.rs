@@ -0,0 +1,68 @@
+use super::{Func, GlobalRef, TableRef};
+use crate::runtime::{HostFunc, MemoryRef, RuntimeValue, Tag};
+use std::collections::HashMap;
+
+/// インスタンス化するときにモジュールのimportへ渡す値。Instance::get_exportが返す値でもある。
+/// 関数はHostFuncとして、immutableなglobalはValueとしてimportする。Func、Table、Globalはまだimportできない
+#[derive(Debug, Clone)]
+pub enum Extern {
+    Func(Func),
+    HostFunc(HostFunc),
+    Table(TableRef),
+    Memory(MemoryRef),
+    Global(GlobalRef),
+    Tag(Tag),
+    /// immutableなglobalの値。importしたインスタンスにコピーされる
+    Value(RuntimeValue),
+}
+
+impl From<HostFunc> for Extern {
+    fn from(func: HostFunc) -> Self {
+        Extern::HostFunc(func)
+    }
+}
+
+impl From<MemoryRef> for Extern {
+    fn from(memory: MemoryRef) -> Self {
+        Extern::Memory(memory)
+    }
+}
+
+impl From<RuntimeValue> for Extern {
+    fn from(value: RuntimeValue) -> Self {
+        Extern::Value(value)
+    }
+}
+
+impl From<Tag> for Extern {
+    fn from(tag: Tag) -> Self {
+        Extern::Tag(tag)
+    }
+}
+
+/// (module名, field名)からExternを引くためのテーブル
+#[derive(Debug, Clone, Default)]
+pub struct Imports {
+    entries: HashMap<(String, String), Extern>,
+}
+
+impl Imports {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn define(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        value: impl Into<Extern>,
+    ) -> &mut Self {
+        self.entries
+            .insert((module.into(), name.into()), value.into());
+        self
+    }
+
+    pub fn get(&self, module: &str, name: &str) -> Option<&Extern> {
+        self.entries.get(&(module.to_string(), name.to_string()))
+    }
+}
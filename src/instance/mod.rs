@@ -1,83 +1,483 @@
+mod func;
+mod global;
+mod imports;
+mod store;
+mod table;
+
+pub use func::{Func, TypedFunc, WasmParams, WasmResults, WasmTy};
+pub use global::GlobalRef;
+pub use imports::{Extern, Imports};
+pub use store::{InstanceId, Store};
+pub use table::TableRef;
+
+use crate::config::Config;
 use crate::module::Module;
-use crate::runtime::{error::RuntimeError, FunctionTable, Memory, Runtime, RuntimeValue};
+use crate::runtime::{
+    error::RuntimeError, FunctionTable, Memory, MemoryRef, Runtime, RuntimeValue, Table, Tag,
+};
 use crate::types::*;
+use crate::validate;
+use std::any::Any;
+use std::sync::atomic::{AtomicU64, Ordering};
 
+/// Instanceごとに振る番号。Func、GlobalRef、TableRefがどのインスタンスのものかを確かめるのに使う
+static NEXT_INSTANCE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// インスタンス化したmodule。メモリ、global、tableはインスタンス化のときに作り、invokeをまたいで持ち続ける
 #[derive(Debug)]
 pub struct Instance {
+    id: u64,
     module: Module,
+    runtime: Runtime,
+    config: Config,
 }
 
 type ValueStack = Vec<RuntimeValue>;
 
 impl Instance {
-    pub fn new(module: Module) -> Self {
-        Self { module }
+    pub fn new(module: Module) -> Result<Self, RuntimeError> {
+        Self::with_imports(module, Imports::new())
+    }
+
+    pub fn with_imports(module: Module, imports: Imports) -> Result<Self, RuntimeError> {
+        Self::with_config(module, imports, Config::default())
+    }
+
+    /// configで有効にしていないproposalを使うmoduleはインスタンス化しない。
+    /// 呼び出しの深さ、fuel、NaNの扱いはinvokeのたびにconfigに従う
+    pub fn with_config(
+        module: Module,
+        imports: Imports,
+        config: Config,
+    ) -> Result<Self, RuntimeError> {
+        Self::instantiate(module, imports, config, &mut ())
+    }
+
+    /// memory、global、tableとsegmentを初期化してから、start関数があれば呼ぶ。
+    /// dataはstart関数から呼ばれたHostFuncへCaller経由で渡す
+    pub(crate) fn instantiate(
+        module: Module,
+        imports: Imports,
+        config: Config,
+        data: &mut dyn Any,
+    ) -> Result<Self, RuntimeError> {
+        validate::check_features(&module, &config)?;
+
+        let runtime = Runtime::new(
+            FunctionTable::from_module(&module),
+            data_segments(&module),
+            types(&module),
+        )
+        .with_config(&config);
+        let mut instance = Self {
+            id: NEXT_INSTANCE_ID.fetch_add(1, Ordering::Relaxed),
+            module,
+            runtime,
+            config,
+        };
+
+        instance.resolve_imports(&imports)?;
+        instance.init_tags();
+        instance.init_memories()?;
+        instance.init_memory_exports();
+        instance.init_globals()?;
+        instance.init_tables();
+        instance.init_elements()?;
+        instance.init_data()?;
+
+        if let Some(start) = instance.module.start_section {
+            instance.call_function_with(start as usize, vec![], data)?;
+        }
+
+        Ok(instance)
     }
 
+    /// exportされた関数を呼ぶ。前の呼び出しで書き換えたメモリやglobalはそのまま見える
     pub fn invoke(
-        &self,
+        &mut self,
         name: impl AsRef<str>,
         args: Vec<RuntimeValue>,
     ) -> Result<ValueStack, RuntimeError> {
-        let index = self.resolve_function_name(name.as_ref());
-        let index = match index {
-            None => return Err(RuntimeError::NotFound(name.as_ref().to_string())),
-            Some(i) => i,
+        let func = self.get_func(name)?;
+        self.call_function(func.index() as usize, args)
+    }
+
+    /// nameでexportされたものを、exportの種類に合わせたハンドルにして返す
+    pub fn get_export(&self, name: impl AsRef<str>) -> Result<Extern, RuntimeError> {
+        let name = name.as_ref();
+        let value = match self.export(name)?.kind {
+            ExternalKind::Function => Extern::Func(self.get_func(name)?),
+            ExternalKind::Table => Extern::Table(self.get_table(name)?),
+            ExternalKind::Memory => Extern::Memory(self.get_memory(name)?),
+            ExternalKind::Global => Extern::Global(self.get_global(name)?),
+            ExternalKind::Tag => Extern::Tag(self.get_tag(name)?),
+            ExternalKind::Unknown => {
+                return Err(RuntimeError::Custom(format!(
+                    "export '{}' has an unknown kind",
+                    name
+                )))
+            }
         };
 
-        let function_table = self.function_table();
+        Ok(value)
+    }
 
-        let func = function_table.get(index).unwrap();
-        let return_length = func.returns.len();
+    /// exportされた関数のハンドル。取っておけば呼ぶたびに名前を引かなくてよい
+    pub fn get_func(&self, name: impl AsRef<str>) -> Result<Func, RuntimeError> {
+        let index = self.resolve_export(name.as_ref(), ExternalKind::Function)?;
+        match self.runtime.func_type(index) {
+            Some(func_type) => Ok(Func::new(self.id, index as u32, func_type)),
+            None => Err(RuntimeError::NotFound(format!("function {}", index))),
+        }
+    }
 
-        println!("exec func info: {:?}", func);
+    /// exportされた関数を、型がParams -> Resultsであることを確かめてから返す
+    pub fn get_typed_func<Params, Results>(
+        &self,
+        name: impl AsRef<str>,
+    ) -> Result<TypedFunc<Params, Results>, RuntimeError>
+    where
+        Params: WasmParams,
+        Results: WasmResults,
+    {
+        self.get_func(name)?.typed()
+    }
 
-        Instance::validate(&func.params, &args)?; // argsとfunc_type.paramsの個数、型をチェックする + errorをいい感じに表示してあげたい
-        let memory = Memory::new(self.init_memory()?);
+    /// ownerのインスタンスから取り出したハンドルでなければエラーにする
+    pub(crate) fn check_owner(&self, owner: u64, handle: &str) -> Result<(), RuntimeError> {
+        if owner != self.id {
+            return Err(RuntimeError::ForeignHandle(handle.to_string()));
+        }
 
-        let mut runtime = Runtime::new(function_table, memory);
-        let mut stack = runtime.execute(index, &args)?;
+        Ok(())
+    }
 
-        stack.reverse();
-        let ret = &stack[0..return_length];
-        Ok(ret.to_vec())
+    pub(crate) fn call_function(
+        &mut self,
+        index: usize,
+        args: Vec<RuntimeValue>,
+    ) -> Result<ValueStack, RuntimeError> {
+        self.call_function_with(index, args, &mut ())
+    }
+
+    /// dataはimportしたHostFuncへCaller経由で渡す。Storeを使わないときは()
+    pub(crate) fn call_function_with(
+        &mut self,
+        index: usize,
+        args: Vec<RuntimeValue>,
+        data: &mut dyn Any,
+    ) -> Result<ValueStack, RuntimeError> {
+        let func_type = match self.runtime.func_type(index) {
+            Some(func_type) => func_type,
+            None => return Err(RuntimeError::NotFound(format!("function {}", index))),
+        };
+        let return_length = func_type.returns().len();
+
+        log::debug!(
+            "exec {} {:?}",
+            self.module.names().display_function(index as u32),
+            func_type
+        );
+
+        Instance::validate(func_type.params(), &args)?; // argsとfunc_type.paramsの個数、型をチェックする + errorをいい感じに表示してあげたい
+
+        let mut stack = self.runtime.execute(index, &args, data)?;
+
+        // 戻り値はスタックの上からreturn_length個を、積まれた順のまま返す
+        if stack.len() < return_length {
+            return Err(RuntimeError::ExpectValueStack);
+        }
+        Ok(stack.split_off(stack.len() - return_length))
     }
 
     pub fn function_table(&self) -> FunctionTable {
         FunctionTable::from_module(&self.module)
     }
 
-    fn resolve_function_name(&self, name: impl AsRef<str>) -> Option<usize> {
-        let export_section = &self.module.export_section;
+    /// exportされたtagを返す。他のインスタンスへimportさせたりUncaughtExceptionと比較するのに使う
+    pub fn get_tag(&self, name: impl AsRef<str>) -> Result<Tag, RuntimeError> {
+        let index = self.resolve_export(name.as_ref(), ExternalKind::Tag)?;
+        match self.runtime.tags.get(index) {
+            Some(tag) => Ok(tag.clone()),
+            None => Err(RuntimeError::NotFound(format!("tag {}", index))),
+        }
+    }
+
+    /// exportされたメモリを返す。ホストからの読み書きや他のインスタンスへのimportに使う
+    pub fn get_memory(&self, name: impl AsRef<str>) -> Result<MemoryRef, RuntimeError> {
+        let index = self.resolve_export(name.as_ref(), ExternalKind::Memory)?;
+        match self.runtime.memories.get(index) {
+            Some(memory) => Ok(memory.clone()),
+            None => Err(RuntimeError::NotFound(format!("memory {}", index))),
+        }
+    }
+
+    /// exportされたメモリへのハンドル。ホストから引数のバッファを書き込んだり結果を読み出したりする
+    pub fn memory(&self, name: impl AsRef<str>) -> Result<MemoryRef, RuntimeError> {
+        self.get_memory(name)
+    }
+
+    pub fn get_global(&self, name: impl AsRef<str>) -> Result<GlobalRef, RuntimeError> {
+        // indexはimportしたglobalも含めて数える
+        let index = self.resolve_export(name.as_ref(), ExternalKind::Global)?;
+        let global_type = self.module.globals().nth(index);
+        match global_type {
+            Some(global_type) if index < self.runtime.globals.len() => {
+                Ok(GlobalRef::new(self.id, index as u32, global_type))
+            }
+            _ => Err(RuntimeError::NotFound(format!("global {}", index))),
+        }
+    }
+
+    pub fn get_table(&self, name: impl AsRef<str>) -> Result<TableRef, RuntimeError> {
+        // indexはimportしたtableも含めて数える
+        let index = self.resolve_export(name.as_ref(), ExternalKind::Table)?;
+        let table_type = self.module.tables().nth(index);
+        match table_type {
+            Some(table_type) if index < self.runtime.tables.len() => {
+                Ok(TableRef::new(self.id, index as u32, table_type))
+            }
+            _ => Err(RuntimeError::NotFound(format!("table {}", index))),
+        }
+    }
+
+    fn export(&self, name: &str) -> Result<&ExportEntry, RuntimeError> {
+        let mut exports = self.module.export_section.iter().flat_map(|s| &s.entries);
+
+        exports
+            .find(|x| x.field_str == name)
+            .ok_or_else(|| RuntimeError::NotFound(name.to_string()))
+    }
+
+    /// nameでexportされたもののindex。exportの種類がkindでなければエラーにする
+    fn resolve_export(&self, name: &str, kind: ExternalKind) -> Result<usize, RuntimeError> {
+        let entry = self.export(name)?;
+        if entry.kind != kind {
+            return Err(RuntimeError::ExportKindMismatch {
+                name: name.to_string(),
+                expected: kind,
+                actual: entry.kind,
+            });
+        }
+
+        Ok(entry.index as usize)
+    }
+
+    fn resolve_imports(&mut self, imports: &Imports) -> Result<(), RuntimeError> {
+        let entries = match self.module.import_section.as_ref() {
+            None => return Ok(()),
+            Some(section) => section.entries.clone(),
+        };
+
+        for import in entries {
+            // テーブル、mutableなグローバルのimportはまだ扱えない。
+            // 読み飛ばすとindexがずれるので、インスタンス化の時点でエラーにする
+            let value = match import.kind {
+                ImportKind::Table(_)
+                | ImportKind::Global(GlobalType {
+                    mutability: true, ..
+                }) => {
+                    return Err(RuntimeError::UnsupportedImport(
+                        import.module_str,
+                        import.field_str,
+                    ))
+                }
+                _ => match imports.get(&import.module_str, &import.field_str) {
+                    Some(value) => value.clone(),
+                    None => {
+                        return Err(RuntimeError::UnresolvedImport(
+                            import.module_str,
+                            import.field_str,
+                        ))
+                    }
+                },
+            };
+
+            match (import.kind, value) {
+                (ImportKind::Function(type_index), Extern::HostFunc(host))
+                    if self.func_type(type_index) == Some(host.func_type()) =>
+                {
+                    self.runtime.host_funcs.push((type_index, host))
+                }
+                (ImportKind::Tag(t), Extern::Tag(tag))
+                    if self.func_type(t.type_index) == Some(tag.func_type()) =>
+                {
+                    self.runtime.tags.push(tag)
+                }
+                (ImportKind::Memory(t), Extern::Memory(memory)) if memory.matches(&t) => {
+                    self.runtime.memories.push(memory)
+                }
+                (ImportKind::Global(t), Extern::Value(v)) if v.matches(&t.content_type) => {
+                    self.runtime.globals.push(v)
+                }
+                _ => {
+                    return Err(RuntimeError::Custom(format!(
+                        "incompatible import type for {}.{}",
+                        import.module_str, import.field_str
+                    )))
+                }
+            }
+        }
+
+        Ok(())
+    }
 
-        let exports = match export_section {
-            None => return None,
-            Some(e) => &e.entries,
+    fn init_tags(&mut self) {
+        let entries = match self.module.tag_section.as_ref() {
+            None => return,
+            Some(section) => section.entries.clone(),
         };
 
-        let entry = exports.iter().find(|x| x.field_str == name.as_ref());
+        for t in entries {
+            let func_type = self
+                .func_type(t.type_index)
+                .cloned()
+                .unwrap_or_else(|| FuncType::new(vec![], vec![]));
+            self.runtime.tags.push(Tag::new(func_type));
+        }
+    }
+
+    /// memory sectionからメモリを確保する
+    fn init_memories(&mut self) -> Result<(), RuntimeError> {
+        if let Some(section) = self.module.memory_section.as_ref() {
+            for memory_type in section.entries.iter() {
+                let memory = Memory::with_page_limit(*memory_type, self.config.max_memory_pages)?;
+                self.runtime.memories.push(MemoryRef::new(memory));
+            }
+        }
 
-        entry.map(|x| x.index as usize)
+        Ok(())
     }
 
-    fn init_memory(&self) -> Result<Vec<u8>, RuntimeError> {
-        let section = self.module.data_section.as_ref();
-        if section.is_none() {
-            return Ok(vec![]);
+    /// active data segmentをmemoryに書き込む。offsetの式がglobalを読むので、globalの初期化より後に呼ぶ
+    fn init_data(&mut self) -> Result<(), RuntimeError> {
+        if let Some(section) = self.module.data_section.as_ref() {
+            for segment in section.segments.iter().filter(|s| !s.passive) {
+                let offset = self.runtime.eval_const_expr(&segment.offset)?;
+                let memory = match self.runtime.memories.get(segment.index as usize) {
+                    Some(memory) => memory,
+                    None => {
+                        return Err(RuntimeError::NotFound(format!("memory {}", segment.index)))
+                    }
+                };
+                let mut memory = memory.lock();
+                // offsetの型はmemoryのアドレスの型に合わせて検証済み
+                let offset = match memory.is_64() {
+                    true => i64::from(offset) as u64,
+                    false => u64::from(u32::from(offset)),
+                };
+                memory.write(offset, &segment.data)?;
+            }
         }
-        let init_memory = section.unwrap().segments.get(0).unwrap().data.clone();
 
-        Ok(init_memory)
+        Ok(())
+    }
+
+    /// HostFuncがCallerから呼び出し元のメモリを引けるように、exportしたメモリを名前で登録する
+    fn init_memory_exports(&mut self) {
+        let entries = self.module.export_section.iter().flat_map(|s| &s.entries);
+        for entry in entries.filter(|entry| entry.kind == ExternalKind::Memory) {
+            if let Some(memory) = self.runtime.memories.get(entry.index as usize) {
+                self.runtime
+                    .memory_exports
+                    .push((entry.field_str.clone(), memory.clone()));
+            }
+        }
+    }
+
+    /// global sectionの初期値を順に評価する。前に定義されたglobalはglobal.getで参照できる
+    fn init_globals(&mut self) -> Result<(), RuntimeError> {
+        let entries = match self.module.global_section.as_ref() {
+            None => return Ok(()),
+            Some(section) => section.entries.clone(),
+        };
+
+        for entry in entries {
+            let v = self.runtime.eval_const_expr(&entry.init)?;
+            self.runtime.globals.push(v);
+        }
+
+        Ok(())
+    }
+
+    fn init_tables(&mut self) {
+        if let Some(section) = self.module.table_section.as_ref() {
+            for table_type in section.entries.iter() {
+                self.runtime.tables.push(Table::new(*table_type));
+            }
+        }
+    }
+
+    /// active segmentはtableへ書き込み、passive segmentだけtable.initのために残す
+    fn init_elements(&mut self) -> Result<(), RuntimeError> {
+        let segments = match self.module.element_section.as_ref() {
+            None => return Ok(()),
+            Some(section) => section.segments.clone(),
+        };
+
+        for segment in segments {
+            let values = match &segment.items {
+                ElementItems::Functions(indices) => indices
+                    .iter()
+                    .map(|index| RuntimeValue::FuncRef(*index))
+                    .collect(),
+                ElementItems::Expressions(exprs) => exprs
+                    .iter()
+                    .map(|expr| self.runtime.eval_const_expr(expr))
+                    .collect::<Result<Vec<_>, _>>()?,
+            };
+
+            let kept = match &segment.mode {
+                ElementMode::Active { table, offset } => {
+                    let offset = u32::from(self.runtime.eval_const_expr(offset)?);
+                    match self.runtime.tables.get_mut(*table as usize) {
+                        Some(table) => table.write(offset, &values)?,
+                        None => return Err(RuntimeError::NotFound(format!("table {}", table))),
+                    }
+                    vec![]
+                }
+                ElementMode::Passive => values,
+                ElementMode::Declarative => vec![],
+            };
+            self.runtime.elem_segments.push(kept);
+        }
+
+        Ok(())
+    }
+
+    fn func_type(&self, index: u32) -> Option<&FuncType> {
+        self.module.type_section.as_ref()?.func_type(index)
     }
 
     fn validate(func_type: &[ValueType], args: &[RuntimeValue]) -> Result<(), RuntimeError> {
         let args_types: Vec<_> = args.iter().map(RuntimeValue::to_type).collect();
 
         let expect = func_type;
-        if expect != args_types {
+        let matched =
+            expect.len() == args.len() && args.iter().zip(expect).all(|(arg, t)| arg.matches(t));
+        if !matched {
             return Err(RuntimeError::InvalidArgs(expect.to_vec(), args_types));
         }
 
         Ok(())
     }
 }
+
+fn types(module: &Module) -> Vec<SubType> {
+    match module.type_section.as_ref() {
+        None => vec![],
+        Some(section) => section.entries.clone(),
+    }
+}
+
+/// memory.initから参照されるdata segment。active segmentはインスタンス化時に使い切ったものとして扱う
+fn data_segments(module: &Module) -> Vec<Vec<u8>> {
+    match module.data_section.as_ref() {
+        None => vec![],
+        Some(section) => section
+            .segments
+            .iter()
+            .map(|s| if s.passive { s.data.clone() } else { vec![] })
+            .collect(),
+    }
+}
@@ -0,0 +1,111 @@
+use super::{Func, Imports, Instance};
+use crate::config::Config;
+use crate::module::Module;
+use crate::runtime::{RuntimeError, RuntimeValue};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Storeごとに振る番号。InstanceIdがどのStoreのものかを確かめるのに使う
+static NEXT_STORE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Storeに登録したインスタンスを指すid。登録したStoreでだけ使える
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstanceId {
+    store: u64,
+    index: usize,
+}
+
+/// ホストのデータと、それを共有するインスタンスをまとめて持つ。
+/// importしたHostFuncはCaller::data、Caller::data_mutでdataを読み書きできる
+#[derive(Debug)]
+pub struct Store<T> {
+    id: u64,
+    data: T,
+    instances: Vec<Instance>,
+    config: Config,
+}
+
+impl<T: 'static> Store<T> {
+    pub fn new(data: T) -> Self {
+        Self::with_config(data, Config::default())
+    }
+
+    /// instantiateするインスタンスはすべてconfigに従う
+    pub fn with_config(data: T, config: Config) -> Self {
+        Self {
+            id: NEXT_STORE_ID.fetch_add(1, Ordering::Relaxed),
+            data,
+            instances: Vec::new(),
+            config,
+        }
+    }
+
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+
+    pub fn into_data(self) -> T {
+        self.data
+    }
+
+    pub fn instantiate(
+        &mut self,
+        module: Module,
+        imports: Imports,
+    ) -> Result<InstanceId, RuntimeError> {
+        let instance = Instance::instantiate(module, imports, self.config.clone(), &mut self.data)?;
+        self.instances.push(instance);
+
+        Ok(InstanceId {
+            store: self.id,
+            index: self.instances.len() - 1,
+        })
+    }
+
+    /// 別のStoreのidを渡すとエラーになる
+    pub fn instance(&self, id: InstanceId) -> Result<&Instance, RuntimeError> {
+        self.check_id(id)?;
+        Ok(&self.instances[id.index])
+    }
+
+    /// 別のStoreのidを渡すとエラーになる
+    pub fn instance_mut(&mut self, id: InstanceId) -> Result<&mut Instance, RuntimeError> {
+        self.check_id(id)?;
+        Ok(&mut self.instances[id.index])
+    }
+
+    /// idのインスタンスがexportした関数を呼ぶ。呼ばれたHostFuncにはこのStoreのdataが渡る
+    pub fn invoke(
+        &mut self,
+        id: InstanceId,
+        name: impl AsRef<str>,
+        args: Vec<RuntimeValue>,
+    ) -> Result<Vec<RuntimeValue>, RuntimeError> {
+        let func = self.instance(id)?.get_func(name)?;
+        self.call(id, &func, args)
+    }
+
+    /// idのインスタンスから取り出したFuncを呼ぶ。別のインスタンスのFuncを渡すとエラーになる
+    pub fn call(
+        &mut self,
+        id: InstanceId,
+        func: &Func,
+        args: Vec<RuntimeValue>,
+    ) -> Result<Vec<RuntimeValue>, RuntimeError> {
+        self.check_id(id)?;
+        let instance = &mut self.instances[id.index];
+        instance.check_owner(func.instance(), "function")?;
+        instance.call_function_with(func.index() as usize, args, &mut self.data)
+    }
+
+    fn check_id(&self, id: InstanceId) -> Result<(), RuntimeError> {
+        if id.store != self.id || id.index >= self.instances.len() {
+            return Err(RuntimeError::ForeignHandle("instance id".to_string()));
+        }
+
+        Ok(())
+    }
+}
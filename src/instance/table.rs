@@ -0,0 +1,68 @@
+use super::Instance;
+use crate::runtime::{RuntimeError, RuntimeValue, Table};
+use crate::types::{TableType, ValueType};
+
+/// インスタンスのtableへのハンドル。GlobalRefと同じく、読み書きするたびにinstanceを渡す
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRef {
+    /// 取り出したインスタンスの番号
+    instance: u64,
+    index: u32,
+    table_type: TableType,
+}
+
+impl TableRef {
+    pub(crate) fn new(instance: u64, index: u32, table_type: TableType) -> Self {
+        Self {
+            instance,
+            index,
+            table_type,
+        }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn table_type(&self) -> &TableType {
+        &self.table_type
+    }
+
+    pub fn size(&self, instance: &Instance) -> Result<u32, RuntimeError> {
+        Ok(self.table(instance)?.size())
+    }
+
+    pub fn get(&self, instance: &Instance, index: u32) -> Result<RuntimeValue, RuntimeError> {
+        self.table(instance)?.get(index)
+    }
+
+    pub fn set(
+        &self,
+        instance: &mut Instance,
+        index: u32,
+        value: RuntimeValue,
+    ) -> Result<(), RuntimeError> {
+        instance.check_owner(self.instance, "table")?;
+        let elem_type = ValueType::Ref(self.table_type.elem_type);
+        if !value.matches(&elem_type) {
+            return Err(RuntimeError::InvalidArgs(
+                vec![elem_type],
+                vec![value.to_type()],
+            ));
+        }
+
+        match instance.runtime.tables.get_mut(self.index as usize) {
+            Some(table) => table.set(index, value),
+            None => Err(RuntimeError::NotFound(format!("table {}", self.index))),
+        }
+    }
+
+    fn table<'a>(&self, instance: &'a Instance) -> Result<&'a Table, RuntimeError> {
+        instance.check_owner(self.instance, "table")?;
+        instance
+            .runtime
+            .tables
+            .get(self.index as usize)
+            .ok_or_else(|| RuntimeError::NotFound(format!("table {}", self.index)))
+    }
+}
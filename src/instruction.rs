@@ -4,6 +4,7 @@ use crate::types::*;
 #[derive(Clone, Debug, PartialEq)]
 pub enum Instruction {
     Reserved,
+    /// immediateを持たない0xFCの命令。デコーダは作らず、実行するとRuntimeError::Unimplementedになる
     Prefix(VerUintN),
     Unreachable,
     Nop,
@@ -11,6 +12,9 @@ pub enum Instruction {
     Loop(BlockType),
     If(BlockType),
     Else,
+    Throw(VerUintN),
+    ThrowRef,
+    TryTable(BlockType, Vec<CatchClause>),
     End,
     Br(VerUintN),
     BrIf(VerUintN),
@@ -18,6 +22,8 @@ pub enum Instruction {
     Return,
     Call(VerUintN),
     CallIndirect(VerUintN, VerUintN),
+    CallRef(VerUintN),
+    ReturnCallRef(VerUintN),
     Drop,
     Select,
     GetLocal(VerUintN),
@@ -25,31 +31,53 @@ pub enum Instruction {
     TeeLocal(VerUintN),
     GetGlobal(VerUintN),
     SetGlobal(VerUintN),
-    I32Load(u32, u32),
-    I64Load(u32, u32),
-    F32Load(u32, u32),
-    F64Load(u32, u32),
-    I32Load8S(u32, u32),
-    I32Load8U(u32, u32),
-    I32Load16S(u32, u32),
-    I32Load16U(u32, u32),
-    I64Load8S(u32, u32),
-    I64Load8U(u32, u32),
-    I64Load16S(u32, u32),
-    I64Load16U(u32, u32),
-    I64Load32S(u32, u32),
-    I64Load32U(u32, u32),
-    I32Store(u32, u32),
-    I64Store(u32, u32),
-    F32Store(u32, u32),
-    F64Store(u32, u32),
-    I32Store8(u32, u32),
-    I32Store16(u32, u32),
-    I64Store8(u32, u32),
-    I64Store16(u32, u32),
-    I64Store32(u32, u32),
+    TableGet(VerUintN),
+    TableSet(VerUintN),
+    TableGrow(VerUintN),
+    TableSize(VerUintN),
+    TableFill(VerUintN),
+    /// element segment、tableの順
+    TableInit(VerUintN, VerUintN),
+    ElemDrop(VerUintN),
+    /// コピー先、コピー元のtableの順
+    TableCopy(VerUintN, VerUintN),
+    I32Load(MemArg),
+    I64Load(MemArg),
+    F32Load(MemArg),
+    F64Load(MemArg),
+    I32Load8S(MemArg),
+    I32Load8U(MemArg),
+    I32Load16S(MemArg),
+    I32Load16U(MemArg),
+    I64Load8S(MemArg),
+    I64Load8U(MemArg),
+    I64Load16S(MemArg),
+    I64Load16U(MemArg),
+    I64Load32S(MemArg),
+    I64Load32U(MemArg),
+    I32Store(MemArg),
+    I64Store(MemArg),
+    F32Store(MemArg),
+    F64Store(MemArg),
+    I32Store8(MemArg),
+    I32Store16(MemArg),
+    I64Store8(MemArg),
+    I64Store16(MemArg),
+    I64Store32(MemArg),
     CurrentMemory(VerUintN),
     GrowMemory(VerUintN),
+    MemoryInit(VerUintN, VerUintN),
+    DataDrop(VerUintN),
+    MemoryCopy(VerUintN, VerUintN),
+    MemoryFill(VerUintN),
+    AtomicNotify(MemArg),
+    AtomicWait32(MemArg),
+    AtomicWait64(MemArg),
+    AtomicFence,
+    AtomicLoad(AtomicWidth, MemArg),
+    AtomicStore(AtomicWidth, MemArg),
+    AtomicRmw(AtomicRmwOp, AtomicWidth, MemArg),
+    AtomicCmpxchg(AtomicWidth, MemArg),
     I32Const(i32),
     I64Const(i64),
     F32Const(f32),
@@ -177,6 +205,39 @@ pub enum Instruction {
     I64ReinterpretF64,
     F32ReinterpretI32,
     F64ReinterpretI64,
+    RefNull(HeapType),
+    RefIsNull,
+    RefFunc(VerUintN),
+    RefEq,
+    RefAsNonNull,
+    BrOnNull(VerUintN),
+    BrOnNonNull(VerUintN),
+    StructNew(VerUintN),
+    StructNewDefault(VerUintN),
+    StructGet(VerUintN, VerUintN),
+    StructGetS(VerUintN, VerUintN),
+    StructGetU(VerUintN, VerUintN),
+    StructSet(VerUintN, VerUintN),
+    ArrayNew(VerUintN),
+    ArrayNewDefault(VerUintN),
+    ArrayNewFixed(VerUintN, VerUintN),
+    ArrayNewData(VerUintN, VerUintN),
+    ArrayGet(VerUintN),
+    ArrayGetS(VerUintN),
+    ArrayGetU(VerUintN),
+    ArraySet(VerUintN),
+    ArrayLen,
+    ArrayFill(VerUintN),
+    ArrayCopy(VerUintN, VerUintN),
+    RefTest(RefType),
+    RefCast(RefType),
+    BrOnCast(VerUintN, RefType, RefType),
+    BrOnCastFail(VerUintN, RefType, RefType),
+    AnyConvertExtern,
+    ExternConvertAny,
+    RefI31,
+    I31GetS,
+    I31GetU,
 }
 
 impl From<Opcode> for Instruction {
@@ -186,6 +247,7 @@ impl From<Opcode> for Instruction {
             Opcode::Unreachable => Unreachable,
             Opcode::Nop => Nop,
             Opcode::Else => Else,
+            Opcode::ThrowRef => ThrowRef,
             Opcode::End => End,
             Opcode::Return => Return,
             Opcode::Drop => Drop,
@@ -313,6 +375,9 @@ impl From<Opcode> for Instruction {
             Opcode::I64ReinterpretF64 => I64ReinterpretF64,
             Opcode::F32ReinterpretI32 => F32ReinterpretI32,
             Opcode::F64ReinterpretI64 => F64ReinterpretI64,
+            Opcode::RefIsNull => RefIsNull,
+            Opcode::RefEq => RefEq,
+            Opcode::RefAsNonNull => RefAsNonNull,
             Opcode::Reserved => Reserved,
             _ => todo!("{:x?}", opcode),
         }
@@ -1,11 +1,35 @@
+mod config;
 mod decode;
+mod encode;
 mod from_le;
 mod instance;
 mod instruction;
 mod module;
 mod opcode;
 mod runtime;
+mod text;
+mod to_le;
 mod types;
+mod validate;
 
-pub use runtime::{RuntimeError, RuntimeValue};
-pub use {instance::Instance, module::Module};
+pub use config::Config;
+pub use decode::{DecodeError, DecodeLimits, Location, Payload, StreamingDecoder};
+pub use from_le::FromLe;
+pub use instance::{
+    Extern, Func, GlobalRef, Imports, InstanceId, Store, TableRef, TypedFunc, WasmParams,
+    WasmResults, WasmTy,
+};
+pub use module::{DataSegmentInfo, ExportType, ExternType, ImportType};
+pub use module::{Feature, FeaturePolicy, Producers, TargetFeature};
+pub use module::{FunctionBuilder, ModuleBuilder};
+pub use runtime::{
+    Caller, Exception, HostFunc, Memory, MemoryRef, ObjectRef, RuntimeError, RuntimeValue, Tag,
+};
+pub use text::{print_with_offsets, ParseError, Position};
+pub use to_le::ToLe;
+pub use types::{
+    BlockType, CustomSection, ExternalKind, FuncType, GlobalType, HeapType, Instruction, MemArg,
+    MemoryType, RefType, ResizableLimits, TableType, ValueType, VerUintN,
+};
+pub use validate::{validate, validate_with_config, ValidationError};
+pub use {instance::Instance, module::Module, module::NameMap, module::SectionType};
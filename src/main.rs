@@ -1,47 +1,115 @@
 use clap::Parser;
+use std::io::Read;
 use wai::*;
 
 #[derive(Parser)]
 #[clap(
     version = "0.2.0",
     author = "k-nasa <htilcs1115@gmail.com>",
-    about = "A simple wasm interpreter"
+    about = "A simple wasm interpreter",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Opts {
-    file_path: String,
+    #[clap(subcommand)]
+    command: Option<Command>,
 
-    #[clap(short, long)]
-    invoke: String,
+    /// "-"なら標準入力から読む。拡張子が.watか.wastならテキスト形式として読む
+    #[clap(required = true)]
+    file_path: Option<String>,
+
+    #[clap(short, long, required = true)]
+    invoke: Option<String>,
 
     #[clap(short, long)]
     args: Vec<RuntimeValue>,
 }
 
+#[derive(clap::Subcommand)]
+enum Command {
+    /// moduleをテキスト形式(.wat)で表示する
+    Print {
+        /// "-"なら標準入力から読む。拡張子が.watか.wastならテキスト形式として読む
+        file_path: String,
+
+        /// 関数本体の命令それぞれに、wasmバイナリ上の位置をコメントで添える
+        #[clap(long)]
+        offsets: bool,
+    },
+}
+
 fn main() -> anyhow::Result<()> {
     std::env::set_var("RUST_LOG", "debug");
     env_logger::init();
 
     let opts: Opts = Opts::parse();
 
-    let filename = opts.file_path;
-    let bytes = std::fs::read(filename)?;
+    if let Some(Command::Print { file_path, offsets }) = opts.command {
+        print!("{}", print_module(&file_path, offsets)?);
+        return Ok(());
+    }
+
+    let file_path = opts.file_path.expect("file_path is required");
+    let invoke = opts.invoke.expect("invoke is required");
+    let m = if file_path == "-" {
+        Module::from_reader(std::io::stdin().lock())?
+    } else if is_text(&file_path) {
+        read_wat(&file_path)?
+    } else {
+        Module::from_byte(std::fs::read(file_path)?)?
+    };
 
-    log::info!("start exec {:?}, args {:?}", opts.invoke, opts.args);
+    log::info!("start exec {:?}, args {:?}", invoke, opts.args);
 
-    let result = run_wasm(bytes, opts.invoke, opts.args)?;
+    let result = run_wasm(m, invoke, opts.args)?;
     log::info!("return value is {:?}", result);
 
     Ok(())
 }
 
+/// 壊れたmoduleも表示できるように、バイナリは検証せずに読む
+fn print_module(file_path: &str, offsets: bool) -> anyhow::Result<String> {
+    if is_text(file_path) {
+        if offsets {
+            anyhow::bail!("--offsets needs a binary module");
+        }
+        return Ok(read_wat(file_path)?.to_wat());
+    }
+
+    let bytes = read_bytes(file_path)?;
+    if offsets {
+        Ok(print_with_offsets(&bytes)?)
+    } else {
+        Ok(Module::from_byte_unchecked(bytes)?.to_wat())
+    }
+}
+
+fn is_text(file_path: &str) -> bool {
+    [".wat", ".wast"].iter().any(|ext| file_path.ends_with(ext))
+}
+
+fn read_wat(file_path: &str) -> anyhow::Result<Module> {
+    let src = std::fs::read_to_string(file_path)?;
+    Module::from_wat(&src).map_err(|e| anyhow::anyhow!("{}: {}", file_path, e))
+}
+
+fn read_bytes(file_path: &str) -> anyhow::Result<Vec<u8>> {
+    if file_path == "-" {
+        let mut bytes = vec![];
+        std::io::stdin().lock().read_to_end(&mut bytes)?;
+        Ok(bytes)
+    } else {
+        Ok(std::fs::read(file_path)?)
+    }
+}
+
 fn run_wasm(
-    wasm_bytes: Vec<u8>,
+    m: Module,
     entory_point: String,
     args: Vec<RuntimeValue>,
 ) -> anyhow::Result<Vec<RuntimeValue>> {
-    let m = Module::from_byte(wasm_bytes)?;
-    log::debug!("module: {:#?}", m);
-    let instance = Instance::new(m);
+    log::debug!("module:\n{}", m.to_wat());
+    let mut instance = Instance::new(m)?;
 
     let values = instance.invoke(&entory_point, args)?;
     Ok(values)
@@ -0,0 +1,308 @@
+use super::Module;
+use crate::types::*;
+use crate::validate::{self, ValidationError};
+
+/// Rustのコードからmoduleを組み立てる。
+/// 追加したものの順にindexを割り振り、追加したときにそのindexを返す。
+/// 関数、table、memory、globalのimportは、同じ種類のものを定義する前に追加する
+#[derive(Debug, Default)]
+pub struct ModuleBuilder {
+    types: Vec<FuncType>,
+    imports: Vec<ImportEntry>,
+    funcs: Vec<u32>,
+    bodies: Vec<FunctionBody>,
+    imported_funcs: u32,
+    tables: Vec<TableType>,
+    imported_tables: u32,
+    memories: Vec<MemoryType>,
+    imported_memories: u32,
+    globals: Vec<GlobalEntry>,
+    imported_globals: u32,
+    exports: Vec<ExportEntry>,
+    start: Option<u32>,
+    data: Vec<DataSegment>,
+}
+
+impl ModuleBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 関数型を追加してtype indexを返す。同じ型がすでにあればそのindexを返す
+    pub fn add_type(&mut self, func_type: FuncType) -> u32 {
+        if let Some(index) = self.types.iter().position(|t| *t == func_type) {
+            return index as u32;
+        }
+
+        self.types.push(func_type);
+        self.types.len() as u32 - 1
+    }
+
+    /// 関数をimportしてfunction indexを返す
+    pub fn import_function(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        func_type: FuncType,
+    ) -> u32 {
+        assert!(
+            self.bodies.is_empty(),
+            "functions must be imported before defining functions"
+        );
+
+        let type_index = self.add_type(func_type);
+        self.import(module, name, ImportKind::Function(type_index));
+        self.imported_funcs += 1;
+        self.imported_funcs - 1
+    }
+
+    /// tableをimportしてtable indexを返す
+    pub fn import_table(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        table_type: TableType,
+    ) -> u32 {
+        assert!(
+            self.tables.is_empty(),
+            "tables must be imported before defining tables"
+        );
+
+        self.import(module, name, ImportKind::Table(table_type));
+        self.imported_tables += 1;
+        self.imported_tables - 1
+    }
+
+    /// memoryをimportしてmemory indexを返す
+    pub fn import_memory(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        memory_type: MemoryType,
+    ) -> u32 {
+        assert!(
+            self.memories.is_empty(),
+            "memories must be imported before defining memories"
+        );
+
+        self.import(module, name, ImportKind::Memory(memory_type));
+        self.imported_memories += 1;
+        self.imported_memories - 1
+    }
+
+    /// globalをimportしてglobal indexを返す
+    pub fn import_global(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        global_type: GlobalType,
+    ) -> u32 {
+        assert!(
+            self.globals.is_empty(),
+            "globals must be imported before defining globals"
+        );
+
+        self.import(module, name, ImportKind::Global(global_type));
+        self.imported_globals += 1;
+        self.imported_globals - 1
+    }
+
+    fn import(&mut self, module: impl Into<String>, name: impl Into<String>, kind: ImportKind) {
+        self.imports.push(ImportEntry {
+            module_str: module.into(),
+            field_str: name.into(),
+            kind,
+        });
+    }
+
+    /// 関数を定義してfunction indexを返す。indexはimportした関数の後ろから数える
+    pub fn add_function(&mut self, function: FunctionBuilder) -> u32 {
+        let type_index = self.add_type(function.func_type);
+        self.funcs.push(type_index);
+
+        // 同じ型が続くlocalはまとめる
+        let mut locales: Vec<LocalEntry> = vec![];
+        for t in function.locals {
+            match locales.last_mut() {
+                Some(entry) if entry.value_type == t => entry.count += 1,
+                _ => locales.push(LocalEntry {
+                    count: 1,
+                    value_type: t,
+                }),
+            }
+        }
+        self.bodies.push(FunctionBody {
+            locales,
+            code: function.code,
+        });
+
+        self.imported_funcs + self.bodies.len() as u32 - 1
+    }
+
+    pub fn add_table(&mut self, table_type: TableType) -> u32 {
+        self.tables.push(table_type);
+        self.imported_tables + self.tables.len() as u32 - 1
+    }
+
+    pub fn add_memory(&mut self, memory_type: MemoryType) -> u32 {
+        self.memories.push(memory_type);
+        self.imported_memories + self.memories.len() as u32 - 1
+    }
+
+    /// initは初期値の定数式。終端のENDは含めない
+    pub fn add_global(&mut self, global_type: GlobalType, init: Vec<Instruction>) -> u32 {
+        self.globals.push(GlobalEntry { global_type, init });
+        self.imported_globals + self.globals.len() as u32 - 1
+    }
+
+    pub fn export_function(&mut self, name: impl Into<String>, index: u32) -> &mut Self {
+        self.export(name, ExternalKind::Function, index)
+    }
+
+    pub fn export_table(&mut self, name: impl Into<String>, index: u32) -> &mut Self {
+        self.export(name, ExternalKind::Table, index)
+    }
+
+    pub fn export_memory(&mut self, name: impl Into<String>, index: u32) -> &mut Self {
+        self.export(name, ExternalKind::Memory, index)
+    }
+
+    pub fn export_global(&mut self, name: impl Into<String>, index: u32) -> &mut Self {
+        self.export(name, ExternalKind::Global, index)
+    }
+
+    fn export(&mut self, name: impl Into<String>, kind: ExternalKind, index: u32) -> &mut Self {
+        self.exports.push(ExportEntry {
+            field_str: name.into(),
+            kind,
+            index,
+        });
+        self
+    }
+
+    /// インスタンス化したときに呼ぶ関数
+    pub fn start(&mut self, index: u32) -> &mut Self {
+        self.start = Some(index);
+        self
+    }
+
+    /// memoryのoffsetに書き込むactive data segmentを追加してdata indexを返す
+    pub fn add_data(&mut self, memory: u32, offset: u64, data: impl Into<Vec<u8>>) -> u32 {
+        let offset = match self.memory64(memory) {
+            true => Instruction::I64Const(offset as i64),
+            false => Instruction::I32Const(offset as i32),
+        };
+        self.push_data(DataSegment {
+            index: memory,
+            offset: vec![offset],
+            data: data.into(),
+            passive: false,
+        })
+    }
+
+    /// memory.initで使うpassive data segmentを追加してdata indexを返す
+    pub fn add_passive_data(&mut self, data: impl Into<Vec<u8>>) -> u32 {
+        self.push_data(DataSegment {
+            index: 0,
+            offset: vec![],
+            data: data.into(),
+            passive: true,
+        })
+    }
+
+    /// memory indexのmemoryがmemory64かどうか。まだ追加していないmemoryならfalse
+    fn memory64(&self, index: u32) -> bool {
+        let imported = self.imports.iter().filter_map(|entry| match &entry.kind {
+            ImportKind::Memory(memory_type) => Some(memory_type),
+            _ => None,
+        });
+        let mut memories = imported.chain(&self.memories);
+        memories
+            .nth(index as usize)
+            .is_some_and(|memory_type| memory_type.memory64)
+    }
+
+    fn push_data(&mut self, segment: DataSegment) -> u32 {
+        self.data.push(segment);
+        self.data.len() as u32 - 1
+    }
+
+    /// moduleを組み立ててvalidate::validateで検証する
+    pub fn build(self) -> Result<Module, ValidationError> {
+        // memory.initやdata.dropで使えるように、data segmentがあればdata count sectionも作る
+        let data_count = match self.data.len() {
+            0 => None,
+            n => Some(n as u32),
+        };
+
+        let m = Module {
+            version: 1,
+            type_section: non_empty(self.types).map(|types| TypeSection {
+                entries: types
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, t)| SubType::new(CompositeType::Func(t), i as u32))
+                    .collect(),
+            }),
+            import_section: non_empty(self.imports).map(|entries| ImportSection { entries }),
+            function_section: non_empty(self.funcs).map(|types| FunctionSection { types }),
+            table_section: non_empty(self.tables).map(|entries| TableSection { entries }),
+            memory_section: non_empty(self.memories).map(|entries| MemorySection { entries }),
+            global_section: non_empty(self.globals).map(|entries| GlobalSection { entries }),
+            export_section: non_empty(self.exports).map(|entries| ExportSection { entries }),
+            start_section: self.start,
+            data_count_section: data_count,
+            code_section: non_empty(self.bodies).map(|bodies| CodeSection { bodies }),
+            data_section: non_empty(self.data).map(|segments| DataSection { segments }),
+            ..Module::default()
+        };
+
+        validate::validate(&m)?;
+
+        Ok(m)
+    }
+}
+
+/// 空のsectionは作らない
+fn non_empty<T>(items: Vec<T>) -> Option<Vec<T>> {
+    if items.is_empty() {
+        None
+    } else {
+        Some(items)
+    }
+}
+
+/// ModuleBuilder::add_functionに渡す関数本体
+#[derive(Debug, Clone)]
+pub struct FunctionBuilder {
+    func_type: FuncType,
+    locals: Vec<ValueType>,
+    code: Vec<Instruction>,
+}
+
+impl FunctionBuilder {
+    pub fn new(params: Vec<ValueType>, returns: Vec<ValueType>) -> Self {
+        Self {
+            func_type: FuncType::new(params, returns),
+            locals: vec![],
+            code: vec![],
+        }
+    }
+
+    /// localを追加してlocal indexを返す。indexは引数の後ろから数える
+    pub fn local(&mut self, t: ValueType) -> u32 {
+        self.locals.push(t);
+        (self.func_type.params.len() + self.locals.len()) as u32 - 1
+    }
+
+    /// 命令を追加する。関数の終わりのENDは含めない
+    pub fn push(&mut self, instruction: Instruction) -> &mut Self {
+        self.code.push(instruction);
+        self
+    }
+
+    pub fn extend(&mut self, instructions: impl IntoIterator<Item = Instruction>) -> &mut Self {
+        self.code.extend(instructions);
+        self
+    }
+}
@@ -0,0 +1,68 @@
+use crate::types::{FuncType, GlobalType, MemoryType, TableType};
+
+/// importやexportの型。関数とtagはtype indexではなく型そのものを持つ
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExternType {
+    Func(FuncType),
+    Table(TableType),
+    Memory(MemoryType),
+    Global(GlobalType),
+    /// tagが運ぶ値の型。resultsは常に空
+    Tag(FuncType),
+}
+
+/// Module::importsが返すimportの1項目
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportType<'a> {
+    pub(crate) module: &'a str,
+    pub(crate) name: &'a str,
+    pub(crate) ty: ExternType,
+}
+
+impl<'a> ImportType<'a> {
+    pub fn module(&self) -> &'a str {
+        self.module
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn ty(&self) -> &ExternType {
+        &self.ty
+    }
+}
+
+/// Module::exportsが返すexportの1項目。indexはimportしたものも含めたindex空間で数える
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportType<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) index: u32,
+    pub(crate) ty: ExternType,
+}
+
+impl<'a> ExportType<'a> {
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn ty(&self) -> &ExternType {
+        &self.ty
+    }
+}
+
+/// data segmentの中身を除いた概要
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DataSegmentInfo {
+    /// 書き込み先のmemory index
+    pub memory: u32,
+    /// active segmentの書き込み先アドレス。passive segmentや、global.getなどを使っていて
+    /// インスタンス化するまで決まらないときはNone
+    pub offset: Option<u64>,
+    /// バイト数
+    pub len: usize,
+}
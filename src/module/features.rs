@@ -0,0 +1,317 @@
+use super::Module;
+use crate::types::*;
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+
+/// wasmのproposal。名前はtarget_features sectionで使われるもの
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Feature {
+    MutableGlobals,
+    SignExt,
+    NontrappingFptoint,
+    MultiValue,
+    BulkMemory,
+    BulkMemoryOpt,
+    CallIndirectOverlong,
+    ReferenceTypes,
+    Simd128,
+    RelaxedSimd,
+    TailCall,
+    ExtendedConst,
+    Atomics,
+    ExceptionHandling,
+    Memory64,
+    MultiMemory,
+    FunctionReferences,
+    Gc,
+    /// waiが知らない名前
+    Other(String),
+}
+
+/// Other以外のすべてのFeature
+pub(crate) const FEATURES: [Feature; 18] = [
+    Feature::MutableGlobals,
+    Feature::SignExt,
+    Feature::NontrappingFptoint,
+    Feature::MultiValue,
+    Feature::BulkMemory,
+    Feature::BulkMemoryOpt,
+    Feature::CallIndirectOverlong,
+    Feature::ReferenceTypes,
+    Feature::Simd128,
+    Feature::RelaxedSimd,
+    Feature::TailCall,
+    Feature::ExtendedConst,
+    Feature::Atomics,
+    Feature::ExceptionHandling,
+    Feature::Memory64,
+    Feature::MultiMemory,
+    Feature::FunctionReferences,
+    Feature::Gc,
+];
+
+impl Feature {
+    pub fn name(&self) -> &str {
+        use Feature::*;
+
+        match self {
+            MutableGlobals => "mutable-globals",
+            SignExt => "sign-ext",
+            NontrappingFptoint => "nontrapping-fptoint",
+            MultiValue => "multivalue",
+            BulkMemory => "bulk-memory",
+            BulkMemoryOpt => "bulk-memory-opt",
+            CallIndirectOverlong => "call-indirect-overlong",
+            ReferenceTypes => "reference-types",
+            Simd128 => "simd128",
+            RelaxedSimd => "relaxed-simd",
+            TailCall => "tail-call",
+            ExtendedConst => "extended-const",
+            Atomics => "atomics",
+            ExceptionHandling => "exception-handling",
+            Memory64 => "memory64",
+            MultiMemory => "multimemory",
+            FunctionReferences => "function-references",
+            Gc => "gc",
+            Other(name) => name,
+        }
+    }
+
+    /// Feature::nameの逆。知らない名前はOtherになる
+    pub fn from_name(name: &str) -> Self {
+        FEATURES
+            .into_iter()
+            .find(|feature| feature.name() == name)
+            .unwrap_or_else(|| Feature::Other(name.to_string()))
+    }
+
+    /// このwaiでデコードと実行ができるか。知らない名前のものは対応していないものとして扱う。
+    /// mutable-globalsはmutableなglobalをimportできないので対応していない
+    pub fn is_supported(&self) -> bool {
+        use Feature::*;
+
+        !matches!(
+            self,
+            MutableGlobals
+                | SignExt
+                | NontrappingFptoint
+                | Simd128
+                | RelaxedSimd
+                | TailCall
+                | Other(_)
+        )
+    }
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// target_features sectionの1項目
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetFeature {
+    pub policy: FeaturePolicy,
+    pub feature: Feature,
+}
+
+/// target_features sectionの項目の先頭につく記号
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeaturePolicy {
+    /// +。このmoduleが使っている
+    Used,
+    /// =。リンクするmoduleがすべて使っていなければならない
+    Required,
+    /// -。このmoduleは使っていない。リンクするmoduleも使ってはいけない
+    Disallowed,
+}
+
+impl TargetFeature {
+    /// moduleがこのfeatureを使うか
+    pub fn is_used(&self) -> bool {
+        self.policy != FeaturePolicy::Disallowed
+    }
+}
+
+/// "producers" custom sectionの中身。フィールド名(language、processed-by、sdk)ごとに、名前とバージョンの組を並べる
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Producers {
+    pub(crate) fields: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl Producers {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// フィールドnameに並んだ名前とバージョンの組。フィールドが無ければ空
+    pub fn field(&self, name: &str) -> &[(String, String)] {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map_or(&[], |(_, values)| values.as_slice())
+    }
+}
+
+/// processed-by: rustc 1.70.0, clang 16; language: Rustのように書く
+impl Display for Producers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (field, values)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}:", field)?;
+            for (j, (name, version)) in values.iter().enumerate() {
+                let separator = if j > 0 { "," } else { "" };
+                match version.is_empty() {
+                    true => write!(f, "{} {}", separator, name)?,
+                    false => write!(f, "{} {} {}", separator, name, version)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// moduleの中身から、どのproposalを使っているかを調べる。
+/// 対応していないproposalの命令はデコードの時点でエラーになるので、ここで見つかるのは対応しているものだけ
+pub(crate) fn scan(m: &Module) -> BTreeSet<Feature> {
+    let mut features = BTreeSet::new();
+
+    let imports: Vec<&ImportKind> = m
+        .import_section
+        .iter()
+        .flat_map(|s| &s.entries)
+        .map(|entry| &entry.kind)
+        .collect();
+
+    let imported_memories = imports.iter().filter_map(|kind| match kind {
+        ImportKind::Memory(memory) => Some(memory),
+        _ => None,
+    });
+    let memories: Vec<&MemoryType> = imported_memories
+        .chain(m.memory_section.iter().flat_map(|s| &s.entries))
+        .collect();
+    if memories.len() > 1 {
+        features.insert(Feature::MultiMemory);
+    }
+    for memory in memories {
+        if memory.memory64 {
+            features.insert(Feature::Memory64);
+        }
+        if memory.shared {
+            features.insert(Feature::Atomics);
+        }
+    }
+
+    let imported_tables = imports
+        .iter()
+        .filter(|kind| matches!(kind, ImportKind::Table(_)));
+    let tables = m.table_section.iter().flat_map(|s| &s.entries);
+    if imported_tables.count() + tables.count() > 1 {
+        features.insert(Feature::ReferenceTypes);
+    }
+
+    for kind in &imports {
+        match kind {
+            ImportKind::Global(global) if global.mutability => {
+                features.insert(Feature::MutableGlobals);
+            }
+            ImportKind::Tag(_) => {
+                features.insert(Feature::ExceptionHandling);
+            }
+            _ => {}
+        }
+    }
+    if m.tag_section.is_some() {
+        features.insert(Feature::ExceptionHandling);
+    }
+
+    let types = m.type_section.iter().flat_map(|s| &s.entries);
+    for t in types {
+        match &t.composite {
+            CompositeType::Func(func_type) if func_type.returns.len() > 1 => {
+                features.insert(Feature::MultiValue);
+            }
+            CompositeType::Func(_) => {}
+            _ => {
+                features.insert(Feature::Gc);
+            }
+        }
+    }
+
+    let mut data = m.data_section.iter().flat_map(|s| &s.segments);
+    if m.data_count_section.is_some() || data.any(|segment| segment.passive) {
+        features.insert(Feature::BulkMemory);
+    }
+
+    let globals = m.global_section.iter().flat_map(|s| &s.entries);
+    let elems = m.element_section.iter().flat_map(|s| &s.segments);
+    let mut const_exprs: Vec<&[Instruction]> = globals.map(|g| g.init.as_slice()).collect();
+    let data = m.data_section.iter().flat_map(|s| &s.segments);
+    const_exprs.extend(data.map(|segment| segment.offset.as_slice()));
+    for segment in elems {
+        if let ElementMode::Active { offset, .. } = &segment.mode {
+            const_exprs.push(offset);
+        }
+        if let ElementItems::Expressions(exprs) = &segment.items {
+            const_exprs.extend(exprs.iter().map(Vec::as_slice));
+        }
+    }
+    if const_exprs.iter().any(|expr| expr.len() > 1) {
+        features.insert(Feature::ExtendedConst);
+    }
+
+    let bodies = m.code_section.iter().flat_map(|s| &s.bodies);
+    let code = bodies.flat_map(|body| &body.code);
+    for instruction in const_exprs.into_iter().flatten().chain(code) {
+        if let Some(feature) = instruction_feature(instruction) {
+            features.insert(feature);
+        }
+        // 型をtype indexで指すblockは、try_tableでもmulti valueを使っている
+        if matches!(
+            instruction,
+            Instruction::Block(BlockType::TypeIndex(_))
+                | Instruction::Loop(BlockType::TypeIndex(_))
+                | Instruction::If(BlockType::TypeIndex(_))
+                | Instruction::TryTable(BlockType::TypeIndex(_), _)
+        ) {
+            features.insert(Feature::MultiValue);
+        }
+    }
+
+    features
+}
+
+/// MVPに無い命令なら、その命令を加えたproposal
+fn instruction_feature(instruction: &Instruction) -> Option<Feature> {
+    use Instruction::*;
+
+    let feature = match instruction {
+        MemoryInit(..) | DataDrop(_) | MemoryCopy(..) | MemoryFill(_) | TableInit(..)
+        | ElemDrop(_) | TableCopy(..) => Feature::BulkMemory,
+        TableGet(_) | TableSet(_) | TableSize(_) | TableGrow(_) | TableFill(_) | RefNull(_)
+        | RefIsNull | RefFunc(_) => Feature::ReferenceTypes,
+        CallIndirect(_, table) if u32::from(*table) != 0 => Feature::ReferenceTypes,
+        AtomicNotify(_) | AtomicWait32(_) | AtomicWait64(_) | AtomicFence | AtomicLoad(..)
+        | AtomicStore(..) | AtomicRmw(..) | AtomicCmpxchg(..) => Feature::Atomics,
+        TryTable(..) | Throw(_) | ThrowRef => Feature::ExceptionHandling,
+        CallRef(_) | ReturnCallRef(_) | RefAsNonNull | BrOnNull(_) | BrOnNonNull(_) => {
+            Feature::FunctionReferences
+        }
+        RefEq | StructNew(_) | StructNewDefault(_) | StructGet(..) | StructGetS(..)
+        | StructGetU(..) | StructSet(..) | ArrayNew(_) | ArrayNewDefault(_) | ArrayNewFixed(..)
+        | ArrayNewData(..) | ArrayGet(_) | ArrayGetS(_) | ArrayGetU(_) | ArraySet(_) | ArrayLen
+        | ArrayFill(_) | ArrayCopy(..) | RefTest(_) | RefCast(_) | BrOnCast(..)
+        | BrOnCastFail(..) => Feature::Gc,
+        _ => return None,
+    };
+
+    Some(feature)
+}
@@ -1,50 +1,386 @@
+mod builder;
+mod extern_type;
+mod features;
+mod names;
+
+pub use builder::{FunctionBuilder, ModuleBuilder};
+pub use extern_type::{DataSegmentInfo, ExportType, ExternType, ImportType};
+pub(crate) use features::FEATURES;
+pub use features::{Feature, FeaturePolicy, Producers, TargetFeature};
+pub(crate) use names::display_function;
+pub use names::NameMap;
+
+use crate::config::Config;
 use crate::decode;
+use crate::encode;
+use crate::text;
 use crate::types::*;
+use crate::validate;
+use std::collections::BTreeSet;
 
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct Module {
     pub(crate) version: u32,
-    pub(crate) custom_section: Option<()>,
+    pub(crate) custom_sections: Vec<CustomSection>,
     pub(crate) type_section: Option<TypeSection>,
-    pub(crate) import_section: Option<()>,
+    pub(crate) import_section: Option<ImportSection>,
     pub(crate) function_section: Option<FunctionSection>,
-    pub(crate) table_section: Option<()>,
-    pub(crate) memory_section: Option<()>,
-    pub(crate) global_section: Option<()>,
+    pub(crate) table_section: Option<TableSection>,
+    pub(crate) memory_section: Option<MemorySection>,
+    pub(crate) tag_section: Option<TagSection>,
+    pub(crate) global_section: Option<GlobalSection>,
     pub(crate) export_section: Option<ExportSection>,
-    pub(crate) element_section: Option<()>,
-    pub(crate) start_section: Option<()>,
+    pub(crate) element_section: Option<ElementSection>,
+    pub(crate) start_section: Option<u32>,
+    pub(crate) data_count_section: Option<u32>,
     pub(crate) code_section: Option<CodeSection>,
     pub(crate) data_section: Option<DataSection>,
+    /// "name" custom sectionを読んだもの。custom sectionそのものもcustom_sectionsに残っている
+    pub(crate) names: NameMap,
 }
 
 impl Module {
+    /// デコードしたあとvalidate::validateで検証する
     pub fn from_byte(byte: impl AsRef<[u8]>) -> Result<Self, decode::DecodeError> {
+        let m = Self::from_byte_unchecked(byte)?;
+        validate::validate(&m)?;
+
+        Ok(m)
+    }
+
+    /// 検証をせずにデコードだけする
+    pub fn from_byte_unchecked(byte: impl AsRef<[u8]>) -> Result<Self, decode::DecodeError> {
         decode::decode(byte.as_ref())
     }
 
+    /// 信頼できないmoduleを読むときに、デフォルトとは別の上限でデコードしてから検証する
+    pub fn from_byte_with_limits(
+        byte: impl AsRef<[u8]>,
+        limits: &decode::DecodeLimits,
+    ) -> Result<Self, decode::DecodeError> {
+        let config = Config {
+            decode_limits: *limits,
+            ..Config::default()
+        };
+        Self::from_byte_with_config(byte, &config)
+    }
+
+    /// configで有効にしていないproposalを使うmoduleはデコードの時点でエラーにする
+    pub fn from_byte_with_config(
+        byte: impl AsRef<[u8]>,
+        config: &Config,
+    ) -> Result<Self, decode::DecodeError> {
+        let m = decode::decode_with_config(byte.as_ref(), config)?;
+        validate::validate_with_config(&m, config)?;
+
+        Ok(m)
+    }
+
+    /// readerから少しずつ読みながらデコードする。関数本体は届いたものから検証する
+    pub fn from_reader(reader: impl std::io::Read) -> Result<Self, decode::DecodeError> {
+        decode::StreamingDecoder::new().read_from(reader, |_| {})
+    }
+
+    /// テキスト形式(.wat)のmoduleを読んでからvalidate::validateで検証する
+    pub fn from_wat(src: &str) -> Result<Self, text::ParseError> {
+        let m = text::parse(src)?;
+        validate::validate(&m)?;
+
+        Ok(m)
+    }
+
+    /// from_watと同じく読んでから、configで有効にしていないproposalを使っていないかもあわせて検証する
+    pub fn from_wat_with_config(src: &str, config: &Config) -> Result<Self, text::ParseError> {
+        let m = text::parse(src)?;
+        validate::validate_with_config(&m, config)?;
+
+        Ok(m)
+    }
+
+    /// テキスト形式(.wat)で書き出す。Module::from_watで読み直すと、name sectionと
+    /// custom section以外は同じmoduleになる。namesは$idとして書くが、読み直しても
+    /// namesには戻らない。custom sectionはテキスト形式では書けないので、名前と大きさだけをコメントで残す
+    pub fn to_wat(&self) -> String {
+        text::print(self)
+    }
+
+    /// name sectionにあった関数やローカル変数などの名前。name sectionが無ければ空
+    pub fn names(&self) -> &NameMap {
+        &self.names
+    }
+
+    /// custom sectionをバイナリ上の順番で返す。中身は解釈していないバイト列のまま
+    pub fn custom_sections(&self) -> impl Iterator<Item = &CustomSection> {
+        self.custom_sections.iter()
+    }
+
+    /// 名前がnameの最初のcustom sectionの中身
+    pub fn custom_section(&self, name: &str) -> Option<&[u8]> {
+        self.custom_sections
+            .iter()
+            .find(|c| c.name == name)
+            .map(|c| c.data.as_slice())
+    }
+
+    /// custom sectionをmoduleの末尾に加える。"name"ならnames()も読み直す
+    pub fn add_custom_section(&mut self, name: impl Into<String>, data: impl Into<Vec<u8>>) {
+        let custom = CustomSection {
+            name: name.into(),
+            data: data.into(),
+            after: self.last_section(),
+        };
+        self.insert_custom_section(custom);
+    }
+
+    /// custom sectionを、afterのsectionの直後にあるcustom sectionの後ろに入れる。
+    /// afterのsectionがmoduleに無くても、エンコードするとそのsectionがあるはずの位置に書かれる
+    pub fn insert_custom_section(&mut self, custom: CustomSection) {
+        let order = |after: Option<SectionType>| after.and_then(|t| t.order()).unwrap_or(0);
+        let position = self
+            .custom_sections
+            .iter()
+            .position(|c| order(c.after) > order(custom.after))
+            .unwrap_or(self.custom_sections.len());
+
+        let is_name = custom.name == "name";
+        self.custom_sections.insert(position, custom);
+        if is_name {
+            self.refresh_names();
+        }
+    }
+
+    /// 名前がnameのcustom sectionをすべて取り除いて、元の順番で返す。"name"ならnames()は空になる
+    pub fn remove_custom_sections(&mut self, name: &str) -> Vec<CustomSection> {
+        let (removed, kept) = std::mem::take(&mut self.custom_sections)
+            .into_iter()
+            .partition(|c| c.name == name);
+        self.custom_sections = kept;
+        if name == "name" {
+            self.refresh_names();
+        }
+
+        removed
+    }
+
+    /// "producers" custom sectionに書かれた、moduleを作ったツールチェーン。sectionが無ければNone
+    pub fn producers(&self) -> Option<Producers> {
+        self.custom_section("producers")
+            .map(decode::decode_producers)
+    }
+
+    /// "target_features" custom sectionに書かれたproposalの一覧
+    pub fn target_features(&self) -> Vec<TargetFeature> {
+        self.custom_section("target_features")
+            .map_or_else(Vec::new, decode::decode_target_features)
+    }
+
+    /// moduleが使うproposal。target_features sectionで宣言されたものと、命令や型から分かるものをあわせる
+    pub fn used_features(&self) -> BTreeSet<Feature> {
+        let declared = self
+            .target_features()
+            .into_iter()
+            .filter(TargetFeature::is_used);
+        let mut features = features::scan(self);
+        features.extend(declared.map(|t| t.feature));
+
+        features
+    }
+
+    /// importをmoduleに書かれた順に返す
+    pub fn imports(&self) -> impl Iterator<Item = ImportType<'_>> {
+        self.import_entries().filter_map(|entry| {
+            let ty = match &entry.kind {
+                ImportKind::Function(type_index) => ExternType::Func(self.type_at(*type_index)?),
+                ImportKind::Table(table_type) => ExternType::Table(*table_type),
+                ImportKind::Memory(memory_type) => ExternType::Memory(*memory_type),
+                ImportKind::Global(global_type) => ExternType::Global(*global_type),
+                ImportKind::Tag(tag_type) => ExternType::Tag(self.type_at(tag_type.type_index)?),
+            };
+
+            Some(ImportType {
+                module: &entry.module_str,
+                name: &entry.field_str,
+                ty,
+            })
+        })
+    }
+
+    /// exportを型とあわせて返す。indexが範囲外のもの(検証していないmodule)は含めない
+    pub fn exports(&self) -> impl Iterator<Item = ExportType<'_>> {
+        let entries = self.export_section.iter().flat_map(|s| &s.entries);
+        entries.filter_map(|entry| {
+            let index = entry.index;
+            let ty = match entry.kind {
+                ExternalKind::Function => ExternType::Func(self.func_type(index)?.clone()),
+                ExternalKind::Table => ExternType::Table(self.tables().nth(index as usize)?),
+                ExternalKind::Memory => ExternType::Memory(self.memories().nth(index as usize)?),
+                ExternalKind::Global => ExternType::Global(self.globals().nth(index as usize)?),
+                ExternalKind::Tag => ExternType::Tag(self.tag_type(index)?),
+                ExternalKind::Unknown => return None,
+            };
+
+            Some(ExportType {
+                name: &entry.field_str,
+                index,
+                ty,
+            })
+        })
+    }
+
+    /// importした関数も含めた関数indexの型
+    pub fn func_type(&self, index: u32) -> Option<&FuncType> {
+        let imported = self.import_entries().filter_map(|entry| match entry.kind {
+            ImportKind::Function(type_index) => Some(type_index),
+            _ => None,
+        });
+        let defined = self.function_section.iter().flat_map(|s| &s.types).copied();
+        let type_index = imported.chain(defined).nth(index as usize)?;
+
+        self.type_section.as_ref()?.func_type(type_index)
+    }
+
+    /// importしたものも含めたmemoryの型。importしたものが先に並ぶ
+    pub fn memories(&self) -> impl Iterator<Item = MemoryType> + '_ {
+        let imported = self.import_entries().filter_map(|entry| match entry.kind {
+            ImportKind::Memory(memory_type) => Some(memory_type),
+            _ => None,
+        });
+        let defined = self.memory_section.iter().flat_map(|s| &s.entries).copied();
+
+        imported.chain(defined)
+    }
+
+    /// importしたものも含めたtableの型
+    pub fn tables(&self) -> impl Iterator<Item = TableType> + '_ {
+        let imported = self.import_entries().filter_map(|entry| match entry.kind {
+            ImportKind::Table(table_type) => Some(table_type),
+            _ => None,
+        });
+        let defined = self.table_section.iter().flat_map(|s| &s.entries).copied();
+
+        imported.chain(defined)
+    }
+
+    /// importしたものも含めたglobalの型
+    pub fn globals(&self) -> impl Iterator<Item = GlobalType> + '_ {
+        let imported = self.import_entries().filter_map(|entry| match entry.kind {
+            ImportKind::Global(global_type) => Some(global_type),
+            _ => None,
+        });
+        let defined = self.global_section.iter().flat_map(|s| &s.entries);
+
+        imported.chain(defined.map(|entry| entry.global_type))
+    }
+
+    /// data segmentの書き込み先と大きさ。中身はコピーしない
+    pub fn data_segments(&self) -> impl Iterator<Item = DataSegmentInfo> + '_ {
+        let segments = self.data_section.iter().flat_map(|s| &s.segments);
+        segments.map(|segment| DataSegmentInfo {
+            memory: segment.index,
+            offset: match segment.offset.as_slice() {
+                [Instruction::I32Const(v)] if !segment.passive => Some(u64::from(*v as u32)),
+                [Instruction::I64Const(v)] if !segment.passive => Some(*v as u64),
+                _ => None,
+            },
+            len: segment.data.len(),
+        })
+    }
+
+    fn import_entries(&self) -> impl Iterator<Item = &ImportEntry> {
+        self.import_section.iter().flat_map(|s| &s.entries)
+    }
+
+    fn type_at(&self, type_index: u32) -> Option<FuncType> {
+        self.type_section.as_ref()?.func_type(type_index).cloned()
+    }
+
+    /// importしたものも含めたtag indexの型
+    fn tag_type(&self, index: u32) -> Option<FuncType> {
+        let imported = self.import_entries().filter_map(|entry| match &entry.kind {
+            ImportKind::Tag(tag_type) => Some(tag_type.type_index),
+            _ => None,
+        });
+        let defined = self.tag_section.iter().flat_map(|s| &s.entries);
+        let type_index = imported
+            .chain(defined.map(|t| t.type_index))
+            .nth(index as usize)?;
+
+        self.type_at(type_index)
+    }
+
+    /// wasmバイナリにエンコードする。Module::from_byteで読み直すと同じmoduleになる
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode::encode(self)
+    }
+
     // TODO refactor, section_typeとsectionの両方を取る必要はない
     pub(crate) fn take_in(&mut self, section: Section) {
         use Section::*;
 
         match section {
-            Custom(i) => self.custom_section = Some(i),
+            Custom(mut i) => {
+                i.after = self.last_section();
+                let is_name = i.name == "name";
+                self.custom_sections.push(i);
+                if is_name {
+                    self.refresh_names();
+                }
+            }
             Type(i) => self.type_section = Some(i),
             Import(i) => self.import_section = Some(i),
             Function(i) => self.function_section = Some(i),
             Table(i) => self.table_section = Some(i),
             Memory(i) => self.memory_section = Some(i),
+            Tag(i) => self.tag_section = Some(i),
             Global(i) => self.global_section = Some(i),
             Export(i) => self.export_section = Some(i),
             Start(i) => self.start_section = Some(i),
             Element(i) => self.element_section = Some(i),
+            DataCount(i) => self.data_count_section = Some(i),
             Code(i) => self.code_section = Some(i),
             Data(i) => self.data_section = Some(i),
         }
     }
+
+    /// name sectionが複数あれば最後のものを使う。壊れていても読めたところまでは使う
+    fn refresh_names(&mut self) {
+        self.names = self
+            .custom_sections
+            .iter()
+            .rev()
+            .find(|c| c.name == "name")
+            .map_or_else(NameMap::default, |c| decode::decode_names(&c.data));
+    }
+
+    /// 読み込んだcustom section以外のsectionのうち、最後のもの
+    fn last_section(&self) -> Option<SectionType> {
+        use SectionType::*;
+
+        let present = [
+            (Type, self.type_section.is_some()),
+            (Import, self.import_section.is_some()),
+            (Function, self.function_section.is_some()),
+            (Table, self.table_section.is_some()),
+            (Memory, self.memory_section.is_some()),
+            (Tag, self.tag_section.is_some()),
+            (Global, self.global_section.is_some()),
+            (Export, self.export_section.is_some()),
+            (Start, self.start_section.is_some()),
+            (Element, self.element_section.is_some()),
+            (DataCount, self.data_count_section.is_some()),
+            (Code, self.code_section.is_some()),
+            (Data, self.data_section.is_some()),
+        ];
+
+        present
+            .iter()
+            .rev()
+            .find(|(_, present)| *present)
+            .map(|(section_type, _)| *section_type)
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SectionType {
     Custom,
     Type,
@@ -58,9 +394,60 @@ pub enum SectionType {
     Element,
     Code,
     Data,
+    Tag,
+    DataCount,
     Unsuport,
 }
 
+impl SectionType {
+    /// custom section以外のsectionが並ぶ順番。tagとdata countは番号と順番が一致しない
+    pub(crate) fn order(&self) -> Option<u8> {
+        use self::SectionType::*;
+
+        let order = match self {
+            Type => 1,
+            Import => 2,
+            Function => 3,
+            Table => 4,
+            Memory => 5,
+            Tag => 6,
+            Global => 7,
+            Export => 8,
+            Start => 9,
+            Element => 10,
+            DataCount => 11,
+            Code => 12,
+            Data => 13,
+            Custom | Unsuport => return None,
+        };
+
+        Some(order)
+    }
+
+    /// section id。From<u8>の逆
+    pub(crate) fn id(&self) -> u8 {
+        use self::SectionType::*;
+
+        match self {
+            Custom => 0x0,
+            Type => 0x1,
+            Import => 0x2,
+            Function => 0x3,
+            Table => 0x4,
+            Memory => 0x5,
+            Global => 0x6,
+            Export => 0x7,
+            Start => 0x8,
+            Element => 0x9,
+            Code => 0xA,
+            Data => 0xB,
+            DataCount => 0xC,
+            Tag => 0xD,
+            Unsuport => unreachable!("unknown section has no id"),
+        }
+    }
+}
+
 impl From<u8> for SectionType {
     fn from(x: u8) -> Self {
         use self::SectionType::*;
@@ -77,6 +464,8 @@ impl From<u8> for SectionType {
             0x9 => Element,
             0xA => Code,
             0xB => Data,
+            0xC => DataCount,
+            0xD => Tag,
             _ => Unsuport,
         }
     }
@@ -84,16 +473,18 @@ impl From<u8> for SectionType {
 
 #[derive(Clone, Debug)]
 pub enum Section {
-    Custom(()),
+    Custom(CustomSection),
     Type(TypeSection),
-    Import(()),
+    Import(ImportSection),
     Function(FunctionSection),
-    Table(()),
-    Memory(()),
-    Global(()),
+    Table(TableSection),
+    Memory(MemorySection),
+    Tag(TagSection),
+    Global(GlobalSection),
     Export(ExportSection),
-    Start(()),
-    Element(()),
+    Start(u32),
+    Element(ElementSection),
+    DataCount(u32),
     Code(CodeSection),
     Data(DataSection),
 }
@@ -107,18 +498,21 @@ mod tests {
         let m = Module::default();
         let expect = Module {
             version: 0,
-            custom_section: None,
+            custom_sections: vec![],
             type_section: None,
             import_section: None,
             function_section: None,
             table_section: None,
             memory_section: None,
+            tag_section: None,
             global_section: None,
             export_section: None,
             element_section: None,
             start_section: None,
+            data_count_section: None,
             code_section: None,
             data_section: None,
+            names: NameMap::default(),
         };
 
         assert_eq!(expect, m);
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+/// "name" custom sectionから読んだ名前。拡張名前(extended name section)のlabelやtypeなども含む。
+/// indexはどれもimportしたものを含めたindex空間で数える
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NameMap {
+    pub(crate) module: Option<String>,
+    pub(crate) functions: BTreeMap<u32, String>,
+    /// 関数indexごとの、引数とローカル変数の名前
+    pub(crate) locals: BTreeMap<u32, BTreeMap<u32, String>>,
+    /// 関数indexごとの、関数本体で何番目に現れたblock、loop、if、try_tableかで数えたラベルの名前
+    pub(crate) labels: BTreeMap<u32, BTreeMap<u32, String>>,
+    pub(crate) types: BTreeMap<u32, String>,
+    pub(crate) tables: BTreeMap<u32, String>,
+    pub(crate) memories: BTreeMap<u32, String>,
+    pub(crate) globals: BTreeMap<u32, String>,
+    pub(crate) elems: BTreeMap<u32, String>,
+    pub(crate) data: BTreeMap<u32, String>,
+    /// type indexごとの、structのフィールドの名前
+    pub(crate) fields: BTreeMap<u32, BTreeMap<u32, String>>,
+    pub(crate) tags: BTreeMap<u32, String>,
+}
+
+impl NameMap {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn module(&self) -> Option<&str> {
+        self.module.as_deref()
+    }
+
+    pub fn function(&self, index: u32) -> Option<&str> {
+        get(&self.functions, index)
+    }
+
+    pub fn local(&self, function: u32, index: u32) -> Option<&str> {
+        get(self.locals.get(&function)?, index)
+    }
+
+    pub fn label(&self, function: u32, index: u32) -> Option<&str> {
+        get(self.labels.get(&function)?, index)
+    }
+
+    pub fn type_name(&self, index: u32) -> Option<&str> {
+        get(&self.types, index)
+    }
+
+    pub fn table(&self, index: u32) -> Option<&str> {
+        get(&self.tables, index)
+    }
+
+    pub fn memory(&self, index: u32) -> Option<&str> {
+        get(&self.memories, index)
+    }
+
+    pub fn global(&self, index: u32) -> Option<&str> {
+        get(&self.globals, index)
+    }
+
+    pub fn elem(&self, index: u32) -> Option<&str> {
+        get(&self.elems, index)
+    }
+
+    pub fn data(&self, index: u32) -> Option<&str> {
+        get(&self.data, index)
+    }
+
+    pub fn field(&self, type_index: u32, index: u32) -> Option<&str> {
+        get(self.fields.get(&type_index)?, index)
+    }
+
+    pub fn tag(&self, index: u32) -> Option<&str> {
+        get(&self.tags, index)
+    }
+
+    /// エラーメッセージなどに書く関数の呼び名。名前があればfunc[3] <add>、無ければfunc[3]
+    pub fn display_function(&self, index: u32) -> String {
+        display_function(index, self.function(index))
+    }
+}
+
+/// NameMap::display_functionと同じ書き方。名前だけを持っているところから使う
+pub(crate) fn display_function(index: u32, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("func[{}] <{}>", index, name),
+        None => format!("func[{}]", index),
+    }
+}
+
+fn get(map: &BTreeMap<u32, String>, index: u32) -> Option<&str> {
+    map.get(&index).map(String::as_str)
+}
@@ -9,6 +9,9 @@ pub enum Opcode {
     Loop,
     If,
     Else,
+    Throw,
+    ThrowRef,
+    TryTable,
     End,
     Br,
     BrIf,
@@ -16,6 +19,8 @@ pub enum Opcode {
     Return,
     Call,
     CallIndirect,
+    CallRef,
+    ReturnCallRef,
     Drop,
     Select,
     GetLocal,
@@ -23,6 +28,8 @@ pub enum Opcode {
     TeeLocal,
     GetGlobal,
     SetGlobal,
+    TableGet,
+    TableSet,
     I32Load,
     I64Load,
     F32Load,
@@ -175,8 +182,17 @@ pub enum Opcode {
     I64ReinterpretF64,
     F32ReinterpretI32,
     F64ReinterpretI64,
+    RefNull,
+    RefIsNull,
+    RefFunc,
+    RefEq,
+    RefAsNonNull,
+    BrOnNull,
+    BrOnNonNull,
     Reserved,
     Prefix,
+    GcPrefix,
+    AtomicPrefix,
 }
 
 impl TryFrom<u8> for Opcode {
@@ -192,6 +208,8 @@ impl TryFrom<u8> for Opcode {
             0x03 => Loop,
             0x04 => If,
             0x05 => Else,
+            0x08 => Throw,
+            0x0A => ThrowRef,
             0x0B => End,
             0x0C => Br,
             0x0D => BrIf,
@@ -199,13 +217,18 @@ impl TryFrom<u8> for Opcode {
             0x0F => Return,
             0x10 => Call,
             0x11 => CallIndirect,
+            0x14 => CallRef,
+            0x15 => ReturnCallRef,
             0x1A => Drop,
             0x1B => Select,
+            0x1F => TryTable,
             0x20 => GetLocal,
             0x21 => SetLocal,
             0x22 => TeeLocal,
             0x23 => GetGlobal,
             0x24 => SetGlobal,
+            0x25 => TableGet,
+            0x26 => TableSet,
             0x28 => I32Load,
             0x29 => I64Load,
             0x2A => F32Load,
@@ -358,35 +381,33 @@ impl TryFrom<u8> for Opcode {
             0xBD => I64ReinterpretF64,
             0xBE => F32ReinterpretI32,
             0xBF => F64ReinterpretI64,
+            0xD0 => RefNull,
+            0xD1 => RefIsNull,
+            0xD2 => RefFunc,
+            0xD3 => RefEq,
+            0xD4 => RefAsNonNull,
+            0xD5 => BrOnNull,
+            0xD6 => BrOnNonNull,
 
             0x6 => Reserved,
             0x7 => Reserved,
-            0x8 => Reserved,
             0x9 => Reserved,
-            0xA => Reserved,
 
             0x12 => Reserved,
             0x13 => Reserved,
             0x1C => Reserved,
 
-            0x25 => Reserved,
-            0x26 => Reserved,
-
             0xC0 => Reserved,
             0xC1 => Reserved,
             0xC2 => Reserved,
             0xC3 => Reserved,
             0xC4 => Reserved,
 
-            0xD0 => Reserved,
-            0xD1 => Reserved,
-            0xD2 => Reserved,
-            0xD3 => Reserved,
-
             0xFF => Reserved,
-            0xFE => Reserved,
+            0xFE => AtomicPrefix,
             0xFD => Reserved,
             0xFC => Prefix,
+            0xFB => GcPrefix,
 
             opcode => {
                 return Err(DecodeError::Unexpected(format!(
@@ -399,3 +420,203 @@ impl TryFrom<u8> for Opcode {
         Ok(opcode)
     }
 }
+
+impl From<Opcode> for u8 {
+    /// TryFrom<u8>の逆。Reservedはいくつかあるバイトのうち0x06にする
+    fn from(opcode: Opcode) -> Self {
+        use Opcode::*;
+
+        match opcode {
+            Unreachable => 0x00,
+            Nop => 0x01,
+            Block => 0x02,
+            Loop => 0x03,
+            If => 0x04,
+            Else => 0x05,
+            Throw => 0x08,
+            ThrowRef => 0x0A,
+            End => 0x0B,
+            Br => 0x0C,
+            BrIf => 0x0D,
+            BrTable => 0x0E,
+            Return => 0x0F,
+            Call => 0x10,
+            CallIndirect => 0x11,
+            CallRef => 0x14,
+            ReturnCallRef => 0x15,
+            Drop => 0x1A,
+            Select => 0x1B,
+            TryTable => 0x1F,
+            GetLocal => 0x20,
+            SetLocal => 0x21,
+            TeeLocal => 0x22,
+            GetGlobal => 0x23,
+            SetGlobal => 0x24,
+            TableGet => 0x25,
+            TableSet => 0x26,
+            I32Load => 0x28,
+            I64Load => 0x29,
+            F32Load => 0x2A,
+            F64Load => 0x2B,
+            I32Load8S => 0x2C,
+            I32Load8U => 0x2D,
+            I32Load16S => 0x2E,
+            I32Load16U => 0x2F,
+            I64Load8S => 0x30,
+            I64Load8U => 0x31,
+            I64Load16S => 0x32,
+            I64Load16U => 0x33,
+            I64Load32S => 0x34,
+            I64Load32U => 0x35,
+            I32Store => 0x36,
+            I64Store => 0x37,
+            F32Store => 0x38,
+            F64Store => 0x39,
+            I32Store8 => 0x3A,
+            I32Store16 => 0x3B,
+            I64Store8 => 0x3C,
+            I64Store16 => 0x3D,
+            I64Store32 => 0x3E,
+            CurrentMemory => 0x3F,
+            GrowMemory => 0x40,
+            I32Const => 0x41,
+            I64Const => 0x42,
+            F32Const => 0x43,
+            F64Const => 0x44,
+            I32Eqz => 0x45,
+            I32Eq => 0x46,
+            I32Ne => 0x47,
+            I32LtS => 0x48,
+            I32LtU => 0x49,
+            I32GtS => 0x4A,
+            I32GtU => 0x4B,
+            I32LeS => 0x4C,
+            I32LeU => 0x4D,
+            I32GeS => 0x4E,
+            I32GeU => 0x4F,
+            I64Eqz => 0x50,
+            I64Eq => 0x51,
+            I64Ne => 0x52,
+            I64LtS => 0x53,
+            I64LtU => 0x54,
+            I64GtS => 0x55,
+            I64GtU => 0x56,
+            I64LeS => 0x57,
+            I64LeU => 0x58,
+            I64GeS => 0x59,
+            I64GeU => 0x5A,
+            F32Eq => 0x5B,
+            F32Ne => 0x5C,
+            F32Lt => 0x5D,
+            F32Gt => 0x5E,
+            F32Le => 0x5F,
+            F32Ge => 0x60,
+            F64Eq => 0x61,
+            F64Ne => 0x62,
+            F64Lt => 0x63,
+            F64Gt => 0x64,
+            F64Le => 0x65,
+            F64Ge => 0x66,
+            I32Clz => 0x67,
+            I32Ctz => 0x68,
+            I32Popcnt => 0x69,
+            I32Add => 0x6A,
+            I32Sub => 0x6B,
+            I32Mul => 0x6C,
+            I32DivS => 0x6D,
+            I32DivU => 0x6E,
+            I32RemS => 0x6F,
+            I32RemU => 0x70,
+            I32And => 0x71,
+            I32Or => 0x72,
+            I32Xor => 0x73,
+            I32Shl => 0x74,
+            I32ShrS => 0x75,
+            I32ShrU => 0x76,
+            I32Rotl => 0x77,
+            I32Rotr => 0x78,
+            I64Clz => 0x79,
+            I64Ctz => 0x7A,
+            I64Popcnt => 0x7B,
+            I64Add => 0x7C,
+            I64Sub => 0x7D,
+            I64Mul => 0x7E,
+            I64DivS => 0x7F,
+            I64DivU => 0x80,
+            I64RemS => 0x81,
+            I64RemU => 0x82,
+            I64And => 0x83,
+            I64Or => 0x84,
+            I64Xor => 0x85,
+            I64Shl => 0x86,
+            I64ShrS => 0x87,
+            I64ShrU => 0x88,
+            I64Rotl => 0x89,
+            I64Rotr => 0x8A,
+            F32Abs => 0x8B,
+            F32Neg => 0x8C,
+            F32Ceil => 0x8D,
+            F32Floor => 0x8E,
+            F32Trunc => 0x8F,
+            F32Nearest => 0x90,
+            F32Sqrt => 0x91,
+            F32Add => 0x92,
+            F32Sub => 0x93,
+            F32Mul => 0x94,
+            F32Div => 0x95,
+            F32Min => 0x96,
+            F32Max => 0x97,
+            F32Copysign => 0x98,
+            F64Abs => 0x99,
+            F64Neg => 0x9A,
+            F64Ceil => 0x9B,
+            F64Floor => 0x9C,
+            F64Trunc => 0x9D,
+            F64Nearest => 0x9E,
+            F64Sqrt => 0x9F,
+            F64Add => 0xA0,
+            F64Sub => 0xA1,
+            F64Mul => 0xA2,
+            F64Div => 0xA3,
+            F64Min => 0xA4,
+            F64Max => 0xA5,
+            F64Copysign => 0xA6,
+            I32WrapI64 => 0xA7,
+            I32TruncSF32 => 0xA8,
+            I32TruncUF32 => 0xA9,
+            I32TruncSF64 => 0xAA,
+            I32TruncUF64 => 0xAB,
+            I64ExtendSI32 => 0xAC,
+            I64ExtendUI32 => 0xAD,
+            I64TruncSF32 => 0xAE,
+            I64TruncUF32 => 0xAF,
+            I64TruncSF64 => 0xB0,
+            I64TruncUF64 => 0xB1,
+            F32ConvertSI32 => 0xB2,
+            F32ConvertUI32 => 0xB3,
+            F32ConvertSI64 => 0xB4,
+            F32ConvertUI64 => 0xB5,
+            F32DemoteF64 => 0xB6,
+            F64ConvertSI32 => 0xB7,
+            F64ConvertUI32 => 0xB8,
+            F64ConvertSI64 => 0xB9,
+            F64ConvertUI64 => 0xBA,
+            F64PromoteF32 => 0xBB,
+            I32ReinterpretF32 => 0xBC,
+            I64ReinterpretF64 => 0xBD,
+            F32ReinterpretI32 => 0xBE,
+            F64ReinterpretI64 => 0xBF,
+            RefNull => 0xD0,
+            RefIsNull => 0xD1,
+            RefFunc => 0xD2,
+            RefEq => 0xD3,
+            RefAsNonNull => 0xD4,
+            BrOnNull => 0xD5,
+            BrOnNonNull => 0xD6,
+            Reserved => 0x06,
+            AtomicPrefix => 0xFE,
+            Prefix => 0xFC,
+            GcPrefix => 0xFB,
+        }
+    }
+}
@@ -69,8 +69,9 @@ impl ActivationStack {
             Some(v) => v,
         };
 
+        // non-nullableな参照型のlocalは代入されるまで値を持たない
         match locals.get(&i) {
-            None => Err(RuntimeError::NotFound("local".to_string())),
+            None => Err(RuntimeError::UninitializedLocal(i)),
             Some(v) => Ok(v),
         }
     }
@@ -86,6 +87,18 @@ impl ActivationStack {
         Ok(())
     }
 
+    pub fn label_base(&self) -> Result<usize, RuntimeError> {
+        match self.last() {
+            None => Err(RuntimeError::ExpectActivationStack),
+            Some(v) => Ok(v.label_base),
+        }
+    }
+
+    /// すべての関数フレームのlocal。GCのrootになる
+    pub fn locals(&self) -> impl Iterator<Item = &RuntimeValue> {
+        self.0.iter().flat_map(|a| a.locals.values())
+    }
+
     pub fn last(&self) -> Option<&Activation> {
         self.0.last()
     }
@@ -103,15 +116,30 @@ impl ActivationStack {
 pub struct Activation {
     pub pc: usize,
     pub function_index: usize,
+    /// 呼び出し時点のlabel stackの高さ。これより上のラベルがこの関数のもの
+    pub label_base: usize,
+    /// 引数を取り除いた呼び出し時点のvalue stackの高さ。戻るときに戻り値以外をここまで捨てる
+    pub stack_height: usize,
 
     pub locals: Locals,
 }
 
 impl Activation {
     pub fn new(function_index: usize, locals: Locals) -> Self {
+        Self::with_base(function_index, locals, 0, 0)
+    }
+
+    pub fn with_base(
+        function_index: usize,
+        locals: Locals,
+        label_base: usize,
+        stack_height: usize,
+    ) -> Self {
         Self {
             function_index,
             locals,
+            label_base,
+            stack_height,
             pc: 0,
         }
     }
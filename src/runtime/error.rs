@@ -1,4 +1,6 @@
+use crate::runtime::{RuntimeValue, Tag};
 use crate::types::*;
+use crate::validate::ValidationError;
 use std::error::Error;
 use std::fmt::{self, Display};
 
@@ -11,6 +13,43 @@ pub enum RuntimeError {
     ExpectActivationStack,
     Unimplemented,
     InvalidArgs(Vec<ValueType>, Vec<ValueType>),
+    /// TypedFuncにしようとした関数の型が違う。期待した型、実際の型の順
+    FuncTypeMismatch(FuncType, FuncType),
+    UncaughtException(Tag, Vec<RuntimeValue>),
+    UnresolvedImport(String, String),
+    /// まだimportできない種類(テーブル、mutableなグローバル)のimport
+    UnsupportedImport(String, String),
+    /// nameでexportされているものの種類が、求めたものと違う
+    ExportKindMismatch {
+        name: String,
+        expected: ExternalKind,
+        actual: ExternalKind,
+    },
+    /// 別のStoreやInstanceから取り出したハンドルを使おうとした
+    ForeignHandle(String),
+    /// immutableなglobalに書き込もうとした
+    ImmutableGlobal(u32),
+    MemoryOutOfBounds,
+    UnalignedAtomic,
+    TableOutOfBounds,
+    /// call_indirectで呼ぼうとしたtableの要素がnull
+    UninitializedElement,
+    /// call_indirectで呼ぼうとした関数の型が、命令のtype indexと合わない
+    IndirectCallTypeMismatch,
+    ArrayOutOfBounds,
+    /// 作ろうとした配列の要素数がConfig::max_array_lenを超えたか、確保できなかった
+    ArrayTooLarge(u64),
+    NullReference,
+    CastFailure,
+    UninitializedLocal(usize),
+    /// 関数呼び出しの深さがConfig::max_call_depthを超えた
+    CallStackExhausted,
+    /// Config::fuelの分だけ命令を実行した
+    OutOfFuel,
+    /// インスタンス化しようとしたmoduleがConfigに合わない
+    Invalid(ValidationError),
+    /// ホストがメモリから文字列として読んだバイト列がUTF-8でない
+    InvalidUtf8(std::string::FromUtf8Error),
     IOError(std::io::Error),
     Custom(String),
 }
@@ -30,6 +69,56 @@ impl Display for RuntimeError {
                 "Invalid argument: expect {:?},but got {:?}",
                 expect, actual
             ),
+            FuncTypeMismatch(expect, actual) => write!(
+                f,
+                "function type mismatch: expect {:?}, but got {:?}",
+                expect, actual
+            ),
+            UncaughtException(tag, payload) => write!(
+                f,
+                "uncaught exception: tag {:?}, payload {:?}",
+                tag.func_type(),
+                payload
+            ),
+            UnresolvedImport(module, name) => {
+                write!(f, "import '{}.{}' is not resolved", module, name)
+            }
+            UnsupportedImport(module, name) => write!(
+                f,
+                "import '{}.{}' is not supported: tables and mutable globals cannot be imported yet",
+                module, name
+            ),
+            ExportKindMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "export '{}' is a {}, not a {}",
+                name,
+                kind_name(*actual),
+                kind_name(*expected)
+            ),
+            ForeignHandle(handle) => write!(
+                f,
+                "{} belongs to a different store or instance",
+                handle
+            ),
+            ImmutableGlobal(index) => write!(f, "global {} is immutable", index),
+            MemoryOutOfBounds => write!(f, "out of bounds memory access"),
+            UnalignedAtomic => write!(f, "unaligned atomic"),
+            TableOutOfBounds => write!(f, "out of bounds table access"),
+            UninitializedElement => write!(f, "uninitialized element"),
+            IndirectCallTypeMismatch => write!(f, "indirect call type mismatch"),
+            ArrayOutOfBounds => write!(f, "out of bounds array access"),
+            ArrayTooLarge(len) => write!(f, "array of {} elements is too large", len),
+            NullReference => write!(f, "null reference"),
+            CastFailure => write!(f, "cast failure"),
+            UninitializedLocal(index) => write!(f, "local {} is not initialized", index),
+            CallStackExhausted => write!(f, "call stack exhausted"),
+            OutOfFuel => write!(f, "all fuel consumed"),
+            Invalid(e) => write!(f, "{}", e),
+            InvalidUtf8(e) => write!(f, "invalid utf-8 in memory: {}", e),
             ExpectCodeSection => {
                 write!(f, "not found code section. wai is expected code section")
             }
@@ -39,18 +128,35 @@ impl Display for RuntimeError {
     }
 }
 
+fn kind_name(kind: ExternalKind) -> &'static str {
+    match kind {
+        ExternalKind::Function => "function",
+        ExternalKind::Table => "table",
+        ExternalKind::Memory => "memory",
+        ExternalKind::Global => "global",
+        ExternalKind::Tag => "tag",
+        ExternalKind::Unknown => "unknown",
+    }
+}
+
 impl From<std::io::Error> for RuntimeError {
     fn from(error: std::io::Error) -> Self {
         Self::IOError(error)
     }
 }
 
+impl From<ValidationError> for RuntimeError {
+    fn from(error: ValidationError) -> Self {
+        Self::Invalid(error)
+    }
+}
+
 impl PartialEq for RuntimeError {
     // TODO implement
     fn eq(&self, other: &Self) -> bool {
         if self == &RuntimeError::Unimplemented && other == &RuntimeError::Unimplemented {
             return true;
         }
-        return false;
+        false
     }
 }
@@ -1,6 +1,7 @@
 use crate::module::Module;
 use crate::types::*;
 
+#[derive(Debug)]
 pub struct FunctionTable(Vec<Function>);
 
 impl FunctionTable {
@@ -26,7 +27,7 @@ impl FunctionTable {
 
         let mut f = vec![];
 
-        // NOTE codes, funcの長さは同じはず。なのでどれの長さを取ってループを回しても良い
+        // NOTE codes, funcの長さが同じことはデコード時に確認している
         for i in 0..codes.bodies.len() {
             let type_index = match funcs.types.get(i) {
                 None => return Self::empty(),
@@ -38,16 +39,20 @@ impl FunctionTable {
                 Some(v) => v,
             };
 
-            let t = match types.entries.get(*type_index as usize) {
+            let t = match types.func_type(*type_index) {
                 None => return Self::empty(),
                 Some(v) => v,
             };
 
-            f.push(Function::new(
-                t.params.clone(),
-                t.returns.clone(),
-                func_body.code.clone(),
-            ))
+            let mut function =
+                Function::new(t.params.clone(), t.returns.clone(), func_body.code.clone());
+            function.type_index = *type_index;
+            for entry in func_body.locales.iter() {
+                for _ in 0..entry.count {
+                    function.locals.push(entry.value_type);
+                }
+            }
+            f.push(function)
         }
 
         Self(f)
@@ -60,16 +65,21 @@ impl FunctionTable {
 
 #[derive(Debug)]
 pub struct Function {
+    pub type_index: u32,
     pub params: Vec<ValueType>,
     pub returns: Vec<ValueType>,
+    /// 引数以外に宣言されたlocalの型
+    pub locals: Vec<ValueType>,
     pub code: Vec<Instruction>,
 }
 
 impl Function {
     pub fn new(params: Vec<ValueType>, returns: Vec<ValueType>, code: Vec<Instruction>) -> Self {
         Self {
+            type_index: 0,
             params,
             returns,
+            locals: vec![],
             code,
         }
     }
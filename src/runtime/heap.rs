@@ -0,0 +1,219 @@
+use crate::runtime::{Exception, RuntimeError, RuntimeValue};
+
+/// これだけ確保したら最初のGCを走らせる
+const INITIAL_THRESHOLD: usize = 1024;
+
+/// GC proposalのstruct/arrayと、exnrefが指す例外の実体
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeapObject {
+    Struct {
+        type_index: u32,
+        fields: Vec<RuntimeValue>,
+    },
+    Array {
+        type_index: u32,
+        elements: Vec<RuntimeValue>,
+    },
+    Exception(Exception),
+}
+
+impl HeapObject {
+    /// struct/arrayの型。例外には型が無いのでNone
+    pub fn type_index(&self) -> Option<u32> {
+        match self {
+            HeapObject::Struct { type_index, .. } | HeapObject::Array { type_index, .. } => {
+                Some(*type_index)
+            }
+            HeapObject::Exception(_) => None,
+        }
+    }
+
+    fn values(&self) -> &[RuntimeValue] {
+        match self {
+            HeapObject::Struct { fields, .. } => fields,
+            HeapObject::Array { elements, .. } => elements,
+            HeapObject::Exception(exception) => &exception.payload,
+        }
+    }
+}
+
+/// RuntimeValue::HeapRefの中身。回収された場所は再利用されるので、その場所の世代も持つ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjectRef {
+    index: u32,
+    generation: u32,
+}
+
+impl ObjectRef {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+/// objectsの1つの場所。回収するたびにgenerationを進め、前の世代を指すObjectRefでは引けなくする
+#[derive(Debug, Default)]
+struct Slot {
+    generation: u32,
+    object: Option<HeapObject>,
+}
+
+/// wasmから確保されたオブジェクトを管理するヒープ。
+/// 回収された場所はfreeから再利用する。invokeの戻り値としてホストに渡ったObjectRefはrootにならないので、
+/// 回収された後に使うと別のオブジェクトではなくエラーになる
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<Slot>,
+    free: Vec<u32>,
+    /// 前回のGCから確保した数
+    allocated: usize,
+    threshold: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: vec![],
+            free: vec![],
+            allocated: 0,
+            threshold: INITIAL_THRESHOLD,
+        }
+    }
+
+    pub fn alloc(&mut self, object: HeapObject) -> ObjectRef {
+        self.allocated += 1;
+
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.objects.push(Slot::default());
+                (self.objects.len() - 1) as u32
+            }
+        };
+        let slot = &mut self.objects[index as usize];
+        slot.object = Some(object);
+
+        ObjectRef {
+            index,
+            generation: slot.generation,
+        }
+    }
+
+    pub fn get(&self, r: ObjectRef) -> Result<&HeapObject, RuntimeError> {
+        match self.objects.get(r.index as usize) {
+            Some(Slot {
+                generation,
+                object: Some(object),
+            }) if *generation == r.generation => Ok(object),
+            _ => Err(RuntimeError::NotFound(format!("heap object {}", r.index))),
+        }
+    }
+
+    pub fn get_mut(&mut self, r: ObjectRef) -> Result<&mut HeapObject, RuntimeError> {
+        match self.objects.get_mut(r.index as usize) {
+            Some(Slot {
+                generation,
+                object: Some(object),
+            }) if *generation == r.generation => Ok(object),
+            _ => Err(RuntimeError::NotFound(format!("heap object {}", r.index))),
+        }
+    }
+
+    /// 生きているオブジェクトの数
+    pub fn live_objects(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.allocated >= self.threshold
+    }
+
+    /// rootsから辿れないオブジェクトを回収するmark & sweep。
+    /// 次のGCは生き残った数に比例して遅らせる
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = RuntimeValue>) {
+        let mut marked = vec![false; self.objects.len()];
+        let mut work_list: Vec<ObjectRef> = roots.into_iter().filter_map(Self::reference).collect();
+
+        while let Some(r) = work_list.pop() {
+            let object = match self.get(r) {
+                Ok(object) => object,
+                Err(_) => continue,
+            };
+            if marked[r.index as usize] {
+                continue;
+            }
+            marked[r.index as usize] = true;
+
+            work_list.extend(object.values().iter().copied().filter_map(Self::reference));
+        }
+
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            if slot.object.is_some() && !marked[index] {
+                slot.object = None;
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free.push(index as u32);
+            }
+        }
+
+        self.allocated = 0;
+        self.threshold = INITIAL_THRESHOLD.max(self.live_objects() * 2);
+    }
+
+    fn reference(v: RuntimeValue) -> Option<ObjectRef> {
+        match v {
+            RuntimeValue::HeapRef(r) | RuntimeValue::ExnRef(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_struct(fields: Vec<RuntimeValue>) -> HeapObject {
+        HeapObject::Struct {
+            type_index: 0,
+            fields,
+        }
+    }
+
+    #[test]
+    fn collect_unreachable_objects() {
+        let mut heap = Heap::new();
+
+        let leaf = heap.alloc(new_struct(vec![RuntimeValue::I32(1)]));
+        let root = heap.alloc(new_struct(vec![RuntimeValue::HeapRef(leaf)]));
+        let garbage = heap.alloc(new_struct(vec![]));
+
+        // 循環しているだけのオブジェクトも回収される
+        let cycle = heap.alloc(new_struct(vec![RuntimeValue::NullRef]));
+        let other = heap.alloc(new_struct(vec![RuntimeValue::HeapRef(cycle)]));
+        if let HeapObject::Struct { fields, .. } = heap.get_mut(cycle).unwrap() {
+            fields[0] = RuntimeValue::HeapRef(other);
+        }
+
+        heap.collect(vec![
+            RuntimeValue::HeapRef(root),
+            RuntimeValue::I32(garbage.index() as i32),
+        ]);
+
+        assert_eq!(heap.live_objects(), 2);
+        assert!(heap.get(root).is_ok());
+        assert!(heap.get(leaf).is_ok());
+        assert!(heap.get(garbage).is_err());
+        assert!(heap.get(cycle).is_err());
+
+        // 回収した場所は再利用されるが、前の世代を指す参照では引けない
+        let reused = heap.alloc(new_struct(vec![]));
+        assert!([garbage, cycle, other]
+            .iter()
+            .any(|r| r.index() == reused.index() && *r != reused));
+        assert!(heap.get(reused).is_ok());
+    }
+}
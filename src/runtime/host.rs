@@ -0,0 +1,104 @@
+use crate::runtime::{MemoryRef, RuntimeError, RuntimeValue};
+use crate::types::FuncType;
+use std::any::{type_name, Any};
+use std::fmt;
+use std::sync::Arc;
+
+type HostFn = dyn Fn(
+        &mut dyn Any,
+        &[(String, MemoryRef)],
+        &[RuntimeValue],
+    ) -> Result<Vec<RuntimeValue>, RuntimeError>
+    + Send
+    + Sync;
+
+/// ホストが定義した関数。関数のimportへ渡すとwasmから呼べる
+#[derive(Clone)]
+pub struct HostFunc {
+    func_type: FuncType,
+    func: Arc<HostFn>,
+}
+
+impl HostFunc {
+    /// Store<T>のデータを使う関数を作る。Storeを使わずにインスタンス化したときのデータは()になる
+    pub fn new<T: 'static>(
+        func_type: FuncType,
+        func: impl Fn(Caller<'_, T>, &[RuntimeValue]) -> Result<Vec<RuntimeValue>, RuntimeError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        let func = move |data: &mut dyn Any, memories: &[(String, MemoryRef)], args: &[_]| {
+            let data = data.downcast_mut::<T>().ok_or_else(|| {
+                RuntimeError::Custom(format!(
+                    "host function expects store data of type {}",
+                    type_name::<T>()
+                ))
+            })?;
+            func(Caller { data, memories }, args)
+        };
+
+        Self {
+            func_type,
+            func: Arc::new(func),
+        }
+    }
+
+    pub fn func_type(&self) -> &FuncType {
+        &self.func_type
+    }
+
+    /// 戻り値の個数と型がfunc_typeと一致しなければエラーにする
+    pub(crate) fn call(
+        &self,
+        data: &mut dyn Any,
+        memories: &[(String, MemoryRef)],
+        args: &[RuntimeValue],
+    ) -> Result<Vec<RuntimeValue>, RuntimeError> {
+        let results = (self.func)(data, memories, args)?;
+
+        let expect = self.func_type.returns();
+        let matched =
+            expect.len() == results.len() && results.iter().zip(expect).all(|(v, t)| v.matches(t));
+        if !matched {
+            let actual = results.iter().map(RuntimeValue::to_type).collect();
+            return Err(RuntimeError::InvalidArgs(expect.to_vec(), actual));
+        }
+
+        Ok(results)
+    }
+}
+
+impl fmt::Debug for HostFunc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HostFunc")
+            .field("func_type", &self.func_type)
+            .finish_non_exhaustive()
+    }
+}
+
+/// HostFuncが呼ばれたときに受け取る、Storeのデータと呼び出し元のインスタンスへの窓口
+pub struct Caller<'a, T> {
+    data: &'a mut T,
+    /// 呼び出し元のインスタンスがexportしているメモリ
+    memories: &'a [(String, MemoryRef)],
+}
+
+impl<T> Caller<'_, T> {
+    pub fn data(&self) -> &T {
+        self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut T {
+        self.data
+    }
+
+    /// 呼び出し元のインスタンスがnameでexportしているメモリ
+    pub fn get_memory(&self, name: &str) -> Result<MemoryRef, RuntimeError> {
+        self.memories
+            .iter()
+            .find(|(export, _)| export == name)
+            .map(|(_, memory)| memory.clone())
+            .ok_or_else(|| RuntimeError::NotFound(format!("memory {}", name)))
+    }
+}
@@ -6,21 +6,21 @@ pub type LabelStack = Vec<Label>;
 pub struct Label {
     pub pc: usize,
     pub label_type: LabelType,
-    pub result_type: BlockType,
+    /// 分岐したときにブロックの外へ持ち出す値の個数。loopでは引数、それ以外では戻り値の個数
+    pub arity: usize,
+    /// ブロックの引数を取り除いたvalue stackの高さ。分岐時にここまで巻き戻す
+    pub stack_height: usize,
 }
 
 impl Label {
-    pub fn new(pc: usize, label_type: LabelType, result_type: BlockType) -> Self {
+    pub fn new(pc: usize, label_type: LabelType, arity: usize, stack_height: usize) -> Self {
         Self {
             pc,
             label_type,
-            result_type,
+            arity,
+            stack_height,
         }
     }
-
-    pub fn new_if(pc: usize, result_type: BlockType, condition: bool) -> Self {
-        Self::new(pc, LabelType::If(condition), result_type)
-    }
 }
 
 #[derive(Debug)]
@@ -28,4 +28,5 @@ pub enum LabelType {
     Block,
     Loop,
     If(bool),
+    TryTable(Vec<CatchClause>),
 }
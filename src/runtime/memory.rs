@@ -1,28 +1,380 @@
 use crate::from_le::FromLe;
 use crate::runtime::error::RuntimeError;
+use crate::to_le::ToLe;
+use crate::types::{AtomicWidth, MemoryType};
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
-pub struct Memory(Vec<u8>);
+pub const PAGE_SIZE: u64 = 65536;
+
+/// memory32で扱える最大ページ数(4GiB)
+const MAX_PAGES_32: u64 = 1 << 16;
+/// memory64で扱える最大ページ数(2^64 byte)
+const MAX_PAGES_64: u64 = 1 << 48;
+
+#[derive(Debug, Default)]
+pub struct Memory {
+    data: Vec<u8>,
+    /// ページ単位の最大値
+    maximum: u64,
+    memory_type: MemoryType,
+}
 
 impl Memory {
-    pub fn new(byte: Vec<u8>) -> Self {
-        Self(byte)
+    pub fn new(memory_type: MemoryType) -> Result<Self, RuntimeError> {
+        Self::with_page_limit(memory_type, None)
+    }
+
+    /// memory typeの上限とは別に、page_limitページより大きくならないメモリを確保する
+    pub fn with_page_limit(
+        memory_type: MemoryType,
+        page_limit: Option<u64>,
+    ) -> Result<Self, RuntimeError> {
+        let limit = if memory_type.memory64 {
+            MAX_PAGES_64
+        } else {
+            MAX_PAGES_32
+        };
+        let limit = page_limit.map_or(limit, |pages| pages.min(limit));
+        let maximum = memory_type.limits.maximum.unwrap_or(limit).min(limit);
+
+        let mut memory = Self {
+            data: vec![],
+            maximum,
+            memory_type,
+        };
+
+        if memory.grow(memory_type.limits.initial).is_none() {
+            return Err(RuntimeError::Custom(format!(
+                "failed to allocate {} pages of memory",
+                memory_type.limits.initial
+            )));
+        }
+
+        Ok(memory)
+    }
+
+    pub fn is_64(&self) -> bool {
+        self.memory_type.memory64
     }
 
-    pub fn load<T>(&mut self, addr: usize) -> Result<T, RuntimeError>
+    pub fn is_shared(&self) -> bool {
+        self.memory_type.shared
+    }
+
+    /// importするときに、このメモリがmemory_typeとして宣言されたimportを満たすかどうか
+    pub fn matches(&self, memory_type: &MemoryType) -> bool {
+        if self.is_64() != memory_type.memory64
+            || self.is_shared() != memory_type.shared
+            || self.size() < memory_type.limits.initial
+        {
+            return false;
+        }
+
+        match (self.memory_type.limits.maximum, memory_type.limits.maximum) {
+            (_, None) => true,
+            (Some(actual), Some(expect)) => actual <= expect,
+            (None, Some(_)) => false,
+        }
+    }
+
+    /// 現在のページ数
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64 / PAGE_SIZE
+    }
+
+    /// deltaページ伸ばして、伸ばす前のページ数を返す。伸ばせなければNone
+    pub fn grow(&mut self, delta: u64) -> Option<u64> {
+        let old = self.size();
+        let new = old.checked_add(delta)?;
+        if new > self.maximum {
+            return None;
+        }
+
+        let new_len = usize::try_from(new.checked_mul(PAGE_SIZE)?).ok()?;
+        self.data
+            .try_reserve_exact(new_len - self.data.len())
+            .ok()?;
+        self.data.resize(new_len, 0);
+
+        Some(old)
+    }
+
+    pub fn load<T>(&self, addr: u64) -> Result<T, RuntimeError>
     where
         T: FromLe,
     {
-        let size = std::mem::size_of::<T>();
+        let size = std::mem::size_of::<T>() as u64;
+        let range = self.range(addr, size)?;
+
+        Ok(T::from_le_bytes(&self.data[range]))
+    }
 
-        // NOTE 長さが足りないときは伸ばしてしまう。
-        // メモリの最大長は決まっているのでそれ以上は伸びないように制限する必要がある
-        while self.0.get(addr..addr + size).is_none() {
-            let cap = self.0.capacity();
-            self.0.extend(vec![0u8; cap]);
+    pub fn store<T>(&mut self, addr: u64, value: T) -> Result<(), RuntimeError>
+    where
+        T: ToLe,
+    {
+        self.write(addr, &value.to_le_bytes())
+    }
+
+    pub fn read(&self, addr: u64, len: u64) -> Result<&[u8], RuntimeError> {
+        let range = self.range(addr, len)?;
+        Ok(&self.data[range])
+    }
+
+    pub fn write(&mut self, addr: u64, bytes: &[u8]) -> Result<(), RuntimeError> {
+        let range = self.range(addr, bytes.len() as u64)?;
+        self.data[range].copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    pub fn fill(&mut self, addr: u64, value: u8, len: u64) -> Result<(), RuntimeError> {
+        let range = self.range(addr, len)?;
+        self.data[range].fill(value);
+
+        Ok(())
+    }
+
+    pub fn copy(&mut self, dst: u64, src: u64, len: u64) -> Result<(), RuntimeError> {
+        let src = self.range(src, len)?;
+        let dst = self.range(dst, len)?;
+        self.data.copy_within(src, dst.start);
+
+        Ok(())
+    }
+
+    /// atomic命令用の読み込み。値はゼロ拡張したビット列として返す
+    pub fn load_atomic(&self, addr: u64, width: AtomicWidth) -> Result<u64, RuntimeError> {
+        let range = self.atomic_range(addr, width)?;
+
+        let mut buf = [0u8; 8];
+        buf[..range.len()].copy_from_slice(&self.data[range]);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// atomic命令用の書き込み。widthを超える上位ビットは捨てられる
+    pub fn store_atomic(
+        &mut self,
+        addr: u64,
+        width: AtomicWidth,
+        value: u64,
+    ) -> Result<(), RuntimeError> {
+        let range = self.atomic_range(addr, width)?;
+        let len = range.len();
+        self.data[range].copy_from_slice(&value.to_le_bytes()[..len]);
+
+        Ok(())
+    }
+
+    /// atomic命令はアラインされていないアドレスにアクセスするとtrapする
+    fn atomic_range(&self, addr: u64, width: AtomicWidth) -> Result<Range<usize>, RuntimeError> {
+        let range = self.range(addr, width.size())?;
+        if !addr.is_multiple_of(width.size()) {
+            return Err(RuntimeError::UnalignedAtomic);
+        }
+
+        Ok(range)
+    }
+
+    /// [addr, addr + len)がメモリの範囲内であればそのRangeを返す
+    fn range(&self, addr: u64, len: u64) -> Result<Range<usize>, RuntimeError> {
+        let end = addr
+            .checked_add(len)
+            .ok_or(RuntimeError::MemoryOutOfBounds)?;
+        if end > self.data.len() as u64 {
+            return Err(RuntimeError::MemoryOutOfBounds);
         }
 
-        let buf = &self.0[addr..addr + size];
+        Ok(addr as usize..end as usize)
+    }
+}
+
+/// memory.atomic.waitで待っているスレッド
+#[derive(Debug, Default)]
+struct Waiter {
+    notified: Mutex<bool>,
+    condvar: Condvar,
+}
+
+#[derive(Debug)]
+struct MemoryCell {
+    memory: Mutex<Memory>,
+    /// アドレスごとの待ち行列。futexのように先に待ったものから起こす
+    waiters: Mutex<HashMap<u64, VecDeque<Arc<Waiter>>>>,
+}
+
+/// インスタンス間やホスト、別スレッドと共有できるメモリへのハンドル
+#[derive(Debug, Clone)]
+pub struct MemoryRef(Arc<MemoryCell>);
+
+impl MemoryRef {
+    pub fn new(memory: Memory) -> Self {
+        Self(Arc::new(MemoryCell {
+            memory: Mutex::new(memory),
+            waiters: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    pub fn matches(&self, memory_type: &MemoryType) -> bool {
+        self.lock().matches(memory_type)
+    }
+
+    /// 現在のページ数
+    pub fn size_pages(&self) -> u64 {
+        self.lock().size()
+    }
+
+    /// deltaページ伸ばして、伸ばす前のページ数を返す
+    pub fn grow(&self, delta: u64) -> Result<u64, RuntimeError> {
+        self.lock().grow(delta).ok_or_else(|| {
+            RuntimeError::Custom(format!("failed to grow memory by {} pages", delta))
+        })
+    }
+
+    /// offsetからbufの長さだけ読む
+    pub fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), RuntimeError> {
+        let memory = self.lock();
+        buf.copy_from_slice(memory.read(offset, buf.len() as u64)?);
+
+        Ok(())
+    }
+
+    pub fn write(&self, offset: u64, bytes: &[u8]) -> Result<(), RuntimeError> {
+        self.lock().write(offset, bytes)
+    }
+
+    /// offsetからリトルエンディアンでTを読む
+    pub fn get<T: FromLe>(&self, offset: u64) -> Result<T, RuntimeError> {
+        self.lock().load(offset)
+    }
+
+    /// offsetへリトルエンディアンでvalueを書く
+    pub fn set<T: ToLe>(&self, offset: u64, value: T) -> Result<(), RuntimeError> {
+        self.lock().store(offset, value)
+    }
+
+    /// offsetからlenバイトをUTF-8の文字列として読む
+    pub fn read_str(&self, offset: u64, len: u64) -> Result<String, RuntimeError> {
+        let bytes = self.lock().read(offset, len)?.to_vec();
+        String::from_utf8(bytes).map_err(RuntimeError::InvalidUtf8)
+    }
+
+    /// offsetへsをUTF-8で書き、書いたバイト数を返す。終端の0は書かない
+    pub fn write_str(&self, offset: u64, s: &str) -> Result<u64, RuntimeError> {
+        self.write(offset, s.as_bytes())?;
+        Ok(s.len() as u64)
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Memory> {
+        // NOTE 書き込み中にpanicしてもメモリの中身自体は壊れないので、poisonは無視する
+        self.0.memory.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// addrの値がexpectedと等しければnotifyされるかtimeoutまで待つ。
+    /// 0: notifyされた、1: 値が異なっていた、2: timeoutした
+    pub(crate) fn wait(
+        &self,
+        addr: u64,
+        width: AtomicWidth,
+        expected: u64,
+        timeout: Option<Duration>,
+    ) -> Result<u32, RuntimeError> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        let memory = self.lock();
+        if !memory.is_shared() {
+            return Err(RuntimeError::Custom(
+                "memory.atomic.wait is expected shared memory".to_string(),
+            ));
+        }
+        if memory.load_atomic(addr, width)? != expected {
+            return Ok(1);
+        }
+
+        // メモリのロックを持ったまま待ち行列に入るので、値の確認とnotifyの間で起こし損ねることはない
+        let waiter = Arc::new(Waiter::default());
+        self.waiters()
+            .entry(addr)
+            .or_default()
+            .push_back(waiter.clone());
+        drop(memory);
+
+        let mut notified = waiter.notified.lock().unwrap_or_else(|e| e.into_inner());
+        while !*notified {
+            notified = match deadline {
+                None => waiter
+                    .condvar
+                    .wait(notified)
+                    .unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    waiter
+                        .condvar
+                        .wait_timeout(notified, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+        if *notified {
+            return Ok(0);
+        }
+        drop(notified);
+
+        // timeoutしたが、その間にnotifyに取り出されていれば起こされたものとして扱う
+        let mut waiters = self.waiters();
+        if let Some(queue) = waiters.get_mut(&addr) {
+            if let Some(i) = queue.iter().position(|w| Arc::ptr_eq(w, &waiter)) {
+                queue.remove(i);
+                return Ok(2);
+            }
+        }
+
+        Ok(0)
+    }
+
+    /// addrで待っているものを最大count個起こして、起こした数を返す
+    pub(crate) fn notify(&self, addr: u64, count: u32) -> Result<u32, RuntimeError> {
+        let memory = self.lock();
+        memory.atomic_range(addr, AtomicWidth::I32)?;
+        if !memory.is_shared() {
+            return Ok(0);
+        }
+
+        let mut waiters = self.waiters();
+        let queue = match waiters.get_mut(&addr) {
+            Some(queue) => queue,
+            None => return Ok(0),
+        };
+
+        let mut woken = 0;
+        while woken < count {
+            let waiter = match queue.pop_front() {
+                Some(waiter) => waiter,
+                None => break,
+            };
+
+            *waiter.notified.lock().unwrap_or_else(|e| e.into_inner()) = true;
+            waiter.condvar.notify_one();
+            woken += 1;
+        }
+
+        Ok(woken)
+    }
+
+    fn waiters(&self) -> MutexGuard<'_, HashMap<u64, VecDeque<Arc<Waiter>>>> {
+        self.0.waiters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
 
-        Ok(T::from_le_bytes(buf))
+impl PartialEq for MemoryRef {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
     }
 }
@@ -1,74 +1,197 @@
 mod activation_stack;
 pub mod error;
 mod function_table;
+pub mod heap;
+mod host;
 mod label_stack;
 pub mod memory;
 pub mod runtime_value;
+pub mod table;
+pub mod tag;
 
 pub use error::RuntimeError;
 pub use function_table::FunctionTable;
-pub use memory::Memory;
+pub use heap::{Heap, HeapObject, ObjectRef};
+pub use host::{Caller, HostFunc};
+pub use memory::{Memory, MemoryRef};
 pub use runtime_value::RuntimeValue;
+pub use table::Table;
+pub use tag::{Exception, Tag};
 
+use crate::config::Config;
 use crate::from_le::FromLe;
 use crate::instruction::Instruction;
+use crate::to_le::ToLe;
 use crate::types::*;
 
 use activation_stack::{Activation, ActivationStack};
 use label_stack::{Label, LabelStack, LabelType};
 
+use std::any::Any;
 use std::collections::HashMap;
+use std::sync::MutexGuard;
+use std::time::Duration;
 
 type ValueStack = Vec<RuntimeValue>;
 
+/// インスタンスが持つ実行環境。メモリ、global、table、GCのheapはinvokeをまたいで引き継がれる
+#[derive(Debug)]
 pub struct Runtime {
+    /// モジュール内で定義した関数。関数のindex空間ではhost_funcsの後に並ぶ
     function_table: FunctionTable,
+    /// importした関数とそのtype index
+    pub(crate) host_funcs: Vec<(u32, HostFunc)>,
+    /// HostFuncに渡すCallerから引けるように、exportしたメモリを名前と組にしておく
+    pub(crate) memory_exports: Vec<(String, MemoryRef)>,
 
     value_stack: ValueStack,
     label_stack: LabelStack,
     activation_stack: ActivationStack,
 
-    memory: Memory,
+    /// importされたもの、モジュール内で定義されたものの順に並ぶ。tags、globals、tablesも同じ
+    pub(crate) memories: Vec<MemoryRef>,
+    /// memory.initで使うdata segment。data.dropされたものとactive segmentは空になっている
+    data_segments: Vec<Vec<u8>>,
+    pub(crate) tags: Vec<Tag>,
+    pub(crate) globals: Vec<RuntimeValue>,
+    pub(crate) tables: Vec<Table>,
+    /// table.initで使うelement segment。elem.dropされたものとactive、declarative segmentは空になっている
+    pub(crate) elem_segments: Vec<Vec<RuntimeValue>>,
+    /// struct/arrayのフィールドの型やキャストの判定に使う
+    types: Vec<SubType>,
+    heap: Heap,
+
+    max_call_depth: usize,
+    max_array_len: usize,
+    /// executeのたびに補充する命令数
+    initial_fuel: Option<u64>,
+    /// 残りの命令数。Noneなら制限しない
+    fuel: Option<u64>,
+    canonicalize_nans: bool,
 }
 
 impl Runtime {
-    pub fn new(function_table: FunctionTable, memory: Memory) -> Self {
+    /// memories、tags、globals、tablesは空で作る。インスタンス化でimportしたものと定義したものを順に入れる
+    pub fn new(
+        function_table: FunctionTable,
+        data_segments: Vec<Vec<u8>>,
+        types: Vec<SubType>,
+    ) -> Self {
         let activation_stack = ActivationStack::new();
 
         Self {
             function_table,
+            host_funcs: Vec::new(),
+            memory_exports: Vec::new(),
             activation_stack,
             value_stack: Vec::new(),
             label_stack: Vec::new(),
 
-            memory,
+            memories: Vec::new(),
+            data_segments,
+            tags: Vec::new(),
+            globals: Vec::new(),
+            tables: Vec::new(),
+            elem_segments: Vec::new(),
+            types,
+            heap: Heap::new(),
+
+            max_call_depth: usize::MAX,
+            max_array_len: usize::MAX,
+            initial_fuel: None,
+            fuel: None,
+            canonicalize_nans: false,
         }
     }
 
+    /// configの呼び出しの深さ、fuel、NaNの扱いで実行する
+    pub fn with_config(self, config: &Config) -> Self {
+        Self {
+            max_call_depth: config.max_call_depth,
+            max_array_len: config.max_array_len,
+            initial_fuel: config.fuel,
+            canonicalize_nans: config.canonicalize_nans,
+            ..self
+        }
+    }
+
+    /// importした関数も含めた関数indexの型
+    pub fn func_type(&self, index: usize) -> Option<FuncType> {
+        if let Some((_, host)) = self.host_funcs.get(index) {
+            return Some(host.func_type().clone());
+        }
+
+        let func = self.function_table.get(index - self.host_funcs.len())?;
+        Some(FuncType::new(func.params.clone(), func.returns.clone()))
+    }
+
+    /// 関数indexのtype index
+    fn type_index(&self, index: usize) -> Option<u32> {
+        match self.host_funcs.get(index) {
+            Some((type_index, _)) => Some(*type_index),
+            None => Some(
+                self.function_table
+                    .get(index - self.host_funcs.len())?
+                    .type_index,
+            ),
+        }
+    }
+
+    /// HostFuncを呼んで、戻り値をスタックに積む
+    fn call_host(&mut self, index: usize, data: &mut dyn Any) -> Result<(), RuntimeError> {
+        let host = self.host_funcs[index].1.clone();
+        let args = self.vpop_n(host.func_type().params().len())?;
+        for v in host.call(data, &self.memory_exports, &args)? {
+            self.vpush(v);
+        }
+
+        Ok(())
+    }
+
     fn _step() -> Result<(), RuntimeError> {
         todo!()
     }
 
+    /// func_indexはimportした関数も含めた関数index。HostFuncにはdataを渡す
     pub fn execute(
         &mut self,
         func_index: usize,
         args: &[RuntimeValue],
+        data: &mut dyn Any,
     ) -> Result<ValueStack, RuntimeError> {
+        // 前の呼び出しがtrapしたときのスタックは捨てる
+        self.value_stack.clear();
+        self.label_stack.clear();
+        self.fuel = self.initial_fuel;
+
+        if func_index < self.host_funcs.len() {
+            self.value_stack.extend_from_slice(args);
+            self.call_host(func_index, data)?;
+            return Ok(std::mem::take(&mut self.value_stack));
+        }
+        let func_index = func_index - self.host_funcs.len();
         self.activation_stack = ActivationStack::init(func_index, args.to_vec());
+        self.init_locals(func_index)?;
 
         while let Some(instruction) = self.get_instruction()? {
             self.increment_pc()?;
+            self.consume_fuel()?;
 
             match instruction {
                 Instruction::Reserved => {}
-                Instruction::Prefix(_) => {}
+                Instruction::Prefix(_) => Err(RuntimeError::Unimplemented)?,
                 Instruction::Nop => {}
 
                 Instruction::Unreachable => unreachable!("call Unreachable instruction"),
-                Instruction::Block(result_type) => self.block(result_type),
-                Instruction::Loop(result_type) => self._loop(result_type),
+                Instruction::Block(block_type) => self.block(block_type)?,
+                Instruction::Loop(block_type) => self._loop(block_type)?,
                 Instruction::If(block_type) => self._if(block_type)?,
                 Instruction::Else => self._else()?,
+                Instruction::TryTable(block_type, catches) => {
+                    self.try_table(block_type, catches)?
+                }
+                Instruction::Throw(index) => self.throw(usize::from(index))?,
+                Instruction::ThrowRef => self.throw_ref()?,
                 Instruction::End => {
                     // FIXME スタックに値がないけど原因がよくわからないのでログを吐いて握りつぶしてしまう
                     if let Err(e) = self.lpop() {
@@ -80,25 +203,19 @@ impl Runtime {
                 // NOTE br_tableは未実装であるが、多くのテストがこのインストラクションに依存している。
                 // unimplementedマクロでパニックするとテスト時のハンドリングが難しいので、br_tableに関してはUnimplementedエラーを返してテストでハンドリングする
                 Instruction::BrTable(_, _) => Err(RuntimeError::Unimplemented)?,
-                Instruction::Return => {
-                    self.apop()?;
-                }
-                Instruction::Call(index) => {
-                    let index = usize::from(index);
-                    let func = self
-                        .function_table
-                        .get(index)
-                        .expect("expect found function");
-
-                    let mut args = HashMap::new();
-                    let len = func.params.len();
-                    for i in 0..len {
-                        args.insert(len - 1 - i, self.vpop()?);
-                    }
-
-                    self.activation_stack.push(Activation::new(index, args));
+                Instruction::Return => self._return()?,
+                Instruction::Call(index) => self.call(usize::from(index), data)?,
+                Instruction::CallRef(_) => {
+                    let index = self.pop_func_ref()?;
+                    self.call(index, data)?;
+                }
+                Instruction::ReturnCallRef(_) => {
+                    let index = self.pop_func_ref()?;
+                    self.return_call(index, data)?;
+                }
+                Instruction::CallIndirect(type_index, table) => {
+                    self.call_indirect(type_index.into(), table.into(), data)?
                 }
-                Instruction::CallIndirect(_, _) => todo!(),
                 Instruction::Drop => {
                     self.vpop()?;
                 }
@@ -111,56 +228,158 @@ impl Runtime {
                     self.activation_stack.set_local(usize::from(i), v)?;
                 }
                 Instruction::TeeLocal(_) => todo!(),
-                Instruction::GetGlobal(_) => todo!(),
-                Instruction::SetGlobal(_) => todo!(),
-
-                Instruction::I32Load(align, offset) => self.load::<i32>(offset, align)?,
-                Instruction::I64Load(align, offset) => self.load::<i64>(offset, align)?,
-                Instruction::F32Load(align, offset) => self.load::<f32>(offset, align)?,
-                Instruction::F64Load(align, offset) => self.load::<f64>(offset, align)?,
+                Instruction::GetGlobal(index) => {
+                    let v = match self.globals.get(usize::from(index)) {
+                        Some(v) => *v,
+                        None => return Err(RuntimeError::NotFound(format!("global {:?}", index))),
+                    };
+                    self.vpush(v);
+                }
+                Instruction::SetGlobal(index) => {
+                    let v = self.vpop()?;
+                    match self.globals.get_mut(usize::from(index)) {
+                        Some(global) => *global = v,
+                        None => return Err(RuntimeError::NotFound(format!("global {:?}", index))),
+                    }
+                }
+                Instruction::TableGet(index) => {
+                    let i = u32::from(self.vpop()?);
+                    let v = self.table(index.into())?.get(i)?;
+                    self.vpush(v);
+                }
+                Instruction::TableSet(index) => {
+                    let v = self.vpop()?;
+                    let i = u32::from(self.vpop()?);
+                    self.table(index.into())?.set(i, v)?;
+                }
+                Instruction::TableSize(index) => {
+                    let size = self.table(index.into())?.size();
+                    self.vpush(RuntimeValue::I32(size as i32));
+                }
+                Instruction::TableGrow(index) => {
+                    let delta = u32::from(self.vpop()?);
+                    let init = self.vpop()?;
+                    let old = self.table(index.into())?.grow(delta, init);
+                    self.vpush(RuntimeValue::I32(old.map_or(-1, |old| old as i32)));
+                }
+                Instruction::TableFill(index) => {
+                    let len = u32::from(self.vpop()?);
+                    let v = self.vpop()?;
+                    let i = u32::from(self.vpop()?);
+                    self.table(index.into())?.fill(i, v, len)?;
+                }
+                Instruction::TableInit(elem, index) => {
+                    self.table_init(elem.into(), index.into())?
+                }
+                Instruction::ElemDrop(elem) => {
+                    if let Some(segment) = self.elem_segments.get_mut(usize::from(elem)) {
+                        segment.clear();
+                    }
+                }
+                Instruction::TableCopy(dst_index, src_index) => {
+                    let len = u32::from(self.vpop()?);
+                    let src = u32::from(self.vpop()?);
+                    let dst = u32::from(self.vpop()?);
 
-                Instruction::I32Load8S(align, offset) => {
-                    self.load_extend::<u8, i32>(offset, align)?
+                    let elements = self.table(src_index.into())?.read(src, len)?.to_vec();
+                    self.table(dst_index.into())?.write(dst, &elements)?;
                 }
-                Instruction::I32Load8U(align, offset) => {
-                    self.load_extend::<u8, i32>(offset, align)?
+
+                Instruction::I32Load(memarg) => self.load::<i32>(memarg)?,
+                Instruction::I64Load(memarg) => self.load::<i64>(memarg)?,
+                Instruction::F32Load(memarg) => self.load::<f32>(memarg)?,
+                Instruction::F64Load(memarg) => self.load::<f64>(memarg)?,
+
+                Instruction::I32Load8S(memarg) => self.load_extend::<i8, i32>(memarg)?,
+                Instruction::I32Load8U(memarg) => self.load_extend::<u8, i32>(memarg)?,
+                Instruction::I32Load16S(memarg) => self.load_extend::<i16, i32>(memarg)?,
+                Instruction::I32Load16U(memarg) => self.load_extend::<u16, i32>(memarg)?,
+                Instruction::I64Load8S(memarg) => self.load_extend::<i8, i64>(memarg)?,
+                Instruction::I64Load8U(memarg) => self.load_extend::<u8, i64>(memarg)?,
+                Instruction::I64Load16S(memarg) => self.load_extend::<i16, i64>(memarg)?,
+                Instruction::I64Load16U(memarg) => self.load_extend::<u16, i64>(memarg)?,
+                Instruction::I64Load32S(memarg) => self.load_extend::<i32, i64>(memarg)?,
+                Instruction::I64Load32U(memarg) => self.load_extend::<u32, i64>(memarg)?,
+
+                Instruction::I32Store(memarg) => self.store::<i32>(memarg)?,
+                Instruction::I64Store(memarg) => self.store::<i64>(memarg)?,
+                Instruction::F32Store(memarg) => self.store::<f32>(memarg)?,
+                Instruction::F64Store(memarg) => self.store::<f64>(memarg)?,
+                Instruction::I32Store8(memarg) => self.store_wrap(memarg, 1)?,
+                Instruction::I32Store16(memarg) => self.store_wrap(memarg, 2)?,
+                Instruction::I64Store8(memarg) => self.store_wrap(memarg, 1)?,
+                Instruction::I64Store16(memarg) => self.store_wrap(memarg, 2)?,
+                Instruction::I64Store32(memarg) => self.store_wrap(memarg, 4)?,
+                Instruction::CurrentMemory(index) => {
+                    let size = self.memory(index.into())?.size();
+                    self.push_address(index.into(), size)?;
                 }
-                Instruction::I32Load16S(align, offset) => {
-                    self.load_extend::<u16, i32>(offset, align)?
+                Instruction::GrowMemory(index) => {
+                    let delta = self.pop_address(index.into())?;
+                    let old = self.memory(index.into())?.grow(delta).unwrap_or(u64::MAX);
+                    self.push_address(index.into(), old)?;
                 }
-                Instruction::I32Load16U(align, offset) => {
-                    self.load_extend::<u16, i32>(offset, align)?
+                Instruction::MemoryInit(data, index) => {
+                    self.memory_init(data.into(), index.into())?
                 }
-                Instruction::I64Load8S(align, offset) => {
-                    self.load_extend::<u8, i64>(offset, align)?
+                Instruction::DataDrop(index) => {
+                    if let Some(segment) = self.data_segments.get_mut(usize::from(index)) {
+                        segment.clear();
+                    }
                 }
-                Instruction::I64Load8U(align, offset) => {
-                    self.load_extend::<u8, i64>(offset, align)?
+                Instruction::MemoryCopy(dst_index, src_index) => {
+                    let len = self.pop_address(src_index.into())?;
+                    let src = self.pop_address(src_index.into())?;
+                    let dst = self.pop_address(dst_index.into())?;
+
+                    if dst_index == src_index {
+                        self.memory(dst_index.into())?.copy(dst, src, len)?;
+                    } else {
+                        let bytes = self.memory(src_index.into())?.read(src, len)?.to_vec();
+                        self.memory(dst_index.into())?.write(dst, &bytes)?;
+                    }
                 }
-                Instruction::I64Load16S(align, offset) => {
-                    self.load_extend::<u16, i64>(offset, align)?
+                Instruction::MemoryFill(index) => {
+                    let len = self.pop_address(index.into())?;
+                    let value = i32::from(self.vpop()?);
+                    let dst = self.pop_address(index.into())?;
+                    self.memory(index.into())?.fill(dst, value as u8, len)?;
                 }
-                Instruction::I64Load16U(align, offset) => {
-                    self.load_extend::<u16, i64>(offset, align)?
+                Instruction::AtomicNotify(memarg) => {
+                    let count = u32::from(self.vpop()?);
+                    let addr = self.effective_address(memarg)?;
+                    let woken = self.memory_ref(memarg.memory)?.notify(addr, count)?;
+                    self.vpush(RuntimeValue::I32(woken as i32));
                 }
-                Instruction::I64Load32S(align, offset) => {
-                    self.load_extend::<u32, i64>(offset, align)?
+                Instruction::AtomicWait32(memarg) => self.atomic_wait(memarg, AtomicWidth::I32)?,
+                Instruction::AtomicWait64(memarg) => self.atomic_wait(memarg, AtomicWidth::I64)?,
+                // NOTE メモリへのアクセスはすべてロックを通すので、fenceでやることはない
+                Instruction::AtomicFence => {}
+                Instruction::AtomicLoad(width, memarg) => {
+                    let addr = self.effective_address(memarg)?;
+                    let v = self.memory(memarg.memory)?.load_atomic(addr, width)?;
+                    self.push_atomic(width, v);
                 }
-                Instruction::I64Load32U(align, offset) => {
-                    self.load_extend::<u32, i64>(offset, align)?
+                Instruction::AtomicStore(width, memarg) => {
+                    let v = i64::from(self.vpop()?) as u64;
+                    let addr = self.effective_address(memarg)?;
+                    self.memory(memarg.memory)?.store_atomic(addr, width, v)?;
                 }
+                Instruction::AtomicRmw(op, width, memarg) => self.atomic_rmw(op, width, memarg)?,
+                Instruction::AtomicCmpxchg(width, memarg) => {
+                    let replacement = i64::from(self.vpop()?) as u64;
+                    let expected = i64::from(self.vpop()?) as u64;
+                    let addr = self.effective_address(memarg)?;
+
+                    let mut memory = self.memory(memarg.memory)?;
+                    let old = memory.load_atomic(addr, width)?;
+                    if old == Self::wrap_atomic(width, expected) {
+                        memory.store_atomic(addr, width, replacement)?;
+                    }
+                    drop(memory);
 
-                Instruction::I32Store(_, _) => todo!(),
-                Instruction::I64Store(_, _) => todo!(),
-                Instruction::F32Store(_, _) => todo!(),
-                Instruction::F64Store(_, _) => todo!(),
-                Instruction::I32Store8(_, _) => todo!(),
-                Instruction::I32Store16(_, _) => todo!(),
-                Instruction::I64Store8(_, _) => todo!(),
-                Instruction::I64Store16(_, _) => todo!(),
-                Instruction::I64Store32(_, _) => todo!(),
-                Instruction::CurrentMemory(_) => todo!(),
-                Instruction::GrowMemory(_) => todo!(),
+                    self.push_atomic(width, old);
+                }
                 Instruction::I32Const(v) => self.value_stack.push(RuntimeValue::I32(v)),
                 Instruction::I64Const(v) => self.value_stack.push(RuntimeValue::I64(v)),
                 Instruction::F32Const(v) => self.value_stack.push(RuntimeValue::F32(v)),
@@ -255,35 +474,36 @@ impl Runtime {
                 Instruction::I64Rotl => todo!(),
                 Instruction::I64Rotr => todo!(),
 
-                Instruction::F32Abs => todo!(),
-                Instruction::F32Neg => todo!(),
-                Instruction::F32Ceil => todo!(),
-                Instruction::F32Floor => todo!(),
-                Instruction::F32Trunc => todo!(),
-                Instruction::F32Nearest => todo!(),
-                Instruction::F32Sqrt => todo!(),
-                Instruction::F32Add => self.add::<f32>(),
-                Instruction::F32Sub => self.sub::<f32>(),
-                Instruction::F32Mul => self.mul::<f32>(),
-                Instruction::F32Div => self.div_s::<f32>(),
-                Instruction::F32Min => todo!(),
-                Instruction::F32Max => todo!(),
-                Instruction::F32Copysign => todo!(),
-
-                Instruction::F64Abs => todo!(),
-                Instruction::F64Neg => todo!(),
-                Instruction::F64Ceil => todo!(),
-                Instruction::F64Floor => todo!(),
-                Instruction::F64Trunc => todo!(),
-                Instruction::F64Nearest => todo!(),
-                Instruction::F64Sqrt => todo!(),
-                Instruction::F64Add => self.add::<f32>(),
-                Instruction::F64Sub => self.sub::<f32>(),
-                Instruction::F64Mul => self.mul::<f32>(),
-                Instruction::F64Div => self.div_s::<f32>(),
-                Instruction::F64Min => todo!(),
-                Instruction::F64Max => todo!(),
-                Instruction::F64Copysign => todo!(),
+                // abs、neg、copysignは符号ビットを操作するだけなのでNaNをそろえない
+                Instruction::F32Abs => self.float_unop(f32::abs, false)?,
+                Instruction::F32Neg => self.float_unop(|v: f32| -v, false)?,
+                Instruction::F32Ceil => self.float_unop(f32::ceil, true)?,
+                Instruction::F32Floor => self.float_unop(f32::floor, true)?,
+                Instruction::F32Trunc => self.float_unop(f32::trunc, true)?,
+                Instruction::F32Nearest => self.float_unop(f32::round_ties_even, true)?,
+                Instruction::F32Sqrt => self.float_unop(f32::sqrt, true)?,
+                Instruction::F32Add => self.float_binop(|a: f32, b| a + b, true)?,
+                Instruction::F32Sub => self.float_binop(|a: f32, b| a - b, true)?,
+                Instruction::F32Mul => self.float_binop(|a: f32, b| a * b, true)?,
+                Instruction::F32Div => self.float_binop(|a: f32, b| a / b, true)?,
+                Instruction::F32Min => self.float_binop(wasm_min::<f32>, true)?,
+                Instruction::F32Max => self.float_binop(wasm_max::<f32>, true)?,
+                Instruction::F32Copysign => self.float_binop(f32::copysign, false)?,
+
+                Instruction::F64Abs => self.float_unop(f64::abs, false)?,
+                Instruction::F64Neg => self.float_unop(|v: f64| -v, false)?,
+                Instruction::F64Ceil => self.float_unop(f64::ceil, true)?,
+                Instruction::F64Floor => self.float_unop(f64::floor, true)?,
+                Instruction::F64Trunc => self.float_unop(f64::trunc, true)?,
+                Instruction::F64Nearest => self.float_unop(f64::round_ties_even, true)?,
+                Instruction::F64Sqrt => self.float_unop(f64::sqrt, true)?,
+                Instruction::F64Add => self.float_binop(|a: f64, b| a + b, true)?,
+                Instruction::F64Sub => self.float_binop(|a: f64, b| a - b, true)?,
+                Instruction::F64Mul => self.float_binop(|a: f64, b| a * b, true)?,
+                Instruction::F64Div => self.float_binop(|a: f64, b| a / b, true)?,
+                Instruction::F64Min => self.float_binop(wasm_min::<f64>, true)?,
+                Instruction::F64Max => self.float_binop(wasm_max::<f64>, true)?,
+                Instruction::F64Copysign => self.float_binop(f64::copysign, false)?,
 
                 Instruction::I32WrapI64 => todo!(),
                 Instruction::I32TruncSF32 => todo!(),
@@ -302,21 +522,193 @@ impl Runtime {
                 Instruction::F32ConvertUI32 => todo!(),
                 Instruction::F32ConvertSI64 => todo!(),
                 Instruction::F32ConvertUI64 => todo!(),
-                Instruction::F32DemoteF64 => todo!(),
+                Instruction::F32DemoteF64 => {
+                    let v = f64::from(self.vpop()?);
+                    self.vpush(RuntimeValue::F32(v as f32));
+                    self.canonicalize_nan();
+                }
 
                 Instruction::F64ConvertSI32 => todo!(),
                 Instruction::F64ConvertUI32 => todo!(),
                 Instruction::F64ConvertSI64 => todo!(),
                 Instruction::F64ConvertUI64 => todo!(),
-                Instruction::F64PromoteF32 => todo!(),
+                Instruction::F64PromoteF32 => {
+                    let v = f32::from(self.vpop()?);
+                    self.vpush(RuntimeValue::F64(f64::from(v)));
+                    self.canonicalize_nan();
+                }
 
                 Instruction::I32ReinterpretF32 => todo!(),
                 Instruction::I64ReinterpretF64 => todo!(),
                 Instruction::F32ReinterpretI32 => todo!(),
                 Instruction::F64ReinterpretI64 => todo!(),
+
+                Instruction::RefNull(_) => self.vpush(RuntimeValue::NullRef),
+                Instruction::RefFunc(index) => self.vpush(RuntimeValue::FuncRef(index.into())),
+                Instruction::RefAsNonNull => {
+                    let v = self.vpop()?;
+                    if v == RuntimeValue::NullRef {
+                        return Err(RuntimeError::NullReference);
+                    }
+                    self.vpush(v);
+                }
+                Instruction::BrOnNull(depth) => {
+                    let v = self.vpop()?;
+                    if v == RuntimeValue::NullRef {
+                        self.br(depth.into())?;
+                    } else {
+                        self.vpush(v);
+                    }
+                }
+                Instruction::BrOnNonNull(depth) => {
+                    let v = self.vpop()?;
+                    if v != RuntimeValue::NullRef {
+                        self.vpush(v);
+                        self.br(depth.into())?;
+                    }
+                }
+                Instruction::RefIsNull => {
+                    let flag = self.vpop()? == RuntimeValue::NullRef;
+                    self.vpush(RuntimeValue::I32(flag as i32));
+                }
+                Instruction::RefEq => {
+                    let (a, b) = (self.vpop()?, self.vpop()?);
+                    self.vpush(RuntimeValue::I32((a == b) as i32));
+                }
+                Instruction::StructNew(index) => self.struct_new(index.into(), false)?,
+                Instruction::StructNewDefault(index) => self.struct_new(index.into(), true)?,
+                Instruction::StructGet(index, field) => {
+                    self.struct_get(index.into(), field.into(), None)?
+                }
+                Instruction::StructGetS(index, field) => {
+                    self.struct_get(index.into(), field.into(), Some(true))?
+                }
+                Instruction::StructGetU(index, field) => {
+                    self.struct_get(index.into(), field.into(), Some(false))?
+                }
+                Instruction::StructSet(index, field) => {
+                    self.struct_set(index.into(), field.into())?
+                }
+                Instruction::ArrayNew(index) => self.array_new_filled(index.into(), false)?,
+                Instruction::ArrayNewDefault(index) => self.array_new_filled(index.into(), true)?,
+                Instruction::ArrayNewFixed(index, len) => {
+                    self.array_new_fixed(index.into(), len.into())?
+                }
+                Instruction::ArrayNewData(index, data) => {
+                    self.array_new_data(index.into(), data.into())?
+                }
+                Instruction::ArrayGet(index) => self.array_get(index.into(), None)?,
+                Instruction::ArrayGetS(index) => self.array_get(index.into(), Some(true))?,
+                Instruction::ArrayGetU(index) => self.array_get(index.into(), Some(false))?,
+                Instruction::ArraySet(index) => {
+                    let v = self.vpop()?;
+                    let i = u32::from(self.vpop()?) as usize;
+                    let r = self.pop_heap_ref()?;
+                    let v = Self::pack(self.array_type(index.into())?.storage, v);
+
+                    let elements = self.array_elements_mut(r)?;
+                    match elements.get_mut(i) {
+                        Some(element) => *element = v,
+                        None => return Err(RuntimeError::ArrayOutOfBounds),
+                    }
+                }
+                Instruction::ArrayLen => {
+                    let r = self.pop_heap_ref()?;
+                    let len = self.array_elements_mut(r)?.len();
+                    self.vpush(RuntimeValue::I32(len as i32));
+                }
+                Instruction::ArrayFill(index) => {
+                    let len = u32::from(self.vpop()?) as usize;
+                    let v = self.vpop()?;
+                    let offset = u32::from(self.vpop()?) as usize;
+                    let r = self.pop_heap_ref()?;
+                    let v = Self::pack(self.array_type(index.into())?.storage, v);
+
+                    let elements = self.array_elements_mut(r)?;
+                    match elements.get_mut(offset..offset + len) {
+                        Some(elements) => elements.fill(v),
+                        None => return Err(RuntimeError::ArrayOutOfBounds),
+                    }
+                }
+                Instruction::ArrayCopy(_, _) => {
+                    let len = u32::from(self.vpop()?) as usize;
+                    let src_offset = u32::from(self.vpop()?) as usize;
+                    let src = self.pop_heap_ref()?;
+                    let dst_offset = u32::from(self.vpop()?) as usize;
+                    let dst = self.pop_heap_ref()?;
+
+                    let values = match self
+                        .array_elements_mut(src)?
+                        .get(src_offset..src_offset + len)
+                    {
+                        Some(values) => values.to_vec(),
+                        None => return Err(RuntimeError::ArrayOutOfBounds),
+                    };
+                    match self
+                        .array_elements_mut(dst)?
+                        .get_mut(dst_offset..dst_offset + len)
+                    {
+                        Some(elements) => elements.copy_from_slice(&values),
+                        None => return Err(RuntimeError::ArrayOutOfBounds),
+                    }
+                }
+                Instruction::RefTest(ref_type) => {
+                    let v = self.vpop()?;
+                    let flag = self.ref_matches(v, ref_type)?;
+                    self.vpush(RuntimeValue::I32(flag as i32));
+                }
+                Instruction::RefCast(ref_type) => {
+                    let v = self.vpop()?;
+                    if !self.ref_matches(v, ref_type)? {
+                        return Err(RuntimeError::CastFailure);
+                    }
+                    self.vpush(v);
+                }
+                Instruction::BrOnCast(depth, _, ref_type) => {
+                    let v = *self
+                        .value_stack
+                        .last()
+                        .ok_or(RuntimeError::ExpectValueStack)?;
+                    if self.ref_matches(v, ref_type)? {
+                        self.br(depth.into())?;
+                    }
+                }
+                Instruction::BrOnCastFail(depth, _, ref_type) => {
+                    let v = *self
+                        .value_stack
+                        .last()
+                        .ok_or(RuntimeError::ExpectValueStack)?;
+                    if !self.ref_matches(v, ref_type)? {
+                        self.br(depth.into())?;
+                    }
+                }
+                // NOTE externrefとanyrefは同じ値で表しているので変換は何もしない
+                Instruction::AnyConvertExtern | Instruction::ExternConvertAny => {}
+                Instruction::RefI31 => {
+                    let v = i32::from(self.vpop()?);
+                    self.vpush(RuntimeValue::I31Ref(v & 0x7fff_ffff));
+                }
+                Instruction::I31GetS | Instruction::I31GetU => {
+                    let v = match self.vpop()? {
+                        RuntimeValue::I31Ref(v) => v,
+                        RuntimeValue::NullRef => return Err(RuntimeError::NullReference),
+                        v => {
+                            return Err(RuntimeError::Custom(format!(
+                                "expect i31ref, but got {:?}",
+                                v
+                            )))
+                        }
+                    };
+                    let v = match instruction {
+                        // 31bit目を符号ビットとして拡張する
+                        Instruction::I31GetS => (v << 1) >> 1,
+                        _ => v,
+                    };
+                    self.vpush(RuntimeValue::I32(v));
+                }
             }
         }
-        Ok(self.value_stack.clone())
+        Ok(std::mem::take(&mut self.value_stack))
     }
 
     fn pop_lr<T>(&mut self) -> (T, T)
@@ -549,33 +941,711 @@ impl Runtime {
         self.value_stack.push(added.into());
     }
 
-    fn load<T>(&mut self, offset: u32, _align: u32) -> Result<(), RuntimeError>
+    fn memory(&self, index: u32) -> Result<MutexGuard<'_, Memory>, RuntimeError> {
+        Ok(self.memory_ref(index)?.lock())
+    }
+
+    fn memory_ref(&self, index: u32) -> Result<&MemoryRef, RuntimeError> {
+        match self.memories.get(index as usize) {
+            Some(memory) => Ok(memory),
+            None => Err(RuntimeError::NotFound(format!("memory {}", index))),
+        }
+    }
+
+    /// 読み書き全体を1回のロックの中で行うので、他のスレッドから途中の状態は見えない
+    fn atomic_rmw(
+        &mut self,
+        op: AtomicRmwOp,
+        width: AtomicWidth,
+        memarg: MemArg,
+    ) -> Result<(), RuntimeError> {
+        let operand = i64::from(self.vpop()?) as u64;
+        let addr = self.effective_address(memarg)?;
+
+        let mut memory = self.memory(memarg.memory)?;
+        let old = memory.load_atomic(addr, width)?;
+        let new = match op {
+            AtomicRmwOp::Add => old.wrapping_add(operand),
+            AtomicRmwOp::Sub => old.wrapping_sub(operand),
+            AtomicRmwOp::And => old & operand,
+            AtomicRmwOp::Or => old | operand,
+            AtomicRmwOp::Xor => old ^ operand,
+            AtomicRmwOp::Xchg => operand,
+        };
+        memory.store_atomic(addr, width, new)?;
+        drop(memory);
+
+        self.push_atomic(width, old);
+        Ok(())
+    }
+
+    fn atomic_wait(&mut self, memarg: MemArg, width: AtomicWidth) -> Result<(), RuntimeError> {
+        // 負のtimeoutは無期限に待つ
+        let timeout = i64::from(self.vpop()?);
+        let timeout = u64::try_from(timeout).ok().map(Duration::from_nanos);
+        let expected = Self::wrap_atomic(width, i64::from(self.vpop()?) as u64);
+        let addr = self.effective_address(memarg)?;
+
+        let result = self
+            .memory_ref(memarg.memory)?
+            .wait(addr, width, expected, timeout)?;
+        self.vpush(RuntimeValue::I32(result as i32));
+
+        Ok(())
+    }
+
+    /// widthのバイト数に収まるように上位ビットを落とす
+    fn wrap_atomic(width: AtomicWidth, v: u64) -> u64 {
+        match width.size() {
+            8 => v,
+            size => v & ((1 << (size * 8)) - 1),
+        }
+    }
+
+    fn push_atomic(&mut self, width: AtomicWidth, v: u64) {
+        if width.is_64() {
+            self.vpush(RuntimeValue::I64(v as i64));
+        } else {
+            self.vpush(RuntimeValue::I32(v as i32));
+        }
+    }
+
+    /// memory64ならi64、そうでなければi32としてアドレスを取り出す
+    fn pop_address(&mut self, index: u32) -> Result<u64, RuntimeError> {
+        let is_64 = self.memory(index)?.is_64();
+
+        let v = self.vpop()?;
+        if is_64 {
+            Ok(i64::from(v) as u64)
+        } else {
+            Ok(u64::from(u32::from(v)))
+        }
+    }
+
+    fn push_address(&mut self, index: u32, v: u64) -> Result<(), RuntimeError> {
+        if self.memory(index)?.is_64() {
+            self.vpush(RuntimeValue::I64(v as i64));
+        } else {
+            self.vpush(RuntimeValue::I32(v as i32));
+        }
+
+        Ok(())
+    }
+
+    fn effective_address(&mut self, memarg: MemArg) -> Result<u64, RuntimeError> {
+        let base_addr = self.pop_address(memarg.memory)?;
+        base_addr
+            .checked_add(memarg.offset)
+            .ok_or(RuntimeError::MemoryOutOfBounds)
+    }
+
+    fn load<T>(&mut self, memarg: MemArg) -> Result<(), RuntimeError>
     where
         T: Into<RuntimeValue> + FromLe,
     {
-        let base_addr: u32 = self.value_stack.pop().unwrap().into();
-        let addr = base_addr + offset;
+        let addr = self.effective_address(memarg)?;
 
-        let result = self.memory.load::<T>(addr as usize)?;
+        let result = self.memory(memarg.memory)?.load::<T>(addr)?;
         self.value_stack.push(result.into());
 
         Ok(())
     }
 
-    fn load_extend<T, U>(&mut self, offset: u32, _align: u32) -> Result<(), RuntimeError>
+    fn load_extend<T, U>(&mut self, memarg: MemArg) -> Result<(), RuntimeError>
     where
         T: FromLe,
         U: Into<RuntimeValue> + From<T>,
     {
-        let base_addr: u32 = self.value_stack.pop().unwrap().into();
-        let addr = base_addr + offset;
+        let addr = self.effective_address(memarg)?;
 
-        let result = self.memory.load::<T>(addr as usize)?;
+        let result = self.memory(memarg.memory)?.load::<T>(addr)?;
 
         self.value_stack.push(U::from(result).into());
         Ok(())
     }
 
+    fn store<T>(&mut self, memarg: MemArg) -> Result<(), RuntimeError>
+    where
+        T: From<RuntimeValue> + ToLe,
+    {
+        let value = T::from(self.vpop()?);
+        let addr = self.effective_address(memarg)?;
+
+        self.memory(memarg.memory)?.store(addr, value)
+    }
+
+    /// 値の下位sizeバイトだけを書き込む(i32.store8など)
+    fn store_wrap(&mut self, memarg: MemArg, size: usize) -> Result<(), RuntimeError> {
+        let value = i64::from(self.vpop()?);
+        let addr = self.effective_address(memarg)?;
+
+        self.memory(memarg.memory)?
+            .write(addr, &value.to_le_bytes()[..size])
+    }
+
+    fn memory_init(&mut self, data: usize, index: u32) -> Result<(), RuntimeError> {
+        let len = u64::from(u32::from(self.vpop()?));
+        let src = u64::from(u32::from(self.vpop()?));
+        let dst = self.pop_address(index)?;
+
+        let segment = match self.data_segments.get(data) {
+            Some(segment) => segment,
+            None => return Err(RuntimeError::NotFound(format!("data segment {}", data))),
+        };
+
+        let end = src
+            .checked_add(len)
+            .ok_or(RuntimeError::MemoryOutOfBounds)?;
+        let bytes = match segment.get(src as usize..end as usize) {
+            Some(bytes) => bytes.to_vec(),
+            None => return Err(RuntimeError::MemoryOutOfBounds),
+        };
+
+        self.memory(index)?.write(dst, &bytes)
+    }
+
+    fn table_init(&mut self, elem: usize, index: u32) -> Result<(), RuntimeError> {
+        let len = u32::from(self.vpop()?);
+        let src = u32::from(self.vpop()?);
+        let dst = u32::from(self.vpop()?);
+
+        let segment = match self.elem_segments.get(elem) {
+            Some(segment) => segment,
+            None => return Err(RuntimeError::NotFound(format!("elem segment {}", elem))),
+        };
+        let end = src.checked_add(len).ok_or(RuntimeError::TableOutOfBounds)?;
+        let elements = match segment.get(src as usize..end as usize) {
+            Some(elements) => elements.to_vec(),
+            None => return Err(RuntimeError::TableOutOfBounds),
+        };
+
+        self.table(index)?.write(dst, &elements)
+    }
+
+    /// tableのi番目の関数を呼ぶ。関数の型はtype_indexと一致するか、そのsubtypeでなければならない
+    fn call_indirect(
+        &mut self,
+        type_index: u32,
+        table: u32,
+        data: &mut dyn Any,
+    ) -> Result<(), RuntimeError> {
+        let i = u32::from(self.vpop()?);
+        let index = match self.table(table)?.get(i)? {
+            RuntimeValue::FuncRef(index) => index as usize,
+            RuntimeValue::NullRef => return Err(RuntimeError::UninitializedElement),
+            v => {
+                return Err(RuntimeError::Custom(format!(
+                    "expect function reference, but got {:?}",
+                    v
+                )))
+            }
+        };
+
+        let actual = match self.type_index(index) {
+            Some(actual) => actual,
+            None => return Err(RuntimeError::NotFound(format!("function {}", index))),
+        };
+        let same_signature = match (
+            self.types.get(actual as usize),
+            self.types.get(type_index as usize),
+        ) {
+            (Some(a), Some(b)) => a.composite == b.composite,
+            _ => false,
+        };
+        if !same_signature && !self.is_subtype(actual, type_index) {
+            return Err(RuntimeError::IndirectCallTypeMismatch);
+        }
+
+        self.call(index, data)
+    }
+
+    /// indexはimportした関数も含めた関数index
+    fn call(&mut self, index: usize, data: &mut dyn Any) -> Result<(), RuntimeError> {
+        if self.activation_stack.len() >= self.max_call_depth {
+            return Err(RuntimeError::CallStackExhausted);
+        }
+        if index < self.host_funcs.len() {
+            return self.call_host(index, data);
+        }
+        let index = index - self.host_funcs.len();
+        let args = self.pop_args(index)?;
+
+        let label_base = self.label_stack.len();
+        let stack_height = self.value_stack.len();
+        self.activation_stack
+            .push(Activation::with_base(index, args, label_base, stack_height));
+        self.init_locals(index)
+    }
+
+    /// 呼び出し元のフレームを捨ててから呼び出す(return_call_ref)。
+    /// 呼び出された関数から戻ると、呼び出し元の呼び出し元へ戻る
+    fn return_call(&mut self, index: usize, data: &mut dyn Any) -> Result<(), RuntimeError> {
+        if index < self.host_funcs.len() {
+            self.call_host(index, data)?;
+            return self._return();
+        }
+        let index = index - self.host_funcs.len();
+        let args = self.pop_args(index)?;
+
+        let activation = self.apop()?;
+        self.label_stack.truncate(activation.label_base);
+        self.value_stack.truncate(activation.stack_height);
+        self.activation_stack.push(Activation::with_base(
+            index,
+            args,
+            activation.label_base,
+            activation.stack_height,
+        ));
+        self.init_locals(index)
+    }
+
+    fn pop_args(&mut self, index: usize) -> Result<HashMap<usize, RuntimeValue>, RuntimeError> {
+        let len = match self.function_table.get(index) {
+            Some(func) => func.params.len(),
+            None => return Err(RuntimeError::NotFound(format!("function {}", index))),
+        };
+
+        let mut args = HashMap::new();
+        for i in 0..len {
+            args.insert(len - 1 - i, self.vpop()?);
+        }
+
+        Ok(args)
+    }
+
+    fn pop_func_ref(&mut self) -> Result<usize, RuntimeError> {
+        match self.vpop()? {
+            RuntimeValue::FuncRef(index) => Ok(index as usize),
+            RuntimeValue::NullRef => Err(RuntimeError::NullReference),
+            v => Err(RuntimeError::Custom(format!(
+                "expect function reference, but got {:?}",
+                v
+            ))),
+        }
+    }
+
+    fn init_locals(&mut self, function_index: usize) -> Result<(), RuntimeError> {
+        let func = match self.function_table.get(function_index) {
+            Some(func) => func,
+            None => {
+                return Err(RuntimeError::NotFound(format!(
+                    "function {}",
+                    function_index
+                )))
+            }
+        };
+
+        // NOTE non-nullableな参照型のlocalには初期値がないので未初期化のまま残す
+        let base = func.params.len();
+        let locals: Vec<_> = func
+            .locals
+            .iter()
+            .map(|t| RuntimeValue::default_of(*t))
+            .collect();
+        for (i, v) in locals.into_iter().enumerate() {
+            if let Some(v) = v {
+                self.activation_stack.set_local(base + i, v)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn table(&mut self, index: u32) -> Result<&mut Table, RuntimeError> {
+        match self.tables.get_mut(index as usize) {
+            Some(table) => Ok(table),
+            None => Err(RuntimeError::NotFound(format!("table {}", index))),
+        }
+    }
+
+    /// 前回のGCから一定数確保していたら、value stack、local、global、tableなどをrootにしてGCする。
+    /// 確保する命令のオペランドがまだvalue stackに乗っている間に呼ぶ
+    /// globalの初期値やsegmentのoffsetなどの定数式を評価する。
+    /// struct.newやarray.newで確保したオブジェクトは、関数の実行と同じヒープに置く
+    pub(crate) fn eval_const_expr(
+        &mut self,
+        expr: &[Instruction],
+    ) -> Result<RuntimeValue, RuntimeError> {
+        self.value_stack.clear();
+
+        for instruction in expr {
+            match *instruction {
+                Instruction::I32Const(v) => self.vpush(RuntimeValue::I32(v)),
+                Instruction::I64Const(v) => self.vpush(RuntimeValue::I64(v)),
+                Instruction::F32Const(v) => self.vpush(RuntimeValue::F32(v)),
+                Instruction::F64Const(v) => self.vpush(RuntimeValue::F64(v)),
+                Instruction::RefNull(_) => self.vpush(RuntimeValue::NullRef),
+                Instruction::RefFunc(index) => self.vpush(RuntimeValue::FuncRef(index.into())),
+                Instruction::GetGlobal(index) => match self.globals.get(usize::from(index)) {
+                    Some(v) => self.vpush(*v),
+                    None => return Err(RuntimeError::NotFound(format!("global {:?}", index))),
+                },
+                Instruction::I32Add => self.const_binop(i32::wrapping_add)?,
+                Instruction::I32Sub => self.const_binop(i32::wrapping_sub)?,
+                Instruction::I32Mul => self.const_binop(i32::wrapping_mul)?,
+                Instruction::I64Add => self.const_binop(i64::wrapping_add)?,
+                Instruction::I64Sub => self.const_binop(i64::wrapping_sub)?,
+                Instruction::I64Mul => self.const_binop(i64::wrapping_mul)?,
+                Instruction::StructNew(index) => self.struct_new(index.into(), false)?,
+                Instruction::StructNewDefault(index) => self.struct_new(index.into(), true)?,
+                Instruction::ArrayNew(index) => self.array_new_filled(index.into(), false)?,
+                Instruction::ArrayNewDefault(index) => self.array_new_filled(index.into(), true)?,
+                Instruction::ArrayNewFixed(index, len) => {
+                    self.array_new_fixed(index.into(), len.into())?
+                }
+                Instruction::RefI31 => {
+                    let v = i32::from(self.vpop()?);
+                    self.vpush(RuntimeValue::I31Ref(v & 0x7fff_ffff));
+                }
+                Instruction::AnyConvertExtern | Instruction::ExternConvertAny => {}
+                _ => {
+                    return Err(RuntimeError::Custom(format!(
+                        "unsupported constant expression {:?}",
+                        instruction
+                    )))
+                }
+            }
+        }
+
+        let v = self.vpop()?;
+        self.value_stack.clear();
+
+        Ok(v)
+    }
+
+    /// 定数式の算術命令。定数式の値はオーバーフローしても折り返す
+    fn const_binop<T>(&mut self, f: impl Fn(T, T) -> T) -> Result<(), RuntimeError>
+    where
+        T: From<RuntimeValue> + Into<RuntimeValue>,
+    {
+        let b = T::from(self.vpop()?);
+        let a = T::from(self.vpop()?);
+        self.vpush(f(a, b).into());
+
+        Ok(())
+    }
+
+    fn maybe_collect(&mut self) {
+        if !self.heap.should_collect() {
+            return;
+        }
+
+        let roots = self
+            .value_stack
+            .iter()
+            .chain(self.activation_stack.locals())
+            .chain(self.globals.iter())
+            .chain(self.tables.iter().flat_map(|t| t.elements().iter()))
+            .chain(self.elem_segments.iter().flatten())
+            .copied();
+
+        self.heap.collect(roots);
+    }
+
+    fn struct_type(&self, index: u32) -> Result<&StructType, RuntimeError> {
+        match self.types.get(index as usize).map(|t| &t.composite) {
+            Some(CompositeType::Struct(struct_type)) => Ok(struct_type),
+            _ => Err(RuntimeError::NotFound(format!("struct type {}", index))),
+        }
+    }
+
+    fn array_type(&self, index: u32) -> Result<FieldType, RuntimeError> {
+        match self.types.get(index as usize).map(|t| &t.composite) {
+            Some(CompositeType::Array(field_type)) => Ok(*field_type),
+            _ => Err(RuntimeError::NotFound(format!("array type {}", index))),
+        }
+    }
+
+    fn default_of(storage: StorageType) -> Result<RuntimeValue, RuntimeError> {
+        match storage {
+            StorageType::I8 | StorageType::I16 => Ok(RuntimeValue::I32(0)),
+            StorageType::Value(t) => RuntimeValue::default_of(t).ok_or_else(|| {
+                RuntimeError::Custom(format!("{:?} does not have default value", t))
+            }),
+        }
+    }
+
+    /// packed typeのフィールドに書き込むときは下位ビットだけを残す
+    fn pack(storage: StorageType, v: RuntimeValue) -> RuntimeValue {
+        match storage {
+            StorageType::I8 => RuntimeValue::I32(i32::from(v) & 0xff),
+            StorageType::I16 => RuntimeValue::I32(i32::from(v) & 0xffff),
+            StorageType::Value(_) => v,
+        }
+    }
+
+    /// packed typeのフィールドを読むときにsignedならば符号拡張する
+    fn unpack(storage: StorageType, v: RuntimeValue, signed: Option<bool>) -> RuntimeValue {
+        match (storage, signed) {
+            (StorageType::I8, Some(true)) => RuntimeValue::I32(i32::from(v) as i8 as i32),
+            (StorageType::I16, Some(true)) => RuntimeValue::I32(i32::from(v) as i16 as i32),
+            _ => v,
+        }
+    }
+
+    fn vpop_n(&mut self, n: usize) -> Result<Vec<RuntimeValue>, RuntimeError> {
+        if self.value_stack.len() < n {
+            return Err(RuntimeError::ExpectValueStack);
+        }
+
+        Ok(self.value_stack.split_off(self.value_stack.len() - n))
+    }
+
+    fn pop_heap_ref(&mut self) -> Result<ObjectRef, RuntimeError> {
+        match self.vpop()? {
+            RuntimeValue::HeapRef(r) => Ok(r),
+            RuntimeValue::NullRef => Err(RuntimeError::NullReference),
+            v => Err(RuntimeError::Custom(format!(
+                "expect reference to struct or array, but got {:?}",
+                v
+            ))),
+        }
+    }
+
+    fn struct_new(&mut self, index: u32, default: bool) -> Result<(), RuntimeError> {
+        self.maybe_collect();
+
+        let storages: Vec<_> = self
+            .struct_type(index)?
+            .fields
+            .iter()
+            .map(|f| f.storage)
+            .collect();
+
+        let fields = if default {
+            storages
+                .into_iter()
+                .map(Self::default_of)
+                .collect::<Result<_, _>>()?
+        } else {
+            let values = self.vpop_n(storages.len())?;
+            storages
+                .into_iter()
+                .zip(values)
+                .map(|(storage, v)| Self::pack(storage, v))
+                .collect()
+        };
+
+        let r = self.heap.alloc(HeapObject::Struct {
+            type_index: index,
+            fields,
+        });
+        self.vpush(RuntimeValue::HeapRef(r));
+
+        Ok(())
+    }
+
+    fn struct_get(
+        &mut self,
+        index: u32,
+        field: usize,
+        signed: Option<bool>,
+    ) -> Result<(), RuntimeError> {
+        let r = self.pop_heap_ref()?;
+        let storage = match self.struct_type(index)?.fields.get(field) {
+            Some(f) => f.storage,
+            None => return Err(RuntimeError::NotFound(format!("field {}", field))),
+        };
+
+        let v = match self.heap.get(r)? {
+            HeapObject::Struct { fields, .. } => fields.get(field).copied(),
+            _ => None,
+        };
+        let v = v.ok_or_else(|| RuntimeError::NotFound(format!("field {}", field)))?;
+        self.vpush(Self::unpack(storage, v, signed));
+
+        Ok(())
+    }
+
+    fn struct_set(&mut self, index: u32, field: usize) -> Result<(), RuntimeError> {
+        let v = self.vpop()?;
+        let r = self.pop_heap_ref()?;
+        let storage = match self.struct_type(index)?.fields.get(field) {
+            Some(f) => f.storage,
+            None => return Err(RuntimeError::NotFound(format!("field {}", field))),
+        };
+
+        match self.heap.get_mut(r)? {
+            HeapObject::Struct { fields, .. } if field < fields.len() => {
+                fields[field] = Self::pack(storage, v);
+                Ok(())
+            }
+            _ => Err(RuntimeError::NotFound(format!("field {}", field))),
+        }
+    }
+
+    /// array.newとarray.new_default。要素の数と、defaultでなければ初期値をvalue stackから取る
+    fn array_new_filled(&mut self, index: u32, default: bool) -> Result<(), RuntimeError> {
+        self.maybe_collect();
+        let len = u32::from(self.vpop()?);
+        let v = match default {
+            true => Self::default_of(self.array_type(index)?.storage)?,
+            false => self.vpop()?,
+        };
+        let elements = self.array_elements(v, len as usize)?;
+        self.array_new(index, elements)
+    }
+
+    fn array_new_fixed(&mut self, index: u32, len: usize) -> Result<(), RuntimeError> {
+        self.maybe_collect();
+        let elements = self.vpop_n(len)?;
+        self.array_new(index, elements)
+    }
+
+    fn array_new(&mut self, index: u32, elements: Vec<RuntimeValue>) -> Result<(), RuntimeError> {
+        let storage = self.array_type(index)?.storage;
+        let elements = elements
+            .into_iter()
+            .map(|v| Self::pack(storage, v))
+            .collect();
+
+        let r = self.heap.alloc(HeapObject::Array {
+            type_index: index,
+            elements,
+        });
+        self.vpush(RuntimeValue::HeapRef(r));
+
+        Ok(())
+    }
+
+    /// vをlen個並べる。長さはwasmが決めるので、max_array_lenを超えたり確保できなければtrapする
+    fn array_elements(
+        &self,
+        v: RuntimeValue,
+        len: usize,
+    ) -> Result<Vec<RuntimeValue>, RuntimeError> {
+        self.check_array_len(len)?;
+
+        let mut elements = Vec::new();
+        elements
+            .try_reserve_exact(len)
+            .map_err(|_| RuntimeError::ArrayTooLarge(len as u64))?;
+        elements.resize(len, v);
+
+        Ok(elements)
+    }
+
+    fn check_array_len(&self, len: usize) -> Result<(), RuntimeError> {
+        if len > self.max_array_len {
+            return Err(RuntimeError::ArrayTooLarge(len as u64));
+        }
+
+        Ok(())
+    }
+
+    /// data segmentのバイト列を要素の型のリトルエンディアンとして読んで配列を作る
+    fn array_new_data(&mut self, index: u32, data: usize) -> Result<(), RuntimeError> {
+        let len = u32::from(self.vpop()?) as usize;
+        let offset = u32::from(self.vpop()?) as usize;
+        self.check_array_len(len)?;
+        let storage = self.array_type(index)?.storage;
+        let size = storage.size();
+
+        let segment = match self.data_segments.get(data) {
+            Some(segment) => segment,
+            None => return Err(RuntimeError::NotFound(format!("data segment {}", data))),
+        };
+        let bytes = len
+            .checked_mul(size)
+            .and_then(|n| segment.get(offset..offset.checked_add(n)?))
+            .ok_or(RuntimeError::MemoryOutOfBounds)?;
+
+        let elements = bytes
+            .chunks(size)
+            .map(|chunk| {
+                let mut buf = [0u8; 8];
+                buf[..size].copy_from_slice(chunk);
+                let bits = u64::from_le_bytes(buf);
+
+                match storage {
+                    StorageType::Value(ValueType::I64) => RuntimeValue::I64(bits as i64),
+                    StorageType::Value(ValueType::F32) => {
+                        RuntimeValue::F32(f32::from_bits(bits as u32))
+                    }
+                    StorageType::Value(ValueType::F64) => RuntimeValue::F64(f64::from_bits(bits)),
+                    _ => RuntimeValue::I32(bits as i32),
+                }
+            })
+            .collect();
+
+        self.array_new(index, elements)
+    }
+
+    fn array_get(&mut self, index: u32, signed: Option<bool>) -> Result<(), RuntimeError> {
+        let i = u32::from(self.vpop()?) as usize;
+        let r = self.pop_heap_ref()?;
+        let storage = self.array_type(index)?.storage;
+
+        let v = match self.array_elements_mut(r)?.get(i) {
+            Some(v) => *v,
+            None => return Err(RuntimeError::ArrayOutOfBounds),
+        };
+        self.vpush(Self::unpack(storage, v, signed));
+
+        Ok(())
+    }
+
+    fn array_elements_mut(&mut self, r: ObjectRef) -> Result<&mut Vec<RuntimeValue>, RuntimeError> {
+        match self.heap.get_mut(r)? {
+            HeapObject::Array { elements, .. } => Ok(elements),
+            _ => Err(RuntimeError::Custom(format!(
+                "expect array, but got struct {}",
+                r.index()
+            ))),
+        }
+    }
+
+    /// ref.testやref.castで値がref_typeに含まれるかどうかを判定する
+    fn ref_matches(&self, v: RuntimeValue, ref_type: RefType) -> Result<bool, RuntimeError> {
+        let heap_type = ref_type.heap_type;
+
+        let matched = match v {
+            RuntimeValue::NullRef => ref_type.nullable,
+            RuntimeValue::I31Ref(_) => {
+                matches!(heap_type, HeapType::Any | HeapType::Eq | HeapType::I31)
+            }
+            RuntimeValue::ExnRef(_) => heap_type == HeapType::Exn,
+            RuntimeValue::FuncRef(index) => match heap_type {
+                HeapType::Func => true,
+                HeapType::Concrete(t) => match self.type_index(index as usize) {
+                    Some(type_index) => self.is_subtype(type_index, t),
+                    None => false,
+                },
+                _ => false,
+            },
+            RuntimeValue::HeapRef(r) => {
+                let object = self.heap.get(r)?;
+                match (heap_type, object) {
+                    (HeapType::Any | HeapType::Eq, _) => true,
+                    (HeapType::Struct, HeapObject::Struct { .. }) => true,
+                    (HeapType::Array, HeapObject::Array { .. }) => true,
+                    (HeapType::Concrete(t), object) => match object.type_index() {
+                        Some(type_index) => self.is_subtype(type_index, t),
+                        None => false,
+                    },
+                    _ => false,
+                }
+            }
+            _ => false,
+        };
+
+        Ok(matched)
+    }
+
+    /// subの親をたどってsuperに行き着くならsubtype。
+    /// NOTE recグループの同値性(iso-recursiveな型の同一視)はまだ見ていない
+    fn is_subtype(&self, sub: u32, sup: u32) -> bool {
+        let mut current = Some(sub);
+        while let Some(t) = current {
+            if t == sup {
+                return true;
+            }
+            current = self.types.get(t as usize).and_then(|t| t.supertype);
+        }
+
+        false
+    }
+
     fn lpop(&mut self) -> Result<Label, RuntimeError> {
         match self.label_stack.pop() {
             Some(label) => Ok(label),
@@ -608,16 +1678,144 @@ impl Runtime {
         self.value_stack.push(v)
     }
 
-    fn block(&mut self, result_type: BlockType) {
-        let pc = self.pc();
+    /// blockの引数と戻り値の個数
+    fn block_arity(&self, block_type: BlockType) -> Result<(usize, usize), RuntimeError> {
+        match block_type {
+            BlockType::Empty => Ok((0, 0)),
+            BlockType::TypeIndex(index) => {
+                match self.types.get(index as usize).map(|t| &t.composite) {
+                    Some(CompositeType::Func(func_type)) => {
+                        Ok((func_type.params.len(), func_type.returns.len()))
+                    }
+                    _ => Err(RuntimeError::NotFound(format!("type {}", index))),
+                }
+            }
+            _ => Ok((0, 1)),
+        }
+    }
+
+    /// 引数をvalue stackに残したままラベルを積む。引数はブロックの中で使うので、巻き戻す高さには含めない
+    fn push_label(
+        &mut self,
+        pc: usize,
+        label_type: LabelType,
+        block_type: BlockType,
+    ) -> Result<(), RuntimeError> {
+        let (params, results) = self.block_arity(block_type)?;
+        let height = match self.value_stack.len().checked_sub(params) {
+            Some(height) => height,
+            None => return Err(RuntimeError::ExpectValueStack),
+        };
+        let arity = match label_type {
+            LabelType::Loop => params,
+            _ => results,
+        };
         self.label_stack
-            .push(Label::new(pc, LabelType::Block, result_type));
+            .push(Label::new(pc, label_type, arity, height));
+
+        Ok(())
     }
 
-    fn _loop(&mut self, result_type: BlockType) {
+    fn block(&mut self, block_type: BlockType) -> Result<(), RuntimeError> {
         let pc = self.pc();
-        self.label_stack
-            .push(Label::new(pc, LabelType::Loop, result_type));
+        self.push_label(pc, LabelType::Block, block_type)
+    }
+
+    fn _loop(&mut self, block_type: BlockType) -> Result<(), RuntimeError> {
+        let pc = self.pc();
+        self.push_label(pc, LabelType::Loop, block_type)
+    }
+
+    fn try_table(
+        &mut self,
+        block_type: BlockType,
+        catches: Vec<CatchClause>,
+    ) -> Result<(), RuntimeError> {
+        let pc = self.pc();
+        self.push_label(pc, LabelType::TryTable(catches), block_type)
+    }
+
+    fn throw(&mut self, tag_index: usize) -> Result<(), RuntimeError> {
+        let tag = match self.tags.get(tag_index) {
+            Some(tag) => tag.clone(),
+            None => return Err(RuntimeError::NotFound(format!("tag {}", tag_index))),
+        };
+
+        // 投げる値はまだvalue stackにあるので、回収してから取り出す
+        self.maybe_collect();
+        let payload = self.vpop_n(tag.func_type().params.len())?;
+
+        let exception = self
+            .heap
+            .alloc(HeapObject::Exception(Exception { tag, payload }));
+        self.unwind(exception)
+    }
+
+    fn throw_ref(&mut self) -> Result<(), RuntimeError> {
+        match self.vpop()? {
+            RuntimeValue::ExnRef(exception) => self.unwind(exception),
+            RuntimeValue::NullRef => Err(RuntimeError::NullReference),
+            v => Err(RuntimeError::Custom(format!(
+                "throw_ref is expected exnref, but got {:?}",
+                v
+            ))),
+        }
+    }
+
+    /// 例外を受け止めるtry_tableが見つかるまでラベルと関数フレームを巻き戻す。
+    /// 見つからなければ呼び出し元(ホスト)へUncaughtExceptionとして返す
+    fn unwind(&mut self, exception: ObjectRef) -> Result<(), RuntimeError> {
+        let Exception { tag, payload } = match self.heap.get(exception)? {
+            HeapObject::Exception(exception) => exception.clone(),
+            _ => {
+                return Err(RuntimeError::NotFound(format!(
+                    "exception {}",
+                    exception.index()
+                )))
+            }
+        };
+
+        for label_index in (0..self.label_stack.len()).rev() {
+            let catches = match &self.label_stack[label_index].label_type {
+                LabelType::TryTable(catches) => catches,
+                _ => continue,
+            };
+
+            let handler = catches.iter().find_map(|catch| match *catch {
+                CatchClause::Catch(t, label) if self.tags.get(t as usize) == Some(&tag) => {
+                    Some((label, false, false))
+                }
+                CatchClause::CatchRef(t, label) if self.tags.get(t as usize) == Some(&tag) => {
+                    Some((label, false, true))
+                }
+                CatchClause::CatchAll(label) => Some((label, true, false)),
+                CatchClause::CatchAllRef(label) => Some((label, true, true)),
+                _ => None,
+            });
+            let (label, catch_all, with_ref) = match handler {
+                Some(handler) => handler,
+                None => continue,
+            };
+
+            while self.activation_stack.label_base()? > label_index {
+                self.apop()?;
+            }
+
+            let height = self.label_stack[label_index].stack_height;
+            self.label_stack.truncate(label_index);
+            self.value_stack.truncate(height);
+
+            if !catch_all {
+                self.value_stack.extend(payload);
+            }
+            if with_ref {
+                self.vpush(RuntimeValue::ExnRef(exception));
+            }
+
+            return self.br(label as usize);
+        }
+
+        Err(RuntimeError::UncaughtException(tag, payload))
     }
 
     fn select(&mut self) -> Result<(), RuntimeError> {
@@ -642,17 +1840,15 @@ impl Runtime {
         }
         let condition = bool::from(self.value_stack.pop().unwrap());
 
+        let pc = self.pc();
         if condition {
-            let pc = self.pc();
-            self.label_stack.push(Label::new_if(pc, block_type, true));
+            self.push_label(pc, LabelType::If(true), block_type)
         } else {
-            self.skip_else_or_end()?;
+            let pc = self.block_end(pc, true)?;
+            self.set_pc(pc)?;
 
-            let pc = self.pc();
-            self.label_stack.push(Label::new_if(pc, block_type, false));
+            self.push_label(pc, LabelType::If(false), block_type)
         }
-
-        Ok(())
     }
 
     fn _else(&mut self) -> Result<(), RuntimeError> {
@@ -660,14 +1856,11 @@ impl Runtime {
 
         match label.label_type {
             LabelType::If(condition) => {
+                // then節を実行し終えたので、else節を飛ばしてENDでラベルを外す
                 if condition {
-                    while let Some(instruction) = self.instructions()?.get(self.pc()) {
-                        self.increment_pc()?;
-                        if *instruction == Instruction::End {
-                            break;
-                        }
-                        continue;
-                    }
+                    let pc = self.pc();
+                    let pc = self.block_end(pc, false)?;
+                    self.set_pc(pc)?;
                 }
             }
             _ => unreachable!("{:?}", label),
@@ -677,27 +1870,49 @@ impl Runtime {
     }
 
     fn br(&mut self, depth: usize) -> Result<(), RuntimeError> {
+        let label_base = self.activation_stack.label_base()?;
+
+        // 関数自体のラベルへの分岐はreturnと同じ
+        if depth >= self.label_stack.len() - label_base {
+            return self._return();
+        }
+
         for _ in 0..depth {
             self.lpop()?;
         }
 
-        let label = if let Some(label) = self.label_stack.last() {
-            label
-        } else {
-            todo!("ラベルがない場合の処理はあとから書く")
+        let label = self.get_label()?;
+        let (pc, height, arity) = (label.pc, label.stack_height, label.arity);
+
+        let pc = match label.label_type {
+            LabelType::Loop => pc,
+            _ => self.block_end(pc, false)?,
         };
 
-        match label.label_type {
-            LabelType::Loop => {
-                let pc = label.pc;
-                self.set_pc(pc)?;
-            }
-            _ => {
-                for _ in 0..depth + 1 {
-                    self.skip_to_end()?
-                }
+        let results = self.vpop_n(arity)?;
+        self.value_stack.truncate(height);
+        self.value_stack.extend(results);
+
+        self.set_pc(pc)
+    }
+
+    /// 戻り値だけを残して、呼び出し時点の高さまでvalue stackを巻き戻す
+    fn _return(&mut self) -> Result<(), RuntimeError> {
+        let activation = self.apop()?;
+        self.label_stack.truncate(activation.label_base);
+
+        let len = match self.function_table.get(activation.function_index) {
+            Some(func) => func.returns.len(),
+            None => {
+                return Err(RuntimeError::NotFound(format!(
+                    "function {}",
+                    activation.function_index
+                )))
             }
         };
+        let results = self.vpop_n(len)?;
+        self.value_stack.truncate(activation.stack_height);
+        self.value_stack.extend(results);
 
         Ok(())
     }
@@ -723,6 +1938,67 @@ impl Runtime {
         self.activation_stack.increment_pc()
     }
 
+    /// 命令1つ分のfuelを使う。使い切っていればエラーにする
+    fn consume_fuel(&mut self) -> Result<(), RuntimeError> {
+        match self.fuel.as_mut() {
+            None => Ok(()),
+            Some(0) => Err(RuntimeError::OutOfFuel),
+            Some(fuel) => {
+                *fuel -= 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// canonicalize_nansが有効なら、スタックの一番上にある浮動小数点数のNaNを正のquiet NaNにそろえる
+    /// canonicalizeがtrueなら、Config::canonicalize_nansに従って結果のNaNをそろえる
+    fn float_unop<T>(
+        &mut self,
+        f: impl FnOnce(T) -> T,
+        canonicalize: bool,
+    ) -> Result<(), RuntimeError>
+    where
+        T: From<RuntimeValue> + Into<RuntimeValue>,
+    {
+        let v = T::from(self.vpop()?);
+        self.vpush(f(v).into());
+        if canonicalize {
+            self.canonicalize_nan();
+        }
+
+        Ok(())
+    }
+
+    fn float_binop<T>(
+        &mut self,
+        f: impl FnOnce(T, T) -> T,
+        canonicalize: bool,
+    ) -> Result<(), RuntimeError>
+    where
+        T: From<RuntimeValue> + Into<RuntimeValue>,
+    {
+        let b = T::from(self.vpop()?);
+        let a = T::from(self.vpop()?);
+        self.vpush(f(a, b).into());
+        if canonicalize {
+            self.canonicalize_nan();
+        }
+
+        Ok(())
+    }
+
+    fn canonicalize_nan(&mut self) {
+        if !self.canonicalize_nans {
+            return;
+        }
+
+        match self.value_stack.last_mut() {
+            Some(RuntimeValue::F32(v)) if v.is_nan() => *v = f32::NAN,
+            Some(RuntimeValue::F64(v)) if v.is_nan() => *v = f64::NAN,
+            _ => {}
+        }
+    }
+
     fn instructions(&self) -> Result<Vec<Instruction>, RuntimeError> {
         let i = match self.activation_stack.last() {
             None => return Ok(vec![]),
@@ -733,30 +2009,26 @@ impl Runtime {
         Ok(instructions)
     }
 
-    fn skip_else_or_end(&mut self) -> Result<(), RuntimeError> {
-        while let Some(instruction) = self.instructions()?.get(self.pc()) {
-            if *instruction == Instruction::Else || *instruction == Instruction::End {
-                break;
-            }
-
-            self.increment_pc()?;
-            continue;
-        }
-
-        Ok(())
-    }
+    /// pcから始まるブロックに対応するENDの位置を返す。
+    /// stop_at_elseがtrueなら同じ深さのELSEでも止まる
+    fn block_end(&self, pc: usize, stop_at_else: bool) -> Result<usize, RuntimeError> {
+        let instructions = self.instructions()?;
+        let mut depth = 0;
 
-    fn skip_to_end(&mut self) -> Result<(), RuntimeError> {
-        while let Some(instruction) = self.instructions()?.get(self.pc()) {
-            if *instruction == Instruction::End {
-                break;
+        for (i, instruction) in instructions.iter().enumerate().skip(pc) {
+            match instruction {
+                Instruction::Block(_)
+                | Instruction::Loop(_)
+                | Instruction::If(_)
+                | Instruction::TryTable(_, _) => depth += 1,
+                Instruction::Else if depth == 0 && stop_at_else => return Ok(i),
+                Instruction::End if depth == 0 => return Ok(i),
+                Instruction::End => depth -= 1,
+                _ => {}
             }
-
-            self.increment_pc()?;
-            continue;
         }
 
-        Ok(())
+        Ok(instructions.len())
     }
 
     fn get_instruction(&mut self) -> Result<Option<Instruction>, RuntimeError> {
@@ -771,7 +2043,71 @@ impl Runtime {
                 return Ok(instruction.cloned());
             }
 
-            self.activation_stack.pop();
+            self._return()?;
+        }
+    }
+}
+
+/// f32とf64のmin、maxを共通に書くためのもの
+trait WasmFloat: Copy + PartialOrd {
+    const NAN: Self;
+    fn is_nan(self) -> bool;
+    fn is_sign_negative(self) -> bool;
+}
+
+impl WasmFloat for f32 {
+    const NAN: Self = f32::NAN;
+
+    fn is_nan(self) -> bool {
+        f32::is_nan(self)
+    }
+
+    fn is_sign_negative(self) -> bool {
+        f32::is_sign_negative(self)
+    }
+}
+
+impl WasmFloat for f64 {
+    const NAN: Self = f64::NAN;
+
+    fn is_nan(self) -> bool {
+        f64::is_nan(self)
+    }
+
+    fn is_sign_negative(self) -> bool {
+        f64::is_sign_negative(self)
+    }
+}
+
+/// どちらかがNaNならNaN。-0と+0では-0を小さいとみなす
+fn wasm_min<T: WasmFloat>(a: T, b: T) -> T {
+    if a.is_nan() || b.is_nan() {
+        T::NAN
+    } else if a == b {
+        if a.is_sign_negative() {
+            a
+        } else {
+            b
+        }
+    } else if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+fn wasm_max<T: WasmFloat>(a: T, b: T) -> T {
+    if a.is_nan() || b.is_nan() {
+        T::NAN
+    } else if a == b {
+        if a.is_sign_negative() {
+            b
+        } else {
+            a
         }
+    } else if a > b {
+        a
+    } else {
+        b
     }
 }
@@ -1,4 +1,5 @@
-use crate::types::ValueType;
+use crate::runtime::heap::ObjectRef;
+use crate::types::{HeapType, RefType, ValueType};
 use core::num::ParseIntError;
 use core::str::FromStr;
 
@@ -9,6 +10,16 @@ pub enum RuntimeValue {
     F32(f32),
     F64(f64),
     V128(u128),
+    /// 例外への参照。例外はstruct/arrayと同じGCヒープに置き、どこからも参照されなくなれば回収する
+    ExnRef(ObjectRef),
+    /// null参照。どの型のnullかは区別しない
+    NullRef,
+    /// i31ref。下位31bitだけを保持する
+    I31Ref(i32),
+    /// GCヒープ上のstruct/arrayへの参照
+    HeapRef(ObjectRef),
+    /// 関数への参照。中身はfunction index
+    FuncRef(u32),
 }
 
 impl RuntimeValue {
@@ -20,20 +31,62 @@ impl RuntimeValue {
             I64(_) => ValueType::I64,
             F32(_) => ValueType::F32,
             F64(_) => ValueType::F64,
+            ExnRef(_) => ValueType::Ref(RefType::new(false, HeapType::Exn)),
+            NullRef => ValueType::Ref(RefType::nullable(HeapType::None)),
+            I31Ref(_) => ValueType::Ref(RefType::new(false, HeapType::I31)),
+            HeapRef(_) => ValueType::Ref(RefType::new(false, HeapType::Eq)),
+            FuncRef(_) => ValueType::Ref(RefType::new(false, HeapType::Func)),
             _ => unreachable!("unreachable type, but got {:?}", self),
         }
     }
+
+    /// 値がtの型として渡せるかどうか。参照の指す先の型までは見ない
+    pub fn matches(&self, t: &ValueType) -> bool {
+        match (self, t) {
+            (RuntimeValue::NullRef, ValueType::Ref(ref_type)) => ref_type.nullable,
+            (v, ValueType::Ref(_)) => v.is_ref(),
+            (v, t) => v.to_type() == *t,
+        }
+    }
+
+    pub fn is_ref(&self) -> bool {
+        use RuntimeValue::*;
+
+        matches!(
+            self,
+            ExnRef(_) | NullRef | I31Ref(_) | HeapRef(_) | FuncRef(_)
+        )
+    }
+
+    /// localやstructのフィールドの初期値。non-nullableな参照型には初期値がない
+    pub fn default_of(t: ValueType) -> Option<Self> {
+        let v = match t {
+            ValueType::I32 => RuntimeValue::I32(0),
+            ValueType::I64 => RuntimeValue::I64(0),
+            ValueType::F32 => RuntimeValue::F32(0.0),
+            ValueType::F64 => RuntimeValue::F64(0.0),
+            ValueType::Ref(ref_type) if ref_type.nullable => RuntimeValue::NullRef,
+            _ => return None,
+        };
+
+        Some(v)
+    }
 }
 
 impl From<RuntimeValue> for i32 {
     fn from(v: RuntimeValue) -> i32 {
         use RuntimeValue::*;
         match v {
-            I32(x) => x as i32,
+            I32(x) => x,
             I64(x) => x as i32,
             F32(x) => x as i32,
             F64(x) => x as i32,
             V128(x) => x as i32,
+            ExnRef(x) => x.index() as i32,
+            NullRef => 0,
+            I31Ref(x) => x,
+            HeapRef(x) => x.index() as i32,
+            FuncRef(x) => x as i32,
         }
     }
 }
@@ -47,6 +100,11 @@ impl From<RuntimeValue> for u32 {
             F32(x) => x as u32,
             F64(x) => x as u32,
             V128(x) => x as u32,
+            ExnRef(x) => x.index(),
+            NullRef => 0,
+            I31Ref(x) => x as u32,
+            HeapRef(x) => x.index(),
+            FuncRef(x) => x,
         }
     }
 }
@@ -56,10 +114,15 @@ impl From<RuntimeValue> for i64 {
         use RuntimeValue::*;
         match v {
             I32(x) => x as i64,
-            I64(x) => x as i64,
+            I64(x) => x,
             F32(x) => x as i64,
             F64(x) => x as i64,
             V128(x) => x as i64,
+            ExnRef(x) => x.index() as i64,
+            NullRef => 0,
+            I31Ref(x) => x as i64,
+            HeapRef(x) => x.index() as i64,
+            FuncRef(x) => x as i64,
         }
     }
 }
@@ -73,6 +136,11 @@ impl From<RuntimeValue> for usize {
             F32(x) => x as usize,
             F64(x) => x as usize,
             V128(x) => x as usize,
+            ExnRef(x) => x.index() as usize,
+            NullRef => 0,
+            I31Ref(x) => x as usize,
+            HeapRef(x) => x.index() as usize,
+            FuncRef(x) => x as usize,
         }
     }
 }
@@ -86,6 +154,11 @@ impl From<RuntimeValue> for bool {
             F32(x) => x as u32 != 0,
             F64(x) => x as u32 != 0,
             V128(x) => x != 0,
+            ExnRef(x) => x.index() != 0,
+            NullRef => false,
+            I31Ref(x) => x != 0,
+            HeapRef(x) => x.index() != 0,
+            FuncRef(x) => x != 0,
         }
     }
 }
@@ -95,9 +168,14 @@ impl From<RuntimeValue> for f32 {
         match v {
             I32(x) => x as f32,
             I64(x) => x as f32,
-            F32(x) => x as f32,
+            F32(x) => x,
             F64(x) => x as f32,
             V128(x) => x as f32,
+            ExnRef(x) => x.index() as f32,
+            NullRef => 0.0,
+            I31Ref(x) => x as f32,
+            HeapRef(x) => x.index() as f32,
+            FuncRef(x) => x as f32,
         }
     }
 }
@@ -109,8 +187,13 @@ impl From<RuntimeValue> for f64 {
             I32(x) => x as f64,
             I64(x) => x as f64,
             F32(x) => x as f64,
-            F64(x) => x as f64,
+            F64(x) => x,
             V128(x) => x as f64,
+            ExnRef(x) => x.index() as f64,
+            NullRef => 0.0,
+            I31Ref(x) => x as f64,
+            HeapRef(x) => x.index() as f64,
+            FuncRef(x) => x as f64,
         }
     }
 }
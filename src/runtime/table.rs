@@ -0,0 +1,94 @@
+use crate::runtime::{RuntimeError, RuntimeValue};
+use crate::types::TableType;
+
+/// 参照を並べたテーブル。要素は最初はすべてnull
+#[derive(Debug, Clone)]
+pub struct Table {
+    elements: Vec<RuntimeValue>,
+    table_type: TableType,
+}
+
+impl Table {
+    pub fn new(table_type: TableType) -> Self {
+        Self {
+            elements: vec![RuntimeValue::NullRef; table_type.limits.initial as usize],
+            table_type,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.elements.len() as u32
+    }
+
+    pub fn get(&self, index: u32) -> Result<RuntimeValue, RuntimeError> {
+        match self.elements.get(index as usize) {
+            Some(v) => Ok(*v),
+            None => Err(RuntimeError::TableOutOfBounds),
+        }
+    }
+
+    pub fn set(&mut self, index: u32, v: RuntimeValue) -> Result<(), RuntimeError> {
+        match self.elements.get_mut(index as usize) {
+            Some(element) => {
+                *element = v;
+                Ok(())
+            }
+            None => Err(RuntimeError::TableOutOfBounds),
+        }
+    }
+
+    /// 成功したら元のサイズを返す。最大値を超えるときはNone
+    pub fn grow(&mut self, delta: u32, init: RuntimeValue) -> Option<u32> {
+        let old = self.size();
+        let new = old.checked_add(delta)?;
+        if let Some(maximum) = self.table_type.limits.maximum {
+            if u64::from(new) > maximum {
+                return None;
+            }
+        }
+
+        self.elements.resize(new as usize, init);
+        Some(old)
+    }
+
+    pub fn fill(&mut self, index: u32, v: RuntimeValue, len: u32) -> Result<(), RuntimeError> {
+        let end = index
+            .checked_add(len)
+            .ok_or(RuntimeError::TableOutOfBounds)?;
+        match self.elements.get_mut(index as usize..end as usize) {
+            Some(elements) => {
+                elements.fill(v);
+                Ok(())
+            }
+            None => Err(RuntimeError::TableOutOfBounds),
+        }
+    }
+
+    /// indexから順にvaluesを書き込む。はみ出すときは何も書き込まない
+    pub fn write(&mut self, index: u32, values: &[RuntimeValue]) -> Result<(), RuntimeError> {
+        let end = (index as usize)
+            .checked_add(values.len())
+            .ok_or(RuntimeError::TableOutOfBounds)?;
+        match self.elements.get_mut(index as usize..end) {
+            Some(elements) => {
+                elements.copy_from_slice(values);
+                Ok(())
+            }
+            None => Err(RuntimeError::TableOutOfBounds),
+        }
+    }
+
+    /// indexから長さlenの要素
+    pub fn read(&self, index: u32, len: u32) -> Result<&[RuntimeValue], RuntimeError> {
+        let end = index
+            .checked_add(len)
+            .ok_or(RuntimeError::TableOutOfBounds)?;
+        self.elements
+            .get(index as usize..end as usize)
+            .ok_or(RuntimeError::TableOutOfBounds)
+    }
+
+    pub(crate) fn elements(&self) -> &[RuntimeValue] {
+        &self.elements
+    }
+}
@@ -0,0 +1,30 @@
+use crate::runtime::RuntimeValue;
+use crate::types::FuncType;
+use std::sync::Arc;
+
+/// exceptionを識別するためのtag。
+/// 同じシグネチャでも別々に定義されたtagは区別されるので、比較は型ではなく実体の同一性で行う
+#[derive(Debug, Clone)]
+pub struct Tag(Arc<FuncType>);
+
+impl Tag {
+    pub fn new(func_type: FuncType) -> Self {
+        Self(Arc::new(func_type))
+    }
+
+    pub fn func_type(&self) -> &FuncType {
+        &self.0
+    }
+}
+
+impl PartialEq for Tag {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exception {
+    pub tag: Tag,
+    pub payload: Vec<RuntimeValue>,
+}
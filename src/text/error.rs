@@ -0,0 +1,57 @@
+use crate::validate::ValidationError;
+use std::error::Error;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// テキストとして読めない。字句や構文の誤り、定義されていない名前など
+    Syntax { message: String, position: Position },
+    /// 読めたが、検証に失敗した
+    Invalid(ValidationError),
+}
+
+/// テキストの中の位置。行と列はどちらも1から数え、列は文字単位
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl ParseError {
+    pub(crate) fn new(message: impl Into<String>, position: Position) -> Self {
+        ParseError::Syntax {
+            message: message.into(),
+            position,
+        }
+    }
+
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            ParseError::Syntax { position, .. } => Some(*position),
+            ParseError::Invalid(_) => None,
+        }
+    }
+}
+
+impl Error for ParseError {}
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ParseError::*;
+        match self {
+            Syntax { message, position } => write!(f, "{} ({})", message, position),
+            Invalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at line {}, column {}", self.line, self.column)
+    }
+}
+
+impl From<ValidationError> for ParseError {
+    fn from(error: ValidationError) -> Self {
+        Self::Invalid(error)
+    }
+}
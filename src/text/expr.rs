@@ -0,0 +1,456 @@
+use super::error::{ParseError, Position};
+use super::keywords;
+use super::lexer::TokenKind;
+use super::number;
+use super::parser::{Kind, Parser};
+use crate::types::*;
+use std::collections::HashMap;
+
+/// 関数本体や定数式の中で使える名前
+#[derive(Debug, Default)]
+pub(crate) struct Scope<'a> {
+    locals: HashMap<&'a str, u32>,
+    /// 外側から順に並んだblockのラベル。名前の無いblockはNone
+    labels: Vec<Option<&'a str>>,
+}
+
+impl<'a> Scope<'a> {
+    pub(crate) fn define_local(
+        &mut self,
+        name: &'a str,
+        index: u32,
+        position: Position,
+    ) -> Result<(), ParseError> {
+        if self.locals.insert(name, index).is_some() {
+            return Err(ParseError::new(
+                format!("duplicate local ${}", name),
+                position,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Parser<'a> {
+    /// 命令を)、end、elseの手前まで読む。括弧で包んだ命令は展開して並べる
+    pub(crate) fn instructions(
+        &mut self,
+        scope: &mut Scope<'a>,
+    ) -> Result<Vec<Instruction>, ParseError> {
+        let mut out = vec![];
+        self.instructions_into(scope, &mut out)?;
+
+        Ok(out)
+    }
+
+    /// 括弧で包んだ命令を1つ読む
+    pub(crate) fn folded(&mut self, scope: &mut Scope<'a>) -> Result<Vec<Instruction>, ParseError> {
+        let mut out = vec![];
+        self.folded_instruction(scope, &mut out)?;
+
+        Ok(out)
+    }
+
+    fn instructions_into(
+        &mut self,
+        scope: &mut Scope<'a>,
+        out: &mut Vec<Instruction>,
+    ) -> Result<(), ParseError> {
+        loop {
+            match self.peek() {
+                Some(TokenKind::LParen) => self.folded_instruction(scope, out)?,
+                Some(TokenKind::Atom(s)) if !matches!(*s, "end" | "else") => {
+                    self.flat_instruction(scope, out)?
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn flat_instruction(
+        &mut self,
+        scope: &mut Scope<'a>,
+        out: &mut Vec<Instruction>,
+    ) -> Result<(), ParseError> {
+        let position = self.position();
+        let keyword = self.atom("instruction")?;
+
+        if !matches!(keyword, "block" | "loop" | "if" | "try_table") {
+            out.push(self.plain_instruction(keyword, position, scope)?);
+            return Ok(());
+        }
+
+        let label = self.id();
+        out.push(self.block_instruction(keyword, scope)?);
+        scope.labels.push(label);
+
+        self.instructions_into(scope, out)?;
+        if keyword == "if" && self.keyword("else") {
+            self.end_label(label)?;
+            out.push(Instruction::Else);
+            self.instructions_into(scope, out)?;
+        }
+        if !self.keyword("end") {
+            return Err(self.expected("end"));
+        }
+        self.end_label(label)?;
+
+        scope.labels.pop();
+        out.push(Instruction::End);
+
+        Ok(())
+    }
+
+    /// 括弧で包んだ命令は、中の命令を先に並べてから自身を置く
+    fn folded_instruction(
+        &mut self,
+        scope: &mut Scope<'a>,
+        out: &mut Vec<Instruction>,
+    ) -> Result<(), ParseError> {
+        self.lparen()?;
+        let position = self.position();
+        let keyword = self.atom("instruction")?;
+
+        match keyword {
+            "block" | "loop" | "try_table" => {
+                let label = self.id();
+                out.push(self.block_instruction(keyword, scope)?);
+                scope.labels.push(label);
+                self.instructions_into(scope, out)?;
+                scope.labels.pop();
+                out.push(Instruction::End);
+            }
+            "if" => {
+                let label = self.id();
+                let instruction = self.block_instruction(keyword, scope)?;
+                // 条件はifのblockの外で評価する
+                while self.peek_lparen() && !self.peek_list("then") {
+                    self.folded_instruction(scope, out)?;
+                }
+                out.push(instruction);
+
+                scope.labels.push(label);
+                if !self.list("then") {
+                    return Err(self.expected("(then"));
+                }
+                self.instructions_into(scope, out)?;
+                self.rparen()?;
+                if self.list("else") {
+                    out.push(Instruction::Else);
+                    self.instructions_into(scope, out)?;
+                    self.rparen()?;
+                }
+                scope.labels.pop();
+                out.push(Instruction::End);
+            }
+            _ => {
+                let instruction = self.plain_instruction(keyword, position, scope)?;
+                while self.peek_lparen() {
+                    self.folded_instruction(scope, out)?;
+                }
+                out.push(instruction);
+            }
+        }
+
+        self.rparen()
+    }
+
+    /// endやelseの後ろに書くラベルは、blockにつけた名前と同じでなければならない
+    fn end_label(&mut self, label: Option<&'a str>) -> Result<(), ParseError> {
+        let position = self.position();
+        match self.id() {
+            Some(id) if label != Some(id) => Err(ParseError::new(
+                format!("mismatched label ${}", id),
+                position,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// block、loop、if、try_tableのラベルより後ろの即値
+    fn block_instruction(
+        &mut self,
+        keyword: &str,
+        scope: &Scope<'a>,
+    ) -> Result<Instruction, ParseError> {
+        let block_type = self.block_type()?;
+
+        let instruction = match keyword {
+            "block" => Instruction::Block(block_type),
+            "loop" => Instruction::Loop(block_type),
+            "if" => Instruction::If(block_type),
+            _ => {
+                // catchのラベルはtry_tableの外側から数える
+                let mut catches = vec![];
+                loop {
+                    let clause = if self.list("catch") {
+                        CatchClause::Catch(self.index(Kind::Tag)?, self.label(scope)?)
+                    } else if self.list("catch_ref") {
+                        CatchClause::CatchRef(self.index(Kind::Tag)?, self.label(scope)?)
+                    } else if self.list("catch_all") {
+                        CatchClause::CatchAll(self.label(scope)?)
+                    } else if self.list("catch_all_ref") {
+                        CatchClause::CatchAllRef(self.label(scope)?)
+                    } else {
+                        break;
+                    };
+                    self.rparen()?;
+                    catches.push(clause);
+                }
+
+                Instruction::TryTable(block_type, catches)
+            }
+        };
+
+        Ok(instruction)
+    }
+
+    /// blockの型。(type x)があるか、引数を取るか複数の値を返すblockは関数型のindexで表す
+    fn block_type(&mut self) -> Result<BlockType, ParseError> {
+        if self.peek_list("type") {
+            let (index, _) = self.type_use()?;
+            return Ok(BlockType::TypeIndex(index));
+        }
+
+        let params: Vec<ValueType> = self.params()?.into_iter().map(|(_, t)| t).collect();
+        let results = self.results()?;
+        let block_type = match (params.as_slice(), results.as_slice()) {
+            ([], []) => BlockType::Empty,
+            ([], [ValueType::I32]) => BlockType::I32,
+            ([], [ValueType::I64]) => BlockType::I64,
+            ([], [ValueType::F32]) => BlockType::F32,
+            ([], [ValueType::F64]) => BlockType::F64,
+            ([], [ValueType::Ref(t)]) => BlockType::Ref(*t),
+            _ => BlockType::TypeIndex(self.func_type_index(FuncType::new(params, results))),
+        };
+
+        Ok(block_type)
+    }
+
+    /// blockを作らない命令の即値を読む
+    fn plain_instruction(
+        &mut self,
+        keyword: &str,
+        position: Position,
+        scope: &Scope<'a>,
+    ) -> Result<Instruction, ParseError> {
+        use Instruction::*;
+
+        let keyword = keywords::alias(keyword);
+        if keyword == "select" {
+            // 型を明示したselectも同じ命令として扱う
+            self.results()?;
+        }
+        if let Some(instruction) = keywords::plain_instruction(keyword) {
+            return Ok(instruction);
+        }
+        if let Some((make, align)) = keywords::memory_instruction(keyword) {
+            return Ok(make(self.memarg(align)?));
+        }
+        if let Some((make, align)) = keywords::atomic_instruction(keyword) {
+            return Ok(make(self.memarg(align)?));
+        }
+
+        let instruction = match keyword {
+            "br" => Br(self.label(scope)?.into()),
+            "br_if" => BrIf(self.label(scope)?.into()),
+            "br_table" => {
+                let mut labels = vec![self.label(scope)?.into()];
+                while self.peek_index() {
+                    labels.push(self.label(scope)?.into());
+                }
+                let default = labels.pop().expect("br_table has at least one label");
+                BrTable(labels, default)
+            }
+            "br_on_null" => BrOnNull(self.label(scope)?.into()),
+            "br_on_non_null" => BrOnNonNull(self.label(scope)?.into()),
+            "br_on_cast" => BrOnCast(
+                self.label(scope)?.into(),
+                self.ref_type()?,
+                self.ref_type()?,
+            ),
+            "br_on_cast_fail" => BrOnCastFail(
+                self.label(scope)?.into(),
+                self.ref_type()?,
+                self.ref_type()?,
+            ),
+            "call" => Call(self.index(Kind::Func)?.into()),
+            "call_indirect" => {
+                let table = self.optional_index(Kind::Table)?.unwrap_or(0);
+                let (type_index, _) = self.type_use()?;
+                CallIndirect(type_index.into(), table.into())
+            }
+            "call_ref" => CallRef(self.index(Kind::Type)?.into()),
+            "return_call_ref" => ReturnCallRef(self.index(Kind::Type)?.into()),
+            "throw" => Throw(self.index(Kind::Tag)?.into()),
+            "local.get" => GetLocal(self.local(scope)?.into()),
+            "local.set" => SetLocal(self.local(scope)?.into()),
+            "local.tee" => TeeLocal(self.local(scope)?.into()),
+            "global.get" => GetGlobal(self.index(Kind::Global)?.into()),
+            "global.set" => SetGlobal(self.index(Kind::Global)?.into()),
+            "table.get" => TableGet(self.table_or_zero()?.into()),
+            "table.set" => TableSet(self.table_or_zero()?.into()),
+            "table.grow" => TableGrow(self.table_or_zero()?.into()),
+            "table.size" => TableSize(self.table_or_zero()?.into()),
+            "table.fill" => TableFill(self.table_or_zero()?.into()),
+            "table.init" => {
+                // indexが2つあれば1つ目はtable
+                let table = if self.peek_index_at(1) {
+                    self.index(Kind::Table)?
+                } else {
+                    0
+                };
+                TableInit(self.index(Kind::Elem)?.into(), table.into())
+            }
+            "elem.drop" => ElemDrop(self.index(Kind::Elem)?.into()),
+            "table.copy" => {
+                let dst = self.optional_index(Kind::Table)?;
+                let src = match dst {
+                    Some(_) => self.index(Kind::Table)?,
+                    None => 0,
+                };
+                TableCopy(dst.unwrap_or(0).into(), src.into())
+            }
+            "memory.size" => CurrentMemory(self.memory_or_zero()?.into()),
+            "memory.grow" => GrowMemory(self.memory_or_zero()?.into()),
+            "memory.fill" => MemoryFill(self.memory_or_zero()?.into()),
+            "memory.copy" => {
+                let dst = self.optional_index(Kind::Memory)?;
+                let src = match dst {
+                    Some(_) => self.index(Kind::Memory)?,
+                    None => 0,
+                };
+                MemoryCopy(dst.unwrap_or(0).into(), src.into())
+            }
+            "memory.init" => {
+                // indexが2つあれば1つ目はmemory
+                let memory = if self.peek_index_at(1) {
+                    self.index(Kind::Memory)?
+                } else {
+                    0
+                };
+                MemoryInit(self.index(Kind::Data)?.into(), memory.into())
+            }
+            "data.drop" => DataDrop(self.index(Kind::Data)?.into()),
+            "i32.const" => I32Const(self.number(number::parse_i32, "i32")?),
+            "i64.const" => I64Const(self.number(number::parse_i64, "i64")?),
+            "f32.const" => F32Const(self.number(number::parse_f32, "f32")?),
+            "f64.const" => F64Const(self.number(number::parse_f64, "f64")?),
+            "ref.null" => RefNull(self.heap_type()?),
+            "ref.func" => RefFunc(self.index(Kind::Func)?.into()),
+            "ref.test" => RefTest(self.ref_type()?),
+            "ref.cast" => RefCast(self.ref_type()?),
+            "struct.new" => StructNew(self.index(Kind::Type)?.into()),
+            "struct.new_default" => StructNewDefault(self.index(Kind::Type)?.into()),
+            "struct.get" | "struct.get_s" | "struct.get_u" | "struct.set" => {
+                let type_index = self.index(Kind::Type)?;
+                let field = self.field(type_index)?;
+                let make = match keyword {
+                    "struct.get" => StructGet,
+                    "struct.get_s" => StructGetS,
+                    "struct.get_u" => StructGetU,
+                    _ => StructSet,
+                };
+                make(type_index.into(), field.into())
+            }
+            "array.new" => ArrayNew(self.index(Kind::Type)?.into()),
+            "array.new_default" => ArrayNewDefault(self.index(Kind::Type)?.into()),
+            "array.new_fixed" => ArrayNewFixed(self.index(Kind::Type)?.into(), self.u32()?.into()),
+            "array.new_data" => ArrayNewData(
+                self.index(Kind::Type)?.into(),
+                self.index(Kind::Data)?.into(),
+            ),
+            "array.get" => ArrayGet(self.index(Kind::Type)?.into()),
+            "array.get_s" => ArrayGetS(self.index(Kind::Type)?.into()),
+            "array.get_u" => ArrayGetU(self.index(Kind::Type)?.into()),
+            "array.set" => ArraySet(self.index(Kind::Type)?.into()),
+            "array.fill" => ArrayFill(self.index(Kind::Type)?.into()),
+            "array.copy" => ArrayCopy(
+                self.index(Kind::Type)?.into(),
+                self.index(Kind::Type)?.into(),
+            ),
+            _ => {
+                return Err(ParseError::new(
+                    format!("unknown instruction `{}`", keyword),
+                    position,
+                ))
+            }
+        };
+
+        Ok(instruction)
+    }
+
+    /// memory index、offset=、align=の順に、どれも省略できる。alignはバイト数で書く
+    fn memarg(&mut self, natural_align: u32) -> Result<MemArg, ParseError> {
+        let memory = self.memory_or_zero()?;
+
+        let mut offset = 0;
+        if let Some(s) = self.peek_atom().and_then(|s| s.strip_prefix("offset=")) {
+            offset = number::parse_u64(s).ok_or_else(|| self.error("invalid offset"))?;
+            self.bump();
+        }
+
+        let mut align = natural_align;
+        if let Some(s) = self.peek_atom().and_then(|s| s.strip_prefix("align=")) {
+            align = match number::parse_u32(s) {
+                Some(bytes) if bytes.is_power_of_two() => bytes.trailing_zeros(),
+                _ => return Err(self.error("alignment must be a power of two")),
+            };
+            self.bump();
+        }
+
+        Ok(MemArg {
+            align,
+            offset,
+            memory,
+        })
+    }
+
+    fn table_or_zero(&mut self) -> Result<u32, ParseError> {
+        Ok(self.optional_index(Kind::Table)?.unwrap_or(0))
+    }
+
+    fn memory_or_zero(&mut self) -> Result<u32, ParseError> {
+        Ok(self.optional_index(Kind::Memory)?.unwrap_or(0))
+    }
+
+    fn local(&mut self, scope: &Scope<'a>) -> Result<u32, ParseError> {
+        let position = self.position();
+        match self.id() {
+            Some(id) => scope
+                .locals
+                .get(id)
+                .copied()
+                .ok_or_else(|| ParseError::new(format!("unknown local ${}", id), position)),
+            None => self.u32(),
+        }
+    }
+
+    /// ラベルの名前は内側から探して、何個外側のblockかに直す
+    fn label(&mut self, scope: &Scope<'a>) -> Result<u32, ParseError> {
+        let position = self.position();
+        match self.id() {
+            Some(id) => scope
+                .labels
+                .iter()
+                .rev()
+                .position(|label| *label == Some(id))
+                .map(|depth| depth as u32)
+                .ok_or_else(|| ParseError::new(format!("unknown label ${}", id), position)),
+            None => self.u32(),
+        }
+    }
+
+    fn field(&mut self, type_index: u32) -> Result<u32, ParseError> {
+        let position = self.position();
+        match self.id() {
+            Some(id) => self
+                .fields
+                .get(&(type_index, id))
+                .copied()
+                .ok_or_else(|| ParseError::new(format!("unknown field ${}", id), position)),
+            None => self.u32(),
+        }
+    }
+}
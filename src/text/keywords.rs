@@ -0,0 +1,345 @@
+use crate::types::*;
+
+/// 即値を持たない命令の名前。block、if、else、endは構造を表すので含めない
+pub(crate) const PLAIN_INSTRUCTIONS: &[(&str, Instruction)] = &[
+    ("unreachable", Instruction::Unreachable),
+    ("nop", Instruction::Nop),
+    ("return", Instruction::Return),
+    ("drop", Instruction::Drop),
+    ("select", Instruction::Select),
+    ("throw_ref", Instruction::ThrowRef),
+    ("atomic.fence", Instruction::AtomicFence),
+    ("i32.eqz", Instruction::I32Eqz),
+    ("i32.eq", Instruction::I32Eq),
+    ("i32.ne", Instruction::I32Ne),
+    ("i32.lt_s", Instruction::I32LtS),
+    ("i32.lt_u", Instruction::I32LtU),
+    ("i32.gt_s", Instruction::I32GtS),
+    ("i32.gt_u", Instruction::I32GtU),
+    ("i32.le_s", Instruction::I32LeS),
+    ("i32.le_u", Instruction::I32LeU),
+    ("i32.ge_s", Instruction::I32GeS),
+    ("i32.ge_u", Instruction::I32GeU),
+    ("i64.eqz", Instruction::I64Eqz),
+    ("i64.eq", Instruction::I64Eq),
+    ("i64.ne", Instruction::I64Ne),
+    ("i64.lt_s", Instruction::I64LtS),
+    ("i64.lt_u", Instruction::I64LtU),
+    ("i64.gt_s", Instruction::I64GtS),
+    ("i64.gt_u", Instruction::I64GtU),
+    ("i64.le_s", Instruction::I64LeS),
+    ("i64.le_u", Instruction::I64LeU),
+    ("i64.ge_s", Instruction::I64GeS),
+    ("i64.ge_u", Instruction::I64GeU),
+    ("f32.eq", Instruction::F32Eq),
+    ("f32.ne", Instruction::F32Ne),
+    ("f32.lt", Instruction::F32Lt),
+    ("f32.gt", Instruction::F32Gt),
+    ("f32.le", Instruction::F32Le),
+    ("f32.ge", Instruction::F32Ge),
+    ("f64.eq", Instruction::F64Eq),
+    ("f64.ne", Instruction::F64Ne),
+    ("f64.lt", Instruction::F64Lt),
+    ("f64.gt", Instruction::F64Gt),
+    ("f64.le", Instruction::F64Le),
+    ("f64.ge", Instruction::F64Ge),
+    ("i32.clz", Instruction::I32Clz),
+    ("i32.ctz", Instruction::I32Ctz),
+    ("i32.popcnt", Instruction::I32Popcnt),
+    ("i32.add", Instruction::I32Add),
+    ("i32.sub", Instruction::I32Sub),
+    ("i32.mul", Instruction::I32Mul),
+    ("i32.div_s", Instruction::I32DivS),
+    ("i32.div_u", Instruction::I32DivU),
+    ("i32.rem_s", Instruction::I32RemS),
+    ("i32.rem_u", Instruction::I32RemU),
+    ("i32.and", Instruction::I32And),
+    ("i32.or", Instruction::I32Or),
+    ("i32.xor", Instruction::I32Xor),
+    ("i32.shl", Instruction::I32Shl),
+    ("i32.shr_s", Instruction::I32ShrS),
+    ("i32.shr_u", Instruction::I32ShrU),
+    ("i32.rotl", Instruction::I32Rotl),
+    ("i32.rotr", Instruction::I32Rotr),
+    ("i64.clz", Instruction::I64Clz),
+    ("i64.ctz", Instruction::I64Ctz),
+    ("i64.popcnt", Instruction::I64Popcnt),
+    ("i64.add", Instruction::I64Add),
+    ("i64.sub", Instruction::I64Sub),
+    ("i64.mul", Instruction::I64Mul),
+    ("i64.div_s", Instruction::I64DivS),
+    ("i64.div_u", Instruction::I64DivU),
+    ("i64.rem_s", Instruction::I64RemS),
+    ("i64.rem_u", Instruction::I64RemU),
+    ("i64.and", Instruction::I64And),
+    ("i64.or", Instruction::I64Or),
+    ("i64.xor", Instruction::I64Xor),
+    ("i64.shl", Instruction::I64Shl),
+    ("i64.shr_s", Instruction::I64ShrS),
+    ("i64.shr_u", Instruction::I64ShrU),
+    ("i64.rotl", Instruction::I64Rotl),
+    ("i64.rotr", Instruction::I64Rotr),
+    ("f32.abs", Instruction::F32Abs),
+    ("f32.neg", Instruction::F32Neg),
+    ("f32.ceil", Instruction::F32Ceil),
+    ("f32.floor", Instruction::F32Floor),
+    ("f32.trunc", Instruction::F32Trunc),
+    ("f32.nearest", Instruction::F32Nearest),
+    ("f32.sqrt", Instruction::F32Sqrt),
+    ("f32.add", Instruction::F32Add),
+    ("f32.sub", Instruction::F32Sub),
+    ("f32.mul", Instruction::F32Mul),
+    ("f32.div", Instruction::F32Div),
+    ("f32.min", Instruction::F32Min),
+    ("f32.max", Instruction::F32Max),
+    ("f32.copysign", Instruction::F32Copysign),
+    ("f64.abs", Instruction::F64Abs),
+    ("f64.neg", Instruction::F64Neg),
+    ("f64.ceil", Instruction::F64Ceil),
+    ("f64.floor", Instruction::F64Floor),
+    ("f64.trunc", Instruction::F64Trunc),
+    ("f64.nearest", Instruction::F64Nearest),
+    ("f64.sqrt", Instruction::F64Sqrt),
+    ("f64.add", Instruction::F64Add),
+    ("f64.sub", Instruction::F64Sub),
+    ("f64.mul", Instruction::F64Mul),
+    ("f64.div", Instruction::F64Div),
+    ("f64.min", Instruction::F64Min),
+    ("f64.max", Instruction::F64Max),
+    ("f64.copysign", Instruction::F64Copysign),
+    ("i32.wrap_i64", Instruction::I32WrapI64),
+    ("i32.trunc_f32_s", Instruction::I32TruncSF32),
+    ("i32.trunc_f32_u", Instruction::I32TruncUF32),
+    ("i32.trunc_f64_s", Instruction::I32TruncSF64),
+    ("i32.trunc_f64_u", Instruction::I32TruncUF64),
+    ("i64.extend_i32_s", Instruction::I64ExtendSI32),
+    ("i64.extend_i32_u", Instruction::I64ExtendUI32),
+    ("i64.trunc_f32_s", Instruction::I64TruncSF32),
+    ("i64.trunc_f32_u", Instruction::I64TruncUF32),
+    ("i64.trunc_f64_s", Instruction::I64TruncSF64),
+    ("i64.trunc_f64_u", Instruction::I64TruncUF64),
+    ("f32.convert_i32_s", Instruction::F32ConvertSI32),
+    ("f32.convert_i32_u", Instruction::F32ConvertUI32),
+    ("f32.convert_i64_s", Instruction::F32ConvertSI64),
+    ("f32.convert_i64_u", Instruction::F32ConvertUI64),
+    ("f32.demote_f64", Instruction::F32DemoteF64),
+    ("f64.convert_i32_s", Instruction::F64ConvertSI32),
+    ("f64.convert_i32_u", Instruction::F64ConvertUI32),
+    ("f64.convert_i64_s", Instruction::F64ConvertSI64),
+    ("f64.convert_i64_u", Instruction::F64ConvertUI64),
+    ("f64.promote_f32", Instruction::F64PromoteF32),
+    ("i32.reinterpret_f32", Instruction::I32ReinterpretF32),
+    ("i64.reinterpret_f64", Instruction::I64ReinterpretF64),
+    ("f32.reinterpret_i32", Instruction::F32ReinterpretI32),
+    ("f64.reinterpret_i64", Instruction::F64ReinterpretI64),
+    ("ref.is_null", Instruction::RefIsNull),
+    ("ref.eq", Instruction::RefEq),
+    ("ref.as_non_null", Instruction::RefAsNonNull),
+    ("array.len", Instruction::ArrayLen),
+    ("any.convert_extern", Instruction::AnyConvertExtern),
+    ("extern.convert_any", Instruction::ExternConvertAny),
+    ("ref.i31", Instruction::RefI31),
+    ("i31.get_s", Instruction::I31GetS),
+    ("i31.get_u", Instruction::I31GetU),
+];
+
+/// 古い仕様の命令名と、いまの名前
+const ALIASES: &[(&str, &str)] = &[
+    ("get_local", "local.get"),
+    ("set_local", "local.set"),
+    ("tee_local", "local.tee"),
+    ("get_global", "global.get"),
+    ("set_global", "global.set"),
+    ("current_memory", "memory.size"),
+    ("grow_memory", "memory.grow"),
+    ("i32.wrap/i64", "i32.wrap_i64"),
+    ("i32.trunc_s/f32", "i32.trunc_f32_s"),
+    ("i32.trunc_u/f32", "i32.trunc_f32_u"),
+    ("i32.trunc_s/f64", "i32.trunc_f64_s"),
+    ("i32.trunc_u/f64", "i32.trunc_f64_u"),
+    ("i64.extend_s/i32", "i64.extend_i32_s"),
+    ("i64.extend_u/i32", "i64.extend_i32_u"),
+    ("i64.trunc_s/f32", "i64.trunc_f32_s"),
+    ("i64.trunc_u/f32", "i64.trunc_f32_u"),
+    ("i64.trunc_s/f64", "i64.trunc_f64_s"),
+    ("i64.trunc_u/f64", "i64.trunc_f64_u"),
+    ("f32.convert_s/i32", "f32.convert_i32_s"),
+    ("f32.convert_u/i32", "f32.convert_i32_u"),
+    ("f32.convert_s/i64", "f32.convert_i64_s"),
+    ("f32.convert_u/i64", "f32.convert_i64_u"),
+    ("f32.demote/f64", "f32.demote_f64"),
+    ("f64.convert_s/i32", "f64.convert_i32_s"),
+    ("f64.convert_u/i32", "f64.convert_i32_u"),
+    ("f64.convert_s/i64", "f64.convert_i64_s"),
+    ("f64.convert_u/i64", "f64.convert_i64_u"),
+    ("f64.promote/f32", "f64.promote_f32"),
+    ("i32.reinterpret/f32", "i32.reinterpret_f32"),
+    ("i64.reinterpret/f64", "i64.reinterpret_f64"),
+    ("f32.reinterpret/i32", "f32.reinterpret_i32"),
+    ("f64.reinterpret/i64", "f64.reinterpret_i64"),
+];
+
+/// memargを受け取ってload/store命令を作る関数
+pub(crate) type MemoryInstruction = fn(MemArg) -> Instruction;
+
+/// load/store命令の名前と、省略したときのalignment(2を底とした対数)
+pub(crate) const MEMORY_INSTRUCTIONS: &[(&str, MemoryInstruction, u32)] = &[
+    ("i32.load", Instruction::I32Load, 2),
+    ("i64.load", Instruction::I64Load, 3),
+    ("f32.load", Instruction::F32Load, 2),
+    ("f64.load", Instruction::F64Load, 3),
+    ("i32.load8_s", Instruction::I32Load8S, 0),
+    ("i32.load8_u", Instruction::I32Load8U, 0),
+    ("i32.load16_s", Instruction::I32Load16S, 1),
+    ("i32.load16_u", Instruction::I32Load16U, 1),
+    ("i64.load8_s", Instruction::I64Load8S, 0),
+    ("i64.load8_u", Instruction::I64Load8U, 0),
+    ("i64.load16_s", Instruction::I64Load16S, 1),
+    ("i64.load16_u", Instruction::I64Load16U, 1),
+    ("i64.load32_s", Instruction::I64Load32S, 2),
+    ("i64.load32_u", Instruction::I64Load32U, 2),
+    ("i32.store", Instruction::I32Store, 2),
+    ("i64.store", Instruction::I64Store, 3),
+    ("f32.store", Instruction::F32Store, 2),
+    ("f64.store", Instruction::F64Store, 3),
+    ("i32.store8", Instruction::I32Store8, 0),
+    ("i32.store16", Instruction::I32Store16, 1),
+    ("i64.store8", Instruction::I64Store8, 0),
+    ("i64.store16", Instruction::I64Store16, 1),
+    ("i64.store32", Instruction::I64Store32, 2),
+    ("memory.atomic.notify", Instruction::AtomicNotify, 2),
+    ("memory.atomic.wait32", Instruction::AtomicWait32, 2),
+    ("memory.atomic.wait64", Instruction::AtomicWait64, 3),
+];
+
+pub(crate) const ATOMIC_WIDTHS: [AtomicWidth; 7] = [
+    AtomicWidth::I32,
+    AtomicWidth::I64,
+    AtomicWidth::I32U8,
+    AtomicWidth::I32U16,
+    AtomicWidth::I64U8,
+    AtomicWidth::I64U16,
+    AtomicWidth::I64U32,
+];
+
+pub(crate) const ATOMIC_RMW_OPS: [(&str, AtomicRmwOp); 6] = [
+    ("add", AtomicRmwOp::Add),
+    ("sub", AtomicRmwOp::Sub),
+    ("and", AtomicRmwOp::And),
+    ("or", AtomicRmwOp::Or),
+    ("xor", AtomicRmwOp::Xor),
+    ("xchg", AtomicRmwOp::Xchg),
+];
+
+/// 抽象ヒープ型の名前
+pub(crate) const HEAP_TYPES: &[(&str, HeapType)] = &[
+    ("func", HeapType::Func),
+    ("extern", HeapType::Extern),
+    ("any", HeapType::Any),
+    ("eq", HeapType::Eq),
+    ("i31", HeapType::I31),
+    ("struct", HeapType::Struct),
+    ("array", HeapType::Array),
+    ("exn", HeapType::Exn),
+    ("none", HeapType::None),
+    ("nofunc", HeapType::NoFunc),
+    ("noextern", HeapType::NoExtern),
+    ("noexn", HeapType::NoExn),
+];
+
+/// funcrefなど、(ref null ht)の省略形
+pub(crate) const REF_TYPES: &[(&str, HeapType)] = &[
+    ("funcref", HeapType::Func),
+    ("externref", HeapType::Extern),
+    ("anyref", HeapType::Any),
+    ("eqref", HeapType::Eq),
+    ("i31ref", HeapType::I31),
+    ("structref", HeapType::Struct),
+    ("arrayref", HeapType::Array),
+    ("exnref", HeapType::Exn),
+    ("nullref", HeapType::None),
+    ("nullfuncref", HeapType::NoFunc),
+    ("nullexternref", HeapType::NoExtern),
+    ("nullexnref", HeapType::NoExn),
+];
+
+/// 古い名前ならいまの名前に読み替える
+pub(crate) fn alias(name: &str) -> &str {
+    ALIASES
+        .iter()
+        .find(|(old, _)| *old == name)
+        .map_or(name, |(_, new)| new)
+}
+
+pub(crate) fn plain_instruction(name: &str) -> Option<Instruction> {
+    PLAIN_INSTRUCTIONS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, instruction)| instruction.clone())
+}
+
+pub(crate) fn memory_instruction(name: &str) -> Option<(MemoryInstruction, u32)> {
+    MEMORY_INSTRUCTIONS
+        .iter()
+        .find(|(n, _, _)| *n == name)
+        .map(|(_, make, align)| (*make, *align))
+}
+
+/// atomic命令の名前。opはload、store、cmpxchg、またはATOMIC_RMW_OPSの名前
+pub(crate) fn atomic_name(op: &str, width: AtomicWidth) -> String {
+    use AtomicWidth::*;
+
+    let (t, bits) = match width {
+        I32 => ("i32", ""),
+        I64 => ("i64", ""),
+        I32U8 => ("i32", "8"),
+        I32U16 => ("i32", "16"),
+        I64U8 => ("i64", "8"),
+        I64U16 => ("i64", "16"),
+        I64U32 => ("i64", "32"),
+    };
+    // 幅が型より狭いものはゼロ拡張するので_uがつく
+    let u = if bits.is_empty() { "" } else { "_u" };
+
+    match op {
+        "load" => format!("{}.atomic.load{}{}", t, bits, u),
+        "store" => format!("{}.atomic.store{}", t, bits),
+        op => format!("{}.atomic.rmw{}.{}{}", t, bits, op, u),
+    }
+}
+
+/// memargを受け取ってatomic命令を作る関数。型と幅を捕まえている
+pub(crate) type AtomicInstruction = Box<dyn Fn(MemArg) -> Instruction>;
+
+/// atomic命令の名前から、memargを受け取って命令を作る関数とalignmentを引く
+pub(crate) fn atomic_instruction(name: &str) -> Option<(AtomicInstruction, u32)> {
+    if !name.contains(".atomic.") {
+        return None;
+    }
+
+    for width in ATOMIC_WIDTHS {
+        let align = width.size().trailing_zeros();
+        if atomic_name("load", width) == name {
+            return Some((Box::new(move |m| Instruction::AtomicLoad(width, m)), align));
+        }
+        if atomic_name("store", width) == name {
+            return Some((Box::new(move |m| Instruction::AtomicStore(width, m)), align));
+        }
+        if atomic_name("cmpxchg", width) == name {
+            return Some((
+                Box::new(move |m| Instruction::AtomicCmpxchg(width, m)),
+                align,
+            ));
+        }
+        for (op_name, op) in ATOMIC_RMW_OPS {
+            if atomic_name(op_name, width) == name {
+                return Some((
+                    Box::new(move |m| Instruction::AtomicRmw(op, width, m)),
+                    align,
+                ));
+            }
+        }
+    }
+
+    None
+}
@@ -0,0 +1,226 @@
+use super::error::{ParseError, Position};
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TokenKind<'a> {
+    LParen,
+    RParen,
+    /// 予約語、数値、offset=4などのidcharの並び
+    Atom(&'a str),
+    /// $から始まる名前。$は含まない
+    Id(&'a str),
+    /// エスケープを展開した文字列。UTF-8とは限らない
+    String(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Token<'a> {
+    pub(crate) kind: TokenKind<'a>,
+    pub(crate) position: Position,
+}
+
+/// テキストをトークンに分ける。コメントと空白は読み飛ばす
+pub(crate) fn tokenize(src: &str) -> Result<(Vec<Token<'_>>, Position), ParseError> {
+    let mut lexer = Lexer {
+        src,
+        chars: src.char_indices().peekable(),
+        line: 1,
+        column: 1,
+    };
+
+    let mut tokens = vec![];
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+
+    Ok((tokens, lexer.position()))
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    /// 次の次の文字。(;と;;の判定に使う
+    fn peek2(&self) -> Option<char> {
+        let mut chars = self.chars.clone();
+        chars.next();
+        chars.next().map(|(_, c)| c)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(c)
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.src.len(), |(i, _)| *i)
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token<'a>>, ParseError> {
+        self.skip_whitespace()?;
+
+        let position = self.position();
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let kind = match c {
+            '(' => {
+                self.bump();
+                TokenKind::LParen
+            }
+            ')' => {
+                self.bump();
+                TokenKind::RParen
+            }
+            '"' => TokenKind::String(self.string()?),
+            c if is_idchar(c) => {
+                let start = self.offset();
+                while self.peek().is_some_and(is_idchar) {
+                    self.bump();
+                }
+                let word = &self.src[start..self.offset()];
+
+                match word.strip_prefix('$') {
+                    Some("") => return Err(ParseError::new("empty identifier", position)),
+                    Some(id) => TokenKind::Id(id),
+                    None => TokenKind::Atom(word),
+                }
+            }
+            c => {
+                return Err(ParseError::new(
+                    format!("unexpected character {:?}", c),
+                    position,
+                ))
+            }
+        };
+
+        Ok(Some(Token { kind, position }))
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), ParseError> {
+        loop {
+            match (self.peek(), self.peek2()) {
+                (Some(' ' | '\t' | '\n' | '\r'), _) => {
+                    self.bump();
+                }
+                (Some(';'), Some(';')) => while !matches!(self.bump(), Some('\n') | None) {},
+                (Some('('), Some(';')) => self.block_comment()?,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// (; ... ;)のコメント。入れ子にできる
+    fn block_comment(&mut self) -> Result<(), ParseError> {
+        let position = self.position();
+        let mut depth = 0;
+        loop {
+            match (self.bump(), self.peek()) {
+                (Some('('), Some(';')) => {
+                    self.bump();
+                    depth += 1;
+                }
+                (Some(';'), Some(')')) => {
+                    self.bump();
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                (Some(_), _) => {}
+                (None, _) => return Err(ParseError::new("unterminated block comment", position)),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, ParseError> {
+        let start = self.position();
+        self.bump();
+
+        let mut bytes = vec![];
+        loop {
+            let position = self.position();
+            let c = match self.bump() {
+                Some('"') => return Ok(bytes),
+                Some('\n') | None => return Err(ParseError::new("unterminated string", start)),
+                Some(c) => c,
+            };
+
+            if c != '\\' {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                continue;
+            }
+
+            let invalid = || ParseError::new("invalid escape in string", position);
+            match self.bump().ok_or_else(invalid)? {
+                't' => bytes.push(b'\t'),
+                'n' => bytes.push(b'\n'),
+                'r' => bytes.push(b'\r'),
+                '"' => bytes.push(b'"'),
+                '\'' => bytes.push(b'\''),
+                '\\' => bytes.push(b'\\'),
+                'u' => {
+                    if self.bump() != Some('{') {
+                        return Err(invalid());
+                    }
+                    let mut code = 0u32;
+                    loop {
+                        match self.bump() {
+                            Some('}') => break,
+                            Some('_') => {}
+                            Some(c) => {
+                                let digit = c.to_digit(16).ok_or_else(invalid)?;
+                                code = code
+                                    .checked_mul(16)
+                                    .and_then(|code| code.checked_add(digit))
+                                    .ok_or_else(invalid)?;
+                            }
+                            None => return Err(invalid()),
+                        }
+                    }
+                    let c = char::from_u32(code).ok_or_else(invalid)?;
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                h => {
+                    let high = h.to_digit(16).ok_or_else(invalid)?;
+                    let low = self
+                        .bump()
+                        .and_then(|c| c.to_digit(16))
+                        .ok_or_else(invalid)?;
+                    bytes.push((high * 16 + low) as u8);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn is_idchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-./:<=>?@\\^_`|~".contains(c)
+}
@@ -0,0 +1,35 @@
+mod error;
+mod expr;
+mod keywords;
+mod lexer;
+mod module;
+mod number;
+mod parser;
+mod printer;
+
+pub use error::{ParseError, Position};
+
+use crate::decode::{self, DecodeError};
+use crate::module::Module;
+use parser::Parser;
+
+/// テキスト形式(.wat)のmoduleを読む。括弧で包んだ書き方と並べて書く書き方のどちらも使え、
+/// 名前やinlineのexport、importなどの省略形は展開する
+pub fn parse(src: &str) -> Result<Module, ParseError> {
+    let (tokens, eof) = lexer::tokenize(src)?;
+
+    Parser::new(tokens, eof).module()
+}
+
+/// moduleをテキスト形式で書き出す。name sectionがあれば関数とローカル変数の名前を使う
+pub(crate) fn print(m: &Module) -> String {
+    printer::print(m, None)
+}
+
+/// wasmバイナリをデコードして、関数本体の命令それぞれにファイル上の位置を(;@1a ;)のコメントで添えて書き出す。
+/// 壊れたmoduleも調べられるように検証はしない
+pub fn print_with_offsets(bytes: &[u8]) -> Result<String, DecodeError> {
+    let (m, offsets) = decode::decode_with_offsets(bytes)?;
+
+    Ok(printer::print(&m, Some(&offsets)))
+}
@@ -0,0 +1,836 @@
+use super::error::ParseError;
+use super::expr::Scope;
+use super::lexer::TokenKind;
+use super::parser::{Kind, Parser};
+use crate::module::Module;
+use crate::types::*;
+
+/// 組み立て中のsection。indexは定義した順に、importしたものから数える
+#[derive(Default)]
+struct Fields {
+    imports: Vec<ImportEntry>,
+    funcs: Vec<u32>,
+    bodies: Vec<FunctionBody>,
+    tables: Vec<TableType>,
+    memories: Vec<MemoryType>,
+    globals: Vec<GlobalEntry>,
+    tags: Vec<TagType>,
+    exports: Vec<ExportEntry>,
+    start: Option<u32>,
+    elems: Vec<ElementSegment>,
+    data: Vec<DataSegment>,
+    func_count: u32,
+    table_count: u32,
+    memory_count: u32,
+    global_count: u32,
+    tag_count: u32,
+}
+
+impl<'a> Parser<'a> {
+    /// (module ...)か、moduleで包まないフィールドの並びを読む。
+    /// moduleの後ろにwastスクリプトのassert_returnなどがあれば読み飛ばす
+    pub(crate) fn module(&mut self) -> Result<Module, ParseError> {
+        let wrapped = self.list("module");
+        if wrapped {
+            self.id();
+            if matches!(self.peek_atom(), Some("binary" | "quote")) {
+                return Err(self.error("binary and quote modules are not supported"));
+            }
+        }
+
+        let start = self.mark();
+        self.define_fields()?;
+        self.reset(start);
+        self.type_fields()?;
+        self.reset(start);
+        let m = self.fields()?;
+
+        if wrapped {
+            self.rparen()?;
+            while let Some(keyword) = self.peek_list_keyword() {
+                if !(keyword.starts_with("assert_") || keyword == "invoke" || keyword == "register")
+                {
+                    break;
+                }
+                self.bump();
+                self.skip_rest()?;
+            }
+        }
+        if !self.is_end() {
+            return Err(self.expected("end of input"));
+        }
+
+        Ok(m)
+    }
+
+    /// フィールドの並びが終わったか
+    fn fields_end(&self) -> bool {
+        matches!(self.peek(), Some(TokenKind::RParen) | None)
+    }
+
+    fn field_keyword(&mut self) -> Result<&'a str, ParseError> {
+        self.lparen()?;
+        self.atom("module field")
+    }
+
+    /// 1つ目のパス。名前のついた定義を探してindexを割り振る
+    fn define_fields(&mut self) -> Result<(), ParseError> {
+        // importは関数、table、memory、global、tagのどの定義よりも前に書く
+        let mut defined = false;
+        let check_import = |defined: bool, position| match defined {
+            true => Err(ParseError::new("import after definition", position)),
+            false => Ok(()),
+        };
+
+        while !self.fields_end() {
+            let position = self.position();
+            match self.field_keyword()? {
+                "type" => {
+                    self.define(Kind::Type)?;
+                    self.skip_rest()?;
+                }
+                "rec" => {
+                    while self.list("type") {
+                        self.define(Kind::Type)?;
+                        self.skip_rest()?;
+                    }
+                    self.rparen()?;
+                }
+                "import" => {
+                    self.name()?;
+                    self.name()?;
+                    self.lparen()?;
+                    let kind = match self.atom("import kind")? {
+                        "func" => Kind::Func,
+                        "table" => Kind::Table,
+                        "memory" => Kind::Memory,
+                        "global" => Kind::Global,
+                        "tag" => Kind::Tag,
+                        s => {
+                            return Err(ParseError::new(
+                                format!("unknown import kind `{}`", s),
+                                position,
+                            ))
+                        }
+                    };
+                    check_import(defined, position)?;
+                    self.define(kind)?;
+                    self.skip_rest()?;
+                    self.rparen()?;
+                }
+                keyword @ ("func" | "table" | "memory" | "global" | "tag") => {
+                    let kind = match keyword {
+                        "func" => Kind::Func,
+                        "table" => Kind::Table,
+                        "memory" => Kind::Memory,
+                        "global" => Kind::Global,
+                        _ => Kind::Tag,
+                    };
+                    self.define(kind)?;
+                    let children = self.skip_children()?;
+                    if children.contains(&"import") {
+                        check_import(defined, position)?;
+                    } else {
+                        defined = true;
+                    }
+
+                    // tableやmemoryに直接書いた要素やデータは、segmentを1つ作る
+                    if kind == Kind::Table && children.contains(&"elem") {
+                        self.define_anonymous(Kind::Elem);
+                    }
+                    if kind == Kind::Memory && children.contains(&"data") {
+                        self.define_anonymous(Kind::Data);
+                    }
+                }
+                "elem" => {
+                    self.define(Kind::Elem)?;
+                    self.skip_rest()?;
+                }
+                "data" => {
+                    self.define(Kind::Data)?;
+                    self.skip_rest()?;
+                }
+                "export" | "start" => self.skip_rest()?,
+                s => {
+                    return Err(ParseError::new(
+                        format!("unknown module field `{}`", s),
+                        position,
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 2つ目のパス。ほかの定義が型を追加する前に、type sectionに書かれた型を読む
+    fn type_fields(&mut self) -> Result<(), ParseError> {
+        while !self.fields_end() {
+            match self.field_keyword()? {
+                "type" => {
+                    let group = self.rec_groups;
+                    self.rec_groups += 1;
+                    self.type_definition(group)?;
+                }
+                "rec" => {
+                    let group = self.rec_groups;
+                    self.rec_groups += 1;
+                    while self.list("type") {
+                        self.type_definition(group)?;
+                    }
+                    self.rparen()?;
+                }
+                _ => self.skip_rest()?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// (typeの後ろから読む
+    fn type_definition(&mut self, rec_group: u32) -> Result<(), ParseError> {
+        self.id();
+        let index = self.types.len() as u32;
+
+        let sub_type = if self.list("sub") {
+            let is_final = self.keyword("final");
+            let supertype = self.optional_index(Kind::Type)?;
+            let composite = self.composite_type(index)?;
+            self.rparen()?;
+
+            SubType {
+                is_final,
+                supertype,
+                composite,
+                rec_group,
+            }
+        } else {
+            SubType::new(self.composite_type(index)?, rec_group)
+        };
+        self.rparen()?;
+
+        self.types.push(sub_type);
+
+        Ok(())
+    }
+
+    fn composite_type(&mut self, index: u32) -> Result<CompositeType, ParseError> {
+        self.lparen()?;
+        let position = self.position();
+        let composite = match self.atom("func, struct or array")? {
+            "func" => {
+                let params = self.params()?;
+                let returns = self.results()?;
+                CompositeType::Func(FuncType::new(
+                    params.into_iter().map(|(_, t)| t).collect(),
+                    returns,
+                ))
+            }
+            "struct" => {
+                let mut fields = vec![];
+                while self.list("field") {
+                    match self.id() {
+                        Some(id) => {
+                            self.fields.insert((index, id), fields.len() as u32);
+                            fields.push(self.field_type()?);
+                        }
+                        None => {
+                            while !self.peek_rparen() {
+                                fields.push(self.field_type()?);
+                            }
+                        }
+                    }
+                    self.rparen()?;
+                }
+                CompositeType::Struct(StructType { fields })
+            }
+            "array" => CompositeType::Array(self.field_type()?),
+            s => {
+                return Err(ParseError::new(
+                    format!("unknown composite type `{}`", s),
+                    position,
+                ))
+            }
+        };
+        self.rparen()?;
+
+        Ok(composite)
+    }
+
+    fn field_type(&mut self) -> Result<FieldType, ParseError> {
+        let mutable = self.list("mut");
+        let storage = match self.peek_atom() {
+            Some("i8") => {
+                self.bump();
+                StorageType::I8
+            }
+            Some("i16") => {
+                self.bump();
+                StorageType::I16
+            }
+            _ => StorageType::Value(self.value_type()?),
+        };
+        if mutable {
+            self.rparen()?;
+        }
+
+        Ok(FieldType { storage, mutable })
+    }
+
+    /// 3つ目のパス。type以外のフィールドを読んでmoduleを組み立てる
+    fn fields(&mut self) -> Result<Module, ParseError> {
+        let mut f = Fields::default();
+
+        while !self.fields_end() {
+            let position = self.position();
+            match self.field_keyword()? {
+                "type" | "rec" => self.skip_rest()?,
+                "import" => self.import(&mut f)?,
+                "func" => self.func(&mut f)?,
+                "table" => self.table(&mut f)?,
+                "memory" => self.memory(&mut f)?,
+                "global" => self.global(&mut f)?,
+                "tag" => self.tag(&mut f)?,
+                "export" => self.export(&mut f)?,
+                "start" => {
+                    if f.start.is_some() {
+                        return Err(ParseError::new("multiple start functions", position));
+                    }
+                    f.start = Some(self.index(Kind::Func)?);
+                    self.rparen()?;
+                }
+                "elem" => self.elem(&mut f)?,
+                "data" => self.data(&mut f)?,
+                s => unreachable!("unknown field {} is rejected in the first pass", s),
+            }
+        }
+
+        let types = std::mem::take(&mut self.types);
+        // data count sectionはdata indexを使う命令があるときだけ作る
+        let uses_data_index = f.bodies.iter().flat_map(|body| &body.code).any(|i| {
+            matches!(
+                i,
+                Instruction::MemoryInit(..)
+                    | Instruction::DataDrop(_)
+                    | Instruction::ArrayNewData(..)
+            )
+        });
+        let data_count = match uses_data_index {
+            true => Some(f.data.len() as u32),
+            false => None,
+        };
+
+        Ok(Module {
+            version: 1,
+            type_section: non_empty(types).map(|entries| TypeSection { entries }),
+            import_section: non_empty(f.imports).map(|entries| ImportSection { entries }),
+            function_section: non_empty(f.funcs).map(|types| FunctionSection { types }),
+            table_section: non_empty(f.tables).map(|entries| TableSection { entries }),
+            memory_section: non_empty(f.memories).map(|entries| MemorySection { entries }),
+            tag_section: non_empty(f.tags).map(|entries| TagSection { entries }),
+            global_section: non_empty(f.globals).map(|entries| GlobalSection { entries }),
+            export_section: non_empty(f.exports).map(|entries| ExportSection { entries }),
+            start_section: f.start,
+            element_section: non_empty(f.elems).map(|segments| ElementSection { segments }),
+            data_count_section: data_count,
+            code_section: non_empty(f.bodies).map(|bodies| CodeSection { bodies }),
+            data_section: non_empty(f.data).map(|segments| DataSection { segments }),
+            ..Module::default()
+        })
+    }
+
+    /// (importの後ろから読む
+    fn import(&mut self, f: &mut Fields) -> Result<(), ParseError> {
+        let module = self.name()?;
+        let name = self.name()?;
+        self.lparen()?;
+        let kind = match self.atom("import kind")? {
+            "func" => {
+                self.id();
+                f.func_count += 1;
+                ImportKind::Function(self.type_use()?.0)
+            }
+            "table" => {
+                self.id();
+                f.table_count += 1;
+                ImportKind::Table(self.table_type()?)
+            }
+            "memory" => {
+                self.id();
+                f.memory_count += 1;
+                ImportKind::Memory(self.memory_type()?)
+            }
+            "global" => {
+                self.id();
+                f.global_count += 1;
+                ImportKind::Global(self.global_type()?)
+            }
+            _ => {
+                self.id();
+                f.tag_count += 1;
+                ImportKind::Tag(self.tag_type()?)
+            }
+        };
+        self.rparen()?;
+        self.rparen()?;
+
+        f.imports.push(ImportEntry {
+            module_str: module,
+            field_str: name,
+            kind,
+        });
+
+        Ok(())
+    }
+
+    /// (export "name")の省略形をすべて読む
+    fn inline_exports(
+        &mut self,
+        f: &mut Fields,
+        kind: ExternalKind,
+        index: u32,
+    ) -> Result<(), ParseError> {
+        while self.list("export") {
+            let name = self.name()?;
+            self.rparen()?;
+            f.exports.push(ExportEntry {
+                field_str: name,
+                kind,
+                index,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// (import "module" "name")の省略形
+    fn inline_import(&mut self) -> Result<Option<(String, String)>, ParseError> {
+        if !self.list("import") {
+            return Ok(None);
+        }
+
+        let module = self.name()?;
+        let name = self.name()?;
+        self.rparen()?;
+
+        Ok(Some((module, name)))
+    }
+
+    fn push_import(f: &mut Fields, (module, name): (String, String), kind: ImportKind) {
+        f.imports.push(ImportEntry {
+            module_str: module,
+            field_str: name,
+            kind,
+        });
+    }
+
+    fn func(&mut self, f: &mut Fields) -> Result<(), ParseError> {
+        self.id();
+        let index = f.func_count;
+        f.func_count += 1;
+        self.inline_exports(f, ExternalKind::Function, index)?;
+
+        if let Some(import) = self.inline_import()? {
+            let (type_index, _) = self.type_use()?;
+            self.rparen()?;
+            Self::push_import(f, import, ImportKind::Function(type_index));
+            return Ok(());
+        }
+
+        let (type_index, names) = self.type_use()?;
+        let mut scope = Scope::default();
+        for (i, name) in names.iter().enumerate() {
+            if let Some(name) = name {
+                scope.define_local(name, i as u32, self.position())?;
+            }
+        }
+
+        // 同じ型が続くlocalはまとめる
+        let mut locales: Vec<LocalEntry> = vec![];
+        let mut local_count = names.len() as u32;
+        while self.list("local") {
+            let mut types = vec![];
+            match self.id() {
+                Some(id) => {
+                    let position = self.position();
+                    scope.define_local(id, local_count, position)?;
+                    types.push(self.value_type()?);
+                }
+                None => {
+                    while !self.peek_rparen() {
+                        types.push(self.value_type()?);
+                    }
+                }
+            }
+            self.rparen()?;
+
+            for t in types {
+                local_count += 1;
+                match locales.last_mut() {
+                    Some(entry) if entry.value_type == t => entry.count += 1,
+                    _ => locales.push(LocalEntry {
+                        count: 1,
+                        value_type: t,
+                    }),
+                }
+            }
+        }
+
+        let code = self.instructions(&mut scope)?;
+        self.rparen()?;
+
+        f.funcs.push(type_index);
+        f.bodies.push(FunctionBody { locales, code });
+
+        Ok(())
+    }
+
+    fn table(&mut self, f: &mut Fields) -> Result<(), ParseError> {
+        self.id();
+        let index = f.table_count;
+        f.table_count += 1;
+        self.inline_exports(f, ExternalKind::Table, index)?;
+
+        if let Some(import) = self.inline_import()? {
+            let table_type = self.table_type()?;
+            self.rparen()?;
+            Self::push_import(f, import, ImportKind::Table(table_type));
+            return Ok(());
+        }
+
+        // 限度を書かずに要素を並べたときは、要素の数がそのままtableの大きさになる
+        if !self.peek_index() {
+            let elem_type = self.ref_type()?;
+            if !self.list("elem") {
+                return Err(self.expected("(elem"));
+            }
+            let items = self.elem_items()?;
+            self.rparen()?;
+            self.rparen()?;
+
+            let len = match &items {
+                ElementItems::Functions(funcs) => funcs.len(),
+                ElementItems::Expressions(exprs) => exprs.len(),
+            } as u64;
+            f.tables.push(TableType {
+                elem_type,
+                limits: ResizableLimits {
+                    initial: len,
+                    maximum: Some(len),
+                },
+            });
+            f.elems.push(ElementSegment {
+                mode: ElementMode::Active {
+                    table: index,
+                    offset: vec![Instruction::I32Const(0)],
+                },
+                elem_type,
+                items,
+            });
+            return Ok(());
+        }
+
+        let table_type = self.table_type()?;
+        self.rparen()?;
+        f.tables.push(table_type);
+
+        Ok(())
+    }
+
+    fn memory(&mut self, f: &mut Fields) -> Result<(), ParseError> {
+        self.id();
+        let index = f.memory_count;
+        f.memory_count += 1;
+        self.inline_exports(f, ExternalKind::Memory, index)?;
+
+        if let Some(import) = self.inline_import()? {
+            let memory_type = self.memory_type()?;
+            self.rparen()?;
+            Self::push_import(f, import, ImportKind::Memory(memory_type));
+            return Ok(());
+        }
+
+        // (data ...)を直接書いたときは、データが収まるだけのページ数になる
+        let memory64 =
+            self.peek_atom() == Some("i64") && self.peek_nth(1) == Some(&TokenKind::LParen);
+        if memory64 {
+            self.bump();
+        }
+        if self.list("data") {
+            let mut data = vec![];
+            while self.peek_string() {
+                data.extend(self.string()?);
+            }
+            self.rparen()?;
+            self.rparen()?;
+
+            let pages = (data.len() as u64).div_ceil(PAGE_SIZE);
+            f.memories.push(MemoryType {
+                limits: ResizableLimits {
+                    initial: pages,
+                    maximum: Some(pages),
+                },
+                memory64,
+                shared: false,
+            });
+            let offset = match memory64 {
+                true => Instruction::I64Const(0),
+                false => Instruction::I32Const(0),
+            };
+            f.data.push(DataSegment {
+                index,
+                offset: vec![offset],
+                data,
+                passive: false,
+            });
+            return Ok(());
+        }
+
+        let memory_type = self.memory_type()?;
+        self.rparen()?;
+        f.memories.push(memory_type);
+
+        Ok(())
+    }
+
+    fn global(&mut self, f: &mut Fields) -> Result<(), ParseError> {
+        self.id();
+        let index = f.global_count;
+        f.global_count += 1;
+        self.inline_exports(f, ExternalKind::Global, index)?;
+
+        if let Some(import) = self.inline_import()? {
+            let global_type = self.global_type()?;
+            self.rparen()?;
+            Self::push_import(f, import, ImportKind::Global(global_type));
+            return Ok(());
+        }
+
+        let global_type = self.global_type()?;
+        let init = self.instructions(&mut Scope::default())?;
+        self.rparen()?;
+        f.globals.push(GlobalEntry { global_type, init });
+
+        Ok(())
+    }
+
+    fn tag(&mut self, f: &mut Fields) -> Result<(), ParseError> {
+        self.id();
+        let index = f.tag_count;
+        f.tag_count += 1;
+        self.inline_exports(f, ExternalKind::Tag, index)?;
+
+        let import = self.inline_import()?;
+        let tag_type = self.tag_type()?;
+        self.rparen()?;
+        match import {
+            Some(import) => Self::push_import(f, import, ImportKind::Tag(tag_type)),
+            None => f.tags.push(tag_type),
+        }
+
+        Ok(())
+    }
+
+    fn export(&mut self, f: &mut Fields) -> Result<(), ParseError> {
+        let name = self.name()?;
+        self.lparen()?;
+        let position = self.position();
+        let (kind, space) = match self.atom("export kind")? {
+            "func" => (ExternalKind::Function, Kind::Func),
+            "table" => (ExternalKind::Table, Kind::Table),
+            "memory" => (ExternalKind::Memory, Kind::Memory),
+            "global" => (ExternalKind::Global, Kind::Global),
+            "tag" => (ExternalKind::Tag, Kind::Tag),
+            s => {
+                return Err(ParseError::new(
+                    format!("unknown export kind `{}`", s),
+                    position,
+                ))
+            }
+        };
+        let index = self.index(space)?;
+        self.rparen()?;
+        self.rparen()?;
+
+        f.exports.push(ExportEntry {
+            field_str: name,
+            kind,
+            index,
+        });
+
+        Ok(())
+    }
+
+    fn elem(&mut self, f: &mut Fields) -> Result<(), ParseError> {
+        self.id();
+
+        let mode = if self.keyword("declare") {
+            ElementMode::Declarative
+        } else if self.list("table") {
+            let table = self.index(Kind::Table)?;
+            self.rparen()?;
+            ElementMode::Active {
+                table,
+                offset: self.offset()?,
+            }
+        } else if self.peek_lparen() && !self.peek_list("ref") {
+            ElementMode::Active {
+                table: 0,
+                offset: self.offset()?,
+            }
+        } else {
+            ElementMode::Passive
+        };
+
+        // funcを省略して関数indexだけを並べる書き方は、table 0へのactive segmentでだけ使える
+        let elem_type = if self.keyword("func") || self.peek_index() || self.peek_rparen() {
+            RefType::nullable(HeapType::Func)
+        } else {
+            self.ref_type()?
+        };
+        let items = self.elem_items()?;
+        self.rparen()?;
+
+        f.elems.push(ElementSegment {
+            mode,
+            elem_type,
+            items,
+        });
+
+        Ok(())
+    }
+
+    /// 関数indexの並びか、(item ...)か括弧で包んだ命令1つの並び
+    fn elem_items(&mut self) -> Result<ElementItems, ParseError> {
+        if !self.peek_lparen() {
+            let mut funcs = vec![];
+            while self.peek_index() {
+                funcs.push(self.index(Kind::Func)?);
+            }
+            return Ok(ElementItems::Functions(funcs));
+        }
+
+        let mut exprs = vec![];
+        while self.peek_lparen() {
+            if self.list("item") {
+                exprs.push(self.instructions(&mut Scope::default())?);
+                self.rparen()?;
+            } else {
+                exprs.push(self.folded(&mut Scope::default())?);
+            }
+        }
+
+        Ok(ElementItems::Expressions(exprs))
+    }
+
+    /// (offset ...)か、括弧で包んだ命令1つ
+    fn offset(&mut self) -> Result<Vec<Instruction>, ParseError> {
+        if self.list("offset") {
+            let offset = self.instructions(&mut Scope::default())?;
+            self.rparen()?;
+            return Ok(offset);
+        }
+
+        self.folded(&mut Scope::default())
+    }
+
+    fn data(&mut self, f: &mut Fields) -> Result<(), ParseError> {
+        self.id();
+
+        let active = if self.list("memory") {
+            let memory = self.index(Kind::Memory)?;
+            self.rparen()?;
+            Some(memory)
+        } else if self.peek_lparen() {
+            Some(0)
+        } else {
+            None
+        };
+
+        let offset = match active {
+            Some(_) => self.offset()?,
+            None => vec![],
+        };
+
+        let mut data = vec![];
+        while self.peek_string() {
+            data.extend(self.string()?);
+        }
+        self.rparen()?;
+
+        f.data.push(DataSegment {
+            index: active.unwrap_or(0),
+            offset,
+            data,
+            passive: active.is_none(),
+        });
+
+        Ok(())
+    }
+
+    fn limits(&mut self) -> Result<ResizableLimits, ParseError> {
+        let initial = self.u64()?;
+        let maximum = match self.peek_atom() {
+            Some(s) if s.starts_with(|c: char| c.is_ascii_digit()) => Some(self.u64()?),
+            _ => None,
+        };
+
+        Ok(ResizableLimits { initial, maximum })
+    }
+
+    fn table_type(&mut self) -> Result<TableType, ParseError> {
+        let limits = self.limits()?;
+        let elem_type = self.ref_type()?;
+
+        Ok(TableType { elem_type, limits })
+    }
+
+    fn memory_type(&mut self) -> Result<MemoryType, ParseError> {
+        let memory64 = self.keyword("i64");
+        if !memory64 {
+            self.keyword("i32");
+        }
+        let limits = self.limits()?;
+        let shared = self.keyword("shared");
+
+        Ok(MemoryType {
+            limits,
+            memory64,
+            shared,
+        })
+    }
+
+    fn global_type(&mut self) -> Result<GlobalType, ParseError> {
+        let mutability = self.list("mut");
+        let content_type = self.value_type()?;
+        if mutability {
+            self.rparen()?;
+        }
+
+        Ok(GlobalType {
+            content_type,
+            mutability,
+        })
+    }
+
+    fn tag_type(&mut self) -> Result<TagType, ParseError> {
+        let (type_index, _) = self.type_use()?;
+
+        Ok(TagType {
+            attribute: 0,
+            type_index,
+        })
+    }
+}
+
+/// 空のsectionは作らない
+fn non_empty<T>(items: Vec<T>) -> Option<Vec<T>> {
+    if items.is_empty() {
+        None
+    } else {
+        Some(items)
+    }
+}
+
+/// memoryの1ページのバイト数
+const PAGE_SIZE: u64 = 64 * 1024;
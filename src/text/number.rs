@@ -0,0 +1,187 @@
+/// 符号と絶対値に分けて読む。0xから始まれば16進数。桁の間には_を入れられる
+fn integer(s: &str) -> Option<(bool, u128)> {
+    let (negative, s) = match s.as_bytes().first()? {
+        b'-' => (true, &s[1..]),
+        b'+' => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let (radix, digits) = match s.strip_prefix("0x") {
+        Some(digits) => (16, digits),
+        None => (10, s),
+    };
+    if digits.is_empty() || digits.starts_with('_') || digits.ends_with('_') {
+        return None;
+    }
+
+    let mut value: u128 = 0;
+    for c in digits.chars().filter(|c| *c != '_') {
+        let digit = c.to_digit(radix)?;
+        value = value
+            .checked_mul(u128::from(radix))?
+            .checked_add(u128::from(digit))?;
+    }
+
+    Some((negative, value))
+}
+
+/// indexやlimitsなどの符号なし整数
+pub(crate) fn parse_u64(s: &str) -> Option<u64> {
+    if s.starts_with(['+', '-']) {
+        return None;
+    }
+
+    let (_, value) = integer(s)?;
+    u64::try_from(value).ok()
+}
+
+pub(crate) fn parse_u32(s: &str) -> Option<u32> {
+    u32::try_from(parse_u64(s)?).ok()
+}
+
+/// i32.constの値。符号なしで書かれた2^31以上の値は2の補数として読む
+pub(crate) fn parse_i32(s: &str) -> Option<i32> {
+    match integer(s)? {
+        (true, value) if value <= 1 << 31 => Some((value as u32).wrapping_neg() as i32),
+        (false, value) if value <= u128::from(u32::MAX) => Some(value as u32 as i32),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_i64(s: &str) -> Option<i64> {
+    match integer(s)? {
+        (true, value) if value <= 1 << 63 => Some((value as u64).wrapping_neg() as i64),
+        (false, value) if value <= u128::from(u64::MAX) => Some(value as u64 as i64),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_f32(s: &str) -> Option<f32> {
+    let (negative, s) = sign(s);
+    let value = match s {
+        "inf" => f32::INFINITY,
+        "nan" => f32::NAN,
+        _ => match s.strip_prefix("nan:0x") {
+            Some(payload) => {
+                let payload = parse_u32(&format!("0x{}", payload))?;
+                if payload == 0 || payload >= 1 << 23 {
+                    return None;
+                }
+                f32::from_bits(0x7f80_0000 | payload)
+            }
+            None => match s.strip_prefix("0x") {
+                Some(hex) => hex_float(hex)? as f32,
+                None => decimal(s)?.parse().ok()?,
+            },
+        },
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+pub(crate) fn parse_f64(s: &str) -> Option<f64> {
+    let (negative, s) = sign(s);
+    let value = match s {
+        "inf" => f64::INFINITY,
+        "nan" => f64::NAN,
+        _ => match s.strip_prefix("nan:0x") {
+            Some(payload) => {
+                let payload = parse_u64(&format!("0x{}", payload))?;
+                if payload == 0 || payload >= 1 << 52 {
+                    return None;
+                }
+                f64::from_bits(0x7ff0_0000_0000_0000 | payload)
+            }
+            None => match s.strip_prefix("0x") {
+                Some(hex) => hex_float(hex)?,
+                None => decimal(s)?.parse().ok()?,
+            },
+        },
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn sign(s: &str) -> (bool, &str) {
+    match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    }
+}
+
+/// 1.5e10などの10進数。str::parseが受け付けるinfinityなどの綴りは除く
+fn decimal(s: &str) -> Option<String> {
+    if !s.starts_with(|c: char| c.is_ascii_digit()) || s.ends_with('_') {
+        return None;
+    }
+    if !s
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-' | '_'))
+    {
+        return None;
+    }
+
+    Some(s.replace('_', ""))
+}
+
+/// 0x1.8p3などの16進数の浮動小数点数。0xは取り除いて渡す
+fn hex_float(s: &str) -> Option<f64> {
+    let (mantissa, exponent) = match s.find(['p', 'P']) {
+        Some(i) => {
+            let (negative, value) = integer(&s[i + 1..])?;
+            let value = i32::try_from(value).ok()?;
+            (&s[..i], if negative { -value } else { value })
+        }
+        None => (s, 0),
+    };
+    let (int, frac) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+        None => (mantissa, ""),
+    };
+    if int.is_empty() || int.starts_with('_') {
+        return None;
+    }
+
+    // 有効桁を60bitまで集め、あふれた桁は指数と丸め用のbitに回す
+    let mut value: u64 = 0;
+    let mut exponent = i64::from(exponent);
+    let mut sticky = false;
+    for (c, is_frac) in int
+        .chars()
+        .map(|c| (c, false))
+        .chain(frac.chars().map(|c| (c, true)))
+    {
+        if c == '_' {
+            continue;
+        }
+        let digit = u64::from(c.to_digit(16)?);
+        if value >> 56 == 0 {
+            value = value * 16 + digit;
+            if is_frac {
+                exponent -= 4;
+            }
+        } else {
+            sticky |= digit != 0;
+            if !is_frac {
+                exponent += 4;
+            }
+        }
+    }
+    if sticky {
+        value |= 1;
+    }
+
+    let mut result = value as f64;
+    while exponent > 0 {
+        let step = exponent.min(1000);
+        result *= 2f64.powi(step as i32);
+        exponent -= step;
+    }
+    while exponent < 0 {
+        let step = (-exponent).min(1000);
+        result /= 2f64.powi(step as i32);
+        exponent += step;
+    }
+
+    Some(result)
+}
@@ -0,0 +1,511 @@
+use super::error::{ParseError, Position};
+use super::keywords::{HEAP_TYPES, REF_TYPES};
+use super::lexer::{Token, TokenKind};
+use super::number;
+use crate::types::*;
+use std::collections::HashMap;
+
+/// 名前をつけられるindex空間
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Kind {
+    Type,
+    Func,
+    Table,
+    Memory,
+    Global,
+    Tag,
+    Elem,
+    Data,
+}
+
+impl Kind {
+    pub(crate) fn name(&self) -> &'static str {
+        use Kind::*;
+
+        match self {
+            Type => "type",
+            Func => "func",
+            Table => "table",
+            Memory => "memory",
+            Global => "global",
+            Tag => "tag",
+            Elem => "elem",
+            Data => "data",
+        }
+    }
+}
+
+/// 1つのindex空間の名前とindexの対応
+#[derive(Debug, Default)]
+pub(crate) struct Space<'a> {
+    names: HashMap<&'a str, u32>,
+    count: u32,
+}
+
+impl<'a> Space<'a> {
+    /// 次のindexを割り振り、名前があれば登録する
+    fn define(&mut self, name: Option<&'a str>) -> Result<u32, String> {
+        let index = self.count;
+        if let Some(name) = name {
+            if self.names.insert(name, index).is_some() {
+                return Err(format!("duplicate identifier ${}", name));
+            }
+        }
+        self.count += 1;
+
+        Ok(index)
+    }
+}
+
+/// トークン列を読み進めながらmoduleを組み立てる。
+/// 名前は後ろで定義されたものも参照できるので、先にすべての定義を見てindexを割り振ってから読む
+pub(crate) struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    /// 入力の終わりの位置
+    eof: Position,
+    spaces: [Space<'a>; 8],
+    /// type sectionに入れる型。関数などが型を直接書いたときは後ろに追加する
+    pub(crate) types: Vec<SubType>,
+    /// 次に作るrecグループの番号
+    pub(crate) rec_groups: u32,
+    /// struct typeのフィールド名。(type index, 名前)からfield index
+    pub(crate) fields: HashMap<(u32, &'a str), u32>,
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(tokens: Vec<Token<'a>>, eof: Position) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            eof,
+            spaces: Default::default(),
+            types: vec![],
+            rec_groups: 0,
+            fields: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn mark(&self) -> usize {
+        self.pos
+    }
+
+    pub(crate) fn reset(&mut self, mark: usize) {
+        self.pos = mark;
+    }
+
+    pub(crate) fn peek(&self) -> Option<&TokenKind<'a>> {
+        self.peek_nth(0)
+    }
+
+    pub(crate) fn peek_nth(&self, n: usize) -> Option<&TokenKind<'a>> {
+        self.tokens.get(self.pos + n).map(|token| &token.kind)
+    }
+
+    pub(crate) fn position(&self) -> Position {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.eof, |token| token.position)
+    }
+
+    /// 次のトークンの位置でのエラー
+    pub(crate) fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError::new(message, self.position())
+    }
+
+    /// 何が来るはずだったかを添えたエラー
+    pub(crate) fn expected(&self, what: &str) -> ParseError {
+        let found = match self.peek() {
+            Some(TokenKind::LParen) => "(".to_string(),
+            Some(TokenKind::RParen) => ")".to_string(),
+            Some(TokenKind::Atom(s)) => format!("`{}`", s),
+            Some(TokenKind::Id(s)) => format!("${}", s),
+            Some(TokenKind::String(_)) => "string".to_string(),
+            None => "end of input".to_string(),
+        };
+
+        self.error(format!("expected {}, but found {}", what, found))
+    }
+
+    pub(crate) fn bump(&mut self) {
+        self.pos += 1;
+    }
+
+    pub(crate) fn is_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    pub(crate) fn peek_lparen(&self) -> bool {
+        matches!(self.peek(), Some(TokenKind::LParen))
+    }
+
+    pub(crate) fn peek_rparen(&self) -> bool {
+        matches!(self.peek(), Some(TokenKind::RParen))
+    }
+
+    pub(crate) fn lparen(&mut self) -> Result<(), ParseError> {
+        if !self.peek_lparen() {
+            return Err(self.expected("("));
+        }
+        self.bump();
+
+        Ok(())
+    }
+
+    pub(crate) fn rparen(&mut self) -> Result<(), ParseError> {
+        if !self.peek_rparen() {
+            return Err(self.expected(")"));
+        }
+        self.bump();
+
+        Ok(())
+    }
+
+    pub(crate) fn peek_atom(&self) -> Option<&'a str> {
+        match self.peek() {
+            Some(TokenKind::Atom(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn atom(&mut self, what: &str) -> Result<&'a str, ParseError> {
+        let atom = self.peek_atom().ok_or_else(|| self.expected(what))?;
+        self.bump();
+
+        Ok(atom)
+    }
+
+    /// 次がkeywordなら読んでtrueを返す
+    pub(crate) fn keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_atom() == Some(keyword) {
+            self.bump();
+            return true;
+        }
+
+        false
+    }
+
+    /// (keywordで始まるリストの先頭のキーワード
+    pub(crate) fn peek_list_keyword(&self) -> Option<&'a str> {
+        match (self.peek(), self.peek_nth(1)) {
+            (Some(TokenKind::LParen), Some(TokenKind::Atom(s))) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn peek_list(&self, keyword: &str) -> bool {
+        self.peek_list_keyword() == Some(keyword)
+    }
+
+    /// 次が(keywordなら、その2つを読んでtrueを返す
+    pub(crate) fn list(&mut self, keyword: &str) -> bool {
+        if self.peek_list(keyword) {
+            self.pos += 2;
+            return true;
+        }
+
+        false
+    }
+
+    pub(crate) fn id(&mut self) -> Option<&'a str> {
+        match self.peek() {
+            Some(TokenKind::Id(id)) => {
+                let id = *id;
+                self.bump();
+                Some(id)
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn peek_string(&self) -> bool {
+        matches!(self.peek(), Some(TokenKind::String(_)))
+    }
+
+    pub(crate) fn string(&mut self) -> Result<Vec<u8>, ParseError> {
+        match self.peek() {
+            Some(TokenKind::String(bytes)) => {
+                let bytes = bytes.clone();
+                self.bump();
+                Ok(bytes)
+            }
+            _ => Err(self.expected("string")),
+        }
+    }
+
+    /// importやexportの名前。UTF-8でなければならない
+    pub(crate) fn name(&mut self) -> Result<String, ParseError> {
+        let position = self.position();
+        String::from_utf8(self.string()?)
+            .map_err(|_| ParseError::new("malformed UTF-8 encoding", position))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, ParseError> {
+        let position = self.position();
+        let s = self.atom("integer")?;
+        number::parse_u32(s)
+            .ok_or_else(|| ParseError::new(format!("invalid u32 `{}`", s), position))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, ParseError> {
+        let position = self.position();
+        let s = self.atom("integer")?;
+        number::parse_u64(s)
+            .ok_or_else(|| ParseError::new(format!("invalid u64 `{}`", s), position))
+    }
+
+    /// 数値リテラルを読む。whatはエラーに出す型の名前
+    pub(crate) fn number<T>(
+        &mut self,
+        parse: fn(&str) -> Option<T>,
+        what: &str,
+    ) -> Result<T, ParseError> {
+        let position = self.position();
+        let s = self.atom(what)?;
+        parse(s)
+            .ok_or_else(|| ParseError::new(format!("invalid {} literal `{}`", what, s), position))
+    }
+
+    /// 次がindexとして読めるトークンか
+    pub(crate) fn peek_index(&self) -> bool {
+        self.peek_index_at(0)
+    }
+
+    pub(crate) fn peek_index_at(&self, n: usize) -> bool {
+        match self.peek_nth(n) {
+            Some(TokenKind::Id(_)) => true,
+            Some(TokenKind::Atom(s)) => number::parse_u32(s).is_some(),
+            _ => false,
+        }
+    }
+
+    /// リストの残りを閉じ括弧まで読み飛ばす
+    pub(crate) fn skip_rest(&mut self) -> Result<(), ParseError> {
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                Some(TokenKind::LParen) => depth += 1,
+                Some(TokenKind::RParen) if depth == 0 => {
+                    self.bump();
+                    return Ok(());
+                }
+                Some(TokenKind::RParen) => depth -= 1,
+                Some(_) => {}
+                None => return Err(self.expected(")")),
+            }
+            self.bump();
+        }
+    }
+
+    /// リストの残りを閉じ括弧まで読み飛ばし、直下にあったリストのキーワードを返す
+    pub(crate) fn skip_children(&mut self) -> Result<Vec<&'a str>, ParseError> {
+        let mut keywords = vec![];
+        loop {
+            match self.peek() {
+                Some(TokenKind::LParen) => {
+                    if let Some(keyword) = self.peek_list_keyword() {
+                        keywords.push(keyword);
+                    }
+                    self.bump();
+                    self.skip_rest()?;
+                }
+                Some(TokenKind::RParen) => {
+                    self.bump();
+                    return Ok(keywords);
+                }
+                Some(_) => self.bump(),
+                None => return Err(self.expected(")")),
+            }
+        }
+    }
+
+    /// 名前があれば読んで、kindの次のindexを割り振る
+    pub(crate) fn define(&mut self, kind: Kind) -> Result<u32, ParseError> {
+        let position = self.position();
+        let id = self.id();
+        self.spaces[kind as usize]
+            .define(id)
+            .map_err(|message| ParseError::new(message, position))
+    }
+
+    /// 名前を持たない定義にindexを割り振る
+    pub(crate) fn define_anonymous(&mut self, kind: Kind) {
+        self.spaces[kind as usize].count += 1;
+    }
+
+    /// 名前か数値で書かれたindex
+    pub(crate) fn index(&mut self, kind: Kind) -> Result<u32, ParseError> {
+        let position = self.position();
+        match self.peek() {
+            Some(TokenKind::Id(id)) => {
+                let id = *id;
+                self.bump();
+                self.spaces[kind as usize]
+                    .names
+                    .get(id)
+                    .copied()
+                    .ok_or_else(|| {
+                        ParseError::new(format!("unknown {} ${}", kind.name(), id), position)
+                    })
+            }
+            Some(TokenKind::Atom(_)) => self.u32(),
+            _ => Err(self.expected(&format!("{} index", kind.name()))),
+        }
+    }
+
+    pub(crate) fn optional_index(&mut self, kind: Kind) -> Result<Option<u32>, ParseError> {
+        if self.peek_index() {
+            return self.index(kind).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    pub(crate) fn value_type(&mut self) -> Result<ValueType, ParseError> {
+        let t = match self.peek_atom() {
+            Some("i32") => ValueType::I32,
+            Some("i64") => ValueType::I64,
+            Some("f32") => ValueType::F32,
+            Some("f64") => ValueType::F64,
+            Some("v128") => return Err(self.error("v128 is not supported")),
+            _ => return self.ref_type().map(ValueType::Ref),
+        };
+        self.bump();
+
+        Ok(t)
+    }
+
+    /// funcrefなどの省略形か、(ref null? heaptype)
+    pub(crate) fn ref_type(&mut self) -> Result<RefType, ParseError> {
+        if let Some(s) = self.peek_atom() {
+            if let Some((_, heap_type)) = REF_TYPES.iter().find(|(name, _)| *name == s) {
+                self.bump();
+                return Ok(RefType::nullable(*heap_type));
+            }
+        }
+
+        if !self.list("ref") {
+            return Err(self.expected("value type"));
+        }
+        let nullable = self.keyword("null");
+        let heap_type = self.heap_type()?;
+        self.rparen()?;
+
+        Ok(RefType::new(nullable, heap_type))
+    }
+
+    pub(crate) fn heap_type(&mut self) -> Result<HeapType, ParseError> {
+        if let Some(s) = self.peek_atom() {
+            if let Some((_, heap_type)) = HEAP_TYPES.iter().find(|(name, _)| *name == s) {
+                self.bump();
+                return Ok(*heap_type);
+            }
+        }
+        if !self.peek_index() {
+            return Err(self.expected("heap type"));
+        }
+
+        self.index(Kind::Type).map(HeapType::Concrete)
+    }
+
+    /// (param $x i32)や(param i32 i64)を続くだけ読む。名前は引数ごとに返す
+    pub(crate) fn params(&mut self) -> Result<Vec<(Option<&'a str>, ValueType)>, ParseError> {
+        let mut params = vec![];
+        while self.list("param") {
+            match self.id() {
+                Some(id) => params.push((Some(id), self.value_type()?)),
+                None => {
+                    while !self.peek_rparen() {
+                        params.push((None, self.value_type()?));
+                    }
+                }
+            }
+            self.rparen()?;
+        }
+
+        Ok(params)
+    }
+
+    pub(crate) fn results(&mut self) -> Result<Vec<ValueType>, ParseError> {
+        let mut results = vec![];
+        while self.list("result") {
+            while !self.peek_rparen() {
+                results.push(self.value_type()?);
+            }
+            self.rparen()?;
+        }
+
+        Ok(results)
+    }
+
+    /// (type x)と引数、戻り値の型。型が書かれていなければ同じ関数型を探し、無ければ追加する。
+    /// 引数の名前は引数の数だけ返す
+    pub(crate) fn type_use(&mut self) -> Result<(u32, Vec<Option<&'a str>>), ParseError> {
+        let position = self.position();
+        let type_index = if self.list("type") {
+            let index = self.index(Kind::Type)?;
+            self.rparen()?;
+            Some(index)
+        } else {
+            None
+        };
+
+        let params = self.params()?;
+        let returns = self.results()?;
+        let names = params.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        let inline = FuncType::new(params.into_iter().map(|(_, t)| t).collect(), returns);
+
+        let type_index = match type_index {
+            Some(index) => index,
+            None => return Ok((self.func_type_index(inline), names)),
+        };
+
+        let func_type = self.func_type(type_index, position)?;
+        if names.is_empty() && inline.returns.is_empty() {
+            return Ok((type_index, vec![None; func_type.params.len()]));
+        }
+        if *func_type != inline {
+            return Err(ParseError::new(
+                "inline function type does not match the type index",
+                position,
+            ));
+        }
+
+        Ok((type_index, names))
+    }
+
+    pub(crate) fn func_type(
+        &self,
+        index: u32,
+        position: Position,
+    ) -> Result<&FuncType, ParseError> {
+        match self.types.get(index as usize).map(|t| &t.composite) {
+            Some(CompositeType::Func(func_type)) => Ok(func_type),
+            Some(_) => Err(ParseError::new(
+                format!("type {} is not a function type", index),
+                position,
+            )),
+            None => Err(ParseError::new(format!("unknown type {}", index), position)),
+        }
+    }
+
+    /// 同じ関数型がすでにあればそのindex、無ければ1つだけのrecグループとして追加する
+    pub(crate) fn func_type_index(&mut self, func_type: FuncType) -> u32 {
+        let found = self.types.iter().position(|t| {
+            t.is_final
+                && t.supertype.is_none()
+                && t.composite == CompositeType::Func(func_type.clone())
+        });
+        if let Some(index) = found {
+            return index as u32;
+        }
+
+        self.types.push(SubType::new(
+            CompositeType::Func(func_type),
+            self.rec_groups,
+        ));
+        self.rec_groups += 1;
+
+        self.types.len() as u32 - 1
+    }
+}
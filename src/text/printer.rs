@@ -0,0 +1,1010 @@
+use super::keywords::{self, ATOMIC_RMW_OPS, HEAP_TYPES, MEMORY_INSTRUCTIONS, PLAIN_INSTRUCTIONS};
+use super::lexer::is_idchar;
+use crate::module::{Module, NameMap};
+use crate::types::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+/// moduleをテキスト形式で書き出す。offsetsがあれば関数本体の各行の先頭に命令のファイル上の位置を書く
+pub(crate) fn print(m: &Module, offsets: Option<&[Vec<usize>]>) -> String {
+    let mut printer = Printer {
+        m,
+        ids: Ids::new(&m.names),
+        offsets,
+        out: String::new(),
+        indent: 0,
+        counts: Counts::default(),
+    };
+    printer.module();
+
+    printer.out
+}
+
+/// 種類ごとに、ここまでに書いたimportと定義の数。次に書くもののindexになる
+#[derive(Default)]
+struct Counts {
+    funcs: u32,
+    tables: u32,
+    memories: u32,
+    globals: u32,
+    tags: u32,
+}
+
+struct Printer<'a> {
+    m: &'a Module,
+    ids: Ids,
+    offsets: Option<&'a [Vec<usize>]>,
+    out: String,
+    indent: usize,
+    counts: Counts,
+}
+
+impl<'a> Printer<'a> {
+    /// 1行書く。offsetを書くときは、offsetの無い行も同じ幅だけ空けて揃える
+    fn line(&mut self, offset: Option<usize>, text: &str) {
+        if self.offsets.is_some() {
+            let prefix = offset.map_or_else(String::new, |offset| format!("(;@{:<6x};)", offset));
+            let _ = write!(self.out, "{:<11}", prefix);
+        }
+        let _ = writeln!(self.out, "{}{}", "  ".repeat(self.indent), text);
+    }
+
+    fn module(&mut self) {
+        let m = self.m;
+
+        match &self.ids.module {
+            Some(name) => {
+                let header = format!("(module ${}", name);
+                self.line(None, &header);
+            }
+            None => self.line(None, "(module"),
+        }
+        self.indent += 1;
+
+        // テキスト形式では書けないので、あったことだけを残す
+        for custom in &m.custom_sections {
+            let text = format!(
+                ";; custom section {} ({} bytes)",
+                string(custom.name.as_bytes()),
+                custom.data.len()
+            );
+            self.line(None, &text);
+        }
+
+        self.types();
+        self.imports();
+        self.funcs();
+        for table in m.table_section.iter().flat_map(|s| &s.entries) {
+            let text = format!(
+                "(table {} {})",
+                header(&self.ids.tables, self.counts.tables),
+                self.table_type(table)
+            );
+            self.counts.tables += 1;
+            self.line(None, &text);
+        }
+        for memory in m.memory_section.iter().flat_map(|s| &s.entries) {
+            let text = format!(
+                "(memory {} {})",
+                header(&self.ids.memories, self.counts.memories),
+                memory_type(memory)
+            );
+            self.counts.memories += 1;
+            self.line(None, &text);
+        }
+        for tag in m.tag_section.iter().flat_map(|s| &s.entries) {
+            let text = format!(
+                "(tag {} (type {}))",
+                header(&self.ids.tags, self.counts.tags),
+                reference(&self.ids.types, tag.type_index)
+            );
+            self.counts.tags += 1;
+            self.line(None, &text);
+        }
+        for global in m.global_section.iter().flat_map(|s| &s.entries) {
+            let text = format!(
+                "(global {} {} {})",
+                header(&self.ids.globals, self.counts.globals),
+                self.global_type(&global.global_type),
+                self.const_expr(&global.init)
+            );
+            self.counts.globals += 1;
+            self.line(None, &text);
+        }
+        for export in m.export_section.iter().flat_map(|s| &s.entries) {
+            let text = format!(
+                "(export {} ({}))",
+                string(export.field_str.as_bytes()),
+                self.export_target(export)
+            );
+            self.line(None, &text);
+        }
+        if let Some(start) = m.start_section {
+            let text = format!("(start {})", reference(&self.ids.funcs, start));
+            self.line(None, &text);
+        }
+        for (i, segment) in m
+            .element_section
+            .iter()
+            .flat_map(|s| &s.segments)
+            .enumerate()
+        {
+            let text = self.elem(i as u32, segment);
+            self.line(None, &text);
+        }
+        for (i, segment) in m.data_section.iter().flat_map(|s| &s.segments).enumerate() {
+            let text = self.data(i as u32, segment);
+            self.line(None, &text);
+        }
+
+        self.indent -= 1;
+        self.line(None, ")");
+    }
+
+    /// 同じrecグループの型が2つ以上あれば(rec ...)でまとめる
+    fn types(&mut self) {
+        let types = match &self.m.type_section {
+            Some(s) => &s.entries,
+            None => return,
+        };
+
+        let mut index = 0;
+        for group in types.chunk_by(|a, b| a.rec_group == b.rec_group) {
+            if group.len() > 1 {
+                self.line(None, "(rec");
+                self.indent += 1;
+            }
+            for sub_type in group {
+                let text = format!(
+                    "(type {} {})",
+                    header(&self.ids.types, index),
+                    self.sub_type(index, sub_type)
+                );
+                self.line(None, &text);
+                index += 1;
+            }
+            if group.len() > 1 {
+                self.indent -= 1;
+                self.line(None, ")");
+            }
+        }
+    }
+
+    fn imports(&mut self) {
+        for entry in self.m.import_section.iter().flat_map(|s| &s.entries) {
+            let desc = match &entry.kind {
+                ImportKind::Function(type_index) => {
+                    let index = self.counts.funcs;
+                    self.counts.funcs += 1;
+                    format!(
+                        "(func {}{})",
+                        header(&self.ids.funcs, index),
+                        self.signature(index, *type_index)
+                    )
+                }
+                ImportKind::Table(table) => {
+                    self.counts.tables += 1;
+                    format!(
+                        "(table {} {})",
+                        header(&self.ids.tables, self.counts.tables - 1),
+                        self.table_type(table)
+                    )
+                }
+                ImportKind::Memory(memory) => {
+                    self.counts.memories += 1;
+                    format!(
+                        "(memory {} {})",
+                        header(&self.ids.memories, self.counts.memories - 1),
+                        memory_type(memory)
+                    )
+                }
+                ImportKind::Global(global) => {
+                    self.counts.globals += 1;
+                    format!(
+                        "(global {} {})",
+                        header(&self.ids.globals, self.counts.globals - 1),
+                        self.global_type(global)
+                    )
+                }
+                ImportKind::Tag(tag) => {
+                    self.counts.tags += 1;
+                    format!(
+                        "(tag {} (type {}))",
+                        header(&self.ids.tags, self.counts.tags - 1),
+                        reference(&self.ids.types, tag.type_index)
+                    )
+                }
+            };
+
+            let text = format!(
+                "(import {} {} {})",
+                string(entry.module_str.as_bytes()),
+                string(entry.field_str.as_bytes()),
+                desc
+            );
+            self.line(None, &text);
+        }
+    }
+
+    fn funcs(&mut self) {
+        let m = self.m;
+        let types = m.function_section.iter().flat_map(|s| &s.types);
+        let bodies = m.code_section.iter().flat_map(|s| &s.bodies);
+
+        for (i, (type_index, body)) in types.zip(bodies).enumerate() {
+            let index = self.counts.funcs;
+            self.counts.funcs += 1;
+
+            let header = format!(
+                "(func {}{}",
+                header(&self.ids.funcs, index),
+                self.signature(index, *type_index)
+            );
+            self.line(None, &header);
+            self.indent += 1;
+
+            let params = m
+                .type_section
+                .as_ref()
+                .and_then(|s| s.func_type(*type_index))
+                .map_or(0, |t| t.params.len() as u32);
+            let locals: Vec<ValueType> = body
+                .locales
+                .iter()
+                .flat_map(|entry| std::iter::repeat_n(entry.value_type, entry.count as usize))
+                .collect();
+            if !locals.is_empty() {
+                let text = self.value_list("local", index, params, &locals);
+                self.line(None, text.trim_start());
+            }
+
+            let offsets = self.offsets.and_then(|offsets| offsets.get(i));
+            self.body(index, &body.code, offsets.map(Vec::as_slice));
+
+            self.indent -= 1;
+            self.line(None, ")");
+        }
+    }
+
+    /// 関数本体の命令を1行に1つずつ、blockの中は字下げして書く
+    fn body(&mut self, func: u32, code: &[Instruction], offsets: Option<&[usize]>) {
+        // 関数本体の外側のblockが@0で、内側ほど大きくなる
+        let mut depth: u32 = 0;
+        // name sectionのラベルは、blockが現れた順に数える
+        let mut blocks = 0;
+        for (i, instruction) in code.iter().enumerate() {
+            let offset = offsets.and_then(|offsets| offsets.get(i).copied());
+
+            match instruction {
+                Instruction::End => {
+                    depth = depth.saturating_sub(1);
+                    self.indent = self.indent.saturating_sub(1);
+                    self.line(offset, "end");
+                }
+                Instruction::Else => {
+                    self.indent = self.indent.saturating_sub(1);
+                    self.line(offset, "else");
+                    self.indent += 1;
+                }
+                Instruction::Block(_)
+                | Instruction::Loop(_)
+                | Instruction::If(_)
+                | Instruction::TryTable(_, _) => {
+                    let text = self.block(func, blocks, instruction, depth);
+                    blocks += 1;
+                    self.line(offset, &text);
+                    depth += 1;
+                    self.indent += 1;
+                }
+                _ => {
+                    let text = self.instruction(func, instruction, depth);
+                    self.line(offset, &text);
+                }
+            }
+        }
+    }
+
+    fn block(&self, func: u32, block: u32, instruction: &Instruction, depth: u32) -> String {
+        let (keyword, block_type) = match instruction {
+            Instruction::Block(t) => ("block", t),
+            Instruction::Loop(t) => ("loop", t),
+            Instruction::If(t) => ("if", t),
+            Instruction::TryTable(t, _) => ("try_table", t),
+            _ => unreachable!("{:?} is not a block", instruction),
+        };
+
+        let mut text = keyword.to_string();
+        if let Some(name) = self
+            .ids
+            .labels
+            .get(&func)
+            .and_then(|labels| labels.get(&block))
+        {
+            let _ = write!(text, " ${}", name);
+        }
+        if let Some(t) = block_result(block_type) {
+            let _ = write!(text, " (result {})", self.value_type(&t));
+        }
+        if let BlockType::TypeIndex(type_index) = block_type {
+            text.push_str(&self.block_signature(*type_index));
+        }
+        // catchのラベルはtry_tableの外側から数える
+        if let Instruction::TryTable(_, catches) = instruction {
+            for catch in catches {
+                let _ = match catch {
+                    CatchClause::Catch(tag, l) => write!(
+                        text,
+                        " (catch {} {}{})",
+                        reference(&self.ids.tags, *tag),
+                        l,
+                        label(depth, *l)
+                    ),
+                    CatchClause::CatchRef(tag, l) => write!(
+                        text,
+                        " (catch_ref {} {}{})",
+                        reference(&self.ids.tags, *tag),
+                        l,
+                        label(depth, *l)
+                    ),
+                    CatchClause::CatchAll(l) => {
+                        write!(text, " (catch_all {}{})", l, label(depth, *l))
+                    }
+                    CatchClause::CatchAllRef(l) => {
+                        write!(text, " (catch_all_ref {}{})", l, label(depth, *l))
+                    }
+                };
+            }
+        }
+        let _ = write!(text, "  ;; label = @{}", depth + 1);
+
+        text
+    }
+
+    /// blockを作らない命令を1つ書く。depthは囲んでいるblockの数で、分岐先のコメントに使う
+    fn instruction(&self, func: u32, instruction: &Instruction, depth: u32) -> String {
+        use Instruction::*;
+
+        if let Some((name, _)) = PLAIN_INSTRUCTIONS.iter().find(|(_, i)| i == instruction) {
+            return name.to_string();
+        }
+        if let Some((name, memarg, natural_align)) = memory_instruction(instruction) {
+            return format!("{}{}", name, self.memarg(&memarg, natural_align));
+        }
+
+        let index = |x: &VerUintN| u32::from(*x);
+        let ids = &self.ids;
+        let ty = |x: &VerUintN| reference(&ids.types, index(x));
+        let field = |t: &VerUintN, f: &VerUintN| match ids.fields.get(&index(t)) {
+            Some(fields) => format!("{} {}", ty(t), reference(fields, index(f))),
+            None => format!("{} {}", ty(t), index(f)),
+        };
+        let branch =
+            |name: &str, l: &VerUintN| format!("{} {}{}", name, index(l), label(depth, index(l)));
+
+        match instruction {
+            Br(l) => branch("br", l),
+            BrIf(l) => branch("br_if", l),
+            BrOnNull(l) => branch("br_on_null", l),
+            BrOnNonNull(l) => branch("br_on_non_null", l),
+            BrTable(targets, default) => {
+                let mut text = "br_table".to_string();
+                for l in targets.iter().chain(std::iter::once(default)) {
+                    let _ = write!(text, " {}{}", index(l), label(depth, index(l)));
+                }
+                text
+            }
+            BrOnCast(l, from, to) => format!(
+                "{} {} {}",
+                branch("br_on_cast", l),
+                self.ref_type(from),
+                self.ref_type(to)
+            ),
+            BrOnCastFail(l, from, to) => format!(
+                "{} {} {}",
+                branch("br_on_cast_fail", l),
+                self.ref_type(from),
+                self.ref_type(to)
+            ),
+            Call(f) => format!("call {}", reference(&ids.funcs, index(f))),
+            CallIndirect(type_index, table) => match index(table) {
+                0 => format!("call_indirect (type {})", ty(type_index)),
+                table => format!(
+                    "call_indirect {} (type {})",
+                    reference(&ids.tables, table),
+                    ty(type_index)
+                ),
+            },
+            CallRef(t) => format!("call_ref {}", ty(t)),
+            ReturnCallRef(t) => format!("return_call_ref {}", ty(t)),
+            Throw(tag) => format!("throw {}", reference(&ids.tags, index(tag))),
+            GetLocal(x) => format!("local.get {}", self.local(func, index(x))),
+            SetLocal(x) => format!("local.set {}", self.local(func, index(x))),
+            TeeLocal(x) => format!("local.tee {}", self.local(func, index(x))),
+            GetGlobal(x) => format!("global.get {}", reference(&ids.globals, index(x))),
+            SetGlobal(x) => format!("global.set {}", reference(&ids.globals, index(x))),
+            TableGet(x) => format!("table.get {}", reference(&ids.tables, index(x))),
+            TableSet(x) => format!("table.set {}", reference(&ids.tables, index(x))),
+            TableGrow(x) => format!("table.grow {}", reference(&ids.tables, index(x))),
+            TableSize(x) => format!("table.size {}", reference(&ids.tables, index(x))),
+            TableFill(x) => format!("table.fill {}", reference(&ids.tables, index(x))),
+            TableInit(elem, table) => format!(
+                "table.init {} {}",
+                reference(&ids.tables, index(table)),
+                reference(&ids.elems, index(elem))
+            ),
+            ElemDrop(x) => format!("elem.drop {}", reference(&ids.elems, index(x))),
+            TableCopy(dst, src) => format!(
+                "table.copy {} {}",
+                reference(&ids.tables, index(dst)),
+                reference(&ids.tables, index(src))
+            ),
+            CurrentMemory(x) => self.with_memory("memory.size", index(x)),
+            GrowMemory(x) => self.with_memory("memory.grow", index(x)),
+            MemoryFill(x) => self.with_memory("memory.fill", index(x)),
+            MemoryCopy(dst, src) => match (index(dst), index(src)) {
+                (0, 0) => "memory.copy".to_string(),
+                (dst, src) => format!(
+                    "memory.copy {} {}",
+                    reference(&ids.memories, dst),
+                    reference(&ids.memories, src)
+                ),
+            },
+            MemoryInit(data, memory) => format!(
+                "{} {}",
+                self.with_memory("memory.init", index(memory)),
+                reference(&ids.data, index(data))
+            ),
+            DataDrop(x) => format!("data.drop {}", reference(&ids.data, index(x))),
+            I32Const(v) => format!("i32.const {}", v),
+            I64Const(v) => format!("i64.const {}", v),
+            F32Const(v) => format!("f32.const {}", f32_text(*v)),
+            F64Const(v) => format!("f64.const {}", f64_text(*v)),
+            RefNull(t) => format!("ref.null {}", self.heap_type(t)),
+            RefFunc(f) => format!("ref.func {}", reference(&ids.funcs, index(f))),
+            RefTest(t) => format!("ref.test {}", self.ref_type(t)),
+            RefCast(t) => format!("ref.cast {}", self.ref_type(t)),
+            StructNew(t) => format!("struct.new {}", ty(t)),
+            StructNewDefault(t) => format!("struct.new_default {}", ty(t)),
+            StructGet(t, f) => format!("struct.get {}", field(t, f)),
+            StructGetS(t, f) => format!("struct.get_s {}", field(t, f)),
+            StructGetU(t, f) => format!("struct.get_u {}", field(t, f)),
+            StructSet(t, f) => format!("struct.set {}", field(t, f)),
+            ArrayNew(t) => format!("array.new {}", ty(t)),
+            ArrayNewDefault(t) => format!("array.new_default {}", ty(t)),
+            ArrayNewFixed(t, n) => format!("array.new_fixed {} {}", ty(t), index(n)),
+            ArrayNewData(t, d) => {
+                format!(
+                    "array.new_data {} {}",
+                    ty(t),
+                    reference(&ids.data, index(d))
+                )
+            }
+            ArrayGet(t) => format!("array.get {}", ty(t)),
+            ArrayGetS(t) => format!("array.get_s {}", ty(t)),
+            ArrayGetU(t) => format!("array.get_u {}", ty(t)),
+            ArraySet(t) => format!("array.set {}", ty(t)),
+            ArrayFill(t) => format!("array.fill {}", ty(t)),
+            ArrayCopy(dst, src) => format!("array.copy {} {}", ty(dst), ty(src)),
+            // テキスト形式に対応する命令が無いので、コメントとして残す
+            Reserved => ";; reserved opcode".to_string(),
+            Prefix(sub) => format!(";; unknown instruction 0xfc {}", index(sub)),
+            _ => unreachable!("{:?} has no text form", instruction),
+        }
+    }
+
+    /// 定数式は括弧で包んだ命令を並べて書く
+    fn const_expr(&self, code: &[Instruction]) -> String {
+        code.iter()
+            .map(|instruction| format!("({})", self.instruction(0, instruction, 0)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn elem(&self, index: u32, segment: &ElementSegment) -> String {
+        let mut text = format!("(elem {}", header(&self.ids.elems, index));
+        match &segment.mode {
+            ElementMode::Active { table, offset } => {
+                if *table != 0 {
+                    let _ = write!(text, " (table {})", reference(&self.ids.tables, *table));
+                }
+                match offset.as_slice() {
+                    [_] => {
+                        let _ = write!(text, " {}", self.const_expr(offset));
+                    }
+                    _ => {
+                        let _ = write!(text, " (offset {})", self.const_expr(offset));
+                    }
+                }
+            }
+            ElementMode::Passive => {}
+            ElementMode::Declarative => text.push_str(" declare"),
+        }
+
+        match &segment.items {
+            ElementItems::Functions(funcs) => {
+                text.push_str(" func");
+                for f in funcs {
+                    let _ = write!(text, " {}", reference(&self.ids.funcs, *f));
+                }
+            }
+            ElementItems::Expressions(exprs) => {
+                let _ = write!(text, " {}", self.ref_type(&segment.elem_type));
+                for expr in exprs {
+                    let _ = match expr.as_slice() {
+                        [_] => write!(text, " {}", self.const_expr(expr)),
+                        _ => write!(text, " (item {})", self.const_expr(expr)),
+                    };
+                }
+            }
+        }
+        text.push(')');
+
+        text
+    }
+
+    fn export_target(&self, export: &ExportEntry) -> String {
+        let ids = &self.ids;
+        match export.kind {
+            ExternalKind::Function => format!("func {}", reference(&ids.funcs, export.index)),
+            ExternalKind::Table => format!("table {}", reference(&ids.tables, export.index)),
+            ExternalKind::Memory => format!("memory {}", reference(&ids.memories, export.index)),
+            ExternalKind::Global => format!("global {}", reference(&ids.globals, export.index)),
+            ExternalKind::Tag => format!("tag {}", reference(&ids.tags, export.index)),
+            ExternalKind::Unknown => format!("unknown {}", export.index),
+        }
+    }
+
+    /// (type N)と、それを展開した引数と戻り値。引数には名前があればつける
+    fn signature(&self, func: u32, type_index: u32) -> String {
+        let mut text = format!(" (type {})", reference(&self.ids.types, type_index));
+        let func_type = self
+            .m
+            .type_section
+            .as_ref()
+            .and_then(|s| s.func_type(type_index));
+        if let Some(func_type) = func_type {
+            text.push_str(&self.value_list("param", func, 0, &func_type.params));
+            if !func_type.returns.is_empty() {
+                let _ = write!(text, " (result {})", self.value_types(&func_type.returns));
+            }
+        }
+
+        text
+    }
+
+    /// blockの(type N)と、それを展開した引数と戻り値。blockの引数には名前をつけない
+    fn block_signature(&self, type_index: u32) -> String {
+        let mut text = format!(" (type {})", reference(&self.ids.types, type_index));
+        let func_type = self
+            .m
+            .type_section
+            .as_ref()
+            .and_then(|s| s.func_type(type_index));
+        if let Some(func_type) = func_type {
+            if !func_type.params.is_empty() {
+                let _ = write!(text, " (param {})", self.value_types(&func_type.params));
+            }
+            if !func_type.returns.is_empty() {
+                let _ = write!(text, " (result {})", self.value_types(&func_type.returns));
+            }
+        }
+
+        text
+    }
+
+    /// 名前のある引数やローカル変数は1つずつ、名前の無いものは続くだけまとめて書く
+    fn value_list(&self, keyword: &str, func: u32, first: u32, types: &[ValueType]) -> String {
+        let mut text = String::new();
+        let mut open = false;
+        for (i, t) in types.iter().enumerate() {
+            match self.local_id(func, first + i as u32) {
+                Some(name) => {
+                    if open {
+                        text.push(')');
+                        open = false;
+                    }
+                    let _ = write!(text, " ({} ${} {})", keyword, name, self.value_type(t));
+                }
+                None => {
+                    if !open {
+                        let _ = write!(text, " ({}", keyword);
+                        open = true;
+                    }
+                    let _ = write!(text, " {}", self.value_type(t));
+                }
+            }
+        }
+        if open {
+            text.push(')');
+        }
+
+        text
+    }
+
+    fn local_id(&self, func: u32, index: u32) -> Option<&String> {
+        self.ids.locals.get(&func)?.get(&index)
+    }
+
+    fn local(&self, func: u32, index: u32) -> String {
+        match self.local_id(func, index) {
+            Some(name) => format!("${}", name),
+            None => index.to_string(),
+        }
+    }
+
+    fn value_type(&self, t: &ValueType) -> String {
+        match t {
+            ValueType::I32 => "i32".to_string(),
+            ValueType::I64 => "i64".to_string(),
+            ValueType::F32 => "f32".to_string(),
+            ValueType::F64 => "f64".to_string(),
+            ValueType::Ref(t) => self.ref_type(t),
+            ValueType::Unknown => "unknown".to_string(),
+        }
+    }
+
+    fn value_types(&self, types: &[ValueType]) -> String {
+        let types: Vec<String> = types.iter().map(|t| self.value_type(t)).collect();
+        types.join(" ")
+    }
+
+    /// nullを許す抽象ヒープ型の参照はfuncrefなどの省略形で書く
+    fn ref_type(&self, t: &RefType) -> String {
+        if t.nullable {
+            if let Some((name, _)) = keywords::REF_TYPES.iter().find(|(_, h)| *h == t.heap_type) {
+                return name.to_string();
+            }
+        }
+
+        match t.nullable {
+            true => format!("(ref null {})", self.heap_type(&t.heap_type)),
+            false => format!("(ref {})", self.heap_type(&t.heap_type)),
+        }
+    }
+
+    fn heap_type(&self, t: &HeapType) -> String {
+        match t {
+            HeapType::Concrete(index) => reference(&self.ids.types, *index),
+            t => HEAP_TYPES
+                .iter()
+                .find(|(_, h)| h == t)
+                .map(|(name, _)| name.to_string())
+                .expect("every abstract heap type has a name"),
+        }
+    }
+
+    /// structのフィールドは、名前があれば(field $name ...)と1つずつ書く
+    fn sub_type(&self, index: u32, sub_type: &SubType) -> String {
+        let composite = match &sub_type.composite {
+            CompositeType::Func(func_type) => {
+                let mut text = "(func".to_string();
+                if !func_type.params.is_empty() {
+                    let _ = write!(text, " (param {})", self.value_types(&func_type.params));
+                }
+                if !func_type.returns.is_empty() {
+                    let _ = write!(text, " (result {})", self.value_types(&func_type.returns));
+                }
+                text.push(')');
+                text
+            }
+            CompositeType::Struct(struct_type) => {
+                let names = self.ids.fields.get(&index);
+                let mut text = "(struct".to_string();
+                for (i, field) in struct_type.fields.iter().enumerate() {
+                    let field = self.field_type(field);
+                    let _ = match names.and_then(|names| names.get(&(i as u32))) {
+                        Some(name) => write!(text, " (field ${} {})", name, field),
+                        None => write!(text, " (field {})", field),
+                    };
+                }
+                text.push(')');
+                text
+            }
+            CompositeType::Array(field) => format!("(array {})", self.field_type(field)),
+        };
+
+        if sub_type.is_final && sub_type.supertype.is_none() {
+            return composite;
+        }
+
+        let mut text = "(sub".to_string();
+        if sub_type.is_final {
+            text.push_str(" final");
+        }
+        if let Some(supertype) = sub_type.supertype {
+            let _ = write!(text, " {}", reference(&self.ids.types, supertype));
+        }
+        let _ = write!(text, " {})", composite);
+
+        text
+    }
+
+    fn field_type(&self, field: &FieldType) -> String {
+        let storage = match &field.storage {
+            StorageType::I8 => "i8".to_string(),
+            StorageType::I16 => "i16".to_string(),
+            StorageType::Value(t) => self.value_type(t),
+        };
+
+        match field.mutable {
+            true => format!("(mut {})", storage),
+            false => storage,
+        }
+    }
+
+    fn table_type(&self, t: &TableType) -> String {
+        format!("{} {}", limits(&t.limits), self.ref_type(&t.elem_type))
+    }
+
+    fn global_type(&self, t: &GlobalType) -> String {
+        match t.mutability {
+            true => format!("(mut {})", self.value_type(&t.content_type)),
+            false => self.value_type(&t.content_type),
+        }
+    }
+
+    fn data(&self, index: u32, segment: &DataSegment) -> String {
+        let mut text = format!("(data {}", header(&self.ids.data, index));
+        if !segment.passive {
+            if segment.index != 0 {
+                let memory = reference(&self.ids.memories, segment.index);
+                let _ = write!(text, " (memory {})", memory);
+            }
+            let _ = match segment.offset.as_slice() {
+                [_] => write!(text, " {}", self.const_expr(&segment.offset)),
+                _ => write!(text, " (offset {})", self.const_expr(&segment.offset)),
+            };
+        }
+        let _ = write!(text, " {})", string(&segment.data));
+
+        text
+    }
+
+    /// memory 0のときはindexを省略する
+    fn with_memory(&self, name: &str, memory: u32) -> String {
+        match memory {
+            0 => name.to_string(),
+            memory => format!("{} {}", name, reference(&self.ids.memories, memory)),
+        }
+    }
+
+    /// 既定値と同じoffsetとalignは省略する。alignはバイト数で書く
+    fn memarg(&self, memarg: &MemArg, natural_align: u32) -> String {
+        let mut text = String::new();
+        if memarg.memory != 0 {
+            let _ = write!(text, " {}", reference(&self.ids.memories, memarg.memory));
+        }
+        if memarg.offset != 0 {
+            let _ = write!(text, " offset={}", memarg.offset);
+        }
+        if memarg.align != natural_align {
+            let _ = match 1u64.checked_shl(memarg.align) {
+                Some(bytes) => write!(text, " align={}", bytes),
+                None => write!(text, " (;align=2**{};)", memarg.align),
+            };
+        }
+
+        text
+    }
+}
+
+/// NameMapの名前を$の後ろに書ける形にしたもの。同じ種類の中で同じ名前が2度出てきたら、後のものは使わない
+#[derive(Debug, Default)]
+struct Ids {
+    module: Option<String>,
+    funcs: HashMap<u32, String>,
+    locals: HashMap<u32, HashMap<u32, String>>,
+    labels: HashMap<u32, HashMap<u32, String>>,
+    types: HashMap<u32, String>,
+    tables: HashMap<u32, String>,
+    memories: HashMap<u32, String>,
+    globals: HashMap<u32, String>,
+    elems: HashMap<u32, String>,
+    data: HashMap<u32, String>,
+    fields: HashMap<u32, HashMap<u32, String>>,
+    tags: HashMap<u32, String>,
+}
+
+impl Ids {
+    fn new(names: &NameMap) -> Self {
+        // 入れ子のblockは同じラベル名を使えるので、ラベルだけは重複を許す
+        let labels = names
+            .labels
+            .iter()
+            .map(|(func, labels)| {
+                let labels = labels
+                    .iter()
+                    .filter_map(|(i, name)| Some((*i, identifier(name)?)))
+                    .collect();
+                (*func, labels)
+            })
+            .collect();
+
+        Self {
+            module: names.module.as_deref().and_then(identifier),
+            funcs: ids(&names.functions),
+            locals: indirect_ids(&names.locals),
+            labels,
+            types: ids(&names.types),
+            tables: ids(&names.tables),
+            memories: ids(&names.memories),
+            globals: ids(&names.globals),
+            elems: ids(&names.elems),
+            data: ids(&names.data),
+            fields: indirect_ids(&names.fields),
+            tags: ids(&names.tags),
+        }
+    }
+}
+
+fn ids(names: &BTreeMap<u32, String>) -> HashMap<u32, String> {
+    let mut used = HashSet::new();
+    names
+        .iter()
+        .filter_map(|(index, name)| {
+            let id = identifier(name)?;
+            used.insert(id.clone()).then_some((*index, id))
+        })
+        .collect()
+}
+
+fn indirect_ids(
+    names: &BTreeMap<u32, BTreeMap<u32, String>>,
+) -> HashMap<u32, HashMap<u32, String>> {
+    names
+        .iter()
+        .map(|(index, names)| (*index, ids(names)))
+        .collect()
+}
+
+/// 定義の先頭に書く、名前とindexのコメント
+fn header(ids: &HashMap<u32, String>, index: u32) -> String {
+    match ids.get(&index) {
+        Some(name) => format!("${} (;{};)", name, index),
+        None => format!("(;{};)", index),
+    }
+}
+
+/// 参照するところには、名前があれば名前を、無ければindexを書く
+fn reference(ids: &HashMap<u32, String>, index: u32) -> String {
+    match ids.get(&index) {
+        Some(name) => format!("${}", name),
+        None => index.to_string(),
+    }
+}
+
+/// $の後ろに書ける名前にする
+fn identifier(name: &str) -> Option<String> {
+    if name.is_empty() {
+        return None;
+    }
+
+    Some(
+        name.chars()
+            .map(|c| if is_idchar(c) { c } else { '_' })
+            .collect(),
+    )
+}
+
+/// "..."で囲んだ文字列。表示できないバイトは\hhで書く
+fn string(bytes: &[u8]) -> String {
+    let mut text = "\"".to_string();
+    for b in bytes {
+        match b {
+            b'"' => text.push_str("\\\""),
+            b'\\' => text.push_str("\\\\"),
+            0x20..=0x7e => text.push(*b as char),
+            _ => {
+                let _ = write!(text, "\\{:02x}", b);
+            }
+        }
+    }
+    text.push('"');
+
+    text
+}
+
+fn block_result(t: &BlockType) -> Option<ValueType> {
+    match t {
+        BlockType::Empty | BlockType::TypeIndex(_) => None,
+        BlockType::I32 => Some(ValueType::I32),
+        BlockType::I64 => Some(ValueType::I64),
+        BlockType::F32 => Some(ValueType::F32),
+        BlockType::F64 => Some(ValueType::F64),
+        BlockType::Ref(t) => Some(ValueType::Ref(*t)),
+    }
+}
+
+fn limits(limits: &ResizableLimits) -> String {
+    match limits.maximum {
+        Some(maximum) => format!("{} {}", limits.initial, maximum),
+        None => limits.initial.to_string(),
+    }
+}
+
+fn memory_type(t: &MemoryType) -> String {
+    let mut text = String::new();
+    if t.memory64 {
+        text.push_str("i64 ");
+    }
+    text.push_str(&limits(&t.limits));
+    if t.shared {
+        text.push_str(" shared");
+    }
+
+    text
+}
+
+/// 分岐先のblockを外側から数えた番号のコメント。labelは内側から数えた深さ
+fn label(depth: u32, label: u32) -> String {
+    match depth.checked_sub(label) {
+        Some(target) => format!(" (;@{};)", target),
+        None => String::new(),
+    }
+}
+
+/// memargを持つ命令の名前、memarg、省略したときのalignment
+fn memory_instruction(instruction: &Instruction) -> Option<(String, MemArg, u32)> {
+    use Instruction::*;
+
+    let atomic = |op: &str, width: &AtomicWidth, memarg: &MemArg| {
+        let name = keywords::atomic_name(op, *width);
+        Some((name, *memarg, width.size().trailing_zeros()))
+    };
+
+    let memarg = match instruction {
+        AtomicLoad(width, memarg) => return atomic("load", width, memarg),
+        AtomicStore(width, memarg) => return atomic("store", width, memarg),
+        AtomicCmpxchg(width, memarg) => return atomic("cmpxchg", width, memarg),
+        AtomicRmw(op, width, memarg) => {
+            let (name, _) = ATOMIC_RMW_OPS.iter().find(|(_, o)| o == op)?;
+            return atomic(name, width, memarg);
+        }
+        I32Load(memarg) | I64Load(memarg) | F32Load(memarg) | F64Load(memarg)
+        | I32Load8S(memarg) | I32Load8U(memarg) | I32Load16S(memarg) | I32Load16U(memarg)
+        | I64Load8S(memarg) | I64Load8U(memarg) | I64Load16S(memarg) | I64Load16U(memarg)
+        | I64Load32S(memarg) | I64Load32U(memarg) | I32Store(memarg) | I64Store(memarg)
+        | F32Store(memarg) | F64Store(memarg) | I32Store8(memarg) | I32Store16(memarg)
+        | I64Store8(memarg) | I64Store16(memarg) | I64Store32(memarg) | AtomicNotify(memarg)
+        | AtomicWait32(memarg) | AtomicWait64(memarg) => *memarg,
+        _ => return None,
+    };
+
+    MEMORY_INSTRUCTIONS
+        .iter()
+        .find(|(_, make, _)| make(memarg) == *instruction)
+        .map(|(name, _, align)| (name.to_string(), memarg, *align))
+}
+
+/// 読み直すと同じbit列になるように書く。NaNはpayloadも書く
+fn f32_text(v: f32) -> String {
+    let sign = if v.is_sign_negative() { "-" } else { "" };
+    if v.is_nan() {
+        let payload = v.to_bits() & 0x7f_ffff;
+        return match payload {
+            0x40_0000 => format!("{}nan", sign),
+            payload => format!("{}nan:0x{:x}", sign, payload),
+        };
+    }
+    if v.is_infinite() {
+        return format!("{}inf", sign);
+    }
+
+    format!("{:?}", v)
+}
+
+fn f64_text(v: f64) -> String {
+    let sign = if v.is_sign_negative() { "-" } else { "" };
+    if v.is_nan() {
+        let payload = v.to_bits() & 0xf_ffff_ffff_ffff;
+        return match payload {
+            0x8_0000_0000_0000 => format!("{}nan", sign),
+            payload => format!("{}nan:0x{:x}", sign, payload),
+        };
+    }
+    if v.is_infinite() {
+        return format!("{}inf", sign);
+    }
+
+    format!("{:?}", v)
+}
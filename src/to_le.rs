@@ -0,0 +1,64 @@
+/// メモリに書き込むときのリトルエンディアンのバイト列への変換
+pub trait ToLe {
+    fn to_le_bytes(self) -> Vec<u8>;
+}
+
+impl ToLe for u8 {
+    fn to_le_bytes(self) -> Vec<u8> {
+        vec![self]
+    }
+}
+
+impl ToLe for i8 {
+    fn to_le_bytes(self) -> Vec<u8> {
+        i8::to_le_bytes(self).to_vec()
+    }
+}
+
+impl ToLe for u16 {
+    fn to_le_bytes(self) -> Vec<u8> {
+        u16::to_le_bytes(self).to_vec()
+    }
+}
+
+impl ToLe for i16 {
+    fn to_le_bytes(self) -> Vec<u8> {
+        i16::to_le_bytes(self).to_vec()
+    }
+}
+
+impl ToLe for u32 {
+    fn to_le_bytes(self) -> Vec<u8> {
+        u32::to_le_bytes(self).to_vec()
+    }
+}
+
+impl ToLe for i32 {
+    fn to_le_bytes(self) -> Vec<u8> {
+        i32::to_le_bytes(self).to_vec()
+    }
+}
+
+impl ToLe for i64 {
+    fn to_le_bytes(self) -> Vec<u8> {
+        i64::to_le_bytes(self).to_vec()
+    }
+}
+
+impl ToLe for u64 {
+    fn to_le_bytes(self) -> Vec<u8> {
+        u64::to_le_bytes(self).to_vec()
+    }
+}
+
+impl ToLe for f32 {
+    fn to_le_bytes(self) -> Vec<u8> {
+        f32::to_le_bytes(self).to_vec()
+    }
+}
+
+impl ToLe for f64 {
+    fn to_le_bytes(self) -> Vec<u8> {
+        f64::to_le_bytes(self).to_vec()
+    }
+}
@@ -1,5 +1,6 @@
 use crate::decode::error::DecodeError;
 pub use crate::instruction::Instruction;
+use crate::module::SectionType;
 use crate::runtime::RuntimeValue;
 use std::convert::TryFrom;
 
@@ -9,9 +10,16 @@ pub enum ValueType {
     I64,
     F32,
     F64,
+    Ref(RefType),
     Unknown,
 }
 
+impl ValueType {
+    pub fn is_ref(&self) -> bool {
+        matches!(self, ValueType::Ref(_))
+    }
+}
+
 impl From<u8> for ValueType {
     fn from(x: u8) -> Self {
         use ValueType::*;
@@ -21,11 +29,77 @@ impl From<u8> for ValueType {
             0x7e => I64,
             0x7d => F32,
             0x7c => F64,
-            _ => unreachable!("unreachable value type, but got {:0x}", x),
+            _ => match HeapType::from_abstract(x) {
+                Some(heap_type) => Ref(RefType::nullable(heap_type)),
+                None => unreachable!("unreachable value type, but got {:0x}", x),
+            },
         }
     }
 }
 
+/// 参照型。nullableなら(ref null ht)、そうでなければ(ref ht)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RefType {
+    pub nullable: bool,
+    pub heap_type: HeapType,
+}
+
+impl RefType {
+    pub fn new(nullable: bool, heap_type: HeapType) -> Self {
+        Self {
+            nullable,
+            heap_type,
+        }
+    }
+
+    pub fn nullable(heap_type: HeapType) -> Self {
+        Self::new(true, heap_type)
+    }
+}
+
+/// 参照が指す先の型。Concreteはtype sectionのindex
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeapType {
+    Func,
+    Extern,
+    Any,
+    Eq,
+    I31,
+    Struct,
+    Array,
+    Exn,
+    None,
+    NoFunc,
+    NoExtern,
+    NoExn,
+    Concrete(u32),
+}
+
+impl HeapType {
+    /// 1バイトで表される抽象ヒープ型。funcrefなどの省略形の値型と同じバイトを使う
+    pub fn from_abstract(x: u8) -> Option<Self> {
+        use HeapType::*;
+
+        let heap_type = match x {
+            0x70 => Func,
+            0x6f => Extern,
+            0x6e => Any,
+            0x6d => Eq,
+            0x6c => I31,
+            0x6b => Struct,
+            0x6a => Array,
+            0x69 => Exn,
+            0x71 => None,
+            0x73 => NoFunc,
+            0x72 => NoExtern,
+            0x74 => NoExn,
+            _ => return Option::None,
+        };
+
+        Some(heap_type)
+    }
+}
+
 impl From<VerUintN> for ValueType {
     fn from(x: VerUintN) -> Self {
         let x: u32 = x.into();
@@ -96,7 +170,10 @@ pub enum BlockType {
     I64,
     F32,
     F64,
+    Ref(RefType),
     Empty,
+    /// 引数を取るか複数の値を返すblock。型はtype sectionの関数型で指す
+    TypeIndex(u32),
 }
 
 impl TryFrom<u8> for BlockType {
@@ -111,12 +188,15 @@ impl TryFrom<u8> for BlockType {
             0x7d => F32,
             0x7c => F64,
             0x40 => Empty,
-            _ => {
-                return Err(DecodeError::Unexpected(format!(
-                    "unexpected return type {}",
-                    x
-                )))
-            }
+            _ => match HeapType::from_abstract(x) {
+                Some(heap_type) => Ref(RefType::nullable(heap_type)),
+                None => {
+                    return Err(DecodeError::Unexpected(format!(
+                        "unexpected return type {}",
+                        x
+                    )))
+                }
+            },
         };
 
         Ok(t)
@@ -149,7 +229,76 @@ impl TryFrom<VerUintN> for BlockType {
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct TypeSection {
-    pub(crate) entries: Vec<FuncType>,
+    pub(crate) entries: Vec<SubType>,
+}
+
+impl TypeSection {
+    pub(crate) fn func_type(&self, index: u32) -> Option<&FuncType> {
+        match &self.entries.get(index as usize)?.composite {
+            CompositeType::Func(func_type) => Some(func_type),
+            _ => None,
+        }
+    }
+}
+
+/// GC proposalのsub type。recグループに入っていない型もそれぞれ1つのグループとして扱う
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubType {
+    pub is_final: bool,
+    pub supertype: Option<u32>,
+    pub composite: CompositeType,
+    /// 何番目のrecグループに属するか
+    pub rec_group: u32,
+}
+
+impl SubType {
+    /// GC proposal以前の書き方の型。finalで親を持たない
+    pub fn new(composite: CompositeType, rec_group: u32) -> Self {
+        Self {
+            is_final: true,
+            supertype: None,
+            composite,
+            rec_group,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompositeType {
+    Func(FuncType),
+    Struct(StructType),
+    Array(FieldType),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructType {
+    pub fields: Vec<FieldType>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldType {
+    pub storage: StorageType,
+    pub mutable: bool,
+}
+
+/// struct/arrayの要素の型。i8とi16はフィールドの中でだけ使えるpacked type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageType {
+    I8,
+    I16,
+    Value(ValueType),
+}
+
+impl StorageType {
+    /// data segmentから読むときのバイト数
+    pub fn size(&self) -> usize {
+        match self {
+            StorageType::I8 => 1,
+            StorageType::I16 => 2,
+            StorageType::Value(ValueType::I64) | StorageType::Value(ValueType::F64) => 8,
+            StorageType::Value(_) => 4,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -158,6 +307,179 @@ pub struct FuncType {
     pub(crate) returns: Vec<ValueType>,
 }
 
+impl FuncType {
+    pub fn new(params: Vec<ValueType>, returns: Vec<ValueType>) -> Self {
+        Self { params, returns }
+    }
+
+    pub fn params(&self) -> &[ValueType] {
+        &self.params
+    }
+
+    pub fn returns(&self) -> &[ValueType] {
+        &self.returns
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportSection {
+    pub entries: Vec<ImportEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportEntry {
+    pub module_str: String,
+    pub field_str: String,
+    pub kind: ImportKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportKind {
+    Function(u32),
+    Table(TableType),
+    Memory(MemoryType),
+    Global(GlobalType),
+    Tag(TagType),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ResizableLimits {
+    pub initial: u64,
+    pub maximum: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TableType {
+    pub elem_type: RefType,
+    pub limits: ResizableLimits,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableSection {
+    pub entries: Vec<TableType>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MemoryType {
+    pub limits: ResizableLimits,
+    /// trueならaddressがi64のmemory (memory64 proposal)
+    pub memory64: bool,
+    /// trueなら複数のスレッドから共有できるmemory (threads proposal)
+    pub shared: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemorySection {
+    pub entries: Vec<MemoryType>,
+}
+
+/// load/store命令の即値
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemArg {
+    pub align: u32,
+    pub offset: u64,
+    /// multi memory proposalで追加されたmemory index。省略されたときは0
+    pub memory: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlobalType {
+    pub content_type: ValueType,
+    pub mutability: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalSection {
+    pub entries: Vec<GlobalEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalEntry {
+    pub global_type: GlobalType,
+    /// 初期値の定数式。終端のENDは含まない
+    pub init: Vec<Instruction>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagSection {
+    pub entries: Vec<TagType>,
+}
+
+/// exception handling proposalのtag。attributeは0(exception)のみ定義されている
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TagType {
+    pub attribute: u8,
+    pub type_index: u32,
+}
+
+/// atomic命令が扱う値の型と幅。Uがついているものはゼロ拡張して読み書きする
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AtomicWidth {
+    I32,
+    I64,
+    I32U8,
+    I32U16,
+    I64U8,
+    I64U16,
+    I64U32,
+}
+
+impl AtomicWidth {
+    /// メモリ上のバイト数
+    pub fn size(&self) -> u64 {
+        use AtomicWidth::*;
+
+        match self {
+            I32U8 | I64U8 => 1,
+            I32U16 | I64U16 => 2,
+            I32 | I64U32 => 4,
+            I64 => 8,
+        }
+    }
+
+    pub fn is_64(&self) -> bool {
+        use AtomicWidth::*;
+
+        matches!(self, I64 | I64U8 | I64U16 | I64U32)
+    }
+}
+
+impl From<u8> for AtomicWidth {
+    /// 7個ずつ並んでいるatomic命令のopcodeの位置から型を決める
+    fn from(x: u8) -> Self {
+        use AtomicWidth::*;
+
+        match x % 7 {
+            0 => I32,
+            1 => I64,
+            2 => I32U8,
+            3 => I32U16,
+            4 => I64U8,
+            5 => I64U16,
+            _ => I64U32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AtomicRmwOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Xchg,
+}
+
+/// try_tableのcatch節。labelはtry_tableの外側から数えたラベルの深さ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CatchClause {
+    Catch(u32, u32),
+    CatchRef(u32, u32),
+    CatchAll(u32),
+    CatchAllRef(u32),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct FunctionSection {
     pub types: Vec<u32>,
@@ -181,6 +503,7 @@ pub enum ExternalKind {
     Table,
     Memory,
     Global,
+    Tag,
     Unknown,
 }
 
@@ -193,24 +516,68 @@ impl From<VerUintN> for ExternalKind {
             0x01 => Table,
             0x02 => Memory,
             0x03 => Global,
+            0x04 => Tag,
             _ => Unknown,
         }
     }
 }
 
+/// custom section。afterは直前にあったcustom section以外のsectionで、先頭にあればNone
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomSection {
+    pub name: String,
+    pub data: Vec<u8>,
+    pub after: Option<SectionType>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementSection {
+    pub segments: Vec<ElementSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementSegment {
+    pub mode: ElementMode,
+    pub elem_type: RefType,
+    pub items: ElementItems,
+}
+
+/// active segmentはインスタンス化時にtableへコピーされる。
+/// declarative segmentはref.funcで参照する関数を宣言するだけのもの
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElementMode {
+    Active {
+        table: u32,
+        /// offsetの定数式。終端のENDは含まない
+        offset: Vec<Instruction>,
+    },
+    Passive,
+    Declarative,
+}
+
+/// segmentの要素。関数indexの列か、要素ごとの定数式(終端のENDは含まない)の列
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElementItems {
+    Functions(Vec<u32>),
+    Expressions(Vec<Vec<Instruction>>),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct DataSegment {
     pub index: u32,
-    pub offset: u32,
+    /// active segmentの書き込み先を求める定数式(終端のENDは含まない)。passive segmentなら空
+    pub offset: Vec<Instruction>,
     pub data: Vec<u8>,
+    /// passive segmentはインスタンス化時にはコピーされず、memory.initで使われる
+    pub passive: bool,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct DataSection {
     pub segments: Vec<DataSegment>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct CodeSection {
     pub(crate) bodies: Vec<FunctionBody>,
 }
@@ -0,0 +1,50 @@
+use crate::module::{display_function, Feature};
+use std::error::Error;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    /// 関数本体の型エラー。indexはimportした関数も含めた関数のindex。
+    /// instruction_indexはエラーになった命令が本体の何番目の命令か(0始まり)で、バイナリ上のoffsetではない。
+    /// nameはname sectionにあった関数の名前
+    Function {
+        index: u32,
+        name: Option<String>,
+        instruction_index: usize,
+        message: String,
+    },
+    /// 関数本体以外(indexの範囲、limits、export名、定数式など)のエラー
+    Module(String),
+    /// configで有効にしていないproposalを使っている
+    DisabledFeatures(Vec<Feature>),
+}
+
+impl Error for ValidationError {}
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ValidationError::*;
+        match self {
+            Function {
+                index,
+                name,
+                instruction_index,
+                message,
+            } => write!(
+                f,
+                "invalid function {} at instruction {}: {}",
+                display_function(*index, name.as_deref()),
+                instruction_index,
+                message
+            ),
+            Module(s) => write!(f, "invalid module: {}", s),
+            DisabledFeatures(features) => {
+                let features: Vec<&str> = features.iter().map(Feature::name).collect();
+                write!(
+                    f,
+                    "module uses features disabled by the configuration: {}",
+                    features.join(", ")
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,991 @@
+use super::Context;
+use crate::types::*;
+
+const I32: ValueType = ValueType::I32;
+const I64: ValueType = ValueType::I64;
+const F32: ValueType = ValueType::F32;
+const F64: ValueType = ValueType::F64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameKind {
+    Function,
+    Block,
+    Loop,
+    If,
+    Else,
+    TryTable,
+}
+
+/// 制御スタックの1フレーム。heightはフレームに入ったときのオペランドスタックの高さ
+#[derive(Debug)]
+struct Frame {
+    kind: FrameKind,
+    params: Vec<ValueType>,
+    results: Vec<ValueType>,
+    height: usize,
+    /// フレームに入ったときのinit_stackの高さ。抜けるときにこれより後で初期化したlocalを戻す
+    init_height: usize,
+    unreachable: bool,
+}
+
+impl Frame {
+    /// brでこのフレームに飛ぶときに渡す値の型。loopだけは先頭に戻るのでparamsになる
+    fn label_types(&self) -> &[ValueType] {
+        if self.kind == FrameKind::Loop {
+            &self.params
+        } else {
+            &self.results
+        }
+    }
+}
+
+/// 関数本体をオペランドスタックと制御スタックで型検査する。
+/// unreachableの後のスタックは何でも取り出せるので、その値はValueType::Unknownで表す
+pub(super) struct FuncValidator<'a> {
+    ctx: &'a Context,
+    locals: Vec<ValueType>,
+    /// 非nullの参照型のlocalは代入されるまで読めない
+    inits: Vec<bool>,
+    init_stack: Vec<usize>,
+    returns: Vec<ValueType>,
+    vals: Vec<ValueType>,
+    ctrls: Vec<Frame>,
+}
+
+impl<'a> FuncValidator<'a> {
+    pub(super) fn new(ctx: &'a Context, func_type: &FuncType, locals: &[LocalEntry]) -> Self {
+        let mut all_locals = func_type.params.clone();
+        let mut inits = vec![true; all_locals.len()];
+        for entry in locals {
+            for _ in 0..entry.count {
+                all_locals.push(entry.value_type);
+                inits.push(is_defaultable(entry.value_type));
+            }
+        }
+
+        let mut validator = Self {
+            ctx,
+            locals: all_locals,
+            inits,
+            init_stack: vec![],
+            returns: func_type.returns.clone(),
+            vals: vec![],
+            ctrls: vec![],
+        };
+        validator.push_ctrl(FrameKind::Function, vec![], func_type.returns.clone());
+        validator
+    }
+
+    /// エラーのときは何番目の命令で失敗したかを一緒に返す。
+    /// 関数本体の最後のENDはデコード時に取り除かれているので、最後に関数のフレームを閉じる
+    pub(super) fn validate(mut self, code: &[Instruction]) -> Result<(), (usize, String)> {
+        for (instruction_index, instruction) in code.iter().enumerate() {
+            self.instruction(instruction)
+                .map_err(|e| (instruction_index, e))?;
+        }
+
+        if self.ctrls.len() != 1 {
+            return Err((code.len(), "unclosed block at end of function".to_string()));
+        }
+        self.pop_ctrl().map_err(|e| (code.len(), e))?;
+
+        Ok(())
+    }
+
+    fn push(&mut self, t: ValueType) {
+        self.vals.push(t);
+    }
+
+    fn push_vals(&mut self, types: &[ValueType]) {
+        self.vals.extend_from_slice(types);
+    }
+
+    fn pop(&mut self) -> Result<ValueType, String> {
+        let frame = self.ctrls.last().expect("control stack is never empty");
+        if self.vals.len() == frame.height {
+            if frame.unreachable {
+                return Ok(ValueType::Unknown);
+            }
+            return Err("type mismatch: operand stack is empty".to_string());
+        }
+
+        Ok(self.vals.pop().unwrap())
+    }
+
+    fn pop_expect(&mut self, expected: ValueType) -> Result<ValueType, String> {
+        let actual = self.pop()?;
+        if actual != ValueType::Unknown
+            && expected != ValueType::Unknown
+            && !self.ctx.is_subtype(actual, expected)
+        {
+            return Err(format!(
+                "type mismatch: expected {:?}, but got {:?}",
+                expected, actual
+            ));
+        }
+
+        Ok(actual)
+    }
+
+    fn pop_vals(&mut self, types: &[ValueType]) -> Result<Vec<ValueType>, String> {
+        let mut popped = vec![ValueType::Unknown; types.len()];
+        for (i, t) in types.iter().enumerate().rev() {
+            popped[i] = self.pop_expect(*t)?;
+        }
+
+        Ok(popped)
+    }
+
+    /// 参照を取り出す。unreachableの後で型が分からないときはNone
+    fn pop_ref(&mut self) -> Result<Option<RefType>, String> {
+        match self.pop()? {
+            ValueType::Ref(r) => Ok(Some(r)),
+            ValueType::Unknown => Ok(None),
+            t => Err(format!(
+                "type mismatch: expected a reference, but got {:?}",
+                t
+            )),
+        }
+    }
+
+    fn op(&mut self, params: &[ValueType], results: &[ValueType]) -> Result<(), String> {
+        self.pop_vals(params)?;
+        self.push_vals(results);
+        Ok(())
+    }
+
+    fn push_ctrl(&mut self, kind: FrameKind, params: Vec<ValueType>, results: Vec<ValueType>) {
+        let height = self.vals.len();
+        self.push_vals(&params);
+        self.ctrls.push(Frame {
+            kind,
+            params,
+            results,
+            height,
+            init_height: self.init_stack.len(),
+            unreachable: false,
+        });
+    }
+
+    fn pop_ctrl(&mut self) -> Result<Frame, String> {
+        let results = self.ctrls.last().unwrap().results.clone();
+        self.pop_vals(&results)?;
+
+        let frame = self.ctrls.pop().unwrap();
+        if self.vals.len() != frame.height {
+            return Err("type mismatch: values remaining on stack at end of block".to_string());
+        }
+        for local in self.init_stack.drain(frame.init_height..) {
+            self.inits[local] = false;
+        }
+
+        Ok(frame)
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.ctrls.last_mut().unwrap();
+        self.vals.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn label(&self, depth: impl Into<u32>) -> Result<Vec<ValueType>, String> {
+        let depth = depth.into() as usize;
+        if depth >= self.ctrls.len() {
+            return Err(format!("unknown label {}", depth));
+        }
+
+        Ok(self.ctrls[self.ctrls.len() - 1 - depth]
+            .label_types()
+            .to_vec())
+    }
+
+    fn local(&self, index: VerUintN) -> Result<(usize, ValueType), String> {
+        let index = usize::from(index);
+        match self.locals.get(index) {
+            Some(t) => Ok((index, *t)),
+            None => Err(format!("unknown local {}", index)),
+        }
+    }
+
+    fn set_init(&mut self, index: usize) {
+        if !self.inits[index] {
+            self.inits[index] = true;
+            self.init_stack.push(index);
+        }
+    }
+
+    /// blockの引数と戻り値の型
+    fn block_type(
+        &self,
+        block_type: &BlockType,
+    ) -> Result<(Vec<ValueType>, Vec<ValueType>), String> {
+        let t = match block_type {
+            BlockType::Empty => return Ok((vec![], vec![])),
+            BlockType::TypeIndex(index) => {
+                let func_type = self.ctx.func_type(*index)?;
+                return Ok((func_type.params.clone(), func_type.returns.clone()));
+            }
+            BlockType::I32 => I32,
+            BlockType::I64 => I64,
+            BlockType::F32 => F32,
+            BlockType::F64 => F64,
+            BlockType::Ref(r) => ValueType::Ref(*r),
+        };
+        self.ctx.check_value_type(t)?;
+
+        Ok((vec![], vec![t]))
+    }
+
+    /// blockの引数をvalue stackから取り、引数を積んだ新しいframeを作る
+    fn push_block(&mut self, kind: FrameKind, block_type: &BlockType) -> Result<(), String> {
+        let (params, results) = self.block_type(block_type)?;
+        self.pop_vals(&params)?;
+        self.push_ctrl(kind, params, results);
+
+        Ok(())
+    }
+
+    fn call(&mut self, func_type: &FuncType) -> Result<(), String> {
+        self.op(&func_type.params, &func_type.returns)
+    }
+
+    /// 末尾呼び出しでは呼び出し先の戻り値がそのまま呼び出し元の戻り値になる
+    fn check_tail_call(&self, func_type: &FuncType) -> Result<(), String> {
+        let returns = &func_type.returns;
+        if returns.len() != self.returns.len()
+            || !returns
+                .iter()
+                .zip(&self.returns)
+                .all(|(a, b)| self.ctx.is_subtype(*a, *b))
+        {
+            return Err(format!(
+                "type mismatch: tail call returns {:?}, but function returns {:?}",
+                returns, self.returns
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_catch(&self, catch: &CatchClause) -> Result<(), String> {
+        let exnref = ValueType::Ref(RefType::new(false, HeapType::Exn));
+        let (payload, label) = match *catch {
+            CatchClause::Catch(tag, label) => (self.ctx.tag(tag)?.params.clone(), label),
+            CatchClause::CatchRef(tag, label) => {
+                let mut payload = self.ctx.tag(tag)?.params.clone();
+                payload.push(exnref);
+                (payload, label)
+            }
+            CatchClause::CatchAll(label) => (vec![], label),
+            CatchClause::CatchAllRef(label) => (vec![exnref], label),
+        };
+
+        let label_types = self.label(label)?;
+        if payload.len() != label_types.len()
+            || !payload
+                .iter()
+                .zip(&label_types)
+                .all(|(a, b)| self.ctx.is_subtype(*a, *b))
+        {
+            return Err(format!(
+                "type mismatch: catch passes {:?} to label {} of type {:?}",
+                payload, label, label_types
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// load/storeのmemargを検査してアドレスの型を返す
+    fn memarg(&self, memarg: &MemArg, size: u64) -> Result<ValueType, String> {
+        let address = self.ctx.address_type(memarg.memory)?;
+        if memarg.align >= 64 || 1u64 << memarg.align > size {
+            return Err("alignment must not be larger than natural".to_string());
+        }
+
+        Ok(address)
+    }
+
+    /// atomic命令のalignmentは自然なalignmentと一致していなければならない
+    fn atomic_memarg(&self, memarg: &MemArg, size: u64) -> Result<ValueType, String> {
+        let address = self.ctx.address_type(memarg.memory)?;
+        if memarg.align >= 64 || 1u64 << memarg.align != size {
+            return Err("atomic alignment must be natural".to_string());
+        }
+
+        Ok(address)
+    }
+
+    fn load(&mut self, memarg: &MemArg, size: u64, t: ValueType) -> Result<(), String> {
+        let address = self.memarg(memarg, size)?;
+        self.op(&[address], &[t])
+    }
+
+    fn store(&mut self, memarg: &MemArg, size: u64, t: ValueType) -> Result<(), String> {
+        let address = self.memarg(memarg, size)?;
+        self.op(&[address, t], &[])
+    }
+
+    fn concrete_ref(nullable: bool, type_index: VerUintN) -> ValueType {
+        ValueType::Ref(RefType::new(
+            nullable,
+            HeapType::Concrete(type_index.into()),
+        ))
+    }
+
+    fn struct_field(
+        &self,
+        type_index: VerUintN,
+        field_index: VerUintN,
+    ) -> Result<FieldType, String> {
+        let struct_type = self.ctx.struct_type(type_index.into())?;
+        match struct_type.fields.get(usize::from(field_index)) {
+            Some(field) => Ok(*field),
+            None => Err(format!("unknown field {}", u32::from(field_index))),
+        }
+    }
+
+    /// get_s/get_uはpacked typeにだけ、signedの指定がないgetはそれ以外にだけ使える
+    fn check_packed(field: &FieldType, signed: bool) -> Result<(), String> {
+        let packed = matches!(field.storage, StorageType::I8 | StorageType::I16);
+        if packed != signed {
+            return Err(format!(
+                "type mismatch: field of type {:?} can not be read by this instruction",
+                field.storage
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_mutable(field: &FieldType) -> Result<(), String> {
+        if !field.mutable {
+            return Err("field is immutable".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn check_defaultable(field: &FieldType) -> Result<(), String> {
+        if !is_defaultable(unpacked(field.storage)) {
+            return Err(format!(
+                "field of type {:?} is not defaultable",
+                field.storage
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn br_on_cast(
+        &mut self,
+        label: VerUintN,
+        from: RefType,
+        to: RefType,
+        on_fail: bool,
+    ) -> Result<(), String> {
+        self.ctx.check_value_type(ValueType::Ref(from))?;
+        self.ctx.check_value_type(ValueType::Ref(to))?;
+        if !self
+            .ctx
+            .is_subtype(ValueType::Ref(to), ValueType::Ref(from))
+        {
+            return Err(format!(
+                "type mismatch: {:?} is not a subtype of {:?}",
+                to, from
+            ));
+        }
+
+        // キャストに失敗したときの型。toがnullを受け取るならnullは必ず成功する側に行く
+        let diff = RefType::new(from.nullable && !to.nullable, from.heap_type);
+        let (branch, fallthrough) = if on_fail { (diff, to) } else { (to, diff) };
+
+        self.pop_expect(ValueType::Ref(from))?;
+        let types = self.label(label)?;
+        let (last, rest) = match types.split_last() {
+            Some((last, rest)) => (*last, rest.to_vec()),
+            None => return Err("type mismatch: label must take a reference".to_string()),
+        };
+        if !self.ctx.is_subtype(ValueType::Ref(branch), last) {
+            return Err(format!(
+                "type mismatch: expected {:?}, but got {:?}",
+                last, branch
+            ));
+        }
+        let popped = self.pop_vals(&rest)?;
+        self.push_vals(&popped);
+        self.push(ValueType::Ref(fallthrough));
+
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) -> Result<(), String> {
+        use Instruction::*;
+
+        match instruction {
+            Reserved => return Err("reserved opcode".to_string()),
+            Prefix(sub_opcode) => {
+                return Err(format!(
+                    "unsupported instruction 0xfc {}",
+                    u32::from(*sub_opcode)
+                ))
+            }
+
+            Unreachable => self.set_unreachable(),
+            Nop => {}
+            Block(block_type) => self.push_block(FrameKind::Block, block_type)?,
+            Loop(block_type) => self.push_block(FrameKind::Loop, block_type)?,
+            If(block_type) => {
+                self.pop_expect(I32)?;
+                self.push_block(FrameKind::If, block_type)?;
+            }
+            Else => {
+                if self.ctrls.last().unwrap().kind != FrameKind::If {
+                    return Err("else without if".to_string());
+                }
+                let frame = self.pop_ctrl()?;
+                self.push_ctrl(FrameKind::Else, frame.params, frame.results);
+            }
+            TryTable(block_type, catches) => {
+                // catchのlabelはtry_tableの外側から数える
+                for catch in catches {
+                    self.check_catch(catch)?;
+                }
+                self.push_block(FrameKind::TryTable, block_type)?;
+            }
+            End => {
+                if self.ctrls.len() == 1 {
+                    return Err("unexpected end of function".to_string());
+                }
+                let frame = self.pop_ctrl()?;
+                // elseの無いifは、条件が偽のときに何も積まずに抜ける
+                if frame.kind == FrameKind::If && frame.params != frame.results {
+                    return Err("type mismatch: if without else must not return values".to_string());
+                }
+                self.push_vals(&frame.results);
+            }
+            Throw(tag) => {
+                let params = self.ctx.tag(u32::from(*tag))?.params.clone();
+                self.pop_vals(&params)?;
+                self.set_unreachable();
+            }
+            ThrowRef => {
+                self.pop_expect(ValueType::Ref(RefType::nullable(HeapType::Exn)))?;
+                self.set_unreachable();
+            }
+            Br(depth) => {
+                let types = self.label(*depth)?;
+                self.pop_vals(&types)?;
+                self.set_unreachable();
+            }
+            BrIf(depth) => {
+                self.pop_expect(I32)?;
+                let types = self.label(*depth)?;
+                let popped = self.pop_vals(&types)?;
+                self.push_vals(&popped);
+            }
+            BrTable(depths, default) => {
+                self.pop_expect(I32)?;
+                let default_types = self.label(*default)?;
+                for depth in depths {
+                    let types = self.label(*depth)?;
+                    if types.len() != default_types.len() {
+                        return Err(
+                            "type mismatch: br_table targets have different arity".to_string()
+                        );
+                    }
+                    let popped = self.pop_vals(&types)?;
+                    self.push_vals(&popped);
+                }
+                self.pop_vals(&default_types)?;
+                self.set_unreachable();
+            }
+            Return => {
+                let returns = self.returns.clone();
+                self.pop_vals(&returns)?;
+                self.set_unreachable();
+            }
+            Call(index) => {
+                let func_type = self.ctx.func(u32::from(*index))?.clone();
+                self.call(&func_type)?;
+            }
+            CallIndirect(type_index, table) => {
+                let table = self.ctx.table(u32::from(*table))?;
+                let funcref = ValueType::Ref(RefType::nullable(HeapType::Func));
+                if !self
+                    .ctx
+                    .is_subtype(ValueType::Ref(table.elem_type), funcref)
+                {
+                    return Err("type mismatch: call_indirect needs a table of funcref".to_string());
+                }
+                let func_type = self.ctx.func_type(u32::from(*type_index))?.clone();
+                self.pop_expect(I32)?;
+                self.call(&func_type)?;
+            }
+            CallRef(type_index) => {
+                let func_type = self.ctx.func_type(u32::from(*type_index))?.clone();
+                self.pop_expect(Self::concrete_ref(true, *type_index))?;
+                self.call(&func_type)?;
+            }
+            ReturnCallRef(type_index) => {
+                let func_type = self.ctx.func_type(u32::from(*type_index))?.clone();
+                self.check_tail_call(&func_type)?;
+                self.pop_expect(Self::concrete_ref(true, *type_index))?;
+                self.pop_vals(&func_type.params)?;
+                self.set_unreachable();
+            }
+            Drop => {
+                self.pop()?;
+            }
+            Select => {
+                self.pop_expect(I32)?;
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+                if t1.is_ref() || t2.is_ref() {
+                    return Err("type mismatch: select needs numeric operands".to_string());
+                }
+                if t1 != t2 && t1 != ValueType::Unknown && t2 != ValueType::Unknown {
+                    return Err(format!(
+                        "type mismatch: select operands {:?} and {:?} differ",
+                        t2, t1
+                    ));
+                }
+                self.push(if t1 == ValueType::Unknown { t2 } else { t1 });
+            }
+
+            GetLocal(index) => {
+                let (index, t) = self.local(*index)?;
+                if !self.inits[index] {
+                    return Err(format!("uninitialized local {}", index));
+                }
+                self.push(t);
+            }
+            SetLocal(index) => {
+                let (index, t) = self.local(*index)?;
+                self.pop_expect(t)?;
+                self.set_init(index);
+            }
+            TeeLocal(index) => {
+                let (index, t) = self.local(*index)?;
+                self.pop_expect(t)?;
+                self.set_init(index);
+                self.push(t);
+            }
+            GetGlobal(index) => {
+                let global = self.ctx.global(u32::from(*index))?;
+                self.push(global.content_type);
+            }
+            SetGlobal(index) => {
+                let global = *self.ctx.global(u32::from(*index))?;
+                if !global.mutability {
+                    return Err(format!("global {} is immutable", u32::from(*index)));
+                }
+                self.pop_expect(global.content_type)?;
+            }
+
+            TableGet(table) => {
+                let elem = ValueType::Ref(self.ctx.table(u32::from(*table))?.elem_type);
+                self.op(&[I32], &[elem])?;
+            }
+            TableSet(table) => {
+                let elem = ValueType::Ref(self.ctx.table(u32::from(*table))?.elem_type);
+                self.op(&[I32, elem], &[])?;
+            }
+            TableGrow(table) => {
+                let elem = ValueType::Ref(self.ctx.table(u32::from(*table))?.elem_type);
+                self.op(&[elem, I32], &[I32])?;
+            }
+            TableSize(table) => {
+                self.ctx.table(u32::from(*table))?;
+                self.push(I32);
+            }
+            TableFill(table) => {
+                let elem = ValueType::Ref(self.ctx.table(u32::from(*table))?.elem_type);
+                self.op(&[I32, elem, I32], &[])?;
+            }
+            TableInit(elem, table) => {
+                let elem = ValueType::Ref(self.ctx.elem(u32::from(*elem))?);
+                let table = ValueType::Ref(self.ctx.table(u32::from(*table))?.elem_type);
+                if !self.ctx.is_subtype(elem, table) {
+                    return Err("type mismatch: elem segment does not fit the table".to_string());
+                }
+                self.op(&[I32, I32, I32], &[])?;
+            }
+            ElemDrop(elem) => {
+                self.ctx.elem(u32::from(*elem))?;
+            }
+            TableCopy(dst, src) => {
+                let dst = ValueType::Ref(self.ctx.table(u32::from(*dst))?.elem_type);
+                let src = ValueType::Ref(self.ctx.table(u32::from(*src))?.elem_type);
+                if !self.ctx.is_subtype(src, dst) {
+                    return Err("type mismatch: table.copy between incompatible tables".to_string());
+                }
+                self.op(&[I32, I32, I32], &[])?;
+            }
+
+            I32Load(m) => self.load(m, 4, I32)?,
+            I64Load(m) => self.load(m, 8, I64)?,
+            F32Load(m) => self.load(m, 4, F32)?,
+            F64Load(m) => self.load(m, 8, F64)?,
+            I32Load8S(m) | I32Load8U(m) => self.load(m, 1, I32)?,
+            I32Load16S(m) | I32Load16U(m) => self.load(m, 2, I32)?,
+            I64Load8S(m) | I64Load8U(m) => self.load(m, 1, I64)?,
+            I64Load16S(m) | I64Load16U(m) => self.load(m, 2, I64)?,
+            I64Load32S(m) | I64Load32U(m) => self.load(m, 4, I64)?,
+            I32Store(m) => self.store(m, 4, I32)?,
+            I64Store(m) => self.store(m, 8, I64)?,
+            F32Store(m) => self.store(m, 4, F32)?,
+            F64Store(m) => self.store(m, 8, F64)?,
+            I32Store8(m) => self.store(m, 1, I32)?,
+            I32Store16(m) => self.store(m, 2, I32)?,
+            I64Store8(m) => self.store(m, 1, I64)?,
+            I64Store16(m) => self.store(m, 2, I64)?,
+            I64Store32(m) => self.store(m, 4, I64)?,
+            CurrentMemory(memory) => {
+                let address = self.ctx.address_type(u32::from(*memory))?;
+                self.push(address);
+            }
+            GrowMemory(memory) => {
+                let address = self.ctx.address_type(u32::from(*memory))?;
+                self.op(&[address], &[address])?;
+            }
+            MemoryInit(data, memory) => {
+                self.ctx.data(u32::from(*data))?;
+                let address = self.ctx.address_type(u32::from(*memory))?;
+                self.op(&[address, I32, I32], &[])?;
+            }
+            DataDrop(data) => {
+                self.ctx.data(u32::from(*data))?;
+            }
+            MemoryCopy(dst, src) => {
+                let dst = self.ctx.address_type(u32::from(*dst))?;
+                let src = self.ctx.address_type(u32::from(*src))?;
+                // 長さはどちらのmemoryにも収まる方の型になる
+                let len = if dst == I64 && src == I64 { I64 } else { I32 };
+                self.op(&[dst, src, len], &[])?;
+            }
+            MemoryFill(memory) => {
+                let address = self.ctx.address_type(u32::from(*memory))?;
+                self.op(&[address, I32, address], &[])?;
+            }
+
+            AtomicNotify(m) => {
+                let address = self.atomic_memarg(m, 4)?;
+                self.op(&[address, I32], &[I32])?;
+            }
+            AtomicWait32(m) => {
+                let address = self.atomic_memarg(m, 4)?;
+                self.op(&[address, I32, I64], &[I32])?;
+            }
+            AtomicWait64(m) => {
+                let address = self.atomic_memarg(m, 8)?;
+                self.op(&[address, I64, I64], &[I32])?;
+            }
+            AtomicFence => {}
+            AtomicLoad(width, m) => {
+                let address = self.atomic_memarg(m, width.size())?;
+                self.op(&[address], &[atomic_type(width)])?;
+            }
+            AtomicStore(width, m) => {
+                let address = self.atomic_memarg(m, width.size())?;
+                self.op(&[address, atomic_type(width)], &[])?;
+            }
+            AtomicRmw(_, width, m) => {
+                let address = self.atomic_memarg(m, width.size())?;
+                let t = atomic_type(width);
+                self.op(&[address, t], &[t])?;
+            }
+            AtomicCmpxchg(width, m) => {
+                let address = self.atomic_memarg(m, width.size())?;
+                let t = atomic_type(width);
+                self.op(&[address, t, t], &[t])?;
+            }
+
+            I32Const(_) => self.push(I32),
+            I64Const(_) => self.push(I64),
+            F32Const(_) => self.push(F32),
+            F64Const(_) => self.push(F64),
+
+            I32Eqz | I32Clz | I32Ctz | I32Popcnt => self.op(&[I32], &[I32])?,
+            I32Eq | I32Ne | I32LtS | I32LtU | I32GtS | I32GtU | I32LeS | I32LeU | I32GeS
+            | I32GeU | I32Add | I32Sub | I32Mul | I32DivS | I32DivU | I32RemS | I32RemU
+            | I32And | I32Or | I32Xor | I32Shl | I32ShrS | I32ShrU | I32Rotl | I32Rotr => {
+                self.op(&[I32, I32], &[I32])?
+            }
+            I64Eqz => self.op(&[I64], &[I32])?,
+            I64Eq | I64Ne | I64LtS | I64LtU | I64GtS | I64GtU | I64LeS | I64LeU | I64GeS
+            | I64GeU => self.op(&[I64, I64], &[I32])?,
+            I64Clz | I64Ctz | I64Popcnt => self.op(&[I64], &[I64])?,
+            I64Add | I64Sub | I64Mul | I64DivS | I64DivU | I64RemS | I64RemU | I64And | I64Or
+            | I64Xor | I64Shl | I64ShrS | I64ShrU | I64Rotl | I64Rotr => {
+                self.op(&[I64, I64], &[I64])?
+            }
+            F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge => self.op(&[F32, F32], &[I32])?,
+            F64Eq | F64Ne | F64Lt | F64Gt | F64Le | F64Ge => self.op(&[F64, F64], &[I32])?,
+            F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt => {
+                self.op(&[F32], &[F32])?
+            }
+            F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32Copysign => {
+                self.op(&[F32, F32], &[F32])?
+            }
+            F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt => {
+                self.op(&[F64], &[F64])?
+            }
+            F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max | F64Copysign => {
+                self.op(&[F64, F64], &[F64])?
+            }
+            I32WrapI64 => self.op(&[I64], &[I32])?,
+            I32TruncSF32 | I32TruncUF32 | I32ReinterpretF32 => self.op(&[F32], &[I32])?,
+            I32TruncSF64 | I32TruncUF64 => self.op(&[F64], &[I32])?,
+            I64ExtendSI32 | I64ExtendUI32 => self.op(&[I32], &[I64])?,
+            I64TruncSF32 | I64TruncUF32 => self.op(&[F32], &[I64])?,
+            I64TruncSF64 | I64TruncUF64 | I64ReinterpretF64 => self.op(&[F64], &[I64])?,
+            F32ConvertSI32 | F32ConvertUI32 | F32ReinterpretI32 => self.op(&[I32], &[F32])?,
+            F32ConvertSI64 | F32ConvertUI64 => self.op(&[I64], &[F32])?,
+            F32DemoteF64 => self.op(&[F64], &[F32])?,
+            F64ConvertSI32 | F64ConvertUI32 => self.op(&[I32], &[F64])?,
+            F64ConvertSI64 | F64ConvertUI64 | F64ReinterpretI64 => self.op(&[I64], &[F64])?,
+            F64PromoteF32 => self.op(&[F32], &[F64])?,
+
+            RefNull(heap_type) => {
+                let t = ValueType::Ref(RefType::nullable(*heap_type));
+                self.ctx.check_value_type(t)?;
+                self.push(t);
+            }
+            RefIsNull => {
+                self.pop_ref()?;
+                self.push(I32);
+            }
+            // NOTE element sectionをまだデコードしていないので、関数が宣言済みかどうかは検査しない
+            RefFunc(index) => {
+                let type_index = self.ctx.func_type_index(u32::from(*index))?;
+                self.push(Self::concrete_ref(false, type_index.into()));
+            }
+            RefEq => {
+                let eqref = ValueType::Ref(RefType::nullable(HeapType::Eq));
+                self.op(&[eqref, eqref], &[I32])?;
+            }
+            RefAsNonNull => {
+                let t = match self.pop_ref()? {
+                    Some(r) => ValueType::Ref(RefType::new(false, r.heap_type)),
+                    None => ValueType::Unknown,
+                };
+                self.push(t);
+            }
+            BrOnNull(depth) => {
+                let r = self.pop_ref()?;
+                let types = self.label(*depth)?;
+                let popped = self.pop_vals(&types)?;
+                self.push_vals(&popped);
+                self.push(match r {
+                    Some(r) => ValueType::Ref(RefType::new(false, r.heap_type)),
+                    None => ValueType::Unknown,
+                });
+            }
+            BrOnNonNull(depth) => {
+                let r = self.pop_ref()?;
+                let types = self.label(*depth)?;
+                let (last, rest) = match types.split_last() {
+                    Some((last, rest)) => (*last, rest.to_vec()),
+                    None => return Err("type mismatch: label must take a reference".to_string()),
+                };
+                if let Some(r) = r {
+                    let non_null = ValueType::Ref(RefType::new(false, r.heap_type));
+                    if !self.ctx.is_subtype(non_null, last) {
+                        return Err(format!(
+                            "type mismatch: expected {:?}, but got {:?}",
+                            last, non_null
+                        ));
+                    }
+                }
+                let popped = self.pop_vals(&rest)?;
+                self.push_vals(&popped);
+            }
+
+            StructNew(type_index) => {
+                let types: Vec<ValueType> = self
+                    .ctx
+                    .struct_type(u32::from(*type_index))?
+                    .fields
+                    .iter()
+                    .map(|field| unpacked(field.storage))
+                    .collect();
+                self.op(&types, &[Self::concrete_ref(false, *type_index)])?;
+            }
+            StructNewDefault(type_index) => {
+                for field in &self.ctx.struct_type(u32::from(*type_index))?.fields {
+                    Self::check_defaultable(field)?;
+                }
+                self.push(Self::concrete_ref(false, *type_index));
+            }
+            StructGet(type_index, field_index)
+            | StructGetS(type_index, field_index)
+            | StructGetU(type_index, field_index) => {
+                let field = self.struct_field(*type_index, *field_index)?;
+                Self::check_packed(&field, !matches!(instruction, StructGet(..)))?;
+                self.op(
+                    &[Self::concrete_ref(true, *type_index)],
+                    &[unpacked(field.storage)],
+                )?;
+            }
+            StructSet(type_index, field_index) => {
+                let field = self.struct_field(*type_index, *field_index)?;
+                Self::check_mutable(&field)?;
+                self.op(
+                    &[
+                        Self::concrete_ref(true, *type_index),
+                        unpacked(field.storage),
+                    ],
+                    &[],
+                )?;
+            }
+            ArrayNew(type_index) => {
+                let field = *self.ctx.array_type(u32::from(*type_index))?;
+                self.op(
+                    &[unpacked(field.storage), I32],
+                    &[Self::concrete_ref(false, *type_index)],
+                )?;
+            }
+            ArrayNewDefault(type_index) => {
+                Self::check_defaultable(self.ctx.array_type(u32::from(*type_index))?)?;
+                self.op(&[I32], &[Self::concrete_ref(false, *type_index)])?;
+            }
+            ArrayNewFixed(type_index, n) => {
+                let field = *self.ctx.array_type(u32::from(*type_index))?;
+                let types = vec![unpacked(field.storage); usize::from(*n)];
+                self.op(&types, &[Self::concrete_ref(false, *type_index)])?;
+            }
+            ArrayNewData(type_index, data) => {
+                let field = *self.ctx.array_type(u32::from(*type_index))?;
+                if unpacked(field.storage).is_ref() {
+                    return Err("array.new_data needs an array of numeric type".to_string());
+                }
+                self.ctx.data(u32::from(*data))?;
+                self.op(&[I32, I32], &[Self::concrete_ref(false, *type_index)])?;
+            }
+            ArrayGet(type_index) | ArrayGetS(type_index) | ArrayGetU(type_index) => {
+                let field = *self.ctx.array_type(u32::from(*type_index))?;
+                Self::check_packed(&field, !matches!(instruction, ArrayGet(..)))?;
+                self.op(
+                    &[Self::concrete_ref(true, *type_index), I32],
+                    &[unpacked(field.storage)],
+                )?;
+            }
+            ArraySet(type_index) => {
+                let field = *self.ctx.array_type(u32::from(*type_index))?;
+                Self::check_mutable(&field)?;
+                self.op(
+                    &[
+                        Self::concrete_ref(true, *type_index),
+                        I32,
+                        unpacked(field.storage),
+                    ],
+                    &[],
+                )?;
+            }
+            ArrayLen => {
+                let arrayref = ValueType::Ref(RefType::nullable(HeapType::Array));
+                self.op(&[arrayref], &[I32])?;
+            }
+            ArrayFill(type_index) => {
+                let field = *self.ctx.array_type(u32::from(*type_index))?;
+                Self::check_mutable(&field)?;
+                self.op(
+                    &[
+                        Self::concrete_ref(true, *type_index),
+                        I32,
+                        unpacked(field.storage),
+                        I32,
+                    ],
+                    &[],
+                )?;
+            }
+            ArrayCopy(dst, src) => {
+                let dst_field = *self.ctx.array_type(u32::from(*dst))?;
+                let src_field = *self.ctx.array_type(u32::from(*src))?;
+                Self::check_mutable(&dst_field)?;
+                let compatible = match (src_field.storage, dst_field.storage) {
+                    (StorageType::Value(s), StorageType::Value(d)) => self.ctx.is_subtype(s, d),
+                    (s, d) => s == d,
+                };
+                if !compatible {
+                    return Err(format!(
+                        "type mismatch: can not copy {:?} into {:?}",
+                        src_field.storage, dst_field.storage
+                    ));
+                }
+                self.op(
+                    &[
+                        Self::concrete_ref(true, *dst),
+                        I32,
+                        Self::concrete_ref(true, *src),
+                        I32,
+                        I32,
+                    ],
+                    &[],
+                )?;
+            }
+            RefTest(ref_type) => {
+                self.ctx.check_value_type(ValueType::Ref(*ref_type))?;
+                let top = RefType::nullable(self.ctx.top_type(ref_type.heap_type));
+                self.op(&[ValueType::Ref(top)], &[I32])?;
+            }
+            RefCast(ref_type) => {
+                self.ctx.check_value_type(ValueType::Ref(*ref_type))?;
+                let top = RefType::nullable(self.ctx.top_type(ref_type.heap_type));
+                self.op(&[ValueType::Ref(top)], &[ValueType::Ref(*ref_type)])?;
+            }
+            BrOnCast(depth, from, to) => self.br_on_cast(*depth, *from, *to, false)?,
+            BrOnCastFail(depth, from, to) => self.br_on_cast(*depth, *from, *to, true)?,
+            AnyConvertExtern | ExternConvertAny => {
+                let (from, to) = if matches!(instruction, AnyConvertExtern) {
+                    (HeapType::Extern, HeapType::Any)
+                } else {
+                    (HeapType::Any, HeapType::Extern)
+                };
+                // nullかどうかは変換の前後で変わらない
+                let nullable = match self.pop_expect(ValueType::Ref(RefType::nullable(from)))? {
+                    ValueType::Ref(r) => r.nullable,
+                    _ => false,
+                };
+                self.push(ValueType::Ref(RefType::new(nullable, to)));
+            }
+            RefI31 => {
+                self.op(
+                    &[I32],
+                    &[ValueType::Ref(RefType::new(false, HeapType::I31))],
+                )?;
+            }
+            I31GetS | I31GetU => {
+                let i31ref = ValueType::Ref(RefType::nullable(HeapType::I31));
+                self.op(&[i31ref], &[I32])?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// struct/arrayのフィールドをスタックに積むときの型。packed typeはi32に拡張される
+fn unpacked(storage: StorageType) -> ValueType {
+    match storage {
+        StorageType::I8 | StorageType::I16 => I32,
+        StorageType::Value(t) => t,
+    }
+}
+
+/// 初期値(0やnull)を持つ型か
+fn is_defaultable(t: ValueType) -> bool {
+    match t {
+        ValueType::Ref(r) => r.nullable,
+        _ => true,
+    }
+}
+
+fn atomic_type(width: &AtomicWidth) -> ValueType {
+    if width.is_64() {
+        I64
+    } else {
+        I32
+    }
+}
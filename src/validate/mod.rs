@@ -0,0 +1,669 @@
+mod error;
+mod func;
+
+pub use error::ValidationError;
+use func::FuncValidator;
+
+use crate::config::Config;
+use crate::module::{Module, NameMap};
+use crate::types::*;
+use std::collections::HashSet;
+
+/// 32bitのmemoryで確保できる最大のページ数
+const MAX_PAGES: u64 = 1 << 16;
+/// memory64で確保できる最大のページ数
+const MAX_PAGES_64: u64 = 1 << 48;
+
+/// デコードしたmoduleを検証する。
+/// 関数本体の型検査のほか、indexの範囲、limits、export名の重複、globalの定数式を調べる
+pub fn validate(module: &Module) -> Result<(), ValidationError> {
+    let ctx = Context::new(module);
+
+    ctx.validate_declarations(module)?;
+    ctx.validate_data(module)?;
+    ctx.validate_code(module)?;
+
+    Ok(())
+}
+
+/// validateに加えて、configで有効にしていないproposalを使っていないかを調べる
+pub fn validate_with_config(module: &Module, config: &Config) -> Result<(), ValidationError> {
+    check_features(module, config)?;
+    validate(module)
+}
+
+/// moduleが使っているproposalがすべてconfigで有効になっているか
+pub(crate) fn check_features(module: &Module, config: &Config) -> Result<(), ValidationError> {
+    let disabled = config.disabled(module.used_features());
+    if !disabled.is_empty() {
+        return Err(ValidationError::DisabledFeatures(disabled));
+    }
+
+    Ok(())
+}
+
+/// 検証に使うmoduleの各index空間。importしたものが先に並ぶ
+pub(crate) struct Context {
+    types: Vec<SubType>,
+    /// 関数ごとのtype index
+    funcs: Vec<u32>,
+    imported_funcs: usize,
+    tables: Vec<TableType>,
+    memories: Vec<MemoryType>,
+    globals: Vec<GlobalType>,
+    imported_globals: usize,
+    /// tagごとのtype index
+    tags: Vec<u32>,
+    /// element segmentごとの要素の型
+    elems: Vec<RefType>,
+    data_count: Option<u32>,
+    /// エラーメッセージに書く関数の名前
+    names: NameMap,
+}
+
+impl Context {
+    pub(crate) fn new(module: &Module) -> Self {
+        let mut ctx = Self {
+            types: module
+                .type_section
+                .as_ref()
+                .map(|s| s.entries.clone())
+                .unwrap_or_default(),
+            funcs: vec![],
+            imported_funcs: 0,
+            tables: vec![],
+            memories: vec![],
+            globals: vec![],
+            imported_globals: 0,
+            tags: vec![],
+            elems: module
+                .element_section
+                .iter()
+                .flat_map(|s| &s.segments)
+                .map(|segment| segment.elem_type)
+                .collect(),
+            data_count: module.data_count_section,
+            names: module.names.clone(),
+        };
+
+        if let Some(section) = &module.import_section {
+            for entry in &section.entries {
+                match &entry.kind {
+                    ImportKind::Function(type_index) => ctx.funcs.push(*type_index),
+                    ImportKind::Table(table_type) => ctx.tables.push(*table_type),
+                    ImportKind::Memory(memory_type) => ctx.memories.push(*memory_type),
+                    ImportKind::Global(global_type) => ctx.globals.push(*global_type),
+                    ImportKind::Tag(tag_type) => ctx.tags.push(tag_type.type_index),
+                }
+            }
+        }
+        ctx.imported_funcs = ctx.funcs.len();
+        ctx.imported_globals = ctx.globals.len();
+
+        if let Some(section) = &module.function_section {
+            ctx.funcs.extend_from_slice(&section.types);
+        }
+        if let Some(section) = &module.table_section {
+            ctx.tables.extend_from_slice(&section.entries);
+        }
+        if let Some(section) = &module.memory_section {
+            ctx.memories.extend_from_slice(&section.entries);
+        }
+        if let Some(section) = &module.global_section {
+            ctx.globals
+                .extend(section.entries.iter().map(|entry| entry.global_type));
+        }
+        if let Some(section) = &module.tag_section {
+            ctx.tags
+                .extend(section.entries.iter().map(|entry| entry.type_index));
+        }
+
+        ctx
+    }
+
+    /// code sectionより前のsectionを検査する。data sectionと関数本体は含まない
+    pub(crate) fn validate_declarations(&self, module: &Module) -> Result<(), ValidationError> {
+        self.validate_types()?;
+        self.validate_imports(module)?;
+        self.validate_tables(module)?;
+        self.validate_memories(module)?;
+        self.validate_globals(module)?;
+        self.validate_tags(module)?;
+        self.validate_exports(module)?;
+        self.validate_start(module)
+    }
+
+    fn validate_types(&self) -> Result<(), ValidationError> {
+        for (index, sub_type) in self.types.iter().enumerate() {
+            self.check_sub_type(index as u32, sub_type)
+                .map_err(|e| ValidationError::Module(format!("type {}: {}", index, e)))?;
+        }
+
+        Ok(())
+    }
+
+    fn check_sub_type(&self, index: u32, sub_type: &SubType) -> Result<(), String> {
+        match &sub_type.composite {
+            CompositeType::Func(func_type) => {
+                for t in func_type.params.iter().chain(&func_type.returns) {
+                    self.check_value_type(*t)?;
+                }
+            }
+            CompositeType::Struct(struct_type) => {
+                for field in &struct_type.fields {
+                    self.check_storage_type(field.storage)?;
+                }
+            }
+            CompositeType::Array(field) => self.check_storage_type(field.storage)?,
+        }
+
+        let supertype = match sub_type.supertype {
+            Some(supertype) => supertype,
+            None => return Ok(()),
+        };
+        if supertype >= index {
+            return Err(format!("supertype {} must be defined before", supertype));
+        }
+        let parent = &self.types[supertype as usize];
+        if parent.is_final {
+            return Err(format!("supertype {} is final", supertype));
+        }
+
+        // TODO フィールドや引数ごとの部分型関係までは調べていない
+        let compatible = match (&sub_type.composite, &parent.composite) {
+            (CompositeType::Func(a), CompositeType::Func(b)) => {
+                a.params.len() == b.params.len() && a.returns.len() == b.returns.len()
+            }
+            (CompositeType::Struct(a), CompositeType::Struct(b)) => {
+                a.fields.len() >= b.fields.len()
+            }
+            (CompositeType::Array(_), CompositeType::Array(_)) => true,
+            _ => false,
+        };
+        if !compatible {
+            return Err(format!("type does not match supertype {}", supertype));
+        }
+
+        Ok(())
+    }
+
+    fn validate_imports(&self, module: &Module) -> Result<(), ValidationError> {
+        let entries = match &module.import_section {
+            Some(section) => &section.entries,
+            None => return Ok(()),
+        };
+
+        for entry in entries {
+            let result = match &entry.kind {
+                ImportKind::Function(type_index) => self.func_type(*type_index).map(|_| ()),
+                ImportKind::Table(table_type) => self.check_table_type(table_type),
+                ImportKind::Memory(memory_type) => check_memory_type(memory_type),
+                ImportKind::Global(global_type) => self.check_value_type(global_type.content_type),
+                ImportKind::Tag(tag_type) => self.check_tag_type(tag_type.type_index),
+            };
+            result.map_err(|e| {
+                ValidationError::Module(format!(
+                    "import {}.{}: {}",
+                    entry.module_str, entry.field_str, e
+                ))
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_tables(&self, module: &Module) -> Result<(), ValidationError> {
+        if let Some(section) = &module.table_section {
+            for (index, table_type) in section.entries.iter().enumerate() {
+                self.check_table_type(table_type)
+                    .map_err(|e| ValidationError::Module(format!("table {}: {}", index, e)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_memories(&self, module: &Module) -> Result<(), ValidationError> {
+        if let Some(section) = &module.memory_section {
+            for (index, memory_type) in section.entries.iter().enumerate() {
+                check_memory_type(memory_type)
+                    .map_err(|e| ValidationError::Module(format!("memory {}: {}", index, e)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_globals(&self, module: &Module) -> Result<(), ValidationError> {
+        let entries = match &module.global_section {
+            Some(section) => &section.entries,
+            None => return Ok(()),
+        };
+
+        for (i, entry) in entries.iter().enumerate() {
+            let index = self.imported_globals + i;
+            self.check_value_type(entry.global_type.content_type)
+                .and_then(|_| {
+                    self.check_const_expr(&entry.init, entry.global_type.content_type, index)
+                })
+                .map_err(|e| ValidationError::Module(format!("global {}: {}", index, e)))?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_tags(&self, module: &Module) -> Result<(), ValidationError> {
+        if let Some(section) = &module.tag_section {
+            for (index, tag_type) in section.entries.iter().enumerate() {
+                self.check_tag_type(tag_type.type_index)
+                    .map_err(|e| ValidationError::Module(format!("tag {}: {}", index, e)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_exports(&self, module: &Module) -> Result<(), ValidationError> {
+        let entries = match &module.export_section {
+            Some(section) => &section.entries,
+            None => return Ok(()),
+        };
+
+        let mut names = HashSet::new();
+        for entry in entries {
+            if !names.insert(entry.field_str.as_str()) {
+                return Err(ValidationError::Module(format!(
+                    "duplicate export name {}",
+                    entry.field_str
+                )));
+            }
+
+            let len = match entry.kind {
+                ExternalKind::Function => self.funcs.len(),
+                ExternalKind::Table => self.tables.len(),
+                ExternalKind::Memory => self.memories.len(),
+                ExternalKind::Global => self.globals.len(),
+                ExternalKind::Tag => self.tags.len(),
+                ExternalKind::Unknown => 0,
+            };
+            if entry.index as usize >= len {
+                return Err(ValidationError::Module(format!(
+                    "export {}: unknown {:?} {}",
+                    entry.field_str, entry.kind, entry.index
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// start関数は引数も戻り値も持たない
+    fn validate_start(&self, module: &Module) -> Result<(), ValidationError> {
+        let index = match module.start_section {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+
+        let func_type = self
+            .func(index)
+            .map_err(|e| ValidationError::Module(format!("start: {}", e)))?;
+        if !func_type.params().is_empty() || !func_type.returns().is_empty() {
+            return Err(ValidationError::Module(format!(
+                "start function {} must have type [] -> []",
+                index
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn validate_data(&self, module: &Module) -> Result<(), ValidationError> {
+        let segments = match &module.data_section {
+            Some(section) => &section.segments[..],
+            None => &[],
+        };
+
+        if let Some(count) = self.data_count {
+            if count as usize != segments.len() {
+                return Err(ValidationError::Module(format!(
+                    "data count {} does not match {} data segments",
+                    count,
+                    segments.len()
+                )));
+            }
+        }
+
+        for (index, segment) in segments.iter().enumerate() {
+            if !segment.passive {
+                self.address_type(segment.index)
+                    .and_then(|t| self.check_const_expr(&segment.offset, t, self.globals.len()))
+                    .map_err(|e| {
+                        ValidationError::Module(format!("data segment {}: {}", index, e))
+                    })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn validate_code(&self, module: &Module) -> Result<(), ValidationError> {
+        let bodies = match &module.code_section {
+            Some(section) => &section.bodies[..],
+            None => &[],
+        };
+
+        let defined = self.funcs.len() - self.imported_funcs;
+        if bodies.len() != defined {
+            return Err(ValidationError::Module(format!(
+                "{} functions are declared, but {} bodies are defined",
+                defined,
+                bodies.len()
+            )));
+        }
+
+        for (i, body) in bodies.iter().enumerate() {
+            self.validate_function((self.imported_funcs + i) as u32, body)?;
+        }
+
+        Ok(())
+    }
+
+    /// 関数本体を1つ検査する。indexはimportした関数も含めたもの
+    pub(crate) fn validate_function(
+        &self,
+        index: u32,
+        body: &FunctionBody,
+    ) -> Result<(), ValidationError> {
+        let error = |instruction_index, message| ValidationError::Function {
+            index,
+            name: self.names.function(index).map(str::to_string),
+            instruction_index,
+            message,
+        };
+
+        let func_type = self.func(index).map_err(|e| error(0, e))?;
+        for entry in &body.locales {
+            self.check_value_type(entry.value_type)
+                .map_err(|e| error(0, e))?;
+        }
+
+        FuncValidator::new(self, func_type, &body.locales)
+            .validate(&body.code)
+            .map_err(|(instruction_index, message)| error(instruction_index, message))
+    }
+
+    /// 定数式を検査する。globalの初期値からは、それより前に定義されたimmutableなglobalだけを参照できる
+    fn check_const_expr(
+        &self,
+        code: &[Instruction],
+        expected: ValueType,
+        global_index: usize,
+    ) -> Result<(), String> {
+        use Instruction::*;
+
+        for instruction in code {
+            match instruction {
+                GetGlobal(index) => {
+                    let index = usize::from(*index);
+                    if index >= global_index {
+                        return Err(format!("unknown global {}", index));
+                    }
+                    if self.globals[index].mutability {
+                        return Err("constant expression required".to_string());
+                    }
+                }
+                I32Const(_) | I64Const(_) | F32Const(_) | F64Const(_) | RefNull(_) | RefFunc(_)
+                | I32Add | I32Sub | I32Mul | I64Add | I64Sub | I64Mul | StructNew(_)
+                | StructNewDefault(_) | ArrayNew(_) | ArrayNewDefault(_) | ArrayNewFixed(..)
+                | RefI31 | AnyConvertExtern | ExternConvertAny => {}
+                _ => return Err("constant expression required".to_string()),
+            }
+        }
+
+        FuncValidator::new(self, &FuncType::new(vec![], vec![expected]), &[])
+            .validate(code)
+            .map_err(|(_, e)| e)
+    }
+
+    fn check_table_type(&self, table_type: &TableType) -> Result<(), String> {
+        self.check_value_type(ValueType::Ref(table_type.elem_type))?;
+        check_limits(&table_type.limits, u64::from(u32::MAX))?;
+        // NOTE テーブルの初期値の式はデコードしていないので、nullで埋められる型しか使えない
+        if !table_type.elem_type.nullable {
+            return Err("table of non-nullable references needs an initializer".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn check_tag_type(&self, type_index: u32) -> Result<(), String> {
+        if !self.func_type(type_index)?.returns.is_empty() {
+            return Err("tag type must not have results".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn check_storage_type(&self, storage: StorageType) -> Result<(), String> {
+        match storage {
+            StorageType::Value(t) => self.check_value_type(t),
+            _ => Ok(()),
+        }
+    }
+
+    /// 型に含まれるtype indexが定義されているか
+    pub(crate) fn check_value_type(&self, t: ValueType) -> Result<(), String> {
+        match t {
+            ValueType::Ref(RefType {
+                heap_type: HeapType::Concrete(index),
+                ..
+            }) if index as usize >= self.types.len() => Err(format!("unknown type {}", index)),
+            ValueType::Unknown => Err("unknown value type".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn func_type(&self, index: u32) -> Result<&FuncType, String> {
+        match self.types.get(index as usize).map(|t| &t.composite) {
+            Some(CompositeType::Func(func_type)) => Ok(func_type),
+            Some(_) => Err(format!("type {} is not a function type", index)),
+            None => Err(format!("unknown type {}", index)),
+        }
+    }
+
+    pub(crate) fn struct_type(&self, index: u32) -> Result<&StructType, String> {
+        match self.types.get(index as usize).map(|t| &t.composite) {
+            Some(CompositeType::Struct(struct_type)) => Ok(struct_type),
+            Some(_) => Err(format!("type {} is not a struct type", index)),
+            None => Err(format!("unknown type {}", index)),
+        }
+    }
+
+    pub(crate) fn array_type(&self, index: u32) -> Result<&FieldType, String> {
+        match self.types.get(index as usize).map(|t| &t.composite) {
+            Some(CompositeType::Array(field)) => Ok(field),
+            Some(_) => Err(format!("type {} is not an array type", index)),
+            None => Err(format!("unknown type {}", index)),
+        }
+    }
+
+    pub(crate) fn func_type_index(&self, index: u32) -> Result<u32, String> {
+        match self.funcs.get(index as usize) {
+            Some(type_index) => Ok(*type_index),
+            None => Err(format!("unknown function {}", index)),
+        }
+    }
+
+    pub(crate) fn func(&self, index: u32) -> Result<&FuncType, String> {
+        self.func_type(self.func_type_index(index)?)
+    }
+
+    pub(crate) fn table(&self, index: u32) -> Result<&TableType, String> {
+        self.tables
+            .get(index as usize)
+            .ok_or_else(|| format!("unknown table {}", index))
+    }
+
+    pub(crate) fn memory(&self, index: u32) -> Result<&MemoryType, String> {
+        self.memories
+            .get(index as usize)
+            .ok_or_else(|| format!("unknown memory {}", index))
+    }
+
+    /// memoryのアドレスの型。memory64ならi64
+    pub(crate) fn address_type(&self, index: u32) -> Result<ValueType, String> {
+        if self.memory(index)?.memory64 {
+            Ok(ValueType::I64)
+        } else {
+            Ok(ValueType::I32)
+        }
+    }
+
+    pub(crate) fn global(&self, index: u32) -> Result<&GlobalType, String> {
+        self.globals
+            .get(index as usize)
+            .ok_or_else(|| format!("unknown global {}", index))
+    }
+
+    pub(crate) fn tag(&self, index: u32) -> Result<&FuncType, String> {
+        match self.tags.get(index as usize) {
+            Some(type_index) => self.func_type(*type_index),
+            None => Err(format!("unknown tag {}", index)),
+        }
+    }
+
+    /// table.initが参照するelement segmentの要素の型
+    pub(crate) fn elem(&self, index: u32) -> Result<RefType, String> {
+        self.elems
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| format!("unknown elem segment {}", index))
+    }
+
+    /// memory.initなどが参照するdata segment。data count sectionが必要
+    pub(crate) fn data(&self, index: u32) -> Result<(), String> {
+        match self.data_count {
+            Some(count) if index < count => Ok(()),
+            Some(_) => Err(format!("unknown data segment {}", index)),
+            None => Err("data count section required".to_string()),
+        }
+    }
+
+    pub(crate) fn is_subtype(&self, a: ValueType, b: ValueType) -> bool {
+        match (a, b) {
+            (ValueType::Ref(a), ValueType::Ref(b)) => {
+                (!a.nullable || b.nullable) && self.is_heap_subtype(a.heap_type, b.heap_type)
+            }
+            _ => a == b,
+        }
+    }
+
+    fn is_heap_subtype(&self, a: HeapType, b: HeapType) -> bool {
+        use HeapType::*;
+
+        if a == b {
+            return true;
+        }
+
+        match (a, b) {
+            (Concrete(a), Concrete(b)) => self.is_concrete_subtype(a, b),
+            (Concrete(a), _) => match self.types.get(a as usize).map(|t| &t.composite) {
+                Some(CompositeType::Func(_)) => b == Func,
+                Some(CompositeType::Struct(_)) => matches!(b, Struct | Eq | Any),
+                Some(CompositeType::Array(_)) => matches!(b, Array | Eq | Any),
+                Option::None => false,
+            },
+            (I31 | Struct | Array, Eq | Any) | (Eq, Any) => true,
+            // 各階層の一番下の型は、同じ階層のすべての型の部分型
+            (None, _) => self.top_type(b) == Any,
+            (NoFunc, _) => self.top_type(b) == Func,
+            (NoExtern, _) => self.top_type(b) == Extern,
+            (NoExn, _) => self.top_type(b) == Exn,
+            _ => false,
+        }
+    }
+
+    fn is_concrete_subtype(&self, a: u32, b: u32) -> bool {
+        let mut current = Some(a);
+        while let Some(index) = current {
+            if self.is_same_type(index, b) {
+                return true;
+            }
+            current = self.types.get(index as usize).and_then(|t| t.supertype);
+        }
+
+        false
+    }
+
+    /// 別々に定義されていても、単独のrecグループで同じ形をしていれば同じ型として扱う。
+    /// TODO 複数の型を含むrecグループ同士の比較
+    fn is_same_type(&self, a: u32, b: u32) -> bool {
+        if a == b {
+            return true;
+        }
+
+        let (x, y) = match (self.types.get(a as usize), self.types.get(b as usize)) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return false,
+        };
+        let alone = |t: &SubType| {
+            self.types
+                .iter()
+                .filter(|u| u.rec_group == t.rec_group)
+                .count()
+                == 1
+        };
+
+        alone(x)
+            && alone(y)
+            && x.is_final == y.is_final
+            && x.supertype == y.supertype
+            && x.composite == y.composite
+    }
+
+    /// 型が属する階層の一番上の型
+    pub(crate) fn top_type(&self, heap_type: HeapType) -> HeapType {
+        use HeapType::*;
+
+        match heap_type {
+            Func | NoFunc => Func,
+            Extern | NoExtern => Extern,
+            Exn | NoExn => Exn,
+            Concrete(index) => match self.types.get(index as usize).map(|t| &t.composite) {
+                Some(CompositeType::Func(_)) => Func,
+                _ => Any,
+            },
+            _ => Any,
+        }
+    }
+}
+
+fn check_limits(limits: &ResizableLimits, max: u64) -> Result<(), String> {
+    if limits.initial > max {
+        return Err(format!("initial size must be at most {}", max));
+    }
+    if let Some(maximum) = limits.maximum {
+        if maximum > max {
+            return Err(format!("maximum size must be at most {}", max));
+        }
+        if limits.initial > maximum {
+            return Err("size minimum must not be greater than maximum".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+fn check_memory_type(memory_type: &MemoryType) -> Result<(), String> {
+    let max = if memory_type.memory64 {
+        MAX_PAGES_64
+    } else {
+        MAX_PAGES
+    };
+    check_limits(&memory_type.limits, max)?;
+    if memory_type.shared && memory_type.limits.maximum.is_none() {
+        return Err("shared memory must have maximum".to_string());
+    }
+
+    Ok(())
+}
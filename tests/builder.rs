@@ -0,0 +1,140 @@
+use wai::*;
+use ValueType::*;
+
+#[test]
+fn add_function() -> anyhow::Result<()> {
+    let mut builder = ModuleBuilder::new();
+
+    let mut add = FunctionBuilder::new(vec![I32, I32], vec![I32]);
+    add.extend([
+        Instruction::GetLocal(0.into()),
+        Instruction::GetLocal(1.into()),
+        Instruction::I32Add,
+    ]);
+    let add = builder.add_function(add);
+
+    // 引数の2倍をaddで計算する
+    let mut double = FunctionBuilder::new(vec![I32], vec![I32]);
+    let tmp = double.local(I32);
+    double
+        .push(Instruction::GetLocal(0.into()))
+        .push(Instruction::SetLocal(tmp.into()))
+        .push(Instruction::GetLocal(tmp.into()))
+        .push(Instruction::GetLocal(tmp.into()))
+        .push(Instruction::Call(add.into()));
+    let double = builder.add_function(double);
+
+    builder
+        .export_function("add", add)
+        .export_function("double", double);
+    let m = builder.build()?;
+
+    // 同じ型は1つにまとめられる
+    assert_eq!(Module::from_byte(m.to_bytes())?, m);
+
+    let mut instance = Instance::new(m)?;
+    assert_eq!(
+        instance.invoke("add", vec![RuntimeValue::I32(1), RuntimeValue::I32(2)])?,
+        vec![RuntimeValue::I32(3)]
+    );
+    assert_eq!(
+        instance.invoke("double", vec![RuntimeValue::I32(21)])?,
+        vec![RuntimeValue::I32(42)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn memory_global_and_data() -> anyhow::Result<()> {
+    let mut builder = ModuleBuilder::new();
+    let memory = builder.add_memory(MemoryType {
+        limits: ResizableLimits {
+            initial: 1,
+            maximum: None,
+        },
+        ..MemoryType::default()
+    });
+    builder.add_data(memory, 16, b"\x2a\x00\x00\x00".to_vec());
+    let passive = builder.add_passive_data(b"\x07".to_vec());
+    let global = builder.add_global(
+        GlobalType {
+            content_type: I32,
+            mutability: false,
+        },
+        vec![Instruction::I32Const(16)],
+    );
+
+    let memarg = MemArg {
+        align: 2,
+        offset: 0,
+        memory,
+    };
+    let mut load = FunctionBuilder::new(vec![], vec![I32]);
+    load.extend([
+        Instruction::GetGlobal(global.into()),
+        Instruction::I32Load(memarg),
+        // memory[0] = 7
+        Instruction::I32Const(0),
+        Instruction::I32Const(0),
+        Instruction::I32Const(1),
+        Instruction::MemoryInit(passive.into(), memory.into()),
+        Instruction::I32Const(0),
+        Instruction::I32Load8U(MemArg { align: 0, ..memarg }),
+        Instruction::I32Add,
+    ]);
+    let load = builder.add_function(load);
+    builder
+        .export_function("load", load)
+        .export_memory("memory", memory);
+
+    let mut instance = Instance::new(builder.build()?)?;
+    assert_eq!(
+        instance.invoke("load", vec![])?,
+        vec![RuntimeValue::I32(49)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn imports_come_first() -> anyhow::Result<()> {
+    let mut builder = ModuleBuilder::new();
+    let memory_type = MemoryType {
+        limits: ResizableLimits {
+            initial: 1,
+            maximum: Some(1),
+        },
+        ..MemoryType::default()
+    };
+    assert_eq!(builder.import_memory("env", "memory", memory_type), 0);
+    assert_eq!(builder.add_memory(memory_type), 1);
+
+    let f = builder.import_function("env", "f", FuncType::new(vec![], vec![]));
+    let g = builder.add_function(FunctionBuilder::new(vec![], vec![]));
+    assert_eq!((f, g), (0, 1));
+    builder.build()?;
+
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "functions must be imported before defining functions")]
+fn import_after_definition() {
+    let mut builder = ModuleBuilder::new();
+    builder.add_function(FunctionBuilder::new(vec![], vec![]));
+    builder.import_function("env", "f", FuncType::new(vec![], vec![]));
+}
+
+#[test]
+fn invalid_function() {
+    let mut builder = ModuleBuilder::new();
+    let mut f = FunctionBuilder::new(vec![], vec![I32]);
+    f.push(Instruction::I64Const(0));
+    builder.add_function(f);
+
+    assert!(matches!(
+        builder.build(),
+        Err(ValidationError::Function { index: 0, .. })
+    ));
+}
@@ -0,0 +1,61 @@
+#![allow(dead_code)]
+
+use wai::*;
+
+/// wastでテキスト形式をバイナリにする。wastがつけるname sectionも含む
+pub fn wast_encode(wat: &str) -> Vec<u8> {
+    let buf = wast::parser::ParseBuffer::new(wat).unwrap();
+    let mut wat = wast::parser::parse::<wast::Wat>(&buf).unwrap();
+    wat.module.encode().unwrap()
+}
+
+/// 符号なしLEB128
+pub fn leb(mut n: usize) -> Vec<u8> {
+    let mut bytes = vec![];
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+pub fn section(id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut s = vec![id];
+    s.extend(leb(payload.len()));
+    s.extend_from_slice(payload);
+    s
+}
+
+/// ヘッダの後にsectionを並べたバイナリ
+pub fn module_bytes(sections: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = b"\0asm".to_vec();
+    bytes.extend_from_slice(&[1, 0, 0, 0]);
+    for s in sections {
+        bytes.extend_from_slice(s);
+    }
+    bytes
+}
+
+pub fn module(sections: &[Vec<u8>]) -> Module {
+    Module::from_byte(module_bytes(sections)).unwrap()
+}
+
+/// (localの宣言, 本体)の組から code sectionを作る
+pub fn code(bodies: &[(&[u8], &[u8])]) -> Vec<u8> {
+    let mut payload = leb(bodies.len());
+    for (locals, body) in bodies {
+        payload.extend(leb(locals.len() + body.len()));
+        payload.extend_from_slice(locals);
+        payload.extend_from_slice(body);
+    }
+    section(0x0A, &payload)
+}
+
+/// export sectionの関数の1項目
+pub fn export(name: &str, index: u8) -> Vec<u8> {
+    [&[name.len() as u8][..], name.as_bytes(), &[0x00, index]].concat()
+}
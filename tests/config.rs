@@ -0,0 +1,199 @@
+use wai::*;
+
+fn without_gc() -> Config {
+    let mut config = Config::new();
+    config.disable(Feature::Gc);
+    config
+}
+
+#[test]
+fn disabled_features_are_rejected_at_every_stage() -> anyhow::Result<()> {
+    let wat = r#"
+        (module
+          (type $point (struct (field i32)))
+          (func (export "f") (result i32) (i32.const 1)))
+    "#;
+    let bytes = Module::from_wat(wat)?.to_bytes();
+    let config = without_gc();
+
+    let error = Module::from_byte_with_config(&bytes, &config).unwrap_err();
+    match error.kind() {
+        DecodeError::UnsupportedFeatures { features, .. } => {
+            assert_eq!(features, &vec![Feature::Gc])
+        }
+        e => panic!("expect UnsupportedFeatures, but got {}", e),
+    }
+    assert!(error
+        .to_string()
+        .starts_with("module uses features disabled by the configuration: gc"));
+
+    let mut decoder = StreamingDecoder::with_config(config.clone());
+    decoder.push(&bytes)?;
+    let error = decoder.finish().unwrap_err();
+    assert!(matches!(
+        error.kind(),
+        DecodeError::UnsupportedFeatures { .. }
+    ));
+
+    let m = Module::from_wat(wat)?;
+    assert_eq!(
+        validate_with_config(&m, &config),
+        Err(ValidationError::DisabledFeatures(vec![Feature::Gc]))
+    );
+    match Instance::with_config(m, Imports::new(), config) {
+        Err(RuntimeError::Invalid(ValidationError::DisabledFeatures(features))) => {
+            assert_eq!(features, vec![Feature::Gc])
+        }
+        other => panic!("expect DisabledFeatures, but got {:?}", other.map(|_| ())),
+    }
+
+    // 使っていないproposalを無効にしても読める
+    let m = Module::from_wat_with_config(
+        r#"(module (func (export "f") (result i32) (i32.const 1)))"#,
+        &Config::mvp(),
+    )?;
+    let mut instance = Instance::with_config(m, Imports::new(), Config::mvp())?;
+    assert_eq!(instance.invoke("f", vec![])?, vec![RuntimeValue::I32(1)]);
+
+    Ok(())
+}
+
+#[test]
+fn fuel_and_call_depth_are_limited() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (func (export "spin") (loop $l (br $l)))
+          (func $recurse (export "recurse") (call $recurse))
+          (func (export "one") (result i32) (i32.const 1)))
+        "#,
+    )?;
+    let mut config = Config::new();
+    config.fuel = Some(1000);
+    config.max_call_depth = 100;
+    let mut instance = Instance::with_config(m, Imports::new(), config)?;
+
+    assert!(matches!(
+        instance.invoke("spin", vec![]),
+        Err(RuntimeError::OutOfFuel)
+    ));
+    assert!(matches!(
+        instance.invoke("recurse", vec![]),
+        Err(RuntimeError::CallStackExhausted)
+    ));
+    // fuelはinvokeごとに満タンから始まる
+    assert_eq!(instance.invoke("one", vec![])?, vec![RuntimeValue::I32(1)]);
+
+    Ok(())
+}
+
+#[test]
+fn memory_pages_are_capped() -> anyhow::Result<()> {
+    let mut config = Config::new();
+    config.max_memory_pages = Some(2);
+
+    let m = Module::from_wat(
+        r#"
+        (module
+          (memory 1)
+          (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0))))
+        "#,
+    )?;
+    let mut instance = Instance::with_config(m, Imports::new(), config.clone())?;
+    assert_eq!(
+        instance.invoke("grow", vec![RuntimeValue::I32(2)])?,
+        vec![RuntimeValue::I32(-1)]
+    );
+    assert_eq!(
+        instance.invoke("grow", vec![RuntimeValue::I32(1)])?,
+        vec![RuntimeValue::I32(1)]
+    );
+
+    let m = Module::from_wat("(module (memory 3))")?;
+    assert!(Instance::with_config(m, Imports::new(), config).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn nans_are_canonicalized() -> anyhow::Result<()> {
+    let wat = r#"
+        (module
+          (func (export "nan") (result f32)
+            (f32.add (f32.const -nan:0x1) (f32.const 1))))
+    "#;
+    let bits = |config: Config| -> anyhow::Result<u32> {
+        let mut instance = Instance::with_config(Module::from_wat(wat)?, Imports::new(), config)?;
+        match instance.invoke("nan", vec![])?[..] {
+            [RuntimeValue::F32(v)] => Ok(v.to_bits()),
+            ref values => panic!("expect f32, but got {:?}", values),
+        }
+    };
+
+    let mut config = Config::new();
+    config.canonicalize_nans = true;
+    assert_eq!(bits(config)?, 0x7fc0_0000);
+    assert_ne!(bits(Config::new())?, 0x7fc0_0000);
+
+    Ok(())
+}
+
+#[test]
+fn f64_nans_are_canonicalized() -> anyhow::Result<()> {
+    let wat = r#"
+        (module
+          (func (export "add") (param f64 f64) (result f64)
+            (f64.add (local.get 0) (local.get 1)))
+          (func (export "sqrt") (param f64) (result f64)
+            (f64.sqrt (local.get 0)))
+          (func (export "min") (param f64 f64) (result f64)
+            (f64.min (local.get 0) (local.get 1)))
+          (func (export "promote") (param f32) (result f64)
+            (f64.promote_f32 (local.get 0)))
+          (func (export "neg") (param f64) (result f64)
+            (f64.neg (local.get 0))))
+    "#;
+    let nan = f64::from_bits(0xfff0_0000_0000_0001);
+    let mut config = Config::new();
+    config.canonicalize_nans = true;
+    let mut instance = Instance::with_config(Module::from_wat(wat)?, Imports::new(), config)?;
+    let mut bits = |name: &str, args: Vec<RuntimeValue>| -> anyhow::Result<u64> {
+        match instance.invoke(name, args)?[..] {
+            [RuntimeValue::F64(v)] => Ok(v.to_bits()),
+            ref values => panic!("expect f64, but got {:?}", values),
+        }
+    };
+
+    // f32ではなくf64のまま計算する
+    let sum = bits("add", vec![RuntimeValue::F64(0.1), RuntimeValue::F64(0.2)])?;
+    assert_eq!(f64::from_bits(sum), 0.1 + 0.2);
+
+    let canonical = 0x7ff8_0000_0000_0000;
+    assert_eq!(
+        bits("add", vec![RuntimeValue::F64(nan), RuntimeValue::F64(1.0)])?,
+        canonical
+    );
+    assert_eq!(bits("sqrt", vec![RuntimeValue::F64(-1.0)])?, canonical);
+    assert_eq!(
+        bits("min", vec![RuntimeValue::F64(nan), RuntimeValue::F64(1.0)])?,
+        canonical
+    );
+    assert_eq!(
+        bits(
+            "promote",
+            vec![RuntimeValue::F32(f32::from_bits(0xff80_0001))]
+        )?,
+        canonical
+    );
+    // negは符号ビットを反転するだけなのでNaNの仮数部は残る
+    assert_eq!(
+        bits("neg", vec![RuntimeValue::F64(nan)])?,
+        0x7ff0_0000_0000_0001
+    );
+    assert_eq!(
+        bits("min", vec![RuntimeValue::F64(0.0), RuntimeValue::F64(-0.0)])?,
+        (-0.0f64).to_bits()
+    );
+
+    Ok(())
+}
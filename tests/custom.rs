@@ -0,0 +1,97 @@
+use wai::*;
+
+/// ヘッダに続けてsectionを並べたバイナリ。sectionは(id, 中身)
+fn module_bytes(sections: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let mut bytes = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    for (id, content) in sections {
+        bytes.push(*id);
+        bytes.push(content.len() as u8);
+        bytes.extend_from_slice(content);
+    }
+
+    bytes
+}
+
+fn custom(name: &str, data: &[u8]) -> (u8, Vec<u8>) {
+    let mut content = vec![name.len() as u8];
+    content.extend_from_slice(name.as_bytes());
+    content.extend_from_slice(data);
+
+    (0, content)
+}
+
+#[test]
+fn custom_sections_keep_order_and_position() -> anyhow::Result<()> {
+    // type sectionに() -> ()の型が1つ
+    let types = (1, vec![0x01, 0x60, 0x00, 0x00]);
+    let bytes = module_bytes(&[
+        custom("first", b"\x01\x02"),
+        types,
+        custom("second", b""),
+        // 中身はwasmとして意味のないバイト列でも読める
+        custom("garbage", b"\xff\xff\xff\xff\x00"),
+        custom("first", b"\x03"),
+    ]);
+    let m = Module::from_byte(&bytes)?;
+
+    let sections: Vec<(&str, Option<SectionType>)> = m
+        .custom_sections()
+        .map(|c| (c.name.as_str(), c.after))
+        .collect();
+    assert_eq!(
+        sections,
+        [
+            ("first", None),
+            ("second", Some(SectionType::Type)),
+            ("garbage", Some(SectionType::Type)),
+            ("first", Some(SectionType::Type)),
+        ]
+    );
+    assert_eq!(m.custom_section("first"), Some(&[0x01, 0x02][..]));
+    assert_eq!(m.custom_section("missing"), None);
+    assert_eq!(m.to_bytes(), bytes);
+
+    Ok(())
+}
+
+#[test]
+fn add_and_remove_custom_sections() -> anyhow::Result<()> {
+    let mut m = Module::from_wat("(module (func))")?;
+    m.add_custom_section("producers", vec![0x00]);
+    m.insert_custom_section(CustomSection {
+        name: "early".to_string(),
+        data: vec![0x2a],
+        after: None,
+    });
+
+    let names: Vec<&str> = m.custom_sections().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["early", "producers"]);
+
+    let decoded = Module::from_byte(m.to_bytes())?;
+    assert_eq!(decoded, m);
+
+    let removed = m.remove_custom_sections("early");
+    assert_eq!(removed.len(), 1);
+    assert_eq!(removed[0].data, [0x2a]);
+    assert!(m.remove_custom_sections("early").is_empty());
+    assert_eq!(m.custom_sections().count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn name_section_follows_changes() -> anyhow::Result<()> {
+    let mut m = Module::from_wat("(module (func))")?;
+    assert_eq!(m.names().function(0), None);
+
+    // 関数名のsubsectionで、関数0の名前をmain
+    let names = [0x01, 0x07, 0x01, 0x00, 0x04, b'm', b'a', b'i', b'n'];
+    m.add_custom_section("name", names.to_vec());
+    assert_eq!(m.names().function(0), Some("main"));
+    assert!(m.to_wat().contains("(func $main (;0;)"));
+
+    m.remove_custom_sections("name");
+    assert!(m.names().is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,224 @@
+use wai::*;
+
+fn location(bytes: &[u8]) -> Location {
+    let e = Module::from_byte(bytes).unwrap_err();
+    *e.location().expect("decode error should have location")
+}
+
+#[test]
+fn invalid_header() {
+    let l = location(b"\0wasm\x01\0\0\0");
+    assert_eq!(l.offset, 0);
+    assert_eq!(l.section, None);
+
+    // 9バイト目にある未知のsection id
+    let l = location(b"\0asm\x01\0\0\0\x20\x00");
+    assert_eq!(l.offset, 8);
+    assert_eq!(l.section, None);
+}
+
+#[test]
+fn error_in_function_body() {
+    #[rustfmt::skip]
+    let bytes = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // type 0: () -> ()
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        // (import "m" "f" (func (type 0)))
+        0x02, 0x07, 0x01, 0x01, 0x6d, 0x01, 0x66, 0x00, 0x00,
+        0x03, 0x03, 0x02, 0x00, 0x00,
+        // 2つ目の関数本体の0xe0が未知のopcode
+        0x0a, 0x08, 0x02, 0x02, 0x00, 0x0b, 0x03, 0x00, 0xe0, 0x0b,
+    ];
+
+    let e = Module::from_byte(bytes).unwrap_err();
+    let l = e.location().unwrap();
+    assert_eq!(l.offset, 36);
+    assert_eq!(l.section, Some(SectionType::Code));
+    // importした関数の次から数える
+    assert_eq!(l.function, Some(2));
+    assert!(e
+        .to_string()
+        .ends_with("(at offset 0x24 in Code section, func[2])"));
+}
+
+#[test]
+fn truncated_section() {
+    // type sectionのsizeがファイルの残りより大きい
+    let l = location(b"\0asm\x01\0\0\0\x01\x10\x01\x60");
+    assert_eq!(l.offset, 10);
+    assert_eq!(l.section, Some(SectionType::Type));
+    assert_eq!(l.function, None);
+}
+
+fn module(sections: &[&[u8]]) -> Vec<u8> {
+    let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+    for s in sections {
+        bytes.extend_from_slice(s);
+    }
+    bytes
+}
+
+const TYPE: &[u8] = &[0x01, 0x04, 0x01, 0x60, 0x00, 0x00];
+const FUNC: &[u8] = &[0x03, 0x02, 0x01, 0x00];
+const CODE: &[u8] = &[0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b];
+
+fn kind(bytes: &[u8]) -> String {
+    Module::from_byte(bytes).unwrap_err().kind().to_string()
+}
+
+#[test]
+fn section_order() {
+    assert!(Module::from_byte(module(&[TYPE, FUNC, CODE])).is_ok());
+
+    let e = Module::from_byte(module(&[TYPE, TYPE, FUNC, CODE])).unwrap_err();
+    assert!(matches!(
+        e.kind(),
+        DecodeError::DuplicateSection(SectionType::Type)
+    ));
+    assert_eq!(e.location().unwrap().offset, 14);
+
+    assert!(matches!(
+        Module::from_byte(module(&[FUNC, TYPE, CODE]))
+            .unwrap_err()
+            .kind(),
+        DecodeError::SectionOutOfOrder(SectionType::Type)
+    ));
+
+    // custom sectionはどこに何回あってもよい
+    let custom: &[u8] = &[0x00, 0x02, 0x01, 0x61];
+    assert!(Module::from_byte(module(&[custom, TYPE, custom, FUNC, CODE, custom])).is_ok());
+}
+
+#[test]
+fn section_size() {
+    // function sectionのsizeが1バイト大きく、code sectionのidまで読んでしまう
+    let e = Module::from_byte(module(&[TYPE, &[0x03, 0x03, 0x01, 0x00], CODE])).unwrap_err();
+    assert!(matches!(e.kind(), DecodeError::SectionSizeMismatch));
+    assert_eq!(e.location().unwrap().section, Some(SectionType::Function));
+
+    // 名前がcustom sectionからはみ出している
+    assert!(matches!(
+        Module::from_byte(module(&[&[0x00, 0x02, 0x05, 0x61]]))
+            .unwrap_err()
+            .kind(),
+        DecodeError::IOError(_)
+    ));
+}
+
+#[test]
+fn inconsistent_lengths() {
+    assert_eq!(
+        kind(&module(&[TYPE, FUNC])),
+        "function and code section have inconsistent lengths"
+    );
+    assert_eq!(
+        kind(&module(&[TYPE, CODE])),
+        "function and code section have inconsistent lengths"
+    );
+    assert_eq!(
+        kind(&module(&[&[0x0c, 0x01, 0x01]])),
+        "data count and data section have inconsistent lengths"
+    );
+}
+
+#[test]
+fn malformed_binary() {
+    assert_eq!(kind(b"\0asm\x02\0\0\0"), "unknown binary version 2");
+
+    // 関数本体がENDで終わっていない
+    let code: &[u8] = &[0x0a, 0x04, 0x01, 0x02, 0x00, 0x01];
+    assert!(kind(&module(&[TYPE, FUNC, code])).ends_with("END opcode expected"));
+
+    // 6バイトのu32
+    let func: &[u8] = &[0x03, 0x07, 0x01, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00];
+    assert!(kind(&module(&[TYPE, func, CODE])).ends_with("integer representation too long"));
+}
+
+fn exceeded(bytes: &[u8], limits: &DecodeLimits) -> &'static str {
+    match Module::from_byte_with_limits(bytes, limits)
+        .unwrap_err()
+        .kind()
+    {
+        DecodeError::LimitExceeded { limit, .. } => limit,
+        e => panic!("expected limit error, but got {:?}", e),
+    }
+}
+
+#[test]
+fn decode_limits() {
+    let bytes = module(&[TYPE, FUNC, CODE]);
+    let default = DecodeLimits::default();
+    assert!(Module::from_byte_with_limits(&bytes, &default).is_ok());
+
+    let limits = DecodeLimits {
+        max_module_size: 8,
+        ..default
+    };
+    assert_eq!(exceeded(&bytes, &limits), "module size");
+
+    let limits = DecodeLimits {
+        max_functions: 0,
+        ..default
+    };
+    assert_eq!(exceeded(&bytes, &limits), "functions");
+
+    let limits = DecodeLimits {
+        max_body_size: 1,
+        ..default
+    };
+    assert_eq!(exceeded(&bytes, &limits), "body size");
+
+    // (local i32 i32)
+    let locals = module(&[
+        TYPE,
+        FUNC,
+        &[0x0a, 0x06, 0x01, 0x04, 0x01, 0x02, 0x7f, 0x0b],
+    ]);
+    let limits = DecodeLimits {
+        max_locals: 1,
+        ..default
+    };
+    assert_eq!(exceeded(&locals, &limits), "locals");
+    // 宣言だけで10万個のlocalを要求する
+    let many_locals = module(&[
+        TYPE,
+        FUNC,
+        &[0x0a, 0x08, 0x01, 0x06, 0x01, 0xa0, 0x8d, 0x06, 0x7f, 0x0b],
+    ]);
+    assert_eq!(exceeded(&many_locals, &default), "locals");
+
+    // block block end end
+    let nested = module(&[
+        TYPE,
+        FUNC,
+        &[
+            0x0a, 0x08, 0x01, 0x06, 0x00, 0x02, 0x40, 0x02, 0x40, 0x0b, 0x0b, 0x0b,
+        ],
+    ]);
+    let limits = DecodeLimits {
+        max_nesting_depth: 1,
+        ..default
+    };
+    assert_eq!(exceeded(&nested, &limits), "nesting depth");
+
+    // (memory 1) (data (i32.const 0) "abcd")
+    let data = module(&[
+        &[0x05, 0x03, 0x01, 0x00, 0x01],
+        &[
+            0x0b, 0x0a, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x04, 0x61, 0x62, 0x63, 0x64,
+        ],
+    ]);
+    assert!(Module::from_byte_with_limits(&data, &default).is_ok());
+    let limits = DecodeLimits {
+        max_data_segment_size: 3,
+        ..default
+    };
+    assert_eq!(exceeded(&data, &limits), "data segment size");
+}
+
+#[test]
+fn unknown_prefix_instruction() {
+    let code = [0x0a, 0x06, 0x01, 0x04, 0x00, 0xfc, 0x12, 0x0b];
+    assert!(kind(&module(&[TYPE, FUNC, &code])).ends_with("unexpected prefix opcode fc 12"));
+}
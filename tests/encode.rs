@@ -0,0 +1,141 @@
+mod common;
+
+use common::*;
+use wai::*;
+
+fn round_trip(bytes: &[u8]) -> Vec<u8> {
+    let m = Module::from_byte_unchecked(bytes).unwrap();
+    let encoded = m.to_bytes();
+    assert_eq!(Module::from_byte_unchecked(&encoded).unwrap(), m);
+
+    encoded
+}
+
+#[test]
+fn examples() {
+    for path in ["examples/add.wasm", "examples/fib.wasm"] {
+        let bytes = std::fs::read(path).unwrap();
+        round_trip(&bytes);
+    }
+}
+
+#[test]
+fn all_sections() {
+    let bytes = wast_encode(
+        r#"
+        (module
+          (type $t (func (param i32) (result i32)))
+          (import "env" "f" (func $f (type $t)))
+          (import "env" "table" (table 1 funcref))
+          (import "env" "memory" (memory 1 2 shared))
+          (import "env" "g" (global i64))
+          (table $t2 2 10 externref)
+          (memory $m 1)
+          (memory $m64 i64 1)
+          (global $g i32 (i32.const -1))
+          (global funcref (ref.func $f))
+          (export "g" (global $g))
+          (export "m" (memory $m))
+          (start $start)
+          (elem (i32.const 0) $f)
+          (elem func $f $start)
+          (elem declare func $start)
+          (elem (table 0) (i32.const 0) funcref (ref.func $f) (ref.null func))
+          (data (i32.const 8) "hello")
+          (data (memory $m64) (i64.const 4294967296) "far")
+          (data "passive")
+          (func $start)
+          (func (export "main") (param i32) (result i32) (local i64 i64) (local f32)
+            block (result i32)
+              loop
+                local.get 0
+                br_table 0 1 1
+              end
+              i32.const 0
+            end
+            drop
+            i32.const 1
+            i32.load offset=4 align=2
+            i64.const -123456789
+            i64.store offset=8
+            f32.const 1.5
+            local.set 2
+            f64.const -0.0
+            drop
+            i32.const 0
+            i32.const 0
+            i32.const 1
+            memory.init 2
+            data.drop 2
+            i32.const 0
+            i32.const 0
+            i32.const 0
+            memory.copy
+            ref.null extern
+            i32.const 1
+            table.grow $t2
+            drop
+            i32.const 0
+            i32.const 1
+            i32.atomic.rmw.add
+            atomic.fence
+            local.get 0
+            call_indirect (type $t)
+            i32.add))
+        "#,
+    );
+
+    round_trip(&bytes);
+}
+
+#[test]
+fn custom_sections_keep_position() {
+    #[rustfmt::skip]
+    let bytes = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // 先頭のcustom section "a"
+        0x00, 0x03, 0x01, 0x61, 0x01,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        // type sectionの後の"b"と"c"
+        0x00, 0x02, 0x01, 0x62,
+        0x00, 0x04, 0x01, 0x63, 0x02, 0x03,
+        0x03, 0x02, 0x01, 0x00,
+        0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b,
+    ];
+
+    // 同じ順番で書き出すので、元のバイト列と一致する
+    assert_eq!(round_trip(&bytes), bytes);
+}
+
+#[test]
+fn rec_groups_and_exceptions() {
+    #[rustfmt::skip]
+    let bytes = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x19, 0x03,
+        // (rec (type (sub (struct (field i32)))) (type (sub final 0 (struct (field i32) (field i32)))))
+        0x4e, 0x02,
+        0x50, 0x00, 0x5f, 0x01, 0x7f, 0x00,
+        0x4f, 0x01, 0x00, 0x5f, 0x02, 0x7f, 0x00, 0x7f, 0x00,
+        // (type (array (mut i8)))
+        0x5e, 0x78, 0x01,
+        // (type (func (param i32)))
+        0x60, 0x01, 0x7f, 0x00,
+        0x03, 0x02, 0x01, 0x03,
+        0x0d, 0x03, 0x01, 0x00, 0x03,
+        0x0a, 0x1d, 0x01, 0x1b, 0x00,
+        // block (result i32) (try_table (catch 0 0) (catch_all_ref 1) local.get 0 throw 0) i32.const 0 end drop
+        0x02, 0x7f,
+        0x1f, 0x40, 0x02, 0x00, 0x00, 0x00, 0x03, 0x01,
+        0x20, 0x00, 0x08, 0x00,
+        0x0b,
+        0x41, 0x00,
+        0x0b,
+        0x1a,
+        // ref.null 0 ref.test (ref null 1) drop
+        0xd0, 0x00, 0xfb, 0x15, 0x01, 0x1a,
+        0x0b,
+    ];
+
+    assert_eq!(round_trip(&bytes), bytes);
+}
@@ -0,0 +1,230 @@
+mod common;
+
+use common::*;
+use wai::*;
+
+// type 0: (i32) -> (), type 1: () -> i32
+const TYPES: &[u8] = &[0x02, 0x60, 0x01, 0x7f, 0x00, 0x60, 0x00, 0x01, 0x7f];
+
+#[test]
+fn throw_and_catch() {
+    let m = module(&[
+        section(0x01, TYPES),
+        section(0x03, &[0x03, 0x01, 0x01, 0x01]),
+        section(0x0D, &[0x01, 0x00, 0x00]),
+        section(
+            0x07,
+            &[
+                &[0x04, 0x05][..],
+                b"catch",
+                &[0x00, 0x00, 0x08],
+                b"uncaught",
+                &[0x00, 0x01, 0x06],
+                b"nested",
+                &[0x00, 0x02, 0x03],
+                b"tag",
+                &[0x04, 0x00],
+            ]
+            .concat(),
+        ),
+        code(&[
+            // block (result i32) try_table (catch 0 0) i32.const 42 throw 0 end i32.const 0 end
+            (
+                &[0x00],
+                &[
+                    0x02, 0x7f, 0x1f, 0x40, 0x01, 0x00, 0x00, 0x00, 0x41, 0x2a, 0x08, 0x00, 0x0b,
+                    0x41, 0x00, 0x0b, 0x0b,
+                ],
+            ),
+            // i32.const 7 throw 0
+            (&[0x00], &[0x41, 0x07, 0x08, 0x00, 0x0b]),
+            // block try_table (catch_all 0) call 1 drop end i32.const 0 return end i32.const 1
+            (
+                &[0x00],
+                &[
+                    0x02, 0x40, 0x1f, 0x40, 0x01, 0x02, 0x00, 0x10, 0x01, 0x1a, 0x0b, 0x41, 0x00,
+                    0x0f, 0x0b, 0x41, 0x01, 0x0b,
+                ],
+            ),
+        ]),
+    ]);
+
+    let mut instance = Instance::new(m).unwrap();
+    let tag = instance.get_tag("tag").unwrap();
+
+    assert_eq!(
+        instance.invoke("catch", vec![]).unwrap(),
+        vec![RuntimeValue::I32(42)]
+    );
+    assert_eq!(
+        instance.invoke("nested", vec![]).unwrap(),
+        vec![RuntimeValue::I32(1)]
+    );
+
+    match instance.invoke("uncaught", vec![]) {
+        Err(RuntimeError::UncaughtException(t, payload)) => {
+            assert_eq!(t, tag);
+            assert_eq!(payload, vec![RuntimeValue::I32(7)]);
+        }
+        v => panic!("expect uncaught exception, but got {:?}", v),
+    }
+}
+
+#[test]
+fn throw_imported_tag() {
+    let m = module(&[
+        section(0x01, TYPES),
+        section(
+            0x02,
+            &[
+                &[0x01, 0x03][..],
+                b"env",
+                &[0x03],
+                b"tag",
+                &[0x04, 0x00, 0x00],
+            ]
+            .concat(),
+        ),
+        section(0x03, &[0x01, 0x01]),
+        section(0x07, &[&[0x01, 0x05][..], b"throw", &[0x00, 0x00]].concat()),
+        code(&[(&[0x00], &[0x41, 0x03, 0x08, 0x00, 0x0b])]),
+    ]);
+
+    let tag = Tag::new(FuncType::new(vec![ValueType::I32], vec![]));
+    let mut imports = Imports::new();
+    imports.define("env", "tag", tag.clone());
+
+    let mut instance = Instance::with_imports(m.clone(), imports).unwrap();
+    match instance.invoke("throw", vec![]) {
+        Err(RuntimeError::UncaughtException(t, payload)) => {
+            assert_eq!(t, tag);
+            assert_eq!(payload, vec![RuntimeValue::I32(3)]);
+        }
+        v => panic!("expect uncaught exception, but got {:?}", v),
+    }
+
+    assert!(matches!(
+        Instance::new(m),
+        Err(RuntimeError::UnresolvedImport(_, _))
+    ));
+}
+
+#[test]
+fn caught_exceptions_are_collected() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (tag $e (param i32))
+          (global $saved (mut exnref) (ref.null exn))
+          (func $catch (param i32) (result i32 exnref)
+            (block $h (result i32 exnref)
+              (try_table (catch_ref $e $h)
+                (throw $e (local.get 0)))
+              (unreachable)))
+          (func (export "run") (param i32) (result i32)
+            (local $i i32)
+            (call $catch (i32.const 7))
+            (global.set $saved)
+            (drop)
+            (loop $l
+              (call $catch (local.get $i))
+              (drop)
+              (drop)
+              (local.set $i (i32.add (local.get $i) (i32.const 1)))
+              (br_if $l (i32.ne (local.get $i) (local.get 0))))
+            (block $h (result i32)
+              (try_table (catch $e $h)
+                (throw_ref (global.get $saved)))
+              (unreachable))))
+        "#,
+    )?;
+    let mut instance = Instance::new(m)?;
+
+    // GCが何度も走っても、globalに残したexnrefの例外は回収されない
+    let run = instance.get_typed_func::<i32, i32>("run")?;
+    assert_eq!(run.call(&mut instance, 5000)?, 7);
+    assert_eq!(run.call(&mut instance, 5000)?, 7);
+
+    Ok(())
+}
+
+#[test]
+fn exceptions_cross_calls_and_are_rethrown() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (tag $e (export "e") (param i32 i64))
+          (func $thrower (param i32) (result i32)
+            (i32.const 1)
+            (i32.const 2)
+            (throw $e (local.get 0) (i64.const 9)))
+          (func (export "cross") (param i32) (result i32)
+            (i32.const 1000)
+            (block $h (result i32 i64)
+              (try_table (catch $e $h)
+                (i32.const 5)
+                (i32.const 6)
+                (call $thrower (local.get 0))
+                (unreachable))
+              (unreachable))
+            (drop)
+            (i32.add))
+          (func (export "catch_ref") (param i32) (result i32)
+            (block $outer (result i32 i64)
+              (try_table (catch $e $outer)
+                (block $h (result i32 i64 exnref)
+                  (try_table (catch_ref $e $h)
+                    (drop (call $thrower (local.get 0))))
+                  (return (i32.const -1)))
+                (throw_ref))
+              (unreachable))
+            (drop))
+          (func (export "catch_all_ref") (param i32) (result i32)
+            (block $outer (result i32 i64)
+              (try_table (catch $e $outer)
+                (block $h (result exnref)
+                  (try_table (catch_all_ref $h)
+                    (drop (call $thrower (local.get 0))))
+                  (return (i32.const -1)))
+                (throw_ref))
+              (unreachable))
+            (drop))
+          (func (export "uncaught") (param i32)
+            (block $h (result exnref)
+              (try_table (catch_all_ref $h)
+                (drop (call $thrower (local.get 0))))
+              (return))
+            (throw_ref))
+          (func (export "throw_null")
+            (throw_ref (ref.null exn))))
+        "#,
+    )?;
+    let mut instance = Instance::new(m)?;
+    let tag = instance.get_tag("e")?;
+
+    // 呼び出し先で積んだ値と、try_tableの中で積んだ値は捨てられ、その外の値は残る
+    assert_eq!(
+        instance.invoke("cross", vec![RuntimeValue::I32(3)])?,
+        vec![RuntimeValue::I32(1003)]
+    );
+    // catch_ref、catch_all_refで受け取ったexnrefを投げ直すと、同じ値のまま外側で捕まえられる
+    for name in ["catch_ref", "catch_all_ref"] {
+        assert_eq!(
+            instance.invoke(name, vec![RuntimeValue::I32(4)])?,
+            vec![RuntimeValue::I32(4)]
+        );
+    }
+    match instance.invoke("uncaught", vec![RuntimeValue::I32(8)]) {
+        Err(RuntimeError::UncaughtException(t, payload)) => {
+            assert_eq!(t, tag);
+            assert_eq!(payload, vec![RuntimeValue::I32(8), RuntimeValue::I64(9)]);
+        }
+        v => panic!("expect uncaught exception, but got {:?}", v),
+    }
+    assert!(matches!(
+        instance.invoke("throw_null", vec![]),
+        Err(RuntimeError::NullReference)
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,210 @@
+mod common;
+
+use common::*;
+use wai::*;
+
+/// moduleの末尾にcustom sectionを足す
+fn push_custom(bytes: &mut Vec<u8>, name: &str, data: &[u8]) {
+    let mut content = vec![name.len() as u8];
+    content.extend_from_slice(name.as_bytes());
+    content.extend_from_slice(data);
+    bytes.push(0);
+    bytes.push(content.len() as u8);
+    bytes.extend_from_slice(&content);
+}
+
+/// "+simd128"のように先頭に記号のついた名前を並べたtarget_features section
+fn target_features(features: &[&str]) -> Vec<u8> {
+    let mut data = vec![features.len() as u8];
+    for feature in features {
+        let (prefix, name) = feature.split_at(1);
+        data.push(prefix.as_bytes()[0]);
+        data.push(name.len() as u8);
+        data.extend_from_slice(name.as_bytes());
+    }
+
+    data
+}
+
+/// processed-byにrustc 1.70.0だけを書いたproducers section
+fn producers() -> Vec<u8> {
+    let mut data = vec![0x01, 12];
+    data.extend_from_slice(b"processed-by");
+    data.extend_from_slice(&[0x01, 5]);
+    data.extend_from_slice(b"rustc");
+    data.push(6);
+    data.extend_from_slice(b"1.70.0");
+
+    data
+}
+
+fn unsupported_features(error: &DecodeError) -> Vec<Feature> {
+    match error.kind() {
+        DecodeError::UnsupportedFeatures { features, .. } => features.clone(),
+        e => panic!("expect UnsupportedFeatures, but got {}", e),
+    }
+}
+
+#[test]
+fn declared_features_are_checked_before_loading() {
+    let mut bytes = wast_encode("(module (func))");
+    push_custom(
+        &mut bytes,
+        "target_features",
+        &target_features(&["+simd128", "+mutable-globals", "-tail-call", "=sign-ext"]),
+    );
+    push_custom(&mut bytes, "producers", &producers());
+
+    let error = Module::from_byte(&bytes).unwrap_err();
+    assert_eq!(
+        unsupported_features(&error),
+        [Feature::MutableGlobals, Feature::SignExt, Feature::Simd128]
+    );
+    assert_eq!(error.location().unwrap().section, Some(SectionType::Custom));
+    assert!(
+        error.to_string().starts_with(
+            "module uses features not supported by wai: mutable-globals, sign-ext, simd128 \
+             (produced by processed-by: rustc 1.70.0)"
+        ),
+        "{}",
+        error
+    );
+
+    let stream_error = StreamingDecoder::new()
+        .read_from(bytes.as_slice(), |_| {})
+        .unwrap_err();
+    assert_eq!(
+        unsupported_features(&stream_error),
+        [Feature::MutableGlobals, Feature::SignExt, Feature::Simd128]
+    );
+}
+
+#[test]
+fn unsupported_opcodes_are_reported_with_declared_features() {
+    // 関数本体がi32.const 0、i32.extend8_s(0xc0)、drop
+    let mut bytes = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    bytes.extend_from_slice(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]);
+    bytes.extend_from_slice(&[0x03, 0x02, 0x01, 0x00]);
+    bytes.extend_from_slice(&[0x0a, 0x08, 0x01, 0x06, 0x00, 0x41, 0x00, 0xc0, 0x1a, 0x0b]);
+    let opcode = bytes.iter().position(|b| *b == 0xc0).unwrap();
+
+    let error = Module::from_byte(&bytes).unwrap_err();
+    assert_eq!(unsupported_features(&error), [Feature::SignExt]);
+    assert_eq!(error.location().unwrap().offset, opcode);
+    assert_eq!(error.location().unwrap().function, Some(0));
+
+    push_custom(
+        &mut bytes,
+        "target_features",
+        &target_features(&["+tail-call", "+future-proposal"]),
+    );
+    let error = Module::from_byte(&bytes).unwrap_err();
+    assert_eq!(
+        unsupported_features(&error),
+        [
+            Feature::SignExt,
+            Feature::TailCall,
+            Feature::Other("future-proposal".to_string())
+        ]
+    );
+    assert_eq!(error.location().unwrap().offset, opcode);
+}
+
+#[test]
+fn simd_is_unsupported_but_multi_value_blocks_are_read() -> anyhow::Result<()> {
+    let simd = wast_encode("(module (func (drop (i32x4.splat (i32.const 0)))))");
+    let error = Module::from_byte(simd).unwrap_err();
+    assert_eq!(unsupported_features(&error), [Feature::Simd128]);
+
+    let multi_value = wast_encode(
+        "(module (func (block (param) (result i32 i32) (i32.const 1) (i32.const 2)) (drop) (drop)))",
+    );
+    let m = Module::from_byte(multi_value)?;
+    let used: Vec<Feature> = m.used_features().into_iter().collect();
+    assert_eq!(used, [Feature::MultiValue]);
+
+    Ok(())
+}
+
+#[test]
+fn used_features() -> anyhow::Result<()> {
+    let mut m = Module::from_wat(
+        r#"
+        (module
+          (memory 1 1 shared)
+          (func (param i32) (result i32)
+            (i32.atomic.rmw.add (i32.const 0) (local.get 0))))
+        "#,
+    )?;
+    m.add_custom_section(
+        "target_features",
+        target_features(&["+bulk-memory", "-simd128"]),
+    );
+    m.add_custom_section("producers", producers());
+
+    let used: Vec<Feature> = m.used_features().into_iter().collect();
+    assert_eq!(used, [Feature::BulkMemory, Feature::Atomics]);
+    assert_eq!(m.target_features().len(), 2);
+    assert_eq!(m.target_features()[1].policy, FeaturePolicy::Disallowed);
+
+    let producers = m.producers().unwrap();
+    assert_eq!(
+        producers.field("processed-by"),
+        [("rustc".to_string(), "1.70.0".to_string())]
+    );
+    assert!(producers.field("language").is_empty());
+
+    // 使わないと宣言しているだけなら読める
+    Module::from_byte(m.to_bytes())?;
+
+    Ok(())
+}
+
+#[test]
+fn modules_using_only_supported_features_instantiate() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (type $s (struct (field i32)))
+          (memory 1)
+          (memory i64 1)
+          (table 2 funcref)
+          (table 1 externref)
+          (global $g i32 (i32.add (i32.const 1) (i32.const 2)))
+          (global $r (ref null $s) (struct.new $s (global.get $g)))
+          (global (export "counter") (mut i32) (i32.const 0))
+          (tag $e)
+          (data (i32.const 0) "hi")
+          (data "passive")
+          (elem (i32.const 0) func $pair)
+          (func $pair (result i32 i32)
+            (i32.const 1)
+            (i32.const 2))
+          (func (export "run") (result i32)
+            (block (result i32 i32)
+              (call $pair))
+            (drop)))
+        "#,
+    )?;
+
+    let used: Vec<Feature> = m.used_features().into_iter().collect();
+    assert_eq!(
+        used,
+        [
+            Feature::MultiValue,
+            Feature::BulkMemory,
+            Feature::ReferenceTypes,
+            Feature::ExtendedConst,
+            Feature::ExceptionHandling,
+            Feature::Memory64,
+            Feature::MultiMemory,
+            Feature::Gc,
+        ]
+    );
+    assert!(used.iter().all(Feature::is_supported));
+
+    let mut instance = Instance::new(m)?;
+    assert_eq!(instance.invoke("run", vec![])?, vec![RuntimeValue::I32(1)]);
+
+    Ok(())
+}
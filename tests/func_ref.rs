@@ -0,0 +1,130 @@
+mod common;
+
+use common::*;
+use wai::*;
+
+// type 0: (i32) -> i32, type 1: () -> i32
+const TYPES: &[u8] = &[0x02, 0x60, 0x01, 0x7f, 0x01, 0x7f, 0x60, 0x00, 0x01, 0x7f];
+
+#[rustfmt::skip]
+fn func_ref_module() -> Module {
+    module(&[
+        section(0x01, TYPES),
+        section(0x03, &[0x07, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]),
+        section(
+            0x07,
+            &[
+                vec![0x05],
+                export("apply", 1),
+                export("tail", 2),
+                export("null_call", 3),
+                export("on_null", 4),
+                export("non_null", 5),
+            ]
+            .concat(),
+        ),
+        code(&[
+            // double: local.get 0 + local.get 0
+            (&[0x00], &[0x20, 0x00, 0x20, 0x00, 0x6a, 0x0b]),
+            // call_ref 0 (local.get 0) (ref.func 0)
+            (&[0x00], &[0x20, 0x00, 0xd2, 0x00, 0x14, 0x00, 0x0b]),
+            // 1 + call 6 (local.get 0)。func 6はreturn_call_refでdoubleを呼ぶ
+            (&[0x00], &[0x41, 0x01, 0x20, 0x00, 0x10, 0x06, 0x6a, 0x0b]),
+            // call_ref 0 (i32.const 1) (ref.null 0)
+            (&[0x00], &[0x41, 0x01, 0xd0, 0x00, 0x14, 0x00, 0x0b]),
+            // paramが0でなければlocal 1にref.func 0を入れ、br_on_nullでnullなら-1を返す
+            (&[0x01, 0x01, 0x63, 0x00], &[
+                0x20, 0x00, 0x04, 0x40, 0xd2, 0x00, 0x21, 0x01, 0x0b,
+                0x02, 0x40,
+                0x20, 0x00, 0x20, 0x01, 0xd5, 0x00, 0x14, 0x00, 0x0f,
+                0x0b,
+                0x41, 0x7f,
+                0x0b,
+            ]),
+            // block (result (ref 0))でbr_on_non_nullが運び出した参照をcall_refする
+            (&[0x00], &[
+                0x20, 0x00,
+                0x02, 0x64, 0x00, 0xd2, 0x00, 0xd6, 0x00, 0x00, 0x0b,
+                0x14, 0x00,
+                0x0b,
+            ]),
+            // return_call_ref 0 (local.get 0) (ref.func 0)
+            (&[0x00], &[0x20, 0x00, 0xd2, 0x00, 0x15, 0x00, 0x0b]),
+        ]),
+    ])
+}
+
+#[test]
+fn call_ref() {
+    let mut instance = Instance::new(func_ref_module()).unwrap();
+
+    assert_eq!(
+        instance
+            .invoke("apply", vec![RuntimeValue::I32(4)])
+            .unwrap(),
+        vec![RuntimeValue::I32(8)]
+    );
+    assert_eq!(
+        instance.invoke("tail", vec![RuntimeValue::I32(4)]).unwrap(),
+        vec![RuntimeValue::I32(9)]
+    );
+    assert!(matches!(
+        instance.invoke("null_call", vec![]),
+        Err(RuntimeError::NullReference)
+    ));
+}
+
+#[test]
+fn branch_on_null() {
+    let mut instance = Instance::new(func_ref_module()).unwrap();
+
+    assert_eq!(
+        instance
+            .invoke("on_null", vec![RuntimeValue::I32(5)])
+            .unwrap(),
+        vec![RuntimeValue::I32(10)]
+    );
+    assert_eq!(
+        instance
+            .invoke("on_null", vec![RuntimeValue::I32(0)])
+            .unwrap(),
+        vec![RuntimeValue::I32(-1)]
+    );
+    assert_eq!(
+        instance
+            .invoke("non_null", vec![RuntimeValue::I32(3)])
+            .unwrap(),
+        vec![RuntimeValue::I32(6)]
+    );
+}
+
+#[test]
+fn uninitialized_local() {
+    // (local (ref 0))を代入せずに読む
+    let bytes = module_bytes(&[
+        section(0x01, TYPES),
+        section(0x03, &[0x01, 0x01]),
+        section(0x07, &[vec![0x01], export("uninit", 0)].concat()),
+        code(&[(
+            &[0x01, 0x01, 0x64, 0x00],
+            &[0x20, 0x00, 0x1a, 0x41, 0x00, 0x0b],
+        )]),
+    ]);
+
+    // 検証で弾かれる
+    assert!(matches!(
+        Module::from_byte(&bytes),
+        Err(DecodeError::Invalid(ValidationError::Function {
+            index: 0,
+            instruction_index: 0,
+            ..
+        }))
+    ));
+
+    // 検証を飛ばしても実行時に検出される
+    let mut instance = Instance::new(Module::from_byte_unchecked(&bytes).unwrap()).unwrap();
+    assert!(matches!(
+        instance.invoke("uninit", vec![]),
+        Err(RuntimeError::UninitializedLocal(0))
+    ));
+}
@@ -0,0 +1,244 @@
+mod common;
+
+use common::*;
+use wai::*;
+
+#[rustfmt::skip]
+const TYPES: &[u8] = &[
+    0x05,
+    // type 0: (struct (field (mut i32)) (field (mut i8)))
+    0x5f, 0x02, 0x7f, 0x01, 0x78, 0x01,
+    // type 1: (array (mut i32))
+    0x5e, 0x7f, 0x01,
+    // (rec (type 2 (sub (struct (field i32)))) (type 3 (sub final 2 (struct (field i32) (field i32)))))
+    0x4e, 0x02,
+    0x50, 0x00, 0x5f, 0x01, 0x7f, 0x00,
+    0x4f, 0x01, 0x02, 0x5f, 0x02, 0x7f, 0x00, 0x7f, 0x00,
+    // type 4: () -> i32
+    0x60, 0x00, 0x01, 0x7f,
+    // type 5: (i32) -> i32
+    0x60, 0x01, 0x7f, 0x01, 0x7f,
+];
+
+#[rustfmt::skip]
+fn gc_module() -> Module {
+    module(&[
+        section(0x01, TYPES),
+        section(0x03, &[0x06, 0x04, 0x05, 0x04, 0x05, 0x05, 0x04]),
+        // (global (mut (ref null 0)) (ref.null 0))
+        section(0x06, &[0x01, 0x63, 0x00, 0x01, 0xd0, 0x00, 0x0b]),
+        section(
+            0x07,
+            &[
+                vec![0x06],
+                export("struct", 0),
+                export("array", 1),
+                export("cast", 2),
+                export("i31", 3),
+                export("gc", 4),
+                export("null", 5),
+            ]
+            .concat(),
+        ),
+        code(&[
+            // struct.new 0 (40, -1)を作り、field 0を2に書き換えてから
+            // field 0 + get_s field 1 + get_u field 1を返す
+            (&[0x01, 0x01, 0x63, 0x00], &[
+                0x41, 0x28, 0x41, 0x7f, 0xfb, 0x00, 0x00, 0x21, 0x00,
+                0x20, 0x00, 0x41, 0x02, 0xfb, 0x05, 0x00, 0x00,
+                0x20, 0x00, 0xfb, 0x02, 0x00, 0x00,
+                0x20, 0x00, 0xfb, 0x03, 0x00, 0x01, 0x6a,
+                0x20, 0x00, 0xfb, 0x04, 0x00, 0x01, 0x6a,
+                0x0b,
+            ]),
+            // 長さがparamで3で埋めた配列を作り、a[0] = 10にしてlen + a[0] + a[len - 1]を返す
+            (&[0x01, 0x01, 0x63, 0x01], &[
+                0x41, 0x03, 0x20, 0x00, 0xfb, 0x06, 0x01, 0x21, 0x01,
+                0x20, 0x01, 0x41, 0x00, 0x41, 0x0a, 0xfb, 0x0e, 0x01,
+                0x20, 0x01, 0xfb, 0x0f,
+                0x20, 0x01, 0x41, 0x00, 0xfb, 0x0b, 0x01, 0x6a,
+                0x20, 0x01, 0x20, 0x00, 0x41, 0x01, 0x6b, 0xfb, 0x0b, 0x01, 0x6a,
+                0x0b,
+            ]),
+            // type 3のstructに対してref.test (ref 2) * 100 + ref.test (ref 0) * 10
+            // + struct.get 2 0 (ref.cast (ref 2))
+            (&[0x01, 0x01, 0x63, 0x03], &[
+                0x41, 0x01, 0x41, 0x02, 0xfb, 0x00, 0x03, 0x21, 0x00,
+                0x20, 0x00, 0xfb, 0x14, 0x02, 0x41, 0xe4, 0x00, 0x6c,
+                0x20, 0x00, 0xfb, 0x14, 0x00, 0x41, 0x0a, 0x6c, 0x6a,
+                0x20, 0x00, 0xfb, 0x16, 0x02, 0xfb, 0x02, 0x02, 0x00, 0x6a,
+                0x0b,
+            ]),
+            // i31.get_s (ref.i31 (local.get 0))
+            (&[0x00], &[0x20, 0x00, 0xfb, 0x1c, 0xfb, 0x1d, 0x0b]),
+            // globalにstructを置いたまま、param回だけ長さ100の配列を捨てながら確保する。
+            // 最後にglobalのfield 0 + 最後の配列の長さを返す
+            (&[0x01, 0x01, 0x63, 0x01], &[
+                0x41, 0x07, 0x41, 0x00, 0xfb, 0x00, 0x00, 0x24, 0x00,
+                0x03, 0x40,
+                0x41, 0x00, 0x41, 0xe4, 0x00, 0xfb, 0x06, 0x01, 0x21, 0x01,
+                0x20, 0x00, 0x41, 0x01, 0x6b, 0x21, 0x00,
+                0x20, 0x00, 0x0d, 0x00,
+                0x0b,
+                0x23, 0x00, 0xfb, 0x02, 0x00, 0x00,
+                0x20, 0x01, 0xfb, 0x0f, 0x6a,
+                0x0b,
+            ]),
+            // struct.get 0 0 (ref.null 0)
+            (&[0x00], &[0xd0, 0x00, 0xfb, 0x02, 0x00, 0x00, 0x0b]),
+        ]),
+    ])
+}
+
+#[test]
+fn struct_and_array() {
+    let mut instance = Instance::new(gc_module()).unwrap();
+
+    assert_eq!(
+        instance.invoke("struct", vec![]).unwrap(),
+        vec![RuntimeValue::I32(2 - 1 + 255)]
+    );
+    assert_eq!(
+        instance
+            .invoke("array", vec![RuntimeValue::I32(5)])
+            .unwrap(),
+        vec![RuntimeValue::I32(5 + 10 + 3)]
+    );
+}
+
+#[test]
+fn cast_and_i31() {
+    let mut instance = Instance::new(gc_module()).unwrap();
+
+    assert_eq!(
+        instance.invoke("cast", vec![]).unwrap(),
+        vec![RuntimeValue::I32(101)]
+    );
+    assert_eq!(
+        instance.invoke("i31", vec![RuntimeValue::I32(-5)]).unwrap(),
+        vec![RuntimeValue::I32(-5)]
+    );
+    assert!(matches!(
+        instance.invoke("null", vec![]),
+        Err(RuntimeError::NullReference)
+    ));
+}
+
+#[test]
+fn collect_garbage() {
+    let mut instance = Instance::new(gc_module()).unwrap();
+
+    // GCが何度か走っても、globalから辿れるstructは回収されない
+    assert_eq!(
+        instance
+            .invoke("gc", vec![RuntimeValue::I32(3000)])
+            .unwrap(),
+        vec![RuntimeValue::I32(107)]
+    );
+}
+
+#[test]
+fn array_length_is_limited() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (type $a (array (mut i32)))
+          (func (export "new") (param i32) (result i32)
+            (array.len (array.new $a (i32.const 0) (local.get 0))))
+          (func (export "new_default") (param i32) (result i32)
+            (array.len (array.new_default $a (local.get 0)))))
+        "#,
+    )?;
+    let mut config = Config::default();
+    config.max_array_len = 16;
+    let mut instance = Instance::with_config(m, Imports::new(), config)?;
+
+    for name in ["new", "new_default"] {
+        assert_eq!(
+            instance.invoke(name, vec![RuntimeValue::I32(16)])?,
+            vec![RuntimeValue::I32(16)]
+        );
+        // ホストのプロセスごと落ちるのではなくtrapになる
+        assert!(matches!(
+            instance.invoke(name, vec![RuntimeValue::I32(0x7fff_ffff)]),
+            Err(RuntimeError::ArrayTooLarge(0x7fff_ffff))
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn host_refs_do_not_alias_after_collection() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (type $box (struct (field i32)))
+          (type $a (array i32))
+          (func (export "new_box") (param i32) (result (ref $box))
+            (struct.new $box (local.get 0)))
+          (func (export "unbox") (param (ref $box)) (result i32)
+            (struct.get $box 0 (local.get 0)))
+          (func (export "churn") (param i32)
+            (loop
+              (drop (array.new_default $a (i32.const 1)))
+              (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+              (br_if 0 (local.get 0)))))
+        "#,
+    )?;
+    let mut instance = Instance::new(m)?;
+
+    let old = instance.invoke("new_box", vec![RuntimeValue::I32(1)])?[0];
+    assert_eq!(
+        instance.invoke("unbox", vec![old])?,
+        vec![RuntimeValue::I32(1)]
+    );
+
+    // ホストが持っている参照はrootではないので回収される。回収された後は別のオブジェクトを指さずにエラーになる
+    instance.invoke("churn", vec![RuntimeValue::I32(2000)])?;
+    let new = instance.invoke("new_box", vec![RuntimeValue::I32(2)])?[0];
+    assert_ne!(old, new);
+    assert!(matches!(
+        instance.invoke("unbox", vec![old]),
+        Err(RuntimeError::NotFound(_))
+    ));
+    assert_eq!(
+        instance.invoke("unbox", vec![new])?,
+        vec![RuntimeValue::I32(2)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn globals_are_initialized_with_gc_and_extended_constants() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (type $s (struct (field i32) (field (ref null $s))))
+          (type $a (array i32))
+          (global $base i32 (i32.const 10))
+          (global $sum i32 (i32.add (global.get $base) (i32.mul (i32.const 2) (i32.const 3))))
+          (global $wrapped i32 (i32.add (i32.const 0x7fffffff) (i32.const 1)))
+          (global $node (ref $s) (struct.new $s (global.get $sum) (struct.new_default $s)))
+          (global $arr (ref $a) (array.new_fixed $a 2 (i32.const 1) (i32.const 2)))
+          (func (export "sum") (result i32) (global.get $sum))
+          (func (export "wrapped") (result i32) (global.get $wrapped))
+          (func (export "field") (result i32) (struct.get $s 0 (global.get $node)))
+          (func (export "len") (result i32) (array.len (global.get $arr))))
+        "#,
+    )?;
+    let mut instance = Instance::new(m)?;
+
+    for (name, expected) in [
+        ("sum", 16),
+        ("wrapped", i32::MIN),
+        ("field", 16),
+        ("len", 2),
+    ] {
+        let f = instance.get_typed_func::<(), i32>(name)?;
+        assert_eq!(f.call(&mut instance, ())?, expected, "{}", name);
+    }
+
+    Ok(())
+}
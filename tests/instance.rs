@@ -0,0 +1,449 @@
+use wai::*;
+
+#[test]
+fn state_persists_across_invocations() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (type $box (struct (field (mut i32))))
+          (memory 1)
+          (table 1 funcref)
+          (global $counter (mut i32) (i32.const 0))
+          (global $box (mut (ref null $box)) (ref.null $box))
+          (func $f)
+          (func (export "count") (result i32)
+            (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+            (global.get $counter))
+          (func (export "store") (param i32)
+            (i32.store (i32.const 8) (local.get 0)))
+          (func (export "load") (result i32)
+            (i32.load (i32.const 8)))
+          (func (export "set_table")
+            (table.set (i32.const 0) (ref.func $f)))
+          (func (export "table_is_null") (result i32)
+            (ref.is_null (table.get (i32.const 0))))
+          (func (export "new_box") (param i32)
+            (global.set $box (struct.new $box (local.get 0))))
+          (func (export "unbox") (result i32)
+            (struct.get $box 0 (global.get $box))))
+        "#,
+    )?;
+    let mut instance = Instance::new(m)?;
+
+    for expect in 1..=3 {
+        assert_eq!(
+            instance.invoke("count", vec![])?,
+            vec![RuntimeValue::I32(expect)]
+        );
+    }
+
+    instance.invoke("store", vec![RuntimeValue::I32(42)])?;
+    assert_eq!(
+        instance.invoke("load", vec![])?,
+        vec![RuntimeValue::I32(42)]
+    );
+
+    assert_eq!(
+        instance.invoke("table_is_null", vec![])?,
+        vec![RuntimeValue::I32(1)]
+    );
+    instance.invoke("set_table", vec![])?;
+    assert_eq!(
+        instance.invoke("table_is_null", vec![])?,
+        vec![RuntimeValue::I32(0)]
+    );
+
+    instance.invoke("new_box", vec![RuntimeValue::I32(7)])?;
+    assert_eq!(
+        instance.invoke("unbox", vec![])?,
+        vec![RuntimeValue::I32(7)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn state_survives_a_trap() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (memory 1)
+          (func (export "store_then_trap")
+            (i32.store (i32.const 0) (i32.const 5))
+            (drop (i32.load (i32.const 65536))))
+          (func (export "load") (result i32)
+            (i32.load (i32.const 0))))
+        "#,
+    )?;
+    let mut instance = Instance::new(m)?;
+
+    assert!(matches!(
+        instance.invoke("store_then_trap", vec![]),
+        Err(RuntimeError::MemoryOutOfBounds)
+    ));
+    // trapするまでに書き込んだものは残り、スタックの残りは次の呼び出しに持ち越さない
+    assert_eq!(instance.invoke("load", vec![])?, vec![RuntimeValue::I32(5)]);
+
+    Ok(())
+}
+
+#[test]
+fn typed_funcs_check_signature_once() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (global $total (mut i64) (i64.const 0))
+          (func (export "add") (param i32 i32) (result i32)
+            (i32.add (local.get 0) (local.get 1)))
+          (func (export "accumulate") (param i64) (result i64)
+            (global.set $total (i64.add (global.get $total) (local.get 0)))
+            (global.get $total))
+          (func (export "scale") (param f64 f32) (result f64)
+            (f64.mul (local.get 0) (f64.const 2)))
+          (func (export "nop")))
+        "#,
+    )?;
+    let mut instance = Instance::new(m)?;
+
+    let add = instance.get_typed_func::<(i32, i32), i32>("add")?;
+    assert_eq!(add.call(&mut instance, (40, 2))?, 42);
+    assert_eq!(add.func().func_type().params(), &[ValueType::I32; 2]);
+
+    let accumulate = instance.get_typed_func::<i64, i64>("accumulate")?;
+    accumulate.call(&mut instance, 5)?;
+    assert_eq!(accumulate.call(&mut instance, 10)?, 15);
+
+    let nop = instance.get_typed_func::<(), ()>("nop")?;
+    nop.call(&mut instance, ())?;
+
+    assert!(matches!(
+        instance.get_typed_func::<(i32, i32), i64>("add"),
+        Err(RuntimeError::FuncTypeMismatch(..))
+    ));
+    assert!(instance.get_typed_func::<(f64, f32), f64>("scale").is_ok());
+    assert!(matches!(
+        instance.get_typed_func::<(), ()>("missing"),
+        Err(RuntimeError::NotFound(_))
+    ));
+
+    // 型を持たないハンドルは名前を引かずにindexで呼べる
+    let func = instance.get_func("add").unwrap();
+    assert_eq!(func.index(), 0);
+    assert_eq!(
+        func.call(
+            &mut instance,
+            vec![RuntimeValue::I32(1), RuntimeValue::I32(2)]
+        )?,
+        vec![RuntimeValue::I32(3)]
+    );
+    assert!(matches!(
+        func.call(&mut instance, vec![RuntimeValue::I64(1)]),
+        Err(RuntimeError::InvalidArgs(..))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn exports_are_looked_up_by_kind() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (memory (export "memory") 1)
+          (table (export "table") 2 funcref)
+          (global (export "counter") (mut i32) (i32.const 1))
+          (global (export "limit") i64 (i64.const 100))
+          (func $get (export "get") (result i32) (global.get 0)))
+        "#,
+    )?;
+    let mut instance = Instance::new(m)?;
+
+    // memoryのindex 0を関数のindexとして呼んではいけない
+    let error = instance.invoke("memory", vec![]).unwrap_err();
+    assert!(matches!(
+        error,
+        RuntimeError::ExportKindMismatch {
+            expected: ExternalKind::Function,
+            actual: ExternalKind::Memory,
+            ..
+        }
+    ));
+    assert_eq!(
+        error.to_string(),
+        "export 'memory' is a memory, not a function"
+    );
+    assert!(matches!(
+        instance.get_func("missing"),
+        Err(RuntimeError::NotFound(_))
+    ));
+
+    assert!(matches!(instance.get_export("get")?, Extern::Func(_)));
+    assert!(matches!(instance.get_export("memory")?, Extern::Memory(_)));
+    assert!(matches!(instance.get_export("table")?, Extern::Table(_)));
+    assert!(matches!(instance.get_export("counter")?, Extern::Global(_)));
+
+    let counter = instance.get_global("counter")?;
+    assert_eq!(counter.get(&instance)?, RuntimeValue::I32(1));
+    counter.set(&mut instance, RuntimeValue::I32(5))?;
+    assert_eq!(instance.invoke("get", vec![])?, vec![RuntimeValue::I32(5)]);
+    assert!(matches!(
+        counter.set(&mut instance, RuntimeValue::I64(5)),
+        Err(RuntimeError::InvalidArgs(..))
+    ));
+
+    let limit = instance.get_global("limit")?;
+    assert_eq!(limit.get(&instance)?, RuntimeValue::I64(100));
+    assert!(matches!(
+        limit.set(&mut instance, RuntimeValue::I64(0)),
+        Err(RuntimeError::ImmutableGlobal(1))
+    ));
+
+    let table = instance.get_table("table")?;
+    assert_eq!(table.size(&instance)?, 2);
+    table.set(&mut instance, 1, RuntimeValue::FuncRef(0))?;
+    assert_eq!(table.get(&instance, 1)?, RuntimeValue::FuncRef(0));
+    assert!(matches!(
+        table.get(&instance, 2),
+        Err(RuntimeError::TableOutOfBounds)
+    ));
+    assert!(matches!(
+        table.set(&mut instance, 0, RuntimeValue::I32(0)),
+        Err(RuntimeError::InvalidArgs(..))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn table_and_mutable_global_imports_are_rejected() -> anyhow::Result<()> {
+    let wat = r#"(module (import "env" "t" (table 1 funcref)))"#;
+    assert!(matches!(
+        Instance::new(Module::from_wat(wat)?),
+        Err(RuntimeError::UnsupportedImport(..))
+    ));
+    // mutableなglobalのimportは対応していないmutable-globalsを使うので、featureの検査で断る
+    let m = Module::from_wat(r#"(module (import "env" "g" (global (mut i32))))"#)?;
+    assert!(matches!(
+        Instance::new(m.clone()),
+        Err(RuntimeError::Invalid(ValidationError::DisabledFeatures(_)))
+    ));
+    assert!(Module::from_byte(m.to_bytes()).is_err());
+
+    // immutableなglobalは値をコピーしてimportする
+    let m = Module::from_wat(
+        r#"
+        (module
+          (import "env" "g" (global i32))
+          (func (export "get") (result i32) (global.get 0)))
+        "#,
+    )?;
+    assert!(matches!(
+        Instance::new(m.clone()),
+        Err(RuntimeError::UnresolvedImport(..))
+    ));
+    let mut imports = Imports::new();
+    imports.define("env", "g", RuntimeValue::I32(7));
+    let mut instance = Instance::with_imports(m, imports)?;
+    assert_eq!(instance.invoke("get", vec![])?, vec![RuntimeValue::I32(7)]);
+
+    Ok(())
+}
+
+#[test]
+fn multiple_results_keep_their_order() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (func (export "pair") (result i32 i64)
+            (i32.const 1)
+            (i64.const 2))
+          (func (export "swap") (param i32 i32) (result i32 i32)
+            (local.get 1)
+            (local.get 0)))
+        "#,
+    )?;
+    let mut instance = Instance::new(m)?;
+
+    assert_eq!(
+        instance.invoke("pair", vec![])?,
+        vec![RuntimeValue::I32(1), RuntimeValue::I64(2)]
+    );
+    let pair = instance.get_typed_func::<(), (i32, i64)>("pair")?;
+    assert_eq!(pair.call(&mut instance, ())?, (1, 2));
+    let swap = instance.get_typed_func::<(i32, i32), (i32, i32)>("swap")?;
+    assert_eq!(swap.call(&mut instance, (3, 4))?, (4, 3));
+
+    Ok(())
+}
+
+#[test]
+fn multi_value_blocks_branch_with_their_values() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (func (export "add") (param i32 i32) (result i32)
+            (local.get 0)
+            (local.get 1)
+            (block (param i32 i32) (result i32)
+              (i32.add)))
+          (func (export "branch") (result i32 i32 i32)
+            (i32.const 9)
+            (block (result i32 i32)
+              (i32.const 7)
+              (i32.const 1)
+              (i32.const 2)
+              (br 0)))
+          (func (export "sum") (param i32) (result i32)
+            (i32.const 0)
+            (local.get 0)
+            (loop (param i32 i32) (result i32 i32)
+              (local.set 0)
+              (local.get 0)
+              (i32.add)
+              (local.get 0)
+              (i32.const 1)
+              (i32.sub)
+              (i32.ne (local.get 0) (i32.const 1))
+              (br_if 0))
+            (drop))
+          (func (export "choose") (param i32) (result i32 i32)
+            (i32.const 5)
+            (local.get 0)
+            (if (param i32) (result i32 i32)
+              (then (i32.const 1))
+              (else (i32.const 2)))))
+        "#,
+    )?;
+    assert_eq!(Module::from_wat(&m.to_wat())?.to_bytes(), m.to_bytes());
+    let mut instance = Instance::new(m)?;
+
+    let add = instance.get_typed_func::<(i32, i32), i32>("add")?;
+    assert_eq!(add.call(&mut instance, (3, 4))?, 7);
+    let branch = instance.get_typed_func::<(), (i32, i32, i32)>("branch")?;
+    assert_eq!(branch.call(&mut instance, ())?, (9, 1, 2));
+    let sum = instance.get_typed_func::<i32, i32>("sum")?;
+    assert_eq!(sum.call(&mut instance, 4)?, 10);
+    let choose = instance.get_typed_func::<i32, (i32, i32)>("choose")?;
+    assert_eq!(choose.call(&mut instance, 1)?, (5, 1));
+    assert_eq!(choose.call(&mut instance, 0)?, (5, 2));
+
+    Ok(())
+}
+
+#[test]
+fn returns_drop_leftover_operands() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (type $t (func (result i32)))
+          (elem declare func $answer)
+          (func $answer (result i32)
+            (i32.const 42))
+          (func $branch (result i32)
+            (i32.const 7)
+            (i32.const 42)
+            (br 0))
+          (func $return (result i32)
+            (i32.const 100)
+            (block
+              (i32.const 41)
+              (i32.const 42)
+              (return))
+            (drop)
+            (i32.const 0))
+          (func $tail (result i32)
+            (i32.const 5)
+            (return_call_ref $t (ref.func $answer)))
+          (func (export "branch") (result i32)
+            (i32.add (i32.const 1) (call $branch)))
+          (func (export "return") (result i32)
+            (i32.add (i32.const 1) (call $return)))
+          (func (export "tail") (result i32)
+            (i32.add (i32.const 1) (call $tail)))
+          (func (export "in_block") (result i32)
+            (block (result i32)
+              (call $branch)
+              (br 0))))
+        "#,
+    )?;
+    let mut instance = Instance::new(m)?;
+
+    for name in ["branch", "return", "tail"] {
+        let f = instance.get_typed_func::<(), i32>(name)?;
+        assert_eq!(f.call(&mut instance, ())?, 43, "{}", name);
+    }
+    let in_block = instance.get_typed_func::<(), i32>("in_block")?;
+    assert_eq!(in_block.call(&mut instance, ())?, 42);
+
+    Ok(())
+}
+
+#[test]
+fn exported_globals_count_imported_ones() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (import "env" "base" (global i64))
+          (global (export "counter") (mut i32) (i32.const 3))
+          (global (export "base") i64 (global.get 0)))
+        "#,
+    )?;
+    let mut imports = Imports::new();
+    imports.define("env", "base", RuntimeValue::I64(100));
+    let mut instance = Instance::with_imports(m, imports)?;
+
+    // importしたglobalが0番なので、定義した1つ目のglobalは1番になる
+    let counter = instance.get_global("counter")?;
+    assert_eq!(counter.index(), 1);
+    assert_eq!(counter.global_type().content_type, ValueType::I32);
+    assert_eq!(counter.get(&instance)?, RuntimeValue::I32(3));
+    counter.set(&mut instance, RuntimeValue::I32(4))?;
+    assert_eq!(counter.get(&instance)?, RuntimeValue::I32(4));
+
+    let base = instance.get_global("base")?;
+    assert_eq!(base.index(), 2);
+    assert!(!base.global_type().mutability);
+    assert_eq!(base.get(&instance)?, RuntimeValue::I64(100));
+
+    Ok(())
+}
+
+#[test]
+fn start_function_runs_after_segments_are_initialized() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (memory 1)
+          (table 1 funcref)
+          (global $sum (mut i32) (i32.const 0))
+          (data (i32.const 0) "\05")
+          (elem (i32.const 0) $seven)
+          (func $seven (result i32) (i32.const 7))
+          (func $start
+            (global.set $sum
+              (i32.add
+                (i32.load8_u (i32.const 0))
+                (call_indirect (result i32) (i32.const 0)))))
+          (start $start)
+          (func (export "sum") (result i32) (global.get $sum)))
+        "#,
+    )?;
+    let mut instance = Instance::new(m)?;
+    assert_eq!(instance.invoke("sum", vec![])?, vec![RuntimeValue::I32(12)]);
+
+    // start関数がtrapするとインスタンス化に失敗する
+    let m = Module::from_wat(
+        r#"
+        (module
+          (memory 1)
+          (func $start (drop (i32.load (i32.const 65536))))
+          (start $start))
+        "#,
+    )?;
+    assert!(matches!(
+        Instance::new(m),
+        Err(RuntimeError::MemoryOutOfBounds)
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,330 @@
+use wai::*;
+
+fn instantiate(wat: &str) -> anyhow::Result<Instance> {
+    let buf = wast::parser::ParseBuffer::new(wat)?;
+    let mut wat = wast::parser::parse::<wast::Wat>(&buf)?;
+    let m = Module::from_byte(wat.module.encode()?)?;
+
+    Instance::new(m).map_err(Into::into)
+}
+
+#[test]
+fn memory64() -> anyhow::Result<()> {
+    let mut instance = instantiate(
+        r#"
+        (module
+          (memory i64 1)
+          (data (i64.const 8) "\2a")
+          (func (export "load") (param i64) (result i32)
+            local.get 0
+            i32.load8_u)
+          (func (export "store_load") (param i64 i64) (result i64)
+            local.get 0
+            local.get 1
+            i64.store offset=4
+            local.get 0
+            i64.load offset=4)
+          (func (export "size") (result i64)
+            memory.size)
+          (func (export "grow") (param i64) (result i64)
+            local.get 0
+            memory.grow)
+          (func (export "fill") (param i64 i64) (result i32)
+            local.get 0
+            i32.const 7
+            local.get 1
+            memory.fill
+            local.get 0
+            i32.load8_u))
+        "#,
+    )?;
+
+    assert_eq!(
+        instance.invoke("load", vec![RuntimeValue::I64(8)])?,
+        vec![RuntimeValue::I32(42)]
+    );
+    assert_eq!(
+        instance.invoke(
+            "store_load",
+            vec![RuntimeValue::I64(100), RuntimeValue::I64(-5)]
+        )?,
+        vec![RuntimeValue::I64(-5)]
+    );
+    assert_eq!(instance.invoke("size", vec![])?, vec![RuntimeValue::I64(1)]);
+    assert_eq!(
+        instance.invoke("grow", vec![RuntimeValue::I64(1)])?,
+        vec![RuntimeValue::I64(1)]
+    );
+    assert_eq!(
+        instance.invoke("grow", vec![RuntimeValue::I64(1 << 48)])?,
+        vec![RuntimeValue::I64(-1)]
+    );
+    assert_eq!(
+        instance.invoke("fill", vec![RuntimeValue::I64(16), RuntimeValue::I64(4)])?,
+        vec![RuntimeValue::I32(7)]
+    );
+
+    // 32bitに切り詰めると0番地になってしまうアドレスもきちんと範囲外になる
+    assert!(matches!(
+        instance.invoke("load", vec![RuntimeValue::I64(1 << 32)]),
+        Err(RuntimeError::MemoryOutOfBounds)
+    ));
+    assert!(matches!(
+        instance.invoke(
+            "store_load",
+            vec![RuntimeValue::I64(-1), RuntimeValue::I64(0)]
+        ),
+        Err(RuntimeError::MemoryOutOfBounds)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn multi_memory() -> anyhow::Result<()> {
+    let mut a = instantiate(
+        r#"
+        (module
+          (memory $m0 1)
+          (memory $m1 1)
+          (data (memory $m1) (i32.const 0) "\01\02\03\04")
+          (export "m1" (memory $m1))
+          (func (export "copy") (result i32)
+            i32.const 16
+            i32.const 0
+            i32.const 4
+            memory.copy $m0 $m1
+            i32.const 16
+            i32.load)
+          (func (export "fill") (param i32)
+            i32.const 8
+            local.get 0
+            i32.const 1
+            memory.fill $m1))
+        "#,
+    )?;
+
+    assert_eq!(
+        a.invoke("copy", vec![])?,
+        vec![RuntimeValue::I32(0x04030201)]
+    );
+    a.invoke("fill", vec![RuntimeValue::I32(99)])?;
+
+    let wat = r#"
+        (module
+          (import "a" "m1" (memory 1))
+          (func (export "load") (param i32) (result i32)
+            local.get 0
+            i32.load8_u))
+        "#;
+    let buf = wast::parser::ParseBuffer::new(wat)?;
+    let mut wat = wast::parser::parse::<wast::Wat>(&buf)?;
+    let m = Module::from_byte(wat.module.encode()?)?;
+
+    let mut imports = Imports::new();
+    imports.define("a", "m1", a.get_memory("m1").unwrap());
+    let mut b = Instance::with_imports(m, imports)?;
+
+    // aが書き込んだ値を、同じメモリをimportしたbから読める
+    assert_eq!(
+        b.invoke("load", vec![RuntimeValue::I32(8)])?,
+        vec![RuntimeValue::I32(99)]
+    );
+    assert!(matches!(
+        a.get_memory("copy"),
+        Err(RuntimeError::ExportKindMismatch { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn host_reads_and_writes_exported_memory() -> anyhow::Result<()> {
+    let mut instance = instantiate(
+        r#"
+        (module
+          (memory (export "memory") 1 2)
+          (func (export "sum") (param i32 i32) (result i32)
+            (i32.add (i32.load (local.get 0)) (i32.load (local.get 1))))
+          (func (export "upper") (param i32)
+            (i32.store8 (local.get 0) (i32.sub (i32.load8_u (local.get 0)) (i32.const 32)))))
+        "#,
+    )?;
+    let memory = instance.memory("memory")?;
+
+    memory.set(0, 40i32)?;
+    memory.set(4, 2i32)?;
+    assert_eq!(
+        instance.invoke("sum", vec![RuntimeValue::I32(0), RuntimeValue::I32(4)])?,
+        vec![RuntimeValue::I32(42)]
+    );
+    memory.set(8, -1.5f64)?;
+    assert_eq!(memory.get::<f64>(8)?, -1.5);
+    assert_eq!(memory.get::<u16>(4)?, 2);
+
+    let len = memory.write_str(100, "hello")?;
+    instance.invoke("upper", vec![RuntimeValue::I32(100)])?;
+    assert_eq!(memory.read_str(100, len)?, "Hello");
+
+    let mut buf = [0; 3];
+    memory.read(101, &mut buf)?;
+    assert_eq!(&buf, b"ell");
+
+    // 範囲外やUTF-8でないバイト列はpanicせずにエラーになる
+    let end = memory.size_pages() * 65536;
+    assert!(matches!(
+        memory.write(end - 1, &[0, 0]),
+        Err(RuntimeError::MemoryOutOfBounds)
+    ));
+    assert!(matches!(
+        memory.get::<i64>(end - 4),
+        Err(RuntimeError::MemoryOutOfBounds)
+    ));
+    assert!(matches!(
+        memory.read(u64::MAX, &mut buf),
+        Err(RuntimeError::MemoryOutOfBounds)
+    ));
+    memory.write(0, &[0xff, 0xfe])?;
+    assert!(matches!(
+        memory.read_str(0, 2),
+        Err(RuntimeError::InvalidUtf8(_))
+    ));
+
+    assert_eq!(memory.grow(1)?, 1);
+    assert_eq!(memory.size_pages(), 2);
+    assert!(memory.grow(1).is_err());
+    assert!(instance.memory("sum").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn data_offsets_are_constant_expressions() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (global $base i32 (i32.const 16))
+          (global $base64 i64 (i64.const 32))
+          (memory $m 1)
+          (memory $m64 i64 1)
+          (data (global.get $base) "hi")
+          (data (offset (i32.add (global.get $base) (i32.const 4))) "\2a")
+          (data (memory $m64) (global.get $base64) "\07")
+          (func (export "load") (param i32) (result i32)
+            local.get 0
+            i32.load8_u)
+          (func (export "load64") (param i64) (result i32)
+            local.get 0
+            i32.load8_u $m64))
+        "#,
+    )?;
+    let offsets: Vec<_> = m.data_segments().map(|info| info.offset).collect();
+    assert_eq!(offsets, vec![None, None, None]);
+
+    // バイナリに書き出してから読み直しても、offsetの式が残る
+    let m = Module::from_byte(m.to_bytes())?;
+    assert!(m
+        .to_wat()
+        .contains("(offset (global.get 0) (i32.const 4) (i32.add))"));
+
+    let mut instance = Instance::new(m)?;
+    for (address, expected) in [(16, 'h' as i32), (17, 'i' as i32), (20, 42)] {
+        assert_eq!(
+            instance.invoke("load", vec![RuntimeValue::I32(address)])?,
+            vec![RuntimeValue::I32(expected)]
+        );
+    }
+    assert_eq!(
+        instance.invoke("load64", vec![RuntimeValue::I64(32)])?,
+        vec![RuntimeValue::I32(7)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn memory_bounds_and_failed_grow() -> anyhow::Result<()> {
+    let mut instance = instantiate(
+        r#"
+        (module
+          (memory i64 1)
+          (func (export "load64") (param i64) (result i64)
+            local.get 0
+            i64.load)
+          (func (export "grow64") (param i64) (result i64)
+            local.get 0
+            memory.grow))
+        "#,
+    )?;
+    let load = |instance: &mut Instance, address: u64| {
+        instance.invoke("load64", vec![RuntimeValue::I64(address as i64)])
+    };
+
+    assert_eq!(load(&mut instance, 65528)?, vec![RuntimeValue::I64(0)]);
+    // 末尾をまたぐアクセスと、32bitでは表せないアドレスはすべて範囲外
+    for address in [65529, 65536, 1 << 32, (1 << 32) + 8, 1 << 40, u64::MAX - 7] {
+        assert!(
+            matches!(
+                load(&mut instance, address),
+                Err(RuntimeError::MemoryOutOfBounds)
+            ),
+            "address {:#x}",
+            address
+        );
+    }
+
+    assert_eq!(
+        instance.invoke("grow64", vec![RuntimeValue::I64(1)])?,
+        vec![RuntimeValue::I64(1)]
+    );
+    assert_eq!(load(&mut instance, 65536)?, vec![RuntimeValue::I64(0)]);
+    assert!(matches!(
+        load(&mut instance, 1 << 32),
+        Err(RuntimeError::MemoryOutOfBounds)
+    ));
+
+    let mut instance = instantiate(
+        r#"
+        (module
+          (memory 1 2)
+          (func (export "grow") (param i32) (result i32)
+            local.get 0
+            memory.grow)
+          (func (export "size") (result i32)
+            memory.size)
+          (func (export "store_load") (param i32 i32) (result i32)
+            local.get 0
+            local.get 1
+            i32.store
+            local.get 0
+            i32.load))
+        "#,
+    )?;
+
+    // maximumを超える、または4GiBを超えるgrowは-1を返し、大きさは変わらない
+    for (delta, expected) in [(1, 1), (1, -1), (0, 2), (0x10000, -1), (-1, -1)] {
+        assert_eq!(
+            instance.invoke("grow", vec![RuntimeValue::I32(delta)])?,
+            vec![RuntimeValue::I32(expected)],
+            "grow {}",
+            delta
+        );
+    }
+    assert_eq!(instance.invoke("size", vec![])?, vec![RuntimeValue::I32(2)]);
+    assert_eq!(
+        instance.invoke(
+            "store_load",
+            vec![RuntimeValue::I32(2 * 65536 - 4), RuntimeValue::I32(9)]
+        )?,
+        vec![RuntimeValue::I32(9)]
+    );
+    assert!(matches!(
+        instance.invoke(
+            "store_load",
+            vec![RuntimeValue::I32(2 * 65536), RuntimeValue::I32(9)]
+        ),
+        Err(RuntimeError::MemoryOutOfBounds)
+    ));
+
+    Ok(())
+}
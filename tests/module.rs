@@ -0,0 +1,92 @@
+use wai::*;
+
+#[test]
+fn imports_exports_and_descriptors() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (type $log (func (param i32 i32)))
+          (import "env" "log" (func (type $log)))
+          (import "env" "memory" (memory 1 4))
+          (import "env" "base" (global i32))
+          (table (export "table") 2 10 funcref)
+          (memory (export "heap") 2)
+          (global (export "counter") (mut i64) (i64.const 0))
+          (tag (export "error") (param i32))
+          (data (memory 1) (i32.const 16) "hello")
+          (data "passive data")
+          (func (export "add") (param i32 i32) (result i32)
+            (i32.add (local.get 0) (local.get 1))))
+        "#,
+    )?;
+
+    let log = FuncType::new(vec![ValueType::I32, ValueType::I32], vec![]);
+    let imports: Vec<_> = m.imports().collect();
+    assert_eq!(imports.len(), 3);
+    assert_eq!((imports[0].module(), imports[0].name()), ("env", "log"));
+    assert_eq!(imports[0].ty(), &ExternType::Func(log.clone()));
+    assert!(matches!(
+        imports[1].ty(),
+        ExternType::Memory(MemoryType {
+            limits: ResizableLimits {
+                initial: 1,
+                maximum: Some(4)
+            },
+            ..
+        })
+    ));
+    assert!(matches!(
+        imports[2].ty(),
+        ExternType::Global(GlobalType {
+            content_type: ValueType::I32,
+            mutability: false
+        })
+    ));
+
+    // 関数のindexはimportした関数から数える
+    assert_eq!(m.func_type(0), Some(&log));
+    let add = FuncType::new(vec![ValueType::I32, ValueType::I32], vec![ValueType::I32]);
+    assert_eq!(m.func_type(1), Some(&add));
+    assert_eq!(m.func_type(2), None);
+
+    let exports: Vec<_> = m
+        .exports()
+        .map(|export| (export.name(), export.index(), export.ty().clone()))
+        .collect();
+    assert_eq!(exports.len(), 5);
+    assert!(exports.contains(&("add", 1, ExternType::Func(add))));
+    assert!(exports.contains(&(
+        "error",
+        0,
+        ExternType::Tag(FuncType::new(vec![ValueType::I32], vec![]))
+    )));
+    assert!(exports.iter().any(|(name, index, ty)| *name == "heap"
+        && *index == 1
+        && matches!(ty, ExternType::Memory(t) if t.limits.initial == 2)));
+    assert!(exports.iter().any(|(name, _, ty)| *name == "table"
+        && matches!(ty, ExternType::Table(t) if t.limits.maximum == Some(10))));
+    assert!(exports.iter().any(|(name, index, ty)| *name == "counter"
+        && *index == 1
+        && matches!(ty, ExternType::Global(t) if t.mutability)));
+
+    assert_eq!(m.memories().count(), 2);
+    assert_eq!(m.tables().count(), 1);
+    assert_eq!(m.globals().count(), 2);
+    assert_eq!(
+        m.data_segments().collect::<Vec<_>>(),
+        vec![
+            DataSegmentInfo {
+                memory: 1,
+                offset: Some(16),
+                len: 5
+            },
+            DataSegmentInfo {
+                memory: 0,
+                offset: None,
+                len: 12
+            },
+        ]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::*;
+use wai::*;
+
+#[test]
+fn name_section_is_decoded() -> anyhow::Result<()> {
+    let m = Module::from_byte(wast_encode(
+        r#"
+        (module $calc
+          (type $binop (func (param i32 i32) (result i32)))
+          (global $counter (mut i32) (i32.const 0))
+          (func $add (type $binop) (param $lhs i32) (param $rhs i32) (result i32)
+            (local $sum i32)
+            (local.set $sum (i32.add (local.get $lhs) (local.get $rhs)))
+            (local.get $sum))
+          (func (param i32)))
+        "#,
+    ))?;
+
+    let names = m.names();
+    assert_eq!(names.module(), Some("calc"));
+    assert_eq!(names.function(0), Some("add"));
+    assert_eq!(names.function(1), None);
+    assert_eq!(names.local(0, 1), Some("rhs"));
+    assert_eq!(names.local(0, 2), Some("sum"));
+    assert_eq!(names.local(1, 0), None);
+    assert_eq!(names.display_function(0), "func[0] <add>");
+    assert_eq!(names.display_function(1), "func[1]");
+
+    Ok(())
+}
+
+#[test]
+fn broken_name_section_is_ignored() -> anyhow::Result<()> {
+    // 関数名のsubsectionの長さが壊れている
+    let bytes = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x08, 0x04, b'n', b'a', b'm', b'e',
+        0x01, 0xff, 0xff,
+    ];
+    let m = Module::from_byte(bytes)?;
+    assert!(m.names().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn validation_error_has_function_name() {
+    let bytes = wast_encode(
+        r#"
+        (module
+          (func $ok)
+          (func $broken (result i32)
+            (i64.const 1)))
+        "#,
+    );
+
+    let message = Module::from_byte(bytes).unwrap_err().to_string();
+    assert!(message.contains("func[1] <broken>"), "{}", message);
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use common::*;
+use wai::*;
+
+#[test]
+fn print_and_parse_again() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (rec
+            (type $node (sub (struct (field i32) (field (mut (ref null $node))))))
+            (type $bytes (array (mut i8))))
+          (type $t (func (param i32) (result i32)))
+          (import "env" "f" (func $f (type $t)))
+          (import "env" "table" (table 1 funcref))
+          (import "env" "g" (global (mut i64)))
+          (table $t2 2 10 externref)
+          (memory $m 1 2 shared)
+          (tag $e (param i32))
+          (global $g i32 (i32.const -1))
+          (global funcref (ref.func $f))
+          (export "g" (global $g))
+          (export "m" (memory $m))
+          (start $start)
+          (elem (i32.const 0) $f)
+          (elem (table 0) (offset (i32.const 1) (i32.const 1) (i32.add)) funcref (ref.func $f) (ref.null func))
+          (elem declare func $start)
+          (data (i32.const -8) "hello\00\ff\"\\")
+          (data $d "passive")
+          (func $start)
+          (func (export "main") (param $x i32) (result i32) (local i64 f32) (local $r (ref null $node))
+            block $outer
+              loop $inner
+                local.get $x
+                br_table $inner $outer $outer
+              end
+            end
+            (block $h (result i32)
+              (try_table (catch $e $h)
+                (throw $e (i32.const 7)))
+              (i32.const 0))
+            drop
+            (i32.store offset=4 align=2 (i32.const 0) (i32.load8_u (local.get $x)))
+            (drop (i32.atomic.rmw8.add_u offset=1 (i32.const 0) (i32.const 1)))
+            (memory.init $d (i32.const 0) (i32.const 0) (i32.const 1))
+            (data.drop $d)
+            (drop (f32.const -nan:0x1))
+            (drop (f64.const -0x0p+0))
+            (drop (f64.const 1e300))
+            (drop (struct.new $node (i32.const 1) (local.get $r)))
+            (if (result i32) (i32.eqz (local.get $x))
+              (then (call $f (i32.const 0xffff_ffff)))
+              (else (call_indirect (type $t) (local.get $x) (i32.const 0))))))
+        "#,
+    )?;
+
+    // NaNを含むのでModuleどうしでは比べられない。バイナリにして比べる
+    let text = m.to_wat();
+    assert!(text.contains("f32.const -nan:0x1"), "{}", text);
+    assert_eq!(Module::from_wat(&text)?.to_bytes(), m.to_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn names_from_name_section() -> anyhow::Result<()> {
+    let wat = r#"
+        (module $math
+          (func $add (export "add") (param $x i32) (param $y i32) (result i32)
+            (i32.add (local.get $x) (local.get $y)))
+          (func $double (param i32) (result i32) (local $tmp i32)
+            (local.set $tmp (call $add (local.get 0) (local.get 0)))
+            (local.get $tmp)))
+    "#;
+    let text = Module::from_byte(wast_encode(wat))?.to_wat();
+
+    assert!(text.starts_with("(module $math\n"), "{}", text);
+    assert!(text.contains("(func $add (;0;) (type 0) (param $x i32) (param $y i32) (result i32)"));
+    assert!(text.contains("(func $double (;1;) (type 1) (param i32) (result i32)"));
+    assert!(text.contains("(local $tmp i32)"));
+    assert!(text.contains("call $add"));
+    assert!(text.contains("(export \"add\" (func $add))"));
+
+    // 名前を書いても、読み直すと名前の無いmoduleと同じになる
+    assert_eq!(Module::from_wat(&text)?, Module::from_wat(wat)?);
+
+    Ok(())
+}
+
+#[test]
+fn extended_names_are_printed() -> anyhow::Result<()> {
+    let wat = r#"
+        (module
+          (type $binop (func (param i32 i32) (result i32)))
+          (memory $mem 1)
+          (global $counter (mut i32) (i32.const 0))
+          (func $add (type $binop) (param $lhs i32) (param $rhs i32) (result i32)
+            (global.set $counter (i32.const 1))
+            (block $exit (result i32)
+              (br $exit (i32.add (local.get $lhs) (local.get $rhs))))))
+    "#;
+    let text = Module::from_byte(wast_encode(wat))?.to_wat();
+
+    assert!(text.contains("(type $binop (;0;) (func"), "{}", text);
+    assert!(text.contains("(func $add (;0;) (type $binop)"), "{}", text);
+    assert!(text.contains("(memory $mem (;0;) 1)"), "{}", text);
+    assert!(text.contains("global.set $counter"), "{}", text);
+    assert!(text.contains("block $exit (result i32)"), "{}", text);
+    assert_eq!(Module::from_wat(&text)?, Module::from_wat(wat)?);
+
+    Ok(())
+}
+
+#[test]
+fn offsets_point_at_opcodes() -> anyhow::Result<()> {
+    let bytes = wast_encode(
+        r#"
+        (module
+          (func (result i32)
+            (block (result i32)
+              (i32.add (i32.const 42) (i32.const 100000)))))
+        "#,
+    );
+    let text = print_with_offsets(&bytes)?;
+
+    let opcodes = [
+        ("block", 0x02),
+        ("i32.const 42", 0x41),
+        ("i32.const 100000", 0x41),
+        ("i32.add", 0x6a),
+        ("end", 0x0b),
+    ];
+    for (instruction, opcode) in opcodes {
+        let line = text
+            .lines()
+            .find(|line| line.contains(instruction))
+            .unwrap_or_else(|| panic!("{} is not printed:\n{}", instruction, text));
+        let offset = line
+            .strip_prefix("(;@")
+            .and_then(|rest| rest.split_whitespace().next())
+            .map(|hex| usize::from_str_radix(hex, 16).unwrap())
+            .unwrap_or_else(|| panic!("no offset in {:?}", line));
+
+        assert_eq!(bytes[offset], opcode, "{}", line);
+    }
+
+    Ok(())
+}
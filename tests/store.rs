@@ -0,0 +1,192 @@
+use wai::*;
+
+#[derive(Default)]
+struct Host {
+    logs: Vec<String>,
+    calls: u32,
+}
+
+#[test]
+fn host_funcs_read_caller_memory_and_store_data() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (import "env" "log" (func $log (param i32 i32)))
+          (import "env" "count" (func $count (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "hello")
+          (data (i32.const 16) "world")
+          (func (export "run") (result i32)
+            (call $log (i32.const 0) (i32.const 5))
+            (call $log (i32.const 16) (i32.const 5))
+            (call $count)))
+        "#,
+    )?;
+
+    let log = HostFunc::new(
+        FuncType::new(vec![ValueType::I32, ValueType::I32], vec![]),
+        |mut caller: Caller<'_, Host>, args| {
+            let (ptr, len) = (i32::from(args[0]), i32::from(args[1]));
+            let s = caller
+                .get_memory("memory")?
+                .read_str(ptr as u64, len as u64)?;
+            caller.data_mut().logs.push(s);
+            Ok(vec![])
+        },
+    );
+    let count = HostFunc::new(
+        FuncType::new(vec![], vec![ValueType::I32]),
+        |mut caller: Caller<'_, Host>, _| {
+            caller.data_mut().calls += 1;
+            Ok(vec![RuntimeValue::I32(caller.data().calls as i32)])
+        },
+    );
+    let mut imports = Imports::new();
+    imports
+        .define("env", "log", log)
+        .define("env", "count", count);
+
+    let mut store = Store::new(Host::default());
+    let id = store.instantiate(m, imports)?;
+
+    assert_eq!(store.invoke(id, "run", vec![])?, vec![RuntimeValue::I32(1)]);
+    assert_eq!(store.invoke(id, "run", vec![])?, vec![RuntimeValue::I32(2)]);
+    assert_eq!(store.data().logs, ["hello", "world", "hello", "world"]);
+
+    // Storeを通さずに呼ぶとdataは()なので型が合わない
+    assert!(matches!(
+        store.instance_mut(id)?.invoke("run", vec![]),
+        Err(RuntimeError::Custom(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn handles_from_another_store_or_instance_are_rejected() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (global (export "g") (mut i32) (i32.const 1))
+          (func (export "f") (result i32) (i32.const 1)))
+        "#,
+    )?;
+
+    let mut store = Store::new(());
+    let id = store.instantiate(m.clone(), Imports::new())?;
+    let other_id = store.instantiate(m.clone(), Imports::new())?;
+    let mut other_store = Store::new(());
+    let foreign_id = other_store.instantiate(m.clone(), Imports::new())?;
+
+    assert!(matches!(
+        store.instance(foreign_id),
+        Err(RuntimeError::ForeignHandle(_))
+    ));
+    assert!(matches!(
+        store.invoke(foreign_id, "f", vec![]),
+        Err(RuntimeError::ForeignHandle(_))
+    ));
+
+    // 同じStoreでも、Funcを取り出したのとは別のインスタンスでは呼べない
+    let f = store.instance(id)?.get_func("f")?;
+    assert_eq!(store.call(id, &f, vec![])?, vec![RuntimeValue::I32(1)]);
+    assert!(matches!(
+        store.call(other_id, &f, vec![]),
+        Err(RuntimeError::ForeignHandle(_))
+    ));
+
+    let mut instance = Instance::new(m)?;
+    assert!(matches!(
+        f.call(&mut instance, vec![]),
+        Err(RuntimeError::ForeignHandle(_))
+    ));
+    let g = store.instance(id)?.get_global("g")?;
+    assert!(matches!(
+        g.get(&instance),
+        Err(RuntimeError::ForeignHandle(_))
+    ));
+    assert!(matches!(
+        g.set(&mut instance, RuntimeValue::I32(2)),
+        Err(RuntimeError::ForeignHandle(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn host_funcs_without_store() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (import "env" "double" (func $double (param i32) (result i32)))
+          (export "double" (func $double))
+          (func (export "quadruple") (param i32) (result i32)
+            (call $double (call $double (local.get 0)))))
+        "#,
+    )?;
+    let double = HostFunc::new(
+        FuncType::new(vec![ValueType::I32], vec![ValueType::I32]),
+        |_: Caller<'_, ()>, args| Ok(vec![RuntimeValue::I32(i32::from(args[0]) * 2)]),
+    );
+    let mut imports = Imports::new();
+    imports.define("env", "double", double);
+    let mut instance = Instance::with_imports(m, imports)?;
+
+    assert_eq!(
+        instance.invoke("quadruple", vec![RuntimeValue::I32(3)])?,
+        vec![RuntimeValue::I32(12)]
+    );
+    // importした関数をそのままexportしても呼べる
+    let double = instance.get_typed_func::<i32, i32>("double")?;
+    assert_eq!(double.call(&mut instance, 5)?, 10);
+
+    Ok(())
+}
+
+#[test]
+fn host_func_signature_must_match_import() -> anyhow::Result<()> {
+    let m = Module::from_wat(r#"(module (import "env" "f" (func (param i32))))"#)?;
+
+    let f = HostFunc::new(
+        FuncType::new(vec![ValueType::I64], vec![]),
+        |_: Caller<'_, ()>, _| Ok(vec![]),
+    );
+    let mut imports = Imports::new();
+    imports.define("env", "f", f);
+    assert!(Instance::with_imports(m.clone(), imports).is_err());
+
+    assert!(matches!(
+        Instance::new(m),
+        Err(RuntimeError::UnresolvedImport(..))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn start_function_sees_store_data() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (import "env" "count" (func $count (result i32)))
+          (func $start (drop (call $count)))
+          (start $start))
+        "#,
+    )?;
+
+    let count = HostFunc::new(
+        FuncType::new(vec![], vec![ValueType::I32]),
+        |mut caller: Caller<'_, Host>, _| {
+            caller.data_mut().calls += 1;
+            Ok(vec![RuntimeValue::I32(caller.data().calls as i32)])
+        },
+    );
+    let mut imports = Imports::new();
+    imports.define("env", "count", count);
+
+    let mut store = Store::new(Host::default());
+    store.instantiate(m, imports)?;
+    assert_eq!(store.data().calls, 1);
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use common::*;
+use wai::*;
+
+#[test]
+fn same_as_from_byte() {
+    for path in ["examples/add.wasm", "examples/fib.wasm"] {
+        let bytes = std::fs::read(path).unwrap();
+        let expected = Module::from_byte(&bytes).unwrap();
+
+        // 1バイトずつ渡しても、まとめて読んでも同じmoduleになる
+        let mut decoder = StreamingDecoder::new();
+        let mut payloads = vec![];
+        for b in &bytes {
+            payloads.extend(decoder.push(&[*b]).unwrap());
+        }
+        assert_eq!(decoder.consumed(), bytes.len(), "{}", path);
+        assert_eq!(decoder.finish().unwrap(), expected, "{}", path);
+        assert_eq!(payloads[0], Payload::Header { version: 1 }, "{}", path);
+
+        assert_eq!(
+            Module::from_reader(&bytes[..]).unwrap(),
+            expected,
+            "{}",
+            path
+        );
+    }
+}
+
+#[test]
+fn payloads() {
+    let bytes = wast_encode(
+        r#"
+        (module
+          (import "env" "f" (func))
+          (func (result i32) i32.const 1)
+          (func (result i32) i32.const 2))
+        "#,
+    );
+
+    let mut payloads = vec![];
+    StreamingDecoder::new()
+        .read_from(&bytes[..], |p| payloads.push(p.clone()))
+        .unwrap();
+
+    let bodies: Vec<_> = payloads
+        .iter()
+        .filter_map(|p| match p {
+            Payload::FunctionBody { index, range } => Some((*index, range.clone())),
+            _ => None,
+        })
+        .collect();
+    // importした関数の次から数える
+    assert_eq!(bodies.len(), 2);
+    assert_eq!(bodies[0].0, 1);
+    assert_eq!(bodies[1].0, 2);
+    assert_eq!(bodies[0].1.end, bodies[1].1.start);
+
+    assert!(matches!(
+        payloads.last(),
+        Some(Payload::Section {
+            section_type: SectionType::Code,
+            range,
+        }) if range.end == bytes.len()
+    ));
+}
+
+#[test]
+fn validate_body_before_end() {
+    let bytes = wast_encode(
+        r#"
+        (module
+          (func (result i32) i64.const 0)
+          (func (result i32) i32.const 0))
+        "#,
+    );
+
+    // 2つ目の関数本体が届く前に、1つ目の型エラーがわかる
+    let mut decoder = StreamingDecoder::new();
+    let e = decoder.push(&bytes[..bytes.len() - 3]).unwrap_err();
+    assert!(matches!(
+        e,
+        DecodeError::Invalid(ValidationError::Function { index: 0, .. })
+    ));
+}
+
+#[test]
+fn truncated() {
+    let bytes = std::fs::read("examples/fib.wasm").unwrap();
+
+    let mut decoder = StreamingDecoder::new();
+    decoder.push(&bytes[..bytes.len() - 1]).unwrap();
+    let e = decoder.finish().unwrap_err();
+    assert!(matches!(e.kind(), DecodeError::IOError(_)));
+
+    assert!(StreamingDecoder::new().finish().is_err());
+}
+
+#[test]
+fn same_errors_as_from_byte() {
+    let type_section: &[u8] = &[0x01, 0x04, 0x01, 0x60, 0x00, 0x00];
+    let func_section: &[u8] = &[0x03, 0x02, 0x01, 0x00];
+    let code_section: &[u8] = &[0x0a, 0x05, 0x01, 0x03, 0x00, 0xe0, 0x0b];
+
+    let cases = [
+        vec![type_section, type_section],
+        vec![func_section, type_section],
+        vec![type_section, func_section, code_section],
+    ];
+    for sections in cases {
+        let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+        for s in sections {
+            bytes.extend_from_slice(s);
+        }
+
+        let expected = Module::from_byte(&bytes).unwrap_err();
+        let e = StreamingDecoder::new()
+            .push(&bytes)
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(e.location(), expected.location());
+        assert_eq!(e.to_string(), expected.to_string());
+    }
+}
+
+#[test]
+fn limits() {
+    let bytes = std::fs::read("examples/fib.wasm").unwrap();
+    let limits = DecodeLimits {
+        max_module_size: 16,
+        ..DecodeLimits::default()
+    };
+
+    let mut decoder = StreamingDecoder::with_limits(limits);
+    decoder.push(&bytes[..16]).unwrap();
+    let e = decoder.push(&bytes[16..]).unwrap_err();
+    assert!(matches!(e.kind(), DecodeError::LimitExceeded { .. }));
+}
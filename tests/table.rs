@@ -0,0 +1,125 @@
+use wai::*;
+
+#[test]
+fn elem_segments_and_call_indirect() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (type $ret (func (result i32)))
+          (type $arg (func (param i32) (result i32)))
+          (table $t 4 funcref)
+          (table $u 4 funcref)
+          (elem (table $t) (i32.const 0) func $one $two)
+          (elem $passive func $three $id)
+          (func $one (result i32) (i32.const 1))
+          (func $two (result i32) (i32.const 2))
+          (func $three (result i32) (i32.const 3))
+          (func $id (param i32) (result i32) (local.get 0))
+          (func (export "call") (param i32) (result i32)
+            (call_indirect $t (type $ret) (local.get 0)))
+          (func (export "call_u") (param i32) (result i32)
+            (call_indirect $u (type $ret) (local.get 0)))
+          (func (export "init") (param i32 i32 i32)
+            (table.init $t $passive (local.get 0) (local.get 1) (local.get 2)))
+          (func (export "drop")
+            (elem.drop $passive))
+          (func (export "copy_to_u")
+            (table.copy $u $t (i32.const 1) (i32.const 0) (i32.const 3))))
+        "#,
+    )?;
+    let mut instance = Instance::new(m)?;
+    let call = |instance: &mut Instance, name: &str, i: i32| {
+        instance.invoke(name, vec![RuntimeValue::I32(i)])
+    };
+
+    // active segmentはインスタンス化のときに書き込まれている
+    assert_eq!(call(&mut instance, "call", 0)?, vec![RuntimeValue::I32(1)]);
+    assert_eq!(call(&mut instance, "call", 1)?, vec![RuntimeValue::I32(2)]);
+    assert!(matches!(
+        call(&mut instance, "call", 2),
+        Err(RuntimeError::UninitializedElement)
+    ));
+    assert!(matches!(
+        call(&mut instance, "call", 4),
+        Err(RuntimeError::TableOutOfBounds)
+    ));
+
+    let args = |a, b, c| {
+        vec![
+            RuntimeValue::I32(a),
+            RuntimeValue::I32(b),
+            RuntimeValue::I32(c),
+        ]
+    };
+    instance.invoke("init", args(2, 0, 2))?;
+    assert_eq!(call(&mut instance, "call", 2)?, vec![RuntimeValue::I32(3)]);
+    // $idは(i32) -> i32なので型が合わない
+    assert!(matches!(
+        call(&mut instance, "call", 3),
+        Err(RuntimeError::IndirectCallTypeMismatch)
+    ));
+    assert!(matches!(
+        instance.invoke("init", args(0, 1, 2)),
+        Err(RuntimeError::TableOutOfBounds)
+    ));
+
+    instance.invoke("copy_to_u", vec![])?;
+    assert_eq!(
+        call(&mut instance, "call_u", 1)?,
+        vec![RuntimeValue::I32(1)]
+    );
+    assert_eq!(
+        call(&mut instance, "call_u", 3)?,
+        vec![RuntimeValue::I32(3)]
+    );
+
+    // dropしたsegmentは長さ0として扱う
+    instance.invoke("drop", vec![])?;
+    instance.invoke("init", args(0, 0, 0))?;
+    assert!(matches!(
+        instance.invoke("init", args(0, 0, 1)),
+        Err(RuntimeError::TableOutOfBounds)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn active_segment_out_of_bounds_fails_instantiation() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (table 1 funcref)
+          (elem (i32.const 1) func $f)
+          (func $f))
+        "#,
+    )?;
+    assert!(matches!(
+        Instance::new(m),
+        Err(RuntimeError::TableOutOfBounds)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn table_instructions_round_trip() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (table $t 2 funcref)
+          (table $u 2 funcref)
+          (elem $e func $f)
+          (func $f
+            (table.init $u $e (i32.const 0) (i32.const 0) (i32.const 1))
+            (elem.drop $e)
+            (table.copy $u $t (i32.const 0) (i32.const 0) (i32.const 1))))
+        "#,
+    )?;
+
+    assert_eq!(Module::from_byte(m.to_bytes())?.to_wat(), m.to_wat());
+    assert_eq!(Module::from_wat(&m.to_wat())?.to_bytes(), m.to_bytes());
+    assert!(m.used_features().contains(&Feature::BulkMemory));
+
+    Ok(())
+}
@@ -53,9 +53,9 @@ fn assert_wasm(filepath: &str) -> anyhow::Result<()> {
                 };
 
                 let args: Vec<RuntimeValue> = args.iter().map(args_to_runtime_value).collect();
-                let instance = Instance::new(m.clone());
+                let mut instance = Instance::new(m.clone())?;
                 println!("{}", name);
-                let actual = match instance.invoke(&name, args.clone()) {
+                let actual = match instance.invoke(name, args.clone()) {
                     Ok(v) => v,
                     Err(e) => {
                         // NOTE umimplementedエラーは読み飛ばす
@@ -0,0 +1,180 @@
+mod common;
+
+use common::*;
+use wai::*;
+
+/// wastでエンコードしたバイナリ。wastがつけるname sectionは取り除く
+fn encode(wat: &str) -> Module {
+    let bytes = wast_encode(wat);
+
+    let mut stripped = bytes[..8].to_vec();
+    let mut rest = &bytes[8..];
+    while !rest.is_empty() {
+        let mut size = 0usize;
+        let mut len = 1;
+        for (i, b) in rest[1..].iter().enumerate() {
+            size |= ((b & 0x7f) as usize) << (7 * i);
+            if b & 0x80 == 0 {
+                len += i + 1;
+                break;
+            }
+        }
+        if rest[0] != 0 {
+            stripped.extend_from_slice(&rest[..len + size]);
+        }
+        rest = &rest[len + size..];
+    }
+
+    Module::from_byte(stripped).unwrap()
+}
+
+#[test]
+fn same_as_binary() {
+    let wat = r#"
+        (module
+          (type $t (func (param i32) (result i32)))
+          (import "env" "f" (func $f (type $t)))
+          (import "env" "table" (table 1 funcref))
+          (import "env" "g" (global i64))
+          (table $t2 2 10 externref)
+          (memory $m 1)
+          (global $g i32 (i32.const -1))
+          (global funcref (ref.func $f))
+          (export "g" (global $g))
+          (export "m" (memory $m))
+          (start $start)
+          (elem (i32.const 0) $f)
+          (elem func $f $start)
+          (elem declare func $start)
+          (elem (table 0) (i32.const 0) funcref (ref.func $f) (ref.null func))
+          (data (i32.const 8) "hello" "\01\02\u{3042}")
+          (data $d "passive")
+          (func $start)
+          (func (export "main") (param $x i32) (result i32) (local i64 i64) (local $y f32)
+            block $outer
+              loop $inner
+                local.get $x
+                br_table $inner $outer $outer
+              end
+            end
+            (i32.store offset=4 align=2 (i32.const 0) (i32.load8_u (local.get $x)))
+            (memory.init $d (i32.const 0) (i32.const 0) (i32.const 1))
+            (data.drop $d)
+            (f32.const 0x1.8p1)
+            (local.set $y)
+            (if (result i32) (i32.eqz (local.get $x))
+              (then (call $f (i32.const 0xffff_ffff)))
+              (else (call_indirect (type $t) (local.get $x) (i32.const 0)))))
+        )
+    "#;
+
+    assert_eq!(Module::from_wat(wat).unwrap(), encode(wat));
+}
+
+#[test]
+fn folded_and_flat() {
+    let flat = Module::from_wat(
+        r#"
+        (module
+          (func (param i32) (result i32)
+            local.get 0
+            if (result i32)
+              i32.const 1
+            else
+              local.get 0
+              i32.const 1
+              i32.sub
+            end))
+        "#,
+    )
+    .unwrap();
+    let folded = Module::from_wat(
+        r#"
+        (func (param i32) (result i32)
+          (if (result i32) (local.get 0)
+            (then (i32.const 1))
+            (else (i32.sub (local.get 0) (i32.const 1)))))
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(flat, folded);
+}
+
+#[test]
+fn inline_imports_and_exports() -> anyhow::Result<()> {
+    let wat = r#"
+        (module
+          (func $log (import "env" "log") (param i32))
+          (global $base (export "base") (import "env" "base") i32)
+          (memory (export "memory") (data "\2a"))
+          (func (export "load") (export "load_again") (result i32)
+            (i32.add (global.get $base) (i32.load8_u (i32.const 0)))))
+    "#;
+    assert_eq!(Module::from_wat(wat)?, encode(wat));
+
+    Ok(())
+}
+
+#[test]
+fn run_example() -> anyhow::Result<()> {
+    let src = std::fs::read_to_string("examples/wat/fib.wat")?;
+    let mut instance = Instance::new(Module::from_wat(&src)?)?;
+
+    for name in ["fib", "fib_recursive"] {
+        assert_eq!(
+            instance.invoke(name, vec![RuntimeValue::I32(10)])?,
+            vec![RuntimeValue::I32(55)]
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn error_position() {
+    let cases = [
+        (
+            "(module\n  (func (result i32)\n    i32.const 1\n    i32.frob))",
+            4,
+            5,
+        ),
+        ("(module (func call $missing))", 1, 20),
+        ("(module\n  (data \"abc)", 2, 9),
+        ("(module (func (i32.const 99999999999)))", 1, 26),
+        ("(module (func (local.get $x)))", 1, 26),
+    ];
+
+    for (src, line, column) in cases {
+        let err = Module::from_wat(src).unwrap_err();
+        assert_eq!(err.position(), Some(Position { line, column }), "{}", err);
+    }
+
+    // 構文は正しいが型が合わない
+    let err = Module::from_wat("(func (result i32) (i64.const 0))").unwrap_err();
+    assert!(matches!(err, ParseError::Invalid(_)), "{}", err);
+}
+
+#[test]
+fn gc_types_and_exceptions() -> anyhow::Result<()> {
+    let m = Module::from_wat(
+        r#"
+        (module
+          (rec
+            (type $node (sub (struct (field $value i32) (field $next (ref null $node)))))
+            (type $list (array (mut (ref null $node)))))
+          (tag $oops (param i32))
+          (func (export "value") (param $n (ref $node)) (result i32)
+            (struct.get $node $value (local.get $n)))
+          (func (export "catch") (result i32)
+            (block $handler (result i32)
+              (try_table (catch $oops $handler)
+                (throw $oops (i32.const 7)))
+              (i32.const 0))))
+        "#,
+    )?;
+
+    assert_eq!(Module::from_byte(m.to_bytes())?, m);
+
+    Ok(())
+}
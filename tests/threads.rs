@@ -0,0 +1,132 @@
+use std::thread;
+use wai::*;
+
+const WAT: &str = r#"
+    (module
+      (import "env" "memory" (memory 1 1 shared))
+      (func (export "inc") (param $n i32)
+        (loop $l
+          i32.const 0
+          i32.const 1
+          i32.atomic.rmw.add
+          drop
+          local.get $n
+          i32.const 1
+          i32.sub
+          local.set $n
+          local.get $n
+          br_if $l))
+      (func (export "get") (result i32)
+        i32.const 0
+        i32.atomic.load)
+      (func (export "wait") (param i64) (result i32)
+        i32.const 4
+        i32.const 0
+        local.get 0
+        memory.atomic.wait32)
+      (func (export "wake") (result i32)
+        i32.const 4
+        i32.const 1
+        i32.atomic.store
+        i32.const 4
+        i32.const 1
+        memory.atomic.notify))
+"#;
+
+fn module() -> Module {
+    let buf = wast::parser::ParseBuffer::new(WAT).unwrap();
+    let mut wat = wast::parser::parse::<wast::Wat>(&buf).unwrap();
+    Module::from_byte(wat.module.encode().unwrap()).unwrap()
+}
+
+fn shared_memory() -> MemoryRef {
+    let memory_type = MemoryType {
+        limits: ResizableLimits {
+            initial: 1,
+            maximum: Some(1),
+        },
+        memory64: false,
+        shared: true,
+    };
+
+    MemoryRef::new(Memory::new(memory_type).unwrap())
+}
+
+fn instantiate(m: Module, memory: MemoryRef) -> Instance {
+    let mut imports = Imports::new();
+    imports.define("env", "memory", memory);
+    Instance::with_imports(m, imports).unwrap()
+}
+
+#[test]
+fn atomic_rmw_from_threads() {
+    let m = module();
+    let memory = shared_memory();
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let (m, memory) = (m.clone(), memory.clone());
+            thread::spawn(move || {
+                let mut instance = instantiate(m, memory);
+                instance
+                    .invoke("inc", vec![RuntimeValue::I32(100)])
+                    .unwrap();
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let mut instance = instantiate(m, memory);
+    assert_eq!(
+        instance.invoke("get", vec![]).unwrap(),
+        vec![RuntimeValue::I32(400)]
+    );
+}
+
+#[test]
+fn wait_and_notify() {
+    let m = module();
+    let memory = shared_memory();
+
+    let waiter = {
+        let (m, memory) = (m.clone(), memory.clone());
+        thread::spawn(move || {
+            let mut instance = instantiate(m, memory);
+            instance
+                .invoke("wait", vec![RuntimeValue::I64(-1)])
+                .unwrap()
+        })
+    };
+
+    let mut instance = instantiate(m, memory);
+    while !waiter.is_finished() {
+        instance.invoke("wake", vec![]).unwrap();
+        thread::yield_now();
+    }
+
+    // notifyで起こされれば0、waitより先に値が書き換わっていれば1が返る
+    let result = waiter.join().unwrap();
+    assert!(result == vec![RuntimeValue::I32(0)] || result == vec![RuntimeValue::I32(1)]);
+
+    // 値が1になっているので、0を期待して待つとすぐに1が返る
+    assert_eq!(
+        instance
+            .invoke("wait", vec![RuntimeValue::I64(-1)])
+            .unwrap(),
+        vec![RuntimeValue::I32(1)]
+    );
+}
+
+#[test]
+fn wait_timeout() {
+    let mut instance = instantiate(module(), shared_memory());
+
+    assert_eq!(
+        instance
+            .invoke("wait", vec![RuntimeValue::I64(1_000_000)])
+            .unwrap(),
+        vec![RuntimeValue::I32(2)]
+    );
+}
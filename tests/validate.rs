@@ -0,0 +1,127 @@
+mod common;
+
+use common::*;
+use wai::*;
+
+fn validation_error(wat: &str) -> ValidationError {
+    match Module::from_byte(wast_encode(wat)) {
+        Err(DecodeError::Invalid(e)) => e,
+        other => panic!("expected validation error, but got {:?}", other),
+    }
+}
+
+#[test]
+fn valid_examples() {
+    for path in ["examples/add.wasm", "examples/fib.wasm"] {
+        let bytes = std::fs::read(path).unwrap();
+        let m = Module::from_byte_unchecked(bytes).unwrap();
+        assert_eq!(validate(&m), Ok(()), "{}", path);
+    }
+}
+
+#[test]
+fn type_mismatch() {
+    let e = validation_error(
+        r#"
+        (module
+          (func)
+          (func (param i64) (result i32)
+            block (result i32)
+              local.get 0
+            end))
+        "#,
+    );
+
+    // block, local.get 0, endの3番目で、i64をi32として返そうとしている
+    assert!(matches!(
+        e,
+        ValidationError::Function {
+            index: 1,
+            instruction_index: 2,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn stack_polymorphic_after_unreachable() {
+    let m = Module::from_byte(wast_encode(
+        r#"
+        (module
+          (func (result i32)
+            unreachable
+            i32.add)
+          (func (param i32) (result i64)
+            block (result i64)
+              i64.const 1
+              local.get 0
+              br_if 0
+              drop
+              i64.const 2
+              return
+            end))
+        "#,
+    ));
+
+    assert!(m.is_ok());
+}
+
+#[test]
+fn invalid_indices() {
+    assert!(matches!(
+        validation_error(r#"(module (func call 3))"#),
+        ValidationError::Function { index: 0, .. }
+    ));
+    assert!(matches!(
+        validation_error(r#"(module (func i32.const 0 i32.load drop))"#),
+        ValidationError::Function {
+            instruction_index: 1,
+            ..
+        }
+    ));
+    assert!(matches!(
+        validation_error(r#"(module (func br 1))"#),
+        ValidationError::Function {
+            instruction_index: 0,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn module_errors() {
+    assert!(matches!(
+        validation_error(r#"(module (memory 65537))"#),
+        ValidationError::Module(_)
+    ));
+    assert!(matches!(
+        validation_error(r#"(module (func (export "f")) (func (export "f")))"#),
+        ValidationError::Module(_)
+    ));
+    assert!(matches!(
+        validation_error(
+            r#"(module (global $g (mut i32) (i32.const 0)) (global i32 (global.get $g)))"#
+        ),
+        ValidationError::Module(_)
+    ));
+    assert!(matches!(
+        validation_error(r#"(module (global i64 (i32.const 0)))"#),
+        ValidationError::Module(_)
+    ));
+    assert!(matches!(
+        validation_error(r#"(module (func $f (param i32)) (start $f))"#),
+        ValidationError::Module(_)
+    ));
+    assert!(matches!(
+        validation_error(r#"(module (memory 1) (data (i64.const 0) "a"))"#),
+        ValidationError::Module(_)
+    ));
+}
+
+#[test]
+fn skip_validation() {
+    let bytes = wast_encode(r#"(module (func (result i32) i64.const 0))"#);
+
+    assert!(Module::from_byte(&bytes).is_err());
+    assert!(Module::from_byte_unchecked(&bytes).is_ok());
+}